@@ -0,0 +1,117 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fuzzy::benchmark;
+use fuzzy::cancellation::CancellationToken;
+use fuzzy::persistence::Persistence;
+use std::fs;
+use std::path::PathBuf;
+
+// About the size of a mid-sized Rails app
+const WORKSPACE_FILE_COUNT: usize = 5_000;
+
+// Parsing and serializing the AST into documents, the work done per file
+// before anything is written to the index
+fn parse(c: &mut Criterion) {
+    let sources: Vec<String> = (0..100).map(benchmark::ruby_source).collect();
+    let source_bytes: usize = sources.iter().map(String::len).sum();
+    let mut parser = Persistence::new().unwrap();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(source_bytes as u64));
+    group.bench_function("parse_file", |b| {
+        b.iter(|| {
+            for source in &sources {
+                parser.parse_file(source, false);
+            }
+        })
+    });
+    group.bench_function("parse_file_interface_only", |b| {
+        b.iter(|| {
+            for source in &sources {
+                parser.parse_file(source, true);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn index(c: &mut Criterion) {
+    let workspace = SyntheticWorkspace::new("index");
+    let workspace_path = workspace.path();
+
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(WORKSPACE_FILE_COUNT as u64));
+    group.bench_function("workspace_5k_files", |b| {
+        b.iter(|| benchmark::index_workspace(&workspace_path).unwrap())
+    });
+    group.finish();
+}
+
+// Criterion reports the mean over every sampled position, the percentiles
+// are printed after so slow outliers aren't averaged away
+fn query(c: &mut Criterion) {
+    let workspace = SyntheticWorkspace::new("query");
+    let persistence = benchmark::index_workspace(&workspace.path()).unwrap();
+    let positions = benchmark::sample_positions(&persistence, benchmark::SAMPLE_SIZE).unwrap();
+
+    let mut group = c.benchmark_group("query");
+    group.throughput(Throughput::Elements(positions.len() as u64));
+    group.bench_function("definition", |b| {
+        b.iter(|| {
+            for position in &positions {
+                persistence
+                    .find_definitions(position.clone(), &CancellationToken::default())
+                    .unwrap();
+            }
+        })
+    });
+    group.bench_function("highlight", |b| {
+        b.iter(|| {
+            for position in &positions {
+                persistence.find_highlights(position.clone()).unwrap();
+            }
+        })
+    });
+    group.finish();
+
+    let query_latency = benchmark::query_latency(&persistence, &positions).unwrap();
+
+    for (name, latency) in [
+        ("definition", &query_latency.definition),
+        ("highlight", &query_latency.highlight),
+    ] {
+        println!(
+            "query/{}: p50 {:?}, p95 {:?} over {} positions",
+            name, latency.p50, latency.p95, latency.samples
+        );
+    }
+}
+
+// Written once per bench and removed when it's done
+struct SyntheticWorkspace {
+    path: PathBuf,
+}
+
+impl SyntheticWorkspace {
+    fn new(name: &str) -> SyntheticWorkspace {
+        let path =
+            std::env::temp_dir().join(format!("fuzzy-benchmark-{}-{}", name, std::process::id()));
+
+        benchmark::write_workspace(&path, WORKSPACE_FILE_COUNT).unwrap();
+
+        SyntheticWorkspace { path }
+    }
+
+    fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for SyntheticWorkspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+criterion_group!(benches, parse, index, query);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 import {
   ExtensionContext,
+  window,
   workspace,
 } from "vscode";
 
@@ -54,7 +55,9 @@ export async function activate(_context: ExtensionContext) {
 
   let clientOptions: LanguageClientOptions = {
     documentSelector: [
-      { scheme: "file", language: "ruby" }
+      { scheme: "file", language: "ruby" },
+      { scheme: "file", pattern: "**/config/locales/**/*.{yml,yaml}" },
+      { scheme: "file", pattern: "**/app/views/**/*.erb" }
     ],
     synchronize: {
       // fileEvents: workspace.createFileSystemWatcher("**/.clientrc"),
@@ -63,12 +66,34 @@ export async function activate(_context: ExtensionContext) {
       allocationType: client_config.get("allocationType"),
       indexGems: client_config.get("indexGems"),
       reportDiagnostics: client_config.get("reportDiagnostics"),
+      forceRename: client_config.get("forceRename"),
+      resolveSymlinks: client_config.get("resolveSymlinks"),
+      indexHeredocConstants: client_config.get("indexHeredocConstants"),
+      fuzzySearch: client_config.get("fuzzySearch"),
+      maxDefinitions: client_config.get("maxDefinitions"),
+      maxReferences: client_config.get("maxReferences"),
+      queryTimeBudgetMs: client_config.get("queryTimeBudgetMs"),
+      maxFileSizeMb: client_config.get("maxFileSizeMb"),
+      stubIndexFileSizeMb: client_config.get("stubIndexFileSizeMb"),
+      indexMemoryBudgetMb: client_config.get("indexMemoryBudgetMb"),
+      testCommandTemplate: client_config.get("testCommandTemplate"),
+      indexIgnoredFiles: client_config.get("indexIgnoredFiles"),
+      rubyVersion: client_config.get("rubyVersion"),
+      dslRules: client_config.get("dslRules"),
     },
   };
 
   // Create the language client and start the client.
   client = new LanguageClient("fuzzy-ruby-server", "Fuzzy Ruby Server", serverOptions, clientOptions);
   client.start();
+
+  // "Run test" code lenses send back the command to run
+  client.onNotification("fuzzy/runTest", ({ command, cwd }) => {
+    const terminal = window.createTerminal({ name: "Fuzzy Ruby Tests", cwd });
+
+    terminal.show();
+    terminal.sendText(command);
+  });
 }
 
 export function deactivate(): Thenable<void> {
@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::Path;
+
+// Rails apps load constants from files named after them, following Zeitwerk's
+// conventions. Each directory in `app/`, their `concerns/` directories, and
+// `lib/` are roots, with namespaces as subdirectories:
+//
+//   Admin::UsersController  =>  app/controllers/admin/users_controller.rb
+//   Billing::Invoice        =>  app/models/billing/invoice.rb
+
+pub fn is_rails_app(workspace_path: &str) -> bool {
+    Path::new(workspace_path)
+        .join("config/application.rb")
+        .is_file()
+}
+
+// Existing files a constant would be autoloaded from
+pub fn constant_file_paths(workspace_path: &str, constant_path: &[String]) -> Vec<String> {
+    let relative_path = constant_path
+        .iter()
+        .map(|name| underscore(name))
+        .collect::<Vec<String>>()
+        .join("/");
+
+    autoload_roots(workspace_path)
+        .iter()
+        .map(|root| format!("{}/{}.rb", root, relative_path))
+        .filter(|path| Path::new(path).is_file())
+        .collect()
+}
+
+fn autoload_roots(workspace_path: &str) -> Vec<String> {
+    let mut roots = vec![];
+
+    if let Ok(entries) = fs::read_dir(Path::new(workspace_path).join("app")) {
+        let mut app_dirs: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| {
+                format!(
+                    "{}/app/{}",
+                    workspace_path,
+                    entry.file_name().to_string_lossy()
+                )
+            })
+            .collect();
+
+        app_dirs.sort();
+
+        for app_dir in app_dirs {
+            let concerns_dir = format!("{}/concerns", app_dir);
+
+            roots.push(app_dir);
+
+            if Path::new(&concerns_dir).is_dir() {
+                roots.push(concerns_dir);
+            }
+        }
+    }
+
+    roots.push(format!("{}/lib", workspace_path));
+
+    roots
+}
+
+// The constant a file name is autoloaded as, `user_profile` is `UserProfile`
+pub fn camelize(name: &str) -> String {
+    name.split("_")
+        .map(|word| {
+            let mut chars = word.chars();
+
+            match chars.next() {
+                Some(first_char) => first_char.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// Words ActiveSupport doesn't inflect by its rules
+static IRREGULAR_PLURALS: &[(&str, &str)] = &[
+    ("child", "children"),
+    ("man", "men"),
+    ("person", "people"),
+    ("woman", "women"),
+];
+
+// Enough of ActiveSupport's `pluralize` for resource names, `company` is
+// `companies`
+pub fn pluralize(name: &str) -> String {
+    let (stem, word) = last_word(name);
+
+    if let Some((_singular, plural)) = IRREGULAR_PLURALS
+        .iter()
+        .find(|(singular, _plural)| *singular == word)
+    {
+        return format!("{}{}", stem, plural);
+    }
+
+    if let Some(word_stem) = name.strip_suffix('y') {
+        if !word_stem.ends_with(['a', 'e', 'i', 'o', 'u']) {
+            return format!("{}ies", word_stem);
+        }
+    }
+
+    if name.ends_with(['s', 'x', 'z']) || name.ends_with("ch") || name.ends_with("sh") {
+        return format!("{}es", name);
+    }
+
+    format!("{}s", name)
+}
+
+// And its `singularize`, for table names, `line_items` is `line_item`
+pub fn singularize(name: &str) -> String {
+    let (stem, word) = last_word(name);
+
+    if let Some((singular, _plural)) = IRREGULAR_PLURALS
+        .iter()
+        .find(|(_singular, plural)| *plural == word)
+    {
+        return format!("{}{}", stem, singular);
+    }
+
+    if name.ends_with("ss") || name.ends_with("us") {
+        return name.to_string();
+    }
+
+    if let Some(word_stem) = name.strip_suffix("ies") {
+        return format!("{}y", word_stem);
+    }
+
+    for suffix in ["aliases", "ches", "shes", "sses", "statuses", "xes"] {
+        if name.ends_with(suffix) {
+            return name[..name.len() - 2].to_string();
+        }
+    }
+
+    name.strip_suffix('s').unwrap_or(name).to_string()
+}
+
+// The words before the last one, with their underscore, and the last
+fn last_word(name: &str) -> (&str, &str) {
+    match name.rfind('_') {
+        Some(index) => name.split_at(index + 1),
+        None => ("", name),
+    }
+}
+
+// ActiveSupport's `underscore`, `HTMLParser` is `html_parser`
+pub fn underscore(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut underscored = String::new();
+
+    for (index, char) in chars.iter().enumerate() {
+        if char.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next = chars.get(index + 1);
+
+            let word_start = previous.is_lowercase() || previous.is_ascii_digit();
+            let acronym_end =
+                previous.is_uppercase() && next.map_or(false, |next| next.is_lowercase());
+
+            if word_start || acronym_end {
+                underscored.push('_');
+            }
+        }
+
+        underscored.extend(char.to_lowercase());
+    }
+
+    underscored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn finds_constants_in_namespace_directories() {
+        let workspace_path = env::temp_dir().join(format!("fuzzy-autoload-{}", std::process::id()));
+        let controller_path = workspace_path.join("app/controllers/admin/users_controller.rb");
+        fs::create_dir_all(controller_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(workspace_path.join("app/models/concerns")).unwrap();
+        fs::write(&controller_path, "").unwrap();
+        let workspace_path = workspace_path.to_str().unwrap();
+
+        let constant_path =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+
+        assert_eq!(
+            constant_file_paths(
+                workspace_path,
+                &constant_path(&["Admin", "UsersController"])
+            ),
+            vec![format!(
+                "{}/app/controllers/admin/users_controller.rb",
+                workspace_path
+            )]
+        );
+        assert!(
+            constant_file_paths(workspace_path, &constant_path(&["UsersController"])).is_empty()
+        );
+        assert_eq!(
+            autoload_roots(workspace_path),
+            vec![
+                format!("{}/app/controllers", workspace_path),
+                format!("{}/app/models", workspace_path),
+                format!("{}/app/models/concerns", workspace_path),
+                format!("{}/lib", workspace_path),
+            ]
+        );
+    }
+
+    #[test]
+    fn inflects_names() {
+        assert_eq!(underscore("UsersController"), "users_controller");
+        assert_eq!(underscore("HTMLParser"), "html_parser");
+        assert_eq!(camelize("user_profile"), "UserProfile");
+        assert_eq!(pluralize("company"), "companies");
+        assert_eq!(pluralize("line_person"), "line_people");
+        assert_eq!(singularize("line_items"), "line_item");
+        assert_eq!(singularize("statuses"), "status");
+    }
+}
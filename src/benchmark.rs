@@ -0,0 +1,204 @@
+use crate::cancellation::CancellationToken;
+use crate::paths;
+use crate::persistence::Persistence;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tower_lsp::lsp_types::{InitializeParams, TextDocumentIdentifier, TextDocumentPositionParams};
+
+// A baseline for performance work, shared by the criterion benches and the
+// `fuzzy.benchmark` command:
+//
+//   cargo bench                 parsing, indexing, and queries
+//   cargo bench -- query        just definition and highlight latency
+//
+// The benches run against a synthetic workspace of model-like classes that
+// call each other's methods. The command samples positions from the
+// editor's own index instead, so latency can be compared across projects.
+
+pub const BENCHMARK_COMMAND: &str = "fuzzy.benchmark";
+
+// Positions each query is timed at
+pub const SAMPLE_SIZE: usize = 200;
+
+const FILES_PER_DIR: usize = 100;
+
+// A class with the kinds of nodes most files have: constants, attribute
+// readers, instance variables, blocks with locals, calls to its own methods
+// and to neighbouring files' classes, and private methods
+pub fn ruby_source(file_number: usize) -> String {
+    let previous = file_number.saturating_sub(1);
+    let next = file_number + 1;
+
+    format!(
+        r#"# Generated for benchmarking
+module Synthetic
+  class Model{file_number} < Base
+    LIMIT = {file_number}
+
+    attr_reader :name, :items
+
+    def initialize(name, items = [])
+      @name = name
+      @items = items
+    end
+
+    def total
+      items.sum {{ |item| item.price * quantity_for(item) }}
+    end
+
+    def quantity_for(item)
+      count = items.count {{ |other| other == item }}
+      count > LIMIT ? LIMIT : count
+    end
+
+    def related_total
+      Model{previous}.new(name).total + Model{next}.new(normalized_name, items).total
+    end
+
+    def self.build(attributes = {{}})
+      new(attributes.fetch(:name), attributes.fetch(:items, []))
+    end
+
+    private
+
+    def normalized_name
+      name.to_s.strip.downcase
+    end
+  end
+end
+"#
+    )
+}
+
+// Spread over directories the way an app's files are
+pub fn write_workspace(workspace_path: &Path, file_count: usize) -> io::Result<()> {
+    for file_number in 0..file_count {
+        let dir_path = workspace_path
+            .join("app/models")
+            .join(format!("group_{}", file_number / FILES_PER_DIR));
+
+        fs::create_dir_all(&dir_path)?;
+        fs::write(
+            dir_path.join(format!("model_{}.rb", file_number)),
+            ruby_source(file_number),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Indexes a workspace in memory, without its gems, so only the workspace's
+// files are measured
+pub fn index_workspace(workspace_path: &str) -> tantivy::Result<Persistence> {
+    let params = InitializeParams {
+        root_uri: Some(paths::path_to_uri(&paths::canonicalize(workspace_path))),
+        initialization_options: Some(json!({ "allocationType": "ram", "indexGems": false })),
+        ..InitializeParams::default()
+    };
+
+    let mut persistence = Persistence::new()?;
+
+    persistence.initialize(&params);
+    persistence.reindex_modified_files()?;
+
+    Ok(persistence)
+}
+
+// Usages spread evenly through the workspace's files
+pub fn sample_positions(
+    persistence: &Persistence,
+    sample_size: usize,
+) -> tantivy::Result<Vec<TextDocumentPositionParams>> {
+    let usages: Vec<TextDocumentPositionParams> = persistence
+        .lsif_occurrences()?
+        .into_iter()
+        .filter(|occurrence| !occurrence.definition)
+        .map(|occurrence| {
+            TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(occurrence.uri),
+                occurrence.range.start,
+            )
+        })
+        .collect();
+
+    let step = (usages.len() / sample_size.max(1)).max(1);
+
+    Ok(usages.into_iter().step_by(step).take(sample_size).collect())
+}
+
+pub struct Latency {
+    pub samples: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+impl Latency {
+    pub fn from_durations(mut durations: Vec<Duration>) -> Latency {
+        durations.sort();
+
+        Latency {
+            samples: durations.len(),
+            p50: percentile(&durations, 50),
+            p95: percentile(&durations, 95),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "samples": self.samples,
+            "p50Ms": self.p50.as_secs_f64() * 1000.0,
+            "p95Ms": self.p95.as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+pub struct QueryLatency {
+    pub definition: Latency,
+    pub highlight: Latency,
+}
+
+impl QueryLatency {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "definition": self.definition.to_json(),
+            "highlight": self.highlight.to_json(),
+        })
+    }
+}
+
+pub fn query_latency(
+    persistence: &Persistence,
+    positions: &[TextDocumentPositionParams],
+) -> tantivy::Result<QueryLatency> {
+    let mut definition_durations = Vec::with_capacity(positions.len());
+    let mut highlight_durations = Vec::with_capacity(positions.len());
+
+    for position in positions {
+        let start_time = Instant::now();
+        persistence.find_definitions(position.clone(), &CancellationToken::default())?;
+        definition_durations.push(start_time.elapsed());
+
+        let start_time = Instant::now();
+        persistence.find_highlights(position.clone())?;
+        highlight_durations.push(start_time.elapsed());
+    }
+
+    Ok(QueryLatency {
+        definition: Latency::from_durations(definition_durations),
+        highlight: Latency::from_durations(highlight_durations),
+    })
+}
+
+// The nearest-rank percentile of sorted durations
+fn percentile(durations: &[Duration], percent: usize) -> Duration {
+    if durations.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (durations.len() * percent + 99) / 100;
+
+    durations[rank.max(1) - 1]
+}
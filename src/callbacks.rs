@@ -0,0 +1,158 @@
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// Rails callbacks, which call the methods they're given by name:
+//
+//   before_action :authenticate_user!, unless: :public_page?
+//   validate :check_totals
+//   after_commit :enqueue_sync, if: [:persisted?, :changed?]
+//
+// The symbols are indexed as calls from the class body, so they're looked
+// up in the class and the classes and modules it inherits from.
+
+static CALLBACK_METHODS: &[&str] = &[
+    // ActionController
+    "after_action",
+    "append_after_action",
+    "append_around_action",
+    "append_before_action",
+    "around_action",
+    "before_action",
+    "prepend_after_action",
+    "prepend_around_action",
+    "prepend_before_action",
+    "skip_after_action",
+    "skip_around_action",
+    "skip_before_action",
+    // ActiveRecord and ActiveModel
+    "after_commit",
+    "after_create",
+    "after_create_commit",
+    "after_destroy",
+    "after_destroy_commit",
+    "after_find",
+    "after_initialize",
+    "after_rollback",
+    "after_save",
+    "after_save_commit",
+    "after_touch",
+    "after_update",
+    "after_update_commit",
+    "after_validation",
+    "around_create",
+    "around_destroy",
+    "around_save",
+    "around_update",
+    "before_create",
+    "before_destroy",
+    "before_save",
+    "before_update",
+    "before_validation",
+    "validate",
+    // ActiveJob
+    "after_enqueue",
+    "after_perform",
+    "around_enqueue",
+    "around_perform",
+    "before_enqueue",
+    "before_perform",
+];
+
+pub fn is_callback(method_name: &str) -> bool {
+    CALLBACK_METHODS.contains(&method_name)
+}
+
+// The methods a callback calls, the callbacks themselves and their `if:`
+// and `unless:` conditions, with the symbols naming them
+pub fn callback_methods(args: &[Node]) -> Vec<(String, &Loc)> {
+    let mut methods = vec![];
+
+    for arg in args {
+        match arg {
+            Node::Sym(_) => methods.extend(method_symbol(arg)),
+            Node::Kwargs(Kwargs { pairs, .. }) => {
+                for pair in pairs {
+                    let (key, value) = match pair {
+                        Node::Pair(Pair { key, value, .. }) => (key.as_ref(), value.as_ref()),
+                        _ => continue,
+                    };
+
+                    let condition = match key {
+                        Node::Sym(Sym { name, .. }) => {
+                            let name = name.to_string_lossy();
+                            name == "if" || name == "unless"
+                        }
+                        _ => false,
+                    };
+
+                    if !condition {
+                        continue;
+                    }
+
+                    match value {
+                        Node::Array(Array { elements, .. }) => {
+                            methods.extend(elements.iter().filter_map(method_symbol));
+                        }
+                        value => methods.extend(method_symbol(value)),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    methods
+}
+
+fn method_symbol(node: &Node) -> Option<(String, &Loc)> {
+    match node {
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => Some((name.to_string_lossy(), expression_l)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    fn called_methods(call: &str) -> Vec<String> {
+        let ast = Parser::new(call.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+
+        match ast.as_ref() {
+            Node::Send(Send {
+                method_name, args, ..
+            }) if is_callback(method_name) => callback_methods(args)
+                .into_iter()
+                .map(|(_name, loc)| call[loc.begin..loc.end].to_string())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn reads_the_methods_a_callback_calls() {
+        assert_eq!(
+            called_methods("before_action :authenticate_user!, :load_cart, only: [:show]"),
+            vec![":authenticate_user!", ":load_cart"]
+        );
+        assert_eq!(
+            called_methods(
+                "after_commit :enqueue_sync, if: [:persisted?, :changed?], unless: :draft?"
+            ),
+            vec![":enqueue_sync", ":persisted?", ":changed?", ":draft?"]
+        );
+        assert_eq!(
+            called_methods("before_save :normalize, prepend: true"),
+            vec![":normalize"]
+        );
+        assert_eq!(
+            called_methods("validates :name, presence: true"),
+            Vec::<String>::new()
+        );
+    }
+}
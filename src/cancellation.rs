@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Checked between the phases of expensive searches so they can stop early.
+// Searches run synchronously while holding the persistence lock, so tower-lsp
+// dropping a cancelled request's future can't interrupt them on its own.
+//
+// A token with a time budget also stops searches once it's spent, which
+// then answer with what they've found so far.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    // Shares the cancellation with the token it's made from
+    pub fn with_time_budget(&self, time_budget: Option<Duration>) -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: time_budget.map(|time_budget| Instant::now() + time_budget),
+            timed_out: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.timed_out.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Whether a search stopped early because the time budget ran out, rather
+    // than being cancelled, so its results are partial but still wanted
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed) && !self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Cancels the token when a request handler's future is dropped, which is
+// how tower-lsp handles `$/cancelRequest`.
+pub struct CancelOnDrop(pub CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancels_when_the_request_is_dropped() {
+        let token = CancellationToken::default();
+        let request = CancelOnDrop(token.clone());
+
+        assert!(!token.is_cancelled());
+
+        drop(request);
+
+        assert!(token.is_cancelled());
+        assert!(!token.timed_out());
+    }
+
+    #[test]
+    fn times_out_once_the_time_budget_is_spent() {
+        let token = CancellationToken::default();
+        let unlimited = token.with_time_budget(None);
+        let spent = token.with_time_budget(Some(Duration::ZERO));
+
+        assert!(!unlimited.is_cancelled());
+        assert!(spent.is_cancelled());
+        assert!(spent.timed_out());
+        assert!(!token.is_cancelled());
+
+        // Cancelling wins over the time budget
+        token.cancel();
+
+        assert!(unlimited.is_cancelled());
+        assert!(!spent.timed_out());
+    }
+}
@@ -0,0 +1,280 @@
+use log::LevelFilter;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const HELP: &str = "\
+Fuzzy Ruby Server, a Ruby language server
+
+Usage:
+  fuzzy [options]                Start the language server
+  fuzzy index <path> [options]   Index a workspace without starting the server
+  fuzzy query def <file>:<line>:<column> [options]
+                                 Print the definitions of what's at a position
+  fuzzy query refs <name> [options]
+                                 Print every usage of a name
+  fuzzy export <path> [options]  Index a workspace and write it as an LSIF dump
+
+Options:
+  --tcp <[host:]port>   Listen for the editor on a TCP port instead of stdio
+  --pipe <name>         Connect to a socket file or named pipe the editor is listening on
+  --log-file <path>     Write logs to a file instead of stderr
+  --log-level <level>   One of off, error, warn, info, debug, or trace
+  --workspace <path>    Workspace to query, defaults to the current directory
+  --json                Print query results as JSON instead of grep-style lines
+  --output <path>       File to write the export to, defaults to dump.lsif
+  -V, --version         Print the version
+  -h, --help            Print this help";
+
+pub enum Transport {
+    Stdio,
+    // Listens for the editor to connect, `--tcp 7658` or `--tcp 0.0.0.0:7658`
+    Tcp(String),
+    // Connects to a socket file or Windows named pipe the editor is listening on
+    Pipe(String),
+}
+
+pub enum QueryKind {
+    // Lines and columns as given on the command line, starting at 1
+    Definitions {
+        path: String,
+        line: u32,
+        column: u32,
+    },
+    References(String),
+}
+
+pub struct Query {
+    pub kind: QueryKind,
+    pub workspace_path: String,
+    pub json: bool,
+}
+
+pub struct Export {
+    pub workspace_path: String,
+    pub output_path: String,
+}
+
+pub enum Command {
+    Serve(Transport),
+    Index(String),
+    Query(Query),
+    Export(Export),
+    Version,
+    Help,
+}
+
+pub struct Cli {
+    pub command: Command,
+    pub log_file: Option<String>,
+    // Falls back to `RUST_LOG` when not given
+    pub log_level: Option<LevelFilter>,
+}
+
+impl Cli {
+    pub fn from_args(args: &[String]) -> Result<Cli, String> {
+        let mut args = args.iter();
+        let mut transport = Transport::Stdio;
+        let mut positional_args = vec![];
+        let mut log_file = None;
+        let mut log_level = None;
+        let mut workspace_path = None;
+        let mut json = false;
+        let mut output_path = None;
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once("=") {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+
+            let mut flag_value = || -> Result<String, String> {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| format!("{} requires a value", flag))
+            };
+
+            match flag {
+                "-h" | "--help" => return Ok(Cli::new(Command::Help)),
+                "-V" | "--version" => return Ok(Cli::new(Command::Version)),
+                "--tcp" => {
+                    let address = flag_value()?;
+
+                    transport = if address.contains(":") {
+                        Transport::Tcp(address)
+                    } else {
+                        Transport::Tcp(format!("127.0.0.1:{}", address))
+                    };
+                }
+                "--pipe" => transport = Transport::Pipe(flag_value()?),
+                "--log-file" => log_file = Some(flag_value()?),
+                "--log-level" => {
+                    let level = flag_value()?;
+
+                    log_level = Some(
+                        level
+                            .parse::<LevelFilter>()
+                            .map_err(|_| format!("Unknown log level `{}`", level))?,
+                    );
+                }
+                "--workspace" => workspace_path = Some(flag_value()?),
+                "--json" => json = true,
+                "--output" => output_path = Some(flag_value()?),
+                // Editors launching servers commonly pass `--stdio`
+                "--stdio" => transport = Transport::Stdio,
+                _ if flag.starts_with("-") => return Err(format!("Unknown option `{}`", flag)),
+                _ => positional_args.push(arg.as_str()),
+            }
+        }
+
+        let command = match positional_args.as_slice() {
+            [] => Command::Serve(transport),
+            ["index", path] => Command::Index(path.to_string()),
+            ["index"] => return Err("index requires a workspace path".to_string()),
+            ["query", "def", position] => Command::Query(Query {
+                kind: parse_position(position)?,
+                workspace_path: workspace_path.unwrap_or(".".to_string()),
+                json,
+            }),
+            ["query", "refs", name] => Command::Query(Query {
+                kind: QueryKind::References(name.to_string()),
+                workspace_path: workspace_path.unwrap_or(".".to_string()),
+                json,
+            }),
+            ["query", ..] => {
+                return Err(
+                    "query requires `def <file>:<line>:<column>` or `refs <name>`".to_string(),
+                )
+            }
+            ["export", path] => Command::Export(Export {
+                workspace_path: path.to_string(),
+                output_path: output_path.unwrap_or("dump.lsif".to_string()),
+            }),
+            ["export"] => return Err("export requires a workspace path".to_string()),
+            [unknown, ..] => return Err(format!("Unknown command `{}`", unknown)),
+        };
+
+        Ok(Cli {
+            command,
+            log_file,
+            log_level,
+        })
+    }
+
+    fn new(command: Command) -> Cli {
+        Cli {
+            command,
+            log_file: None,
+            log_level: None,
+        }
+    }
+}
+
+// Splits from the right so Windows drive letters stay with the path
+fn parse_position(position: &str) -> Result<QueryKind, String> {
+    let invalid_position = || format!("Expected <file>:<line>:<column>, got `{}`", position);
+
+    let mut parts = position.rsplitn(3, ":");
+    let column = parts.next().and_then(|column| column.parse::<u32>().ok());
+    let line = parts.next().and_then(|line| line.parse::<u32>().ok());
+    let path = parts.next();
+
+    match (path, line, column) {
+        (Some(path), Some(line), Some(column)) if line > 0 && column > 0 => {
+            Ok(QueryKind::Definitions {
+                path: path.to_string(),
+                line,
+                column,
+            })
+        }
+        _ => Err(invalid_position()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &[&str]) -> Result<Cli, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        Cli::from_args(&args)
+    }
+
+    #[test]
+    fn serves_over_stdio_unless_told_otherwise() {
+        let stdio = cli(&["--stdio", "--log-level", "debug"]).unwrap();
+
+        assert!(matches!(stdio.command, Command::Serve(Transport::Stdio)));
+        assert_eq!(stdio.log_level, Some(LevelFilter::Debug));
+
+        let tcp = cli(&["--tcp", "0.0.0.0:7658"]).unwrap();
+
+        assert!(matches!(
+            tcp.command,
+            Command::Serve(Transport::Tcp(address)) if address == "0.0.0.0:7658"
+        ));
+    }
+
+    #[test]
+    fn takes_values_after_an_equals_sign() {
+        let cli = cli(&["--tcp=7658", "--log-file=/tmp/fuzzy.log"]).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Command::Serve(Transport::Tcp(address)) if address == "127.0.0.1:7658"
+        ));
+        assert_eq!(cli.log_file.as_deref(), Some("/tmp/fuzzy.log"));
+    }
+
+    #[test]
+    fn rejects_unknown_flags_and_missing_values() {
+        assert_eq!(
+            cli(&["--verbose"]).err(),
+            Some("Unknown option `--verbose`".to_string())
+        );
+        assert_eq!(
+            cli(&["--log-file"]).err(),
+            Some("--log-file requires a value".to_string())
+        );
+        assert_eq!(
+            cli(&["--log-level=loud"]).err(),
+            Some("Unknown log level `loud`".to_string())
+        );
+        assert_eq!(
+            cli(&["reindex"]).err(),
+            Some("Unknown command `reindex`".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_commands_and_their_paths() {
+        assert!(matches!(
+            cli(&["index", "."]).unwrap().command,
+            Command::Index(path) if path == "."
+        ));
+        assert!(matches!(
+            cli(&["query", "refs", "User", "--json"]).unwrap().command,
+            Command::Query(Query {
+                kind: QueryKind::References(name),
+                json: true,
+                ..
+            }) if name == "User"
+        ));
+    }
+
+    #[test]
+    fn splits_positions_from_the_right() {
+        assert!(matches!(
+            parse_position("app/models/user.rb:3:4"),
+            Ok(QueryKind::Definitions { path, line: 3, column: 4 }) if path == "app/models/user.rb"
+        ));
+        assert!(matches!(
+            parse_position(r"C:\a.rb:3:4"),
+            Ok(QueryKind::Definitions { path, line: 3, column: 4 }) if path == r"C:\a.rb"
+        ));
+        assert!(parse_position("user.rb:3").is_err());
+        assert!(parse_position("user.rb:0:4").is_err());
+        assert!(parse_position(r"C:\a.rb:x:4").is_err());
+    }
+}
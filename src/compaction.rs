@@ -0,0 +1,151 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use tantivy::indexer::{LogMergePolicy, MergePolicy};
+use tantivy::{Index, IndexWriter};
+
+// Each edit commits a segment of its own, and the writers committing them
+// are dropped before they get to merge anything, so over a long session
+// small segments pile up that every query has to search:
+//
+//   merge policy  writers merge segments of a size as they commit, set by
+//                 the `indexMergeMinSegments` and `indexMergeMaxDocs` settings
+//   idle          once nothing's been indexed for a while, the index is
+//                 compacted if merging has fallen behind
+//   command       `fuzzy.compactIndex` compacts it right away
+//
+// Compacting merges every segment into one, dropping deleted documents and
+// the documents of files' superseded generations.
+
+pub const COMPACT_INDEX_COMMAND: &str = "fuzzy.compactIndex";
+
+// How long the indexer waits without a job before checking the segments
+pub const IDLE_BEFORE_COMPACTING: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergeSettings {
+    // Segments of a similar size are merged once there are this many
+    pub min_segments: usize,
+    // Segments with more documents are left alone until compacted
+    pub max_docs: usize,
+}
+
+impl Default for MergeSettings {
+    fn default() -> MergeSettings {
+        MergeSettings {
+            min_segments: 8,
+            max_docs: 10_000_000,
+        }
+    }
+}
+
+impl MergeSettings {
+    pub fn merge_policy(&self) -> Box<dyn MergePolicy> {
+        let mut merge_policy = LogMergePolicy::default();
+        merge_policy.set_min_num_segments(self.min_segments.max(2));
+        merge_policy.set_max_docs_before_merge(self.max_docs);
+
+        Box::new(merge_policy)
+    }
+
+    // The policy leaves fewer than `min_segments` of each size, so twice
+    // that many means edits are being committed faster than they're merged
+    pub fn needs_compacting(&self, segment_count: usize) -> bool {
+        segment_count > 2 * self.min_segments.max(2)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Compaction {
+    pub segments_before: usize,
+    pub segments_after: usize,
+    pub deleted_docs_removed: u32,
+}
+
+impl Compaction {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "segmentsBefore": self.segments_before,
+            "segmentsAfter": self.segments_after,
+            "deletedDocsRemoved": self.deleted_docs_removed,
+        })
+    }
+}
+
+// Merges the index's segments into one, waiting for the merge to finish,
+// after committing the deletes the writer was given. An index already in
+// one segment without deletes is left as it is.
+pub fn compact(index: &Index, mut index_writer: IndexWriter) -> tantivy::Result<Compaction> {
+    index_writer.commit()?;
+
+    let segment_metas = index.searchable_segment_metas()?;
+    let deleted_docs: u32 = segment_metas
+        .iter()
+        .map(|segment_meta| segment_meta.num_deleted_docs())
+        .sum();
+
+    if segment_metas.len() > 1 || deleted_docs > 0 {
+        let segment_ids: Vec<_> = segment_metas
+            .iter()
+            .map(|segment_meta| segment_meta.id())
+            .collect();
+
+        index_writer.merge(&segment_ids).wait()?;
+    }
+
+    index_writer.wait_merging_threads()?;
+
+    Ok(Compaction {
+        segments_before: segment_metas.len(),
+        segments_after: index.searchable_segment_ids()?.len(),
+        deleted_docs_removed: deleted_docs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::{Schema, STRING};
+    use tantivy::{doc, Term};
+
+    #[test]
+    fn compacts_only_once_merging_falls_behind() {
+        let merge_settings = MergeSettings::default();
+
+        assert!(!merge_settings.needs_compacting(1));
+        assert!(!merge_settings.needs_compacting(16));
+        assert!(merge_settings.needs_compacting(17));
+
+        let merge_settings = MergeSettings {
+            min_segments: 0,
+            max_docs: 100,
+        };
+
+        assert!(merge_settings.needs_compacting(5));
+    }
+
+    #[test]
+    fn merges_every_segment_into_one() {
+        let mut schema_builder = Schema::builder();
+        let name = schema_builder.add_text_field("name", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_with_num_threads(1, 20_000_000).unwrap();
+
+        // A commit per edit, like the indexer makes while typing
+        for edit in ["order", "product", "customer"] {
+            index_writer.add_document(doc!(name => edit)).unwrap();
+            index_writer.commit().unwrap();
+        }
+
+        index_writer.delete_term(Term::from_field_text(name, "product"));
+        index_writer.commit().unwrap();
+        drop(index_writer);
+
+        let index_writer = index.writer_with_num_threads(1, 20_000_000).unwrap();
+        let compaction = compact(&index, index_writer).unwrap();
+
+        assert_eq!(compaction.segments_before, 3);
+        assert_eq!(compaction.segments_after, 1);
+        assert_eq!(compaction.deleted_docs_removed, 1);
+        assert_eq!(index.reader().unwrap().searcher().num_docs(), 2);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::resolver::{ASSIGNMENT_TYPE_RESTRICTIONS, USAGE_TYPE_RESTRICTIONS};
+use log::info;
+use regex::Regex;
+use serde_json::Value;
+
+// Rules teaching the indexer a project's own DSLs, from `dsl_rules` in the
+// project's config:
+//
+//   [[dsl_rules]]
+//   pattern = "^register\\(\"(?P<name>[\\w.]+)\""
+//   kind = "definition"
+//   type = "ContainerKey"
+//
+//   [[dsl_rules]]
+//   pattern = "^Import\\[\"(?P<name>[\\w.]+)\"\\]"
+//   kind = "usage"
+//   type = "ContainerKey"
+//
+//   [[dsl_rules]]
+//   pattern = "^step :(?P<name>\\w+)"
+//   kind = "usage"
+//   type = "Send"
+//
+// Each method call's source, without its block, is matched against every
+// rule. The `name` capture group is the symbol's name and where it is, or
+// the whole match without one, and a rule's `name`, like `services.$1`, can
+// build it from the captures instead. Types the server has of its own, like
+// `Send` for method calls, are looked up like those. Any other type only
+// matches its own kind, by name across the workspace.
+
+#[derive(Clone, Debug)]
+pub struct DslRule {
+    pattern: Regex,
+    definition: bool,
+    node_type: &'static str,
+    name: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DslSymbol {
+    pub name: String,
+    pub node_type: &'static str,
+    pub definition: bool,
+    // Byte offsets into the matched source
+    pub start: usize,
+    pub end: usize,
+}
+
+// The valid rules in the config's list, others are logged and skipped
+pub fn rules(config: &Value) -> Vec<DslRule> {
+    config
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|rule_config| match rule(rule_config) {
+            Ok(rule) => Some(rule),
+            Err(error) => {
+                info!("Ignoring DSL rule {}: {}", rule_config, error);
+                None
+            }
+        })
+        .collect()
+}
+
+fn rule(config: &Value) -> Result<DslRule, String> {
+    let setting = |key: &str| config.get(key).and_then(Value::as_str);
+
+    let pattern = setting("pattern").ok_or("`pattern` is missing")?;
+    let pattern = Regex::new(pattern).map_err(|error| error.to_string())?;
+
+    let definition = match setting("kind") {
+        Some("definition") => true,
+        Some("usage") => false,
+        _ => return Err("`kind` must be \"definition\" or \"usage\"".to_string()),
+    };
+
+    let node_type = match setting("type") {
+        Some(node_type) if !node_type.trim().is_empty() => node_type,
+        _ => return Err("`type` is missing".to_string()),
+    };
+
+    Ok(DslRule {
+        pattern,
+        definition,
+        // Rules are read once a session, and documents are indexed with
+        // static types
+        node_type: Box::leak(node_type.to_string().into_boxed_str()),
+        name: setting("name").map(String::from),
+    })
+}
+
+// The symbols every rule finds in a method call's source
+pub fn symbols(rules: &[DslRule], source: &str) -> Vec<DslSymbol> {
+    let mut symbols = vec![];
+
+    for rule in rules {
+        for captures in rule.pattern.captures_iter(source) {
+            let symbol_match = match captures.name("name") {
+                Some(name_match) => name_match,
+                None => captures.get(0).unwrap(),
+            };
+
+            let name = match &rule.name {
+                Some(name_template) => {
+                    let mut name = String::new();
+                    captures.expand(name_template, &mut name);
+                    name
+                }
+                None => symbol_match.as_str().to_string(),
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            symbols.push(DslSymbol {
+                name,
+                node_type: rule.node_type,
+                definition: rule.definition,
+                start: symbol_match.start(),
+                end: symbol_match.end(),
+            });
+        }
+    }
+
+    symbols
+}
+
+// A type only DSL rules index, not one the server has of its own
+pub fn is_dsl_type(rules: &[DslRule], node_type: &str) -> bool {
+    !USAGE_TYPE_RESTRICTIONS.contains_key(node_type)
+        && !ASSIGNMENT_TYPE_RESTRICTIONS.contains_key(node_type)
+        && rules.iter().any(|rule| rule.node_type == node_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Each symbol found in the source, as `kind Type name @ text`
+    fn dsl_symbols(rules_config: Value, source: &str) -> Vec<String> {
+        symbols(&rules(&rules_config), source)
+            .iter()
+            .map(|symbol| {
+                let kind = if symbol.definition {
+                    "definition"
+                } else {
+                    "usage"
+                };
+
+                format!(
+                    "{} {} {} @ {}",
+                    kind,
+                    symbol.node_type,
+                    symbol.name,
+                    &source[symbol.start..symbol.end]
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_symbols_rules_capture() {
+        let rules_config = json!([
+            {
+                "pattern": "^Import\\[\"(?P<name>[\\w.]+)\"\\]",
+                "kind": "usage",
+                "type": "ContainerKey"
+            },
+            { "pattern": "^step :(?P<name>\\w+)", "kind": "usage", "type": "Send" }
+        ]);
+
+        assert_eq!(
+            dsl_symbols(rules_config.clone(), "Import[\"services.mailer\"]"),
+            vec!["usage ContainerKey services.mailer @ services.mailer"]
+        );
+        assert_eq!(
+            dsl_symbols(rules_config.clone(), "step :validate"),
+            vec!["usage Send validate @ validate"]
+        );
+        assert_eq!(
+            dsl_symbols(rules_config, "import(\"services.mailer\")"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn builds_names_from_captures() {
+        let rules_config = json!([{
+            "pattern": "^register_mailer :(\\w+)",
+            "kind": "definition",
+            "type": "ContainerKey",
+            "name": "mailers.$1"
+        }]);
+
+        assert_eq!(
+            dsl_symbols(rules_config, "register_mailer :welcome"),
+            vec!["definition ContainerKey mailers.welcome @ register_mailer :welcome"]
+        );
+    }
+
+    #[test]
+    fn skips_invalid_rules() {
+        let rules_config = json!([
+            { "pattern": "(", "kind": "usage", "type": "ContainerKey" },
+            { "pattern": "^step", "kind": "call", "type": "Send" },
+            { "pattern": "^step", "kind": "usage" },
+            { "pattern": "^step", "kind": "usage", "type": "Send" }
+        ]);
+
+        let dsl_rules = rules(&rules_config);
+
+        assert_eq!(dsl_rules.len(), 1);
+        assert!(!is_dsl_type(&dsl_rules, "Send"));
+        assert!(!is_dsl_type(&dsl_rules, "ContainerKey"));
+    }
+}
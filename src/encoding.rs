@@ -0,0 +1,172 @@
+use crate::positions::LineIndex;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lib_ruby_parser::source::{Decoder, DecoderResult};
+use lib_ruby_parser::Loc;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tower_lsp::lsp_types::{Position, Range};
+
+// Ruby source is UTF-8 unless a magic comment on the first line, or the
+// second after a shebang, says otherwise:
+//
+//   # encoding: Shift_JIS
+//   # -*- coding: iso-8859-1 -*-
+//   # vim: set fileencoding=euc-jp :
+//
+// Files are decoded to UTF-8 when read so they can be parsed and indexed like
+// any other, and so they match the text the editor sends for open files.
+
+pub fn read_ruby_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    Ok(decode(&fs::read(path)?))
+}
+
+pub fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+
+    let encoding = magic_comment_encoding(bytes)
+        .and_then(|name| ruby_encoding(&name))
+        .unwrap_or(UTF_8);
+
+    let (text, _had_errors) = encoding.decode_without_bom_handling(bytes);
+
+    text.into_owned()
+}
+
+// Text is always decoded before it's parsed, so the parser only needs to
+// accept whatever encoding the magic comment names instead of rejecting it
+pub fn parser_decoder() -> Decoder {
+    Decoder::new(Box::new(|_encoding: String, input: Vec<u8>| {
+        DecoderResult::Ok(input)
+    }))
+}
+
+// Parser locations are byte offsets into the UTF-8 text, while LSP columns
+// count UTF-16 code units. Wraps the parsed text to convert between them so
+// positions past multibyte characters line up with what the editor shows,
+// following the rules in `positions`.
+pub struct SourceInput<'a> {
+    lines: LineIndex<'a>,
+    text: &'a str,
+}
+
+impl<'a> SourceInput<'a> {
+    pub fn new(text: &'a str) -> SourceInput<'a> {
+        SourceInput {
+            lines: LineIndex::new(text),
+            text,
+        }
+    }
+
+    pub fn position(&self, pos: usize) -> Position {
+        self.lines.position(pos)
+    }
+
+    pub fn range(&self, loc: &Loc) -> Range {
+        self.lines.range(loc)
+    }
+
+    // The line, start column and end column a node is indexed with
+    pub fn line_span(&self, loc: &Loc) -> (usize, usize, usize) {
+        self.lines.line_span(loc)
+    }
+
+    pub fn line(&self, pos: usize) -> usize {
+        self.position(pos).line as usize
+    }
+
+    // Whether only whitespace comes before the position on its line
+    pub fn starts_line(&self, pos: usize) -> bool {
+        match self.text.get(..pos) {
+            Some(before) => before.rsplit("\n").next().unwrap_or("").trim().is_empty(),
+            None => false,
+        }
+    }
+
+    pub fn source(&self, loc: &Loc) -> &'a str {
+        self.text.get(loc.begin..loc.end).unwrap_or("")
+    }
+}
+
+fn magic_comment_encoding(bytes: &[u8]) -> Option<String> {
+    let mut lines = bytes.split(|byte| *byte == b'\n').take(2);
+    let first_line = lines.next()?;
+
+    let magic_comment_line = if first_line.starts_with(b"#!") {
+        lines.next()?
+    } else {
+        first_line
+    };
+
+    // Encoding names are ASCII, so the comment can be read before decoding
+    let comment = String::from_utf8_lossy(magic_comment_line).to_ascii_lowercase();
+
+    if !comment.trim_start().starts_with("#") {
+        return None;
+    }
+
+    let (_before, after) = comment
+        .split_once("coding:")
+        .or_else(|| comment.split_once("coding="))?;
+
+    let name: String = after
+        .trim_start()
+        .chars()
+        .take_while(|char| char.is_ascii_alphanumeric() || *char == '-' || *char == '_')
+        .collect();
+
+    if name.len() > 0 {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+// Ruby's encoding names, falling back to the WHATWG labels encoding_rs knows
+fn ruby_encoding(name: &str) -> Option<&'static Encoding> {
+    match name {
+        "binary" | "ascii-8bit" => Some(WINDOWS_1252),
+        "us-ascii" | "ascii" => Some(UTF_8),
+        "cp932" | "windows-31j" | "sjis" => Encoding::for_label(b"shift_jis"),
+        "eucjp" | "eucjp-ms" | "cp51932" => Encoding::for_label(b"euc-jp"),
+        _ => Encoding::for_label(name.as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_with_the_magic_comments_encoding() {
+        assert_eq!(
+            decode(b"# encoding: Shift_JIS\nputs \"\x82\xa0\"\n"),
+            "# encoding: Shift_JIS\nputs \"\u{3042}\"\n"
+        );
+        assert_eq!(
+            decode(b"# -*- coding: iso-8859-1 -*-\nputs \"caf\xe9\"\n"),
+            "# -*- coding: iso-8859-1 -*-\nputs \"caf\u{e9}\"\n"
+        );
+    }
+
+    #[test]
+    fn reads_the_magic_comment_after_a_shebang() {
+        assert_eq!(
+            decode(b"#!/usr/bin/env ruby\n# vim: set fileencoding=euc-jp :\n\xa4\xa2"),
+            "#!/usr/bin/env ruby\n# vim: set fileencoding=euc-jp :\n\u{3042}"
+        );
+        assert_eq!(
+            magic_comment_encoding(b"puts 1\n# encoding: euc-jp\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn replaces_invalid_bytes() {
+        assert_eq!(decode(b"\xEF\xBB\xBFputs \"\xff\""), "puts \"\u{fffd}\"");
+        assert_eq!(
+            decode(b"# encoding: nonsense\nputs \"\xff\""),
+            "# encoding: nonsense\nputs \"\u{fffd}\""
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// FactoryBot factories and traits, declared in a define block:
+//
+//   FactoryBot.define do
+//     factory :user, aliases: [:author] do
+//       trait :with_posts do ... end
+//
+//       factory :admin_user do ... end       nested in `user`
+//     end
+//   end
+//
+// and built by name in specs, `create(:admin_user, :with_posts)`. A trait
+// can be used with the factory it's declared in, the factories nested in
+// that one, or any factory when it's declared outside of one.
+
+static BUILD_METHODS: &[&str] = &[
+    "attributes_for",
+    "attributes_for_list",
+    "attributes_for_pair",
+    "build",
+    "build_list",
+    "build_pair",
+    "build_stubbed",
+    "build_stubbed_list",
+    "build_stubbed_pair",
+    "create",
+    "create_list",
+    "create_pair",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct FactorySymbol<'a> {
+    // "Factory" or "Trait"
+    pub node_type: &'static str,
+    pub name: String,
+    // Factory: the factories it's nested in, or its `parent:`. Trait: the
+    // factory it's declared in or used with.
+    pub scope: Vec<String>,
+    pub declaration: bool,
+    pub loc: &'a Loc,
+}
+
+// `FactoryBot.define`, or `FactoryGirl.define` in older suites
+pub fn is_define_block(call: &Node) -> bool {
+    match call {
+        Node::Send(Send {
+            recv: Some(recv),
+            method_name,
+            ..
+        }) if method_name == "define" => matches!(
+            recv.as_ref(),
+            Node::Const(Const { name, .. }) if name == "FactoryBot" || name == "FactoryGirl"
+        ),
+        _ => false,
+    }
+}
+
+// The factories a block's body is nested in, one more inside a `factory`
+pub fn nested_scope(scope: &[String], call: &Node) -> Vec<String> {
+    let mut nested_scope = scope.to_vec();
+
+    if let Node::Send(Send {
+        recv: None,
+        method_name,
+        args,
+        ..
+    }) = call
+    {
+        if method_name == "factory" {
+            if let Some((name, _loc)) = args.first().and_then(symbol_name) {
+                nested_scope.push(name);
+            }
+        }
+    }
+
+    nested_scope
+}
+
+// The factories and traits a call in a define block declares
+pub fn declarations<'a>(
+    scope: &[String],
+    method_name: &str,
+    args: &'a [Node],
+) -> Vec<FactorySymbol<'a>> {
+    let (name, loc) = match args.first().and_then(symbol_name) {
+        Some(name) => name,
+        None => return vec![],
+    };
+
+    match method_name {
+        "factory" => {
+            let factory_scope = match option(args, "parent").and_then(symbol_name) {
+                Some((parent, _loc)) => vec![parent],
+                None => scope.to_vec(),
+            };
+
+            let mut factories = vec![(name, loc)];
+            factories.extend(option(args, "aliases").map_or(vec![], symbol_names));
+
+            factories
+                .into_iter()
+                .map(|(name, loc)| FactorySymbol {
+                    node_type: "Factory",
+                    name,
+                    scope: factory_scope.clone(),
+                    declaration: true,
+                    loc,
+                })
+                .collect()
+        }
+        "trait" => vec![FactorySymbol {
+            node_type: "Trait",
+            name,
+            scope: scope.last().cloned().into_iter().collect(),
+            declaration: true,
+            loc,
+        }],
+        _ => vec![],
+    }
+}
+
+// The factory and traits a call builds with, `create(:user, :with_posts)`,
+// or an association in a factory names, `association :author` or
+// `association :author, factory: [:user, :admin]`
+pub fn usages<'a>(
+    recv: Option<&Node>,
+    method_name: &str,
+    args: &'a [Node],
+) -> Vec<FactorySymbol<'a>> {
+    let factory_recv = match recv {
+        Some(Node::Const(Const { name, .. })) => name == "FactoryBot" || name == "FactoryGirl",
+        Some(_) => false,
+        None => true,
+    };
+
+    let names: Vec<(String, &Loc)> = if factory_recv && BUILD_METHODS.contains(&method_name) {
+        args.iter().filter_map(symbol_name).collect()
+    } else if recv.is_none() && method_name == "association" {
+        match option(args, "factory") {
+            Some(factory) => symbol_names(factory),
+            None => args.first().and_then(symbol_name).into_iter().collect(),
+        }
+    } else {
+        vec![]
+    };
+
+    let mut names = names.into_iter();
+
+    let (factory_name, factory_loc) = match names.next() {
+        Some(factory) => factory,
+        None => return vec![],
+    };
+
+    let mut usages = vec![FactorySymbol {
+        node_type: "Factory",
+        name: factory_name.clone(),
+        scope: vec![],
+        declaration: false,
+        loc: factory_loc,
+    }];
+
+    for (name, loc) in names {
+        usages.push(FactorySymbol {
+            node_type: "Trait",
+            name,
+            scope: vec![factory_name.clone()],
+            declaration: false,
+            loc,
+        });
+    }
+
+    usages
+}
+
+// A keyword option's value
+fn option<'a>(args: &'a [Node], option_name: &str) -> Option<&'a Node> {
+    let pairs = match args.last() {
+        Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs,
+        _ => return None,
+    };
+
+    pairs.iter().find_map(|pair| match pair {
+        Node::Pair(Pair { key, value, .. }) => match symbol_name(key) {
+            Some((key_name, _)) if key_name == option_name => Some(value.as_ref()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// `:name`, or each of `[:name, :other]`
+fn symbol_names(node: &Node) -> Vec<(String, &Loc)> {
+    match node {
+        Node::Array(Array { elements, .. }) => elements.iter().filter_map(symbol_name).collect(),
+        node => symbol_name(node).into_iter().collect(),
+    }
+}
+
+fn symbol_name(node: &Node) -> Option<(String, &Loc)> {
+    match node {
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => Some((name.to_string_lossy(), expression_l)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    // Each factory symbol in a call, as `Type scope#name`
+    fn factory_symbols(call: &str, scope: &[&str]) -> Vec<String> {
+        let ast = Parser::new(call.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+        let scope: Vec<String> = scope.iter().map(|name| name.to_string()).collect();
+
+        let (recv, method_name, args) = match ast.as_ref() {
+            Node::Send(Send {
+                recv,
+                method_name,
+                args,
+                ..
+            }) => (recv.as_deref(), method_name, args),
+            _ => return vec![],
+        };
+
+        let mut symbols = declarations(&scope, method_name, args);
+        symbols.extend(usages(recv, method_name, args));
+
+        symbols
+            .iter()
+            .map(|symbol| {
+                format!(
+                    "{} {}#{}",
+                    symbol.node_type,
+                    symbol.scope.join("::"),
+                    symbol.name
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_declared_factories_and_traits() {
+        assert_eq!(
+            factory_symbols("factory :admin_user, aliases: [:admin]", &["user"]),
+            vec!["Factory user#admin_user", "Factory user#admin"]
+        );
+        assert_eq!(
+            factory_symbols("factory :guest, parent: :user, class: 'User'", &[]),
+            vec!["Factory user#guest"]
+        );
+        assert_eq!(
+            factory_symbols("trait :with_posts", &["user", "admin_user"]),
+            vec!["Trait admin_user#with_posts"]
+        );
+        assert_eq!(
+            factory_symbols("trait :archived", &[]),
+            vec!["Trait #archived"]
+        );
+    }
+
+    #[test]
+    fn reads_the_factories_and_traits_built_with() {
+        assert_eq!(
+            factory_symbols("create(:admin_user, :with_posts, name: 'Ada')", &[]),
+            vec!["Factory #admin_user", "Trait admin_user#with_posts"]
+        );
+        assert_eq!(
+            factory_symbols("FactoryBot.build_list(:user, 3, :archived)", &[]),
+            vec!["Factory #user", "Trait user#archived"]
+        );
+        assert_eq!(
+            factory_symbols("association :author, factory: [:user, :admin]", &[]),
+            vec!["Factory #user", "Trait user#admin"]
+        );
+        assert_eq!(
+            factory_symbols("association :author", &[]),
+            vec!["Factory #author"]
+        );
+        assert_eq!(
+            factory_symbols("user.create(:post)", &[]),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+// Formats files with the `formatCommand` setting. The command is split into
+// arguments the way a shell would, but run without one, so it works the
+// same on Windows. `{path}` in an argument is the file's path relative to
+// its workspace folder, for formatters that read their config by path:
+//
+//   bundle exec rubocop --autocorrect --stdin {path} --stderr
+//
+// The file's text is written to the formatter's stdin and the formatted text
+// read from its stdout. Pipes and redirects aren't supported, a formatter
+// needing them can be wrapped in a script.
+
+// How long a formatter can run before it's killed
+pub const FORMAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What's needed to format a file, copied out of the server's state so the
+// formatter runs without holding up other requests
+#[derive(Debug)]
+pub struct FormatJob {
+    args: Vec<String>,
+    working_dir: String,
+    text: String,
+}
+
+impl FormatJob {
+    pub fn new(
+        format_command: &str,
+        relative_path: &str,
+        working_dir: String,
+        text: String,
+    ) -> Result<FormatJob, String> {
+        let args: Vec<String> = split_command(format_command)?
+            .into_iter()
+            .map(|arg| arg.replace("{path}", relative_path))
+            .collect();
+
+        if args.is_empty() {
+            return Err("`formatCommand` is empty".to_string());
+        }
+
+        Ok(FormatJob {
+            args,
+            working_dir,
+            text,
+        })
+    }
+
+    // The edit replacing the file's text with the formatted text, none when
+    // it's already formatted. A formatter still running after the timeout
+    // is killed.
+    pub async fn run(self, timeout: Duration) -> Result<Vec<TextEdit>, String> {
+        let command = self.args.join(" ");
+
+        let mut child = Command::new(&self.args[0])
+            .args(&self.args[1..])
+            .current_dir(&self.working_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|error| format!("Unable to run `{}`: {}", command, error))?;
+
+        // Written while the output's read, so a formatter that writes before
+        // it's done reading can't fill the pipe and deadlock
+        let mut stdin = child.stdin.take().unwrap();
+        let text = self.text.clone();
+        let writer = tokio::spawn(async move {
+            let _ = stdin.write_all(text.as_bytes()).await;
+        });
+
+        // The child is dropped, and so killed, when time runs out
+        let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => {
+                output.map_err(|error| format!("Unable to run `{}`: {}", command, error))?
+            }
+            Err(_) => {
+                writer.abort();
+
+                return Err(format!(
+                    "`{}` didn't finish within {}s",
+                    command,
+                    timeout.as_secs_f64()
+                ));
+            }
+        };
+        let _ = writer.await;
+
+        if !output.status.success() {
+            return Err(format!(
+                "`{}` failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(edits(&self.text, &String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+fn edits(text: &str, formatted_text: &str) -> Vec<TextEdit> {
+    if formatted_text.is_empty() || formatted_text == text {
+        return vec![];
+    }
+
+    // Replaces everything, past the last line so no trailing text is left
+    let whole_document = Range::new(
+        Position::new(0, 0),
+        Position::new(text.lines().count() as u32 + 1, 0),
+    );
+
+    vec![TextEdit::new(whole_document, formatted_text.to_string())]
+}
+
+// Splits a command into arguments at whitespace outside of quotes. Single
+// quotes keep everything, and backslashes escape quotes, whitespace, and
+// backslashes, but are otherwise kept, so Windows paths needn't be quoted.
+pub fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '\'' => {
+                in_arg = true;
+
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(char) => arg.push(char),
+                        None => return Err(format!("Unclosed quote in `{}`", command)),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.peek(), Some('"' | '\\')) => {
+                            arg.extend(chars.next());
+                        }
+                        Some(char) => arg.push(char),
+                        None => return Err(format!("Unclosed quote in `{}`", command)),
+                    }
+                }
+            }
+            '\\' if chars.peek().map_or(false, |next| is_escapable(*next)) => {
+                in_arg = true;
+                arg.extend(chars.next());
+            }
+            char if char.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            char => {
+                in_arg = true;
+                arg.push(char);
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+fn is_escapable(char: char) -> bool {
+    char.is_whitespace() || matches!(char, '\'' | '"' | '\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_commands_like_a_shell() {
+        assert_eq!(
+            split_command("bundle exec rubocop -a --stdin {path}").unwrap(),
+            vec!["bundle", "exec", "rubocop", "-a", "--stdin", "{path}"]
+        );
+        assert_eq!(
+            split_command(r#"rufo --config 'my config.rb' "a \"b\"" c\ d ''"#).unwrap(),
+            vec!["rufo", "--config", "my config.rb", "a \"b\"", "c d", ""]
+        );
+        assert_eq!(
+            split_command(r"C:\Ruby\bin\rufo.bat {path}").unwrap(),
+            vec![r"C:\Ruby\bin\rufo.bat", "{path}"]
+        );
+        assert!(split_command("rufo 'unclosed").is_err());
+    }
+
+    #[test]
+    fn puts_the_path_in_its_own_argument() {
+        let job = FormatJob::new(
+            "rubocop --stdin {path}",
+            "app/models/it's here.rb",
+            "/app".to_string(),
+            String::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            job.args,
+            vec!["rubocop", "--stdin", "app/models/it's here.rb"]
+        );
+        assert!(FormatJob::new("  ", "a.rb", "/app".to_string(), String::new()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kills_formatters_that_run_too_long() {
+        let job = FormatJob::new("sleep 5", "a.rb", "/".to_string(), "a\n".to_string()).unwrap();
+        let error = job.run(Duration::from_millis(100)).await.unwrap_err();
+
+        assert!(error.contains("didn't finish"), "{}", error);
+
+        let job = FormatJob::new("cat", "a.rb", "/".to_string(), "a\n".to_string()).unwrap();
+
+        assert_eq!(job.run(FORMAT_TIMEOUT).await.unwrap(), vec![]);
+    }
+}
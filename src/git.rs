@@ -0,0 +1,90 @@
+use crate::i18n;
+use crate::partials;
+use crate::paths;
+use std::collections::HashSet;
+use std::process::Command;
+
+// Asks git which files changed since the revision a workspace was indexed
+// at, so startup only needs to look at those rather than walking and
+// statting every file. Anything that isn't a git repo, or a revision git
+// no longer has, falls back to the full walk.
+
+pub fn head_revision(workspace_path: &str) -> Option<String> {
+    let revision = git_output(workspace_path, &["rev-parse", "--verify", "HEAD"])?;
+    let revision = revision.trim();
+
+    if revision.is_empty() {
+        None
+    } else {
+        Some(revision.to_string())
+    }
+}
+
+// Ruby, locale, and template files changed since the revision, committed or
+// not, and untracked files, ignored ones too when they're indexed. Deleted files are
+// included so their documents can be removed.
+pub fn changed_file_paths(
+    workspace_path: &str,
+    revision: &str,
+    include_ignored: bool,
+) -> Option<HashSet<String>> {
+    // Comparing the revision to the working tree covers commits, staged
+    // changes, and unstaged ones at once
+    let changed = git_output(
+        workspace_path,
+        &[
+            "diff",
+            "--name-only",
+            "--no-renames",
+            "--relative",
+            "-z",
+            revision,
+        ],
+    )?;
+    let untracked = if include_ignored {
+        git_output(workspace_path, &["ls-files", "--others", "-z"])?
+    } else {
+        git_output(
+            workspace_path,
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )?
+    };
+
+    let changed_file_paths = changed
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|relative_path| is_walked(relative_path))
+        .map(|relative_path| paths::normalize(&format!("{}/{}", workspace_path, relative_path)))
+        .collect();
+
+    Some(changed_file_paths)
+}
+
+// Files the workspace walk would have found, it skips these directories
+fn is_walked(relative_path: &str) -> bool {
+    let mut dir_names = relative_path.split("/").rev().skip(1);
+
+    (relative_path.ends_with(".rb")
+        || i18n::is_locale_file(relative_path)
+        || partials::is_template(relative_path))
+        && !dir_names.any(|dir_name| {
+            dir_name.contains("node_modules")
+                || dir_name.contains("tmp")
+                || dir_name.contains(".git")
+        })
+}
+
+fn git_output(workspace_path: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(workspace_path)
+        .args(args)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
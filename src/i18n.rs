@@ -0,0 +1,352 @@
+use crate::autoload;
+use crate::positions;
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// Rails' translations, looked up by key with `t("users.show.title")` and
+// defined in `config/locales/**/*.yml`:
+//
+//   en:
+//     users:
+//       show:
+//         title: "Profile"        users.show.title, in `en`
+//
+// Locale files are indexed alongside Ruby files, their keys under their own
+// category so they're only found from translation lookups. They're read
+// without a YAML parser, which wouldn't say where each key is: a key is a
+// line's `name:`, under the keys indented less above it. Values spanning
+// lines, like block scalars and lists, are skipped over.
+
+#[derive(Debug, PartialEq)]
+pub struct LocaleKey {
+    pub locale: String,
+    // Without the locale, `users.show.title`
+    pub key: String,
+    pub line: usize,
+    pub start_column: usize,
+    pub end_column: usize,
+}
+
+pub fn is_locale_file(path: &str) -> bool {
+    let yaml = path.ends_with(".yml") || path.ends_with(".yaml");
+
+    yaml && (path.starts_with("config/locales/") || path.contains("/config/locales/"))
+}
+
+// Every key in a locale file, parents included since `t("users.show")`
+// looks up all of its children
+pub fn locale_keys(text: &str) -> Vec<LocaleKey> {
+    let mut locale_keys = vec![];
+    // The keys the current line is nested in, with their indentation
+    let mut parents: Vec<(usize, String)> = vec![];
+    // Inside a value spanning lines, like a block scalar, which ends at a
+    // line indented as much as its key
+    let mut multiline_value_indent: Option<usize> = None;
+
+    for (line, line_text) in text.split('\n').enumerate() {
+        let line_text = line_text.strip_suffix('\r').unwrap_or(line_text);
+        let content = line_text.trim_start();
+        let indent = line_text.len() - content.len();
+
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+
+        match multiline_value_indent {
+            Some(value_indent) if indent > value_indent => continue,
+            _ => multiline_value_indent = None,
+        }
+
+        if content.starts_with("- ") || content.starts_with("---") {
+            continue;
+        }
+
+        let (name, name_offset, value) = match key_name(content) {
+            Some(key_name) => key_name,
+            None => continue,
+        };
+
+        while parents
+            .last()
+            .map_or(false, |(parent_indent, _)| *parent_indent >= indent)
+        {
+            parents.pop();
+        }
+
+        // Anchors merged in with `<<: *defaults` aren't keys of their own
+        if name == "<<" {
+            continue;
+        }
+
+        if let Some((_, locale)) = parents.first() {
+            let mut key_parts: Vec<&str> = parents[1..]
+                .iter()
+                .map(|(_, parent)| parent.as_str())
+                .collect();
+            key_parts.push(&name);
+
+            let start = indent + name_offset;
+            let start_column = positions::utf16_len(&line_text[..start]) as usize;
+
+            locale_keys.push(LocaleKey {
+                locale: locale.clone(),
+                key: key_parts.join("."),
+                line,
+                start_column,
+                end_column: start_column + positions::utf16_len(&name) as usize,
+            });
+        }
+
+        // Lines indented under a key with a value continue the value, only
+        // a key without one has children
+        if value.trim().is_empty() {
+            parents.push((indent, name));
+        } else {
+            multiline_value_indent = Some(indent);
+        }
+    }
+
+    locale_keys
+}
+
+// A line's key, where it starts past any quote, and the value after it:
+// `title: "Profile"`, `"title": Profile`, or `title:`
+fn key_name(content: &str) -> Option<(String, usize, &str)> {
+    if let Some(quote) = content
+        .chars()
+        .next()
+        .filter(|char| *char == '"' || *char == '\'')
+    {
+        let quoted = &content[1..];
+        let name_end = quoted.find(quote)?;
+        let value = quoted[name_end + 1..].strip_prefix(':')?;
+
+        return Some((quoted[..name_end].to_string(), 1, value));
+    }
+
+    let colon = content
+        .match_indices(':')
+        .map(|(index, _)| index)
+        .find(|index| {
+            content[index + 1..].is_empty() || content[index + 1..].starts_with([' ', '\t'])
+        })?;
+
+    Some((
+        content[..colon].trim_end().to_string(),
+        0,
+        &content[colon + 1..],
+    ))
+}
+
+// `t`, `translate`, or `I18n.t`
+pub fn is_translate_call(recv: Option<&Node>, method_name: &str) -> bool {
+    let i18n_recv = match recv {
+        Some(Node::Const(Const { name, scope, .. })) => name == "I18n" && scope.is_none(),
+        Some(_) => false,
+        None => true,
+    };
+
+    i18n_recv && (method_name == "t" || method_name == "translate")
+}
+
+// The full key a translation looks up, with the string or symbol naming it:
+//
+//   t("users.show.title")                     users.show.title
+//   t(:title, scope: [:users, :show])         users.show.title
+//   t(".title")       in UsersController#show  users.show.title
+//
+// A key starting with `.` is relative to the controller action it's
+// looked up in, other lazy lookups like a view's can't be resolved here.
+pub fn translation_key<'a>(args: &'a [Node], fuzzy_scope: &[String]) -> Option<(String, &'a Loc)> {
+    let (key, loc) = match args.first()? {
+        Node::Str(Str {
+            value,
+            expression_l,
+            ..
+        }) => (value.to_string_lossy(), expression_l),
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => (name.to_string_lossy(), expression_l),
+        _ => return None,
+    };
+
+    if key.is_empty() {
+        return None;
+    }
+
+    if key.starts_with('.') {
+        return Some((format!("{}{}", action_scope(fuzzy_scope)?, key), loc));
+    }
+
+    match scope_option(args) {
+        Some(scope) => Some((format!("{}.{}", scope, key), loc)),
+        None => Some((key, loc)),
+    }
+}
+
+// `admin.users.show` in `Admin::UsersController#show`
+fn action_scope(fuzzy_scope: &[String]) -> Option<String> {
+    let controller_index = fuzzy_scope
+        .iter()
+        .rposition(|scope_name| scope_name.ends_with("Controller"))?;
+    let action = fuzzy_scope.get(controller_index + 1)?;
+
+    let mut scope_parts: Vec<String> = fuzzy_scope[..controller_index]
+        .iter()
+        .map(|module| autoload::underscore(module))
+        .collect();
+    scope_parts.push(autoload::underscore(
+        fuzzy_scope[controller_index].trim_end_matches("Controller"),
+    ));
+    scope_parts.push(action.to_string());
+
+    Some(scope_parts.join("."))
+}
+
+// `scope: "users.show"` or `scope: [:users, :show]`
+fn scope_option(args: &[Node]) -> Option<String> {
+    let pairs = match args.last() {
+        Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs,
+        _ => return None,
+    };
+
+    let scope = pairs.iter().find_map(|pair| match pair {
+        Node::Pair(Pair { key, value, .. }) => match key.as_ref() {
+            Node::Sym(Sym { name, .. }) if name.to_string_lossy() == "scope" => {
+                Some(value.as_ref())
+            }
+            _ => None,
+        },
+        _ => None,
+    })?;
+
+    let scope_parts: Vec<&Node> = match scope {
+        Node::Array(Array { elements, .. }) => elements.iter().collect(),
+        node => vec![node],
+    };
+
+    let scope_names: Vec<String> = scope_parts
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Str(Str { value, .. }) => Some(value.to_string_lossy()),
+            Node::Sym(Sym { name, .. }) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+
+    if scope_names.is_empty() {
+        None
+    } else {
+        Some(scope_names.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    fn keys(text: &str) -> Vec<(String, String, usize, usize)> {
+        locale_keys(text)
+            .into_iter()
+            .map(|locale_key| {
+                (
+                    locale_key.locale,
+                    locale_key.key,
+                    locale_key.line,
+                    locale_key.start_column,
+                )
+            })
+            .collect()
+    }
+
+    // The key looked up by the first call in a method of `UsersController`
+    fn call_key(call: &str) -> Option<String> {
+        let ast = Parser::new(call.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+        let fuzzy_scope = vec![
+            "Admin".to_string(),
+            "UsersController".to_string(),
+            "show".to_string(),
+        ];
+
+        match ast.as_ref() {
+            Node::Send(Send {
+                recv,
+                method_name,
+                args,
+                ..
+            }) if is_translate_call(recv.as_deref(), method_name) => {
+                translation_key(args, &fuzzy_scope).map(|(key, _loc)| key)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_nested_keys_with_their_positions() {
+        let text = r#"en:
+  users:
+    show:
+      title: "Profile"
+      "intro": |
+        not: a key
+    index:
+      empty: Nobody yet
+"#;
+
+        assert_eq!(
+            keys(text),
+            vec![
+                ("en".to_string(), "users".to_string(), 1, 2),
+                ("en".to_string(), "users.show".to_string(), 2, 4),
+                ("en".to_string(), "users.show.title".to_string(), 3, 6),
+                ("en".to_string(), "users.show.intro".to_string(), 4, 7),
+                ("en".to_string(), "users.index".to_string(), 6, 4),
+                ("en".to_string(), "users.index.empty".to_string(), 7, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_lists_comments_and_merged_anchors() {
+        let text = r#"fr:
+  # comment: here
+  date:
+    <<: *defaults
+    abbr_day_names:
+    - dim
+    - lun
+    order: [day, month]
+"#;
+
+        assert_eq!(
+            keys(text),
+            vec![
+                ("fr".to_string(), "date".to_string(), 2, 2),
+                ("fr".to_string(), "date.abbr_day_names".to_string(), 4, 4),
+                ("fr".to_string(), "date.order".to_string(), 7, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_key_a_call_looks_up() {
+        assert_eq!(
+            call_key("t('users.show.title')"),
+            Some("users.show.title".to_string())
+        );
+        assert_eq!(
+            call_key("I18n.t(:title, scope: [:users, :show])"),
+            Some("users.show.title".to_string())
+        );
+        assert_eq!(
+            call_key("t('.title')"),
+            Some("admin.users.show.title".to_string())
+        );
+        assert_eq!(call_key("user.t('title')"), None);
+        assert!(is_locale_file("/app/config/locales/devise.en.yml"));
+        assert!(!is_locale_file("/app/config/database.yml"));
+    }
+}
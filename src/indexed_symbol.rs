@@ -0,0 +1,338 @@
+use crate::name_parts;
+use crate::paths;
+use crate::positions;
+use std::fmt;
+use std::sync::OnceLock;
+use tantivy::schema::*;
+use tantivy::Document;
+use tower_lsp::lsp_types::{Location, Position, Range};
+
+// The index's schema, and the symbols stored in it. Requests read documents
+// as `IndexedSymbol`s rather than picking fields out of them, so a field
+// that's missing is reported once instead of unwrapped everywhere.
+
+#[derive(Clone)]
+pub struct SchemaFields {
+    pub file_path_id: Field,
+    pub file_path: Field,
+    pub category_field: Field,
+    pub fuzzy_ruby_scope_field: Field,
+    pub class_scope_field: Field,
+    pub name_field: Field,
+    pub name_parts_field: Field,
+    pub node_type_field: Field,
+    pub line_field: Field,
+    pub start_column_field: Field,
+    pub end_column_field: Field,
+    pub columns_field: Field,
+    pub user_space_field: Field,
+    pub parameters_field: Field,
+    pub workspace_folder_field: Field,
+    pub signature_field: Field,
+    pub documentation_field: Field,
+    pub visibility_field: Field,
+    pub receiver_field: Field,
+    pub generation_field: Field,
+}
+
+pub fn schema() -> &'static (Schema, SchemaFields) {
+    static SCHEMA: OnceLock<(Schema, SchemaFields)> = OnceLock::new();
+
+    SCHEMA.get_or_init(|| {
+        let mut schema_builder = Schema::builder();
+        let schema_fields = SchemaFields {
+            file_path_id: schema_builder.add_text_field(
+                "file_path_id",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            file_path: schema_builder.add_text_field(
+                "file_path",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            category_field: schema_builder.add_text_field(
+                "category",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            fuzzy_ruby_scope_field: schema_builder.add_text_field(
+                "fuzzy_ruby_scope",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            class_scope_field: schema_builder.add_text_field(
+                "class_scope",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            name_field: schema_builder.add_text_field(
+                "name",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            name_parts_field: schema_builder.add_text_field(
+                "name_parts",
+                TextOptions::default().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(name_parts::TOKENIZER_NAME)
+                        .set_index_option(IndexRecordOption::Basic),
+                ),
+            ),
+            node_type_field: schema_builder.add_text_field(
+                "node_type",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            line_field: schema_builder.add_u64_field("line", INDEXED | STORED),
+            start_column_field: schema_builder.add_u64_field("start_column", INDEXED | STORED),
+            end_column_field: schema_builder.add_u64_field("end_column", INDEXED | STORED),
+            columns_field: schema_builder.add_u64_field("columns", INDEXED | STORED),
+            user_space_field: schema_builder.add_bool_field("user_space", INDEXED | STORED),
+            parameters_field: schema_builder.add_text_field("parameters", STORED),
+            workspace_folder_field: schema_builder.add_text_field(
+                "workspace_folder",
+                TextOptions::default()
+                    .set_indexing_options(
+                        TextFieldIndexing::default()
+                            .set_tokenizer("raw")
+                            .set_index_option(IndexRecordOption::Basic),
+                    )
+                    .set_stored(),
+            ),
+            signature_field: schema_builder.add_text_field("signature", STORED),
+            documentation_field: schema_builder.add_text_field("documentation", STORED),
+            visibility_field: schema_builder.add_text_field("visibility", STRING | STORED),
+            receiver_field: schema_builder.add_text_field("receiver", STRING | STORED),
+            generation_field: schema_builder.add_u64_field("generation", INDEXED | STORED),
+        };
+
+        let schema = schema_builder.build();
+
+        (schema, schema_fields)
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexedSymbol {
+    pub file_path_id: String,
+    // The folder workspace files were indexed from, their path is relative
+    // to it. Gems and include dirs store an absolute path.
+    pub workspace_folder: Option<String>,
+    pub file_path: Vec<String>,
+    pub category: String,
+    pub fuzzy_ruby_scope: Vec<String>,
+    pub class_scope: Vec<String>,
+    pub name: String,
+    pub node_type: String,
+    pub line: u32,
+    pub start_column: u32,
+    pub end_column: u32,
+    pub user_space: bool,
+    pub parameters: Vec<String>,
+    pub signature: Option<String>,
+    pub documentation: Option<String>,
+    pub visibility: Option<String>,
+    // Calls: what the method is called on, see `receivers`
+    pub receiver: Option<String>,
+    // Which indexing of the file it's from, see `Persistence::generations`
+    pub generation: u64,
+}
+
+impl IndexedSymbol {
+    pub fn path(&self) -> String {
+        let file_path_parts: Vec<&str> = self.file_path.iter().map(String::as_str).collect();
+
+        match &self.workspace_folder {
+            Some(workspace_folder) => format!("{}/{}", workspace_folder, file_path_parts.join("/")),
+            None => paths::from_parts(&file_path_parts),
+        }
+    }
+
+    // Symbols are indexed on a single line
+    pub fn range(&self) -> Range {
+        Range::new(
+            Position::new(self.line, self.start_column),
+            Position::new(self.line, self.end_column),
+        )
+    }
+
+    pub fn location(&self) -> Location {
+        Location::new(paths::path_to_uri(&self.path()), self.range())
+    }
+
+    // The position is within the symbol's name, either end included
+    pub fn contains(&self, position: Position) -> bool {
+        self.line == position.line
+            && positions::touching_columns(self.start_column, self.end_column)
+                .contains(&position.character)
+    }
+}
+
+// A document without a field every symbol is indexed with, from an index
+// written by a version that didn't have it
+#[derive(Debug)]
+pub struct MissingField(&'static str);
+
+impl fmt::Display for MissingField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "indexed document has no `{}`", self.0)
+    }
+}
+
+impl std::error::Error for MissingField {}
+
+impl TryFrom<&Document> for IndexedSymbol {
+    type Error = MissingField;
+
+    fn try_from(document: &Document) -> Result<Self, Self::Error> {
+        let (_schema, fields) = schema();
+
+        let text = |field: Field| document.get_first(field).and_then(Value::as_text);
+        let texts = |field: Field| -> Vec<String> {
+            document
+                .get_all(field)
+                .flat_map(Value::as_text)
+                .map(String::from)
+                .collect()
+        };
+        let required_text = |field: Field, name: &'static str| {
+            text(field).map(String::from).ok_or(MissingField(name))
+        };
+        let required_u32 = |field: Field, name: &'static str| {
+            document
+                .get_first(field)
+                .and_then(Value::as_u64)
+                .map(|value| value as u32)
+                .ok_or(MissingField(name))
+        };
+
+        Ok(IndexedSymbol {
+            file_path_id: required_text(fields.file_path_id, "file_path_id")?,
+            workspace_folder: text(fields.workspace_folder_field).map(String::from),
+            file_path: texts(fields.file_path),
+            category: required_text(fields.category_field, "category")?,
+            fuzzy_ruby_scope: texts(fields.fuzzy_ruby_scope_field),
+            class_scope: texts(fields.class_scope_field),
+            name: required_text(fields.name_field, "name")?,
+            node_type: required_text(fields.node_type_field, "node_type")?,
+            line: required_u32(fields.line_field, "line")?,
+            start_column: required_u32(fields.start_column_field, "start_column")?,
+            end_column: required_u32(fields.end_column_field, "end_column")?,
+            user_space: document
+                .get_first(fields.user_space_field)
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            parameters: texts(fields.parameters_field),
+            signature: text(fields.signature_field).map(String::from),
+            documentation: text(fields.documentation_field).map(String::from),
+            visibility: text(fields.visibility_field).map(String::from),
+            receiver: text(fields.receiver_field).map(String::from),
+            generation: document
+                .get_first(fields.generation_field)
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        })
+    }
+}
+
+impl From<IndexedSymbol> for Document {
+    fn from(symbol: IndexedSymbol) -> Self {
+        let (_schema, fields) = schema();
+        let mut document = Document::default();
+
+        document.add_text(fields.file_path_id, symbol.file_path_id);
+
+        if let Some(workspace_folder) = symbol.workspace_folder {
+            document.add_text(fields.workspace_folder_field, workspace_folder);
+        }
+
+        for path_part in symbol.file_path {
+            document.add_text(fields.file_path, path_part);
+        }
+
+        for fuzzy_scope in symbol.fuzzy_ruby_scope {
+            document.add_text(fields.fuzzy_ruby_scope_field, fuzzy_scope);
+        }
+
+        for class_scope in symbol.class_scope {
+            document.add_text(fields.class_scope_field, class_scope);
+        }
+
+        for parameter in symbol.parameters {
+            document.add_text(fields.parameters_field, parameter);
+        }
+
+        if let Some(signature) = symbol.signature {
+            document.add_text(fields.signature_field, signature);
+        }
+
+        if let Some(documentation) = symbol.documentation {
+            document.add_text(fields.documentation_field, documentation);
+        }
+
+        if let Some(visibility) = symbol.visibility {
+            document.add_text(fields.visibility_field, visibility);
+        }
+
+        if let Some(receiver) = symbol.receiver {
+            document.add_text(fields.receiver_field, receiver);
+        }
+
+        // Only definitions are searched by their parts
+        if symbol.category == "assignment" {
+            document.add_text(fields.name_parts_field, &symbol.name);
+        }
+
+        document.add_text(fields.category_field, symbol.category);
+        document.add_text(fields.name_field, symbol.name);
+        document.add_text(fields.node_type_field, symbol.node_type);
+        document.add_u64(fields.line_field, symbol.line.into());
+        document.add_u64(fields.start_column_field, symbol.start_column.into());
+        document.add_u64(fields.end_column_field, symbol.end_column.into());
+        document.add_bool(fields.user_space_field, symbol.user_space);
+        document.add_u64(fields.generation_field, symbol.generation);
+
+        // Every column the name covers, so a position anywhere in it finds it
+        for column in positions::touching_columns(symbol.start_column, symbol.end_column) {
+            document.add_u64(fields.columns_field, column.into());
+        }
+
+        document
+    }
+}
@@ -0,0 +1,441 @@
+use crate::compaction::{self, Compaction};
+use crate::paths;
+use crate::persistence::{ParsedFile, Persistence};
+use crate::ruby_version;
+use crate::telemetry;
+
+use log::info;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tantivy::IndexWriter;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+use tower_lsp::Client;
+
+pub enum IndexJob {
+    // Walk the workspace folders for added, modified, and deleted files
+    Workspace,
+    // Index the include dirs and gems, only done once per session
+    Dependencies,
+    // Reindex an open file with the editor's unsaved text
+    File { uri: Url, text: String },
+    // Remove a closed buffer that isn't a file, see
+    // `Persistence::is_detached_buffer`
+    Discard { uri: Url },
+    // Index everything again, dependencies included, after the index has
+    // been cleared
+    Rebuild,
+    // Merge the index into one segment, replying with how it went, or None
+    // when another window writes it
+    Compact(oneshot::Sender<Option<Compaction>>),
+}
+
+// All indexing happens on one task so requests only wait on the persistence
+// lock for the moment each file's documents are added, rather than for a
+// whole walk. Files are parsed with a separate Persistence, which only needs
+// the serializer state, so parsing doesn't hold the lock at all.
+pub fn spawn(
+    client: Client,
+    persistence: Arc<RwLock<Persistence>>,
+) -> (UnboundedSender<IndexJob>, JoinHandle<()>) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let indexing_task = tokio::spawn(async move {
+        Indexer {
+            client,
+            persistence,
+            parser: None,
+            receiver,
+            dependencies_indexed: false,
+            queued_workspace: false,
+            queued_dependencies: false,
+            queued_files: HashMap::new(),
+        }
+        .run()
+        .await;
+    });
+
+    (sender, indexing_task)
+}
+
+struct Indexer {
+    client: Client,
+    persistence: Arc<RwLock<Persistence>>,
+    // Created from the settings once the server is initialized
+    parser: Option<Persistence>,
+    receiver: UnboundedReceiver<IndexJob>,
+    dependencies_indexed: bool,
+    // Jobs waiting for another window's server to stop writing the shared
+    // index
+    queued_workspace: bool,
+    queued_dependencies: bool,
+    queued_files: HashMap<Url, String>,
+}
+
+impl Indexer {
+    async fn run(&mut self) {
+        loop {
+            let idle =
+                tokio::time::timeout(compaction::IDLE_BEFORE_COMPACTING, self.receiver.recv());
+
+            let job = match idle.await {
+                Ok(Some(job)) => job,
+                Ok(None) => break,
+                // Nothing's been indexed for a while, a good time to catch up
+                // on merging what edits committed
+                Err(_) => {
+                    if let Err(error) = self.compact_index(false).await {
+                        info!("Unable to compact the index: {}", error);
+                    }
+
+                    continue;
+                }
+            };
+
+            let mut workspace = false;
+            let mut dependencies = false;
+            let mut files = HashMap::new();
+            let mut discarded = HashSet::new();
+            let mut compaction_replies = vec![];
+
+            // Typing queues a job per keystroke, only the latest text for
+            // each file needs indexing
+            let mut next_job = Some(job);
+
+            while let Some(job) = next_job {
+                match job {
+                    IndexJob::Workspace => workspace = true,
+                    IndexJob::Dependencies => dependencies = true,
+                    IndexJob::File { uri, text } => {
+                        discarded.remove(&uri);
+                        files.insert(uri, text);
+                    }
+                    IndexJob::Discard { uri } => {
+                        files.remove(&uri);
+                        discarded.insert(uri);
+                    }
+                    IndexJob::Rebuild => {
+                        workspace = true;
+                        dependencies = true;
+                        self.dependencies_indexed = false;
+                    }
+                    IndexJob::Compact(reply) => compaction_replies.push(reply),
+                }
+
+                next_job = self.receiver.try_recv().ok();
+            }
+
+            if self.parser.is_none() {
+                self.parser = Some(self.persistence.read().await.parser());
+            }
+
+            // Edits still get their diagnostics, but the rest waits until
+            // this server can write the index. Only a server reading another
+            // window's index needs the write lock to try claiming it.
+            let can_write = !self.persistence.read().await.reads_shared_index()
+                || self.persistence.write().await.claim_index_writer();
+
+            if !can_write {
+                for (uri, text) in files {
+                    if let Err(error) = self.index_file(&uri, &text).await {
+                        info!("Unable to check {}: {}", uri, error);
+                    }
+
+                    self.queued_files.insert(uri, text);
+                }
+
+                self.queued_workspace |= workspace;
+                self.queued_dependencies |= dependencies;
+
+                for reply in compaction_replies {
+                    let _ = reply.send(None);
+                }
+
+                continue;
+            }
+
+            workspace |= std::mem::take(&mut self.queued_workspace);
+            dependencies |= std::mem::take(&mut self.queued_dependencies);
+
+            for (uri, text) in std::mem::take(&mut self.queued_files) {
+                files.entry(uri).or_insert(text);
+            }
+
+            for uri in discarded {
+                if let Err(error) = self.discard_file(&uri).await {
+                    info!("Unable to remove {}: {}", uri, error);
+                }
+            }
+
+            // Edits come first, they're what the editor is waiting on
+            for (uri, text) in files {
+                if let Err(error) = self.index_file(&uri, &text).await {
+                    info!("Unable to index {}: {}", uri, error);
+                }
+            }
+
+            if workspace {
+                if let Err(error) = self.index_workspace().await {
+                    info!("Unable to index workspace: {}", error);
+                }
+            }
+
+            if dependencies && !self.dependencies_indexed {
+                if let Err(error) = self.index_dependencies().await {
+                    info!("Unable to index dependencies: {}", error);
+                }
+
+                self.dependencies_indexed = true;
+            }
+
+            // After the rest, so what they committed is compacted too
+            if !compaction_replies.is_empty() {
+                let compaction = match self.compact_index(true).await {
+                    Ok(compaction) => compaction,
+                    Err(error) => {
+                        info!("Unable to compact the index: {}", error);
+                        None
+                    }
+                };
+
+                for reply in compaction_replies {
+                    let _ = reply.send(compaction.clone());
+                }
+            }
+        }
+    }
+
+    async fn index_file(&mut self, uri: &Url, text: &String) -> tantivy::Result<()> {
+        let path = paths::uri_to_path(uri);
+        let parsed_file = self.parser().parse_text(&path, text, false);
+        let persistence = self.persistence.read().await;
+
+        // Documents from the last version that parsed cleanly are kept
+        let parse_errors = parsed_file.diagnostics.iter().any(|diagnostic| {
+            diagnostic.severity != Some(DiagnosticSeverity::WARNING)
+                && !ruby_version::is_newer_syntax(diagnostic)
+        });
+        let stale = parse_errors && persistence.mark_stale(&path);
+
+        // Clients that pull diagnostics ask for them after each change
+        if persistence.report_diagnostics && !persistence.pull_diagnostics {
+            let diagnostics = persistence.file_diagnostics(&parsed_file, stale);
+
+            self.client
+                .publish_diagnostics(uri.clone(), diagnostics, None)
+                .await;
+        }
+
+        if parse_errors {
+            return Ok(());
+        }
+
+        let mut index_writer = match persistence.file_index_writer() {
+            Some(index_writer) => index_writer?,
+            None => return Ok(()),
+        };
+
+        if persistence.is_excluded(&path) {
+            return Ok(());
+        }
+
+        let user_space = persistence.is_user_space(&path);
+
+        persistence.index_parsed_file(parsed_file, &path, user_space, &index_writer)?;
+        persistence.commit(&mut index_writer)?;
+
+        Ok(())
+    }
+
+    async fn discard_file(&mut self, uri: &Url) -> tantivy::Result<()> {
+        let persistence = self.persistence.read().await;
+        let mut index_writer = match persistence.file_index_writer() {
+            Some(index_writer) => index_writer?,
+            None => return Ok(()),
+        };
+
+        persistence.delete_path(&paths::uri_to_path(uri), &index_writer);
+        persistence.commit(&mut index_writer)?;
+
+        Ok(())
+    }
+
+    // Unless forced, only compacts once merging has fallen behind
+    async fn compact_index(&mut self, forced: bool) -> tantivy::Result<Option<Compaction>> {
+        let persistence = self.persistence.read().await;
+        let index = match persistence.writable_index() {
+            Some(index) => index.clone(),
+            None => return Ok(None),
+        };
+
+        if !forced
+            && !persistence
+                .merge_settings
+                .needs_compacting(index.searchable_segment_ids()?.len())
+        {
+            return Ok(None);
+        }
+
+        let index_writer = match persistence.index_writer() {
+            Some(index_writer) => index_writer?,
+            None => return Ok(None),
+        };
+
+        persistence.delete_superseded_generations(&index_writer)?;
+
+        // Merging doesn't need the lock, requests aren't held up by it
+        drop(persistence);
+
+        let compaction = tokio::task::block_in_place(|| compaction::compact(&index, index_writer))?;
+
+        info!(
+            "Compacted the index from {} segments to {}",
+            compaction.segments_before, compaction.segments_after
+        );
+        self.record_index_health().await;
+
+        Ok(Some(compaction))
+    }
+
+    async fn index_workspace(&mut self) -> tantivy::Result<()> {
+        let workspace_walk = self.persistence.read().await.workspace_walk();
+        let changes = tokio::task::block_in_place(|| workspace_walk.changes());
+
+        if changes.is_empty() {
+            info!("No file changes, skipping periodic reindexing.");
+        } else {
+            let mut index_writer = match self.persistence.read().await.index_writer() {
+                Some(index_writer) => index_writer?,
+                None => return Ok(()),
+            };
+
+            for file_path_id in &changes.deleted_file_path_ids {
+                self.persistence
+                    .read()
+                    .await
+                    .delete_file(file_path_id, &index_writer);
+            }
+
+            let mut warm_up_queue = changes.warm_up_queue();
+            let mut last_tier = None;
+
+            while let Some((tier, path)) = warm_up_queue.pop() {
+                // Each tier is searchable as soon as it's indexed
+                if last_tier.map_or(false, |last_tier| last_tier != tier) {
+                    self.commit(&mut index_writer).await?;
+                }
+
+                last_tier = Some(tier);
+
+                if let Some(parsed_file) = self.parse_path(&path, false) {
+                    self.persistence.read().await.index_parsed_file(
+                        parsed_file,
+                        &path,
+                        true,
+                        &index_writer,
+                    )?;
+                }
+            }
+
+            self.commit(&mut index_writer).await?;
+            info!("Indexing workspace complete!");
+        }
+
+        let changed = !changes.is_empty();
+        let mut persistence = self.persistence.write().await;
+        persistence.finish_workspace_changes(changes);
+
+        // Reindexing starts new result IDs, so clients that pull diagnostics
+        // are asked to pull them again
+        let refresh_diagnostics =
+            changed && persistence.report_diagnostics && persistence.pull_diagnostics;
+        drop(persistence);
+
+        if refresh_diagnostics {
+            let _ = self.client.workspace_diagnostic_refresh().await;
+        }
+
+        if changed {
+            self.record_index_health().await;
+        }
+
+        Ok(())
+    }
+
+    // How big the index has grown and how fragmented it is, when telemetry
+    // is turned on
+    async fn record_index_health(&self) {
+        let persistence = self.persistence.read().await;
+        let sink = match &persistence.telemetry {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        match persistence.index_health() {
+            Ok(event) => telemetry::emit(&self.client, sink, event).await,
+            Err(error) => info!("Unable to read the index's health: {}", error),
+        }
+    }
+
+    async fn index_dependencies(&mut self) -> tantivy::Result<()> {
+        let dependency_walk = self.persistence.read().await.dependency_walk();
+        let mut file_paths = tokio::task::block_in_place(|| dependency_walk.included_file_paths());
+
+        for path in tokio::task::block_in_place(|| dependency_walk.gem_file_paths()) {
+            file_paths.push((path, true));
+        }
+
+        // Committed on their own, so Ruby's core stubs start in a segment
+        // apart from the project's dependencies
+        let stdlib_file_paths = tokio::task::block_in_place(|| dependency_walk.stdlib_file_paths())
+            .into_iter()
+            .map(|path| (path, true))
+            .collect();
+
+        for file_paths in [file_paths, stdlib_file_paths] {
+            if file_paths.is_empty() {
+                continue;
+            }
+
+            let mut index_writer = match self.persistence.read().await.index_writer() {
+                Some(index_writer) => index_writer?,
+                None => return Ok(()),
+            };
+
+            for (path, interface_only) in &file_paths {
+                if let Some(parsed_file) = self.parse_path(path, *interface_only) {
+                    self.persistence.read().await.index_parsed_file(
+                        parsed_file,
+                        path,
+                        false,
+                        &index_writer,
+                    )?;
+                }
+            }
+
+            self.commit(&mut index_writer).await?;
+        }
+
+        Ok(())
+    }
+
+    // Committing large batches takes a while, so it's done without the lock,
+    // which is only needed again to publish the generations committed
+    async fn commit(&self, index_writer: &mut IndexWriter) -> tantivy::Result<()> {
+        index_writer.commit()?;
+        self.persistence.read().await.publish_generations();
+
+        Ok(())
+    }
+
+    fn parse_path(&mut self, path: &str, interface_only: bool) -> Option<ParsedFile> {
+        tokio::task::block_in_place(|| self.parser().parse_path(path, interface_only))
+    }
+
+    fn parser(&mut self) -> &mut Persistence {
+        self.parser.as_mut().unwrap()
+    }
+}
@@ -0,0 +1,40 @@
+// The server's modules, shared by the `fuzzy` binary with the benches in
+// `benches/` and the tests in `tests/`, which can only reach a library's
+// code.
+
+pub mod autoload;
+pub mod benchmark;
+pub mod callbacks;
+pub mod cancellation;
+pub mod cli;
+pub mod compaction;
+pub mod dsl_rules;
+pub mod encoding;
+pub mod factories;
+pub mod formatter;
+pub mod git;
+pub mod i18n;
+pub mod indexed_symbol;
+pub mod indexer;
+pub mod lsif;
+pub mod name_parts;
+pub mod parse_cache;
+pub mod partials;
+pub mod paths;
+pub mod persistence;
+pub mod plugins;
+pub mod positions;
+pub mod project_config;
+pub mod rbs;
+pub mod receivers;
+pub mod refactorings;
+pub mod resolver;
+pub mod results;
+pub mod routes;
+pub mod ruby_version;
+pub mod schema;
+pub mod server;
+pub mod stdlib;
+pub mod telemetry;
+pub mod test_runner;
+pub mod warm_up;
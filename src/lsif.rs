@@ -0,0 +1,368 @@
+use crate::cli;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use tower_lsp::lsp_types::{Range, Url};
+
+// Writes the workspace's assignments and usages as an LSIF dump, one JSON
+// vertex or edge per line, so code review tools like Sourcegraph can offer
+// the same navigation as the editor:
+//
+//   range  --next-->  resultSet  --textDocument/definition-->  definitionResult
+//                                --textDocument/references-->  referenceResult
+//                                --moniker-->                  moniker
+//
+// Usages link to definitions the same way the server links them, by name
+// within each kind of symbol, so a method's references are every call with
+// its name.
+
+pub const VERSION: &str = "0.6.0";
+
+pub struct Occurrence {
+    pub uri: Url,
+    pub range: Range,
+    // Occurrences sharing a symbol are linked together
+    pub symbol: String,
+    pub definition: bool,
+    // Locals aren't given a moniker, they can't be referred to elsewhere
+    pub local: bool,
+}
+
+pub struct ExportSummary {
+    pub documents: usize,
+    pub symbols: usize,
+    pub occurrences: usize,
+}
+
+// The symbol a document's node type belongs to, None for documents that
+// don't name anything, like implicit `yield` targets and superclass links
+pub fn symbol(
+    node_type: &str,
+    name: &str,
+    path: &str,
+    fuzzy_ruby_scope: &[String],
+    class_scope: &[String],
+) -> Option<(String, bool)> {
+    match node_type {
+        "Alias" | "CSend" | "Def" | "Defs" | "Send" | "Super" | "ZSuper" => {
+            Some((format!("method {}", name), false))
+        }
+        "Casgn" | "Class" | "Const" | "Module" => Some((format!("constant {}", name), false)),
+        "Gvar" | "Gvasgn" => Some((format!("global {}", name), false)),
+        "Ivar" | "Ivasgn" => Some((
+            format!("instance {}#{}", class_scope.join("::"), name),
+            false,
+        )),
+        "Cvar" | "Cvasgn" => Some((format!("class {}#{}", class_scope.join("::"), name), false)),
+        "Arg" | "Blockarg" | "ForwardArg" | "ForwardedArgs" | "Kwarg" | "Kwoptarg"
+        | "Kwrestarg" | "Lvar" | "Lvasgn" | "MatchVar" | "Optarg" | "Restarg" | "Shadowarg"
+        | "BackRef" | "NthRef" | "RegexpMatch" => Some((
+            format!("local {}:{}#{}", path, fuzzy_ruby_scope.join("::"), name),
+            true,
+        )),
+        _ => None,
+    }
+}
+
+pub fn write_dump<W: Write>(
+    writer: &mut W,
+    project_root: &Url,
+    occurrences: Vec<Occurrence>,
+) -> io::Result<ExportSummary> {
+    let mut dump = Dump { writer, next_id: 0 };
+
+    dump.vertex(json!({
+        "label": "metaData",
+        "version": VERSION,
+        "projectRoot": project_root,
+        "positionEncoding": "utf-16",
+        "toolInfo": { "name": "fuzzy", "version": cli::VERSION },
+    }))?;
+    let project_id = dump.vertex(json!({ "label": "project", "kind": "ruby" }))?;
+
+    // Sorted so dumps of the same index are identical
+    let mut files: BTreeMap<String, Vec<Occurrence>> = BTreeMap::new();
+
+    for occurrence in occurrences {
+        files
+            .entry(occurrence.uri.to_string())
+            .or_default()
+            .push(occurrence);
+    }
+
+    let mut document_ids = vec![];
+    // symbol => [(document id, range id, definition)]
+    let mut symbol_ranges: BTreeMap<String, (bool, Vec<(u64, u64, bool)>)> = BTreeMap::new();
+    let mut occurrence_count = 0;
+
+    for (uri, mut occurrences) in files {
+        occurrences.sort_by_key(|occurrence| {
+            (
+                occurrence.range.start.line,
+                occurrence.range.start.character,
+                !occurrence.definition,
+            )
+        });
+        // The same name can be indexed twice at one position, like an
+        // op-assign's read and write, but a range only has one result set
+        occurrences.dedup_by_key(|occurrence| occurrence.range);
+
+        let document_id = dump.vertex(json!({
+            "label": "document",
+            "uri": uri,
+            "languageId": "ruby",
+        }))?;
+        let mut range_ids = vec![];
+
+        for occurrence in occurrences {
+            let range_id = dump.vertex(json!({
+                "label": "range",
+                "start": occurrence.range.start,
+                "end": occurrence.range.end,
+            }))?;
+
+            range_ids.push(range_id);
+            symbol_ranges
+                .entry(occurrence.symbol)
+                .or_insert((occurrence.local, vec![]))
+                .1
+                .push((document_id, range_id, occurrence.definition));
+            occurrence_count += 1;
+        }
+
+        dump.edge_to_many("contains", document_id, &range_ids, None)?;
+        document_ids.push(document_id);
+    }
+
+    dump.edge_to_many("contains", project_id, &document_ids, None)?;
+
+    for (symbol, (local, ranges)) in &symbol_ranges {
+        let result_set_id = dump.vertex(json!({ "label": "resultSet" }))?;
+
+        for (_document_id, range_id, _definition) in ranges {
+            dump.edge("next", *range_id, result_set_id)?;
+        }
+
+        if !local {
+            let moniker_id = dump.vertex(json!({
+                "label": "moniker",
+                "scheme": "ruby",
+                "identifier": symbol,
+                "kind": "export",
+            }))?;
+
+            dump.edge("moniker", result_set_id, moniker_id)?;
+        }
+
+        let definitions: Vec<&(u64, u64, bool)> = ranges
+            .iter()
+            .filter(|(_, _, definition)| *definition)
+            .collect();
+
+        if !definitions.is_empty() {
+            let definition_result_id = dump.vertex(json!({ "label": "definitionResult" }))?;
+
+            dump.edge(
+                "textDocument/definition",
+                result_set_id,
+                definition_result_id,
+            )?;
+
+            for (document_id, range_ids) in by_document(definitions.iter().copied()) {
+                dump.item_edge(definition_result_id, document_id, &range_ids, None)?;
+            }
+        }
+
+        let reference_result_id = dump.vertex(json!({ "label": "referenceResult" }))?;
+
+        dump.edge(
+            "textDocument/references",
+            result_set_id,
+            reference_result_id,
+        )?;
+
+        for definition in [true, false] {
+            let property = if definition {
+                "definitions"
+            } else {
+                "references"
+            };
+            let matching_ranges = ranges
+                .iter()
+                .filter(|(_, _, range_definition)| *range_definition == definition);
+
+            for (document_id, range_ids) in by_document(matching_ranges) {
+                dump.item_edge(reference_result_id, document_id, &range_ids, Some(property))?;
+            }
+        }
+    }
+
+    Ok(ExportSummary {
+        documents: document_ids.len(),
+        symbols: symbol_ranges.len(),
+        occurrences: occurrence_count,
+    })
+}
+
+// Item edges name the document their ranges are in
+fn by_document<'a>(ranges: impl Iterator<Item = &'a (u64, u64, bool)>) -> BTreeMap<u64, Vec<u64>> {
+    let mut range_ids: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+
+    for (document_id, range_id, _definition) in ranges {
+        range_ids.entry(*document_id).or_default().push(*range_id);
+    }
+
+    range_ids
+}
+
+struct Dump<'a, W: Write> {
+    writer: &'a mut W,
+    next_id: u64,
+}
+
+impl<'a, W: Write> Dump<'a, W> {
+    fn vertex(&mut self, vertex: Value) -> io::Result<u64> {
+        self.element("vertex", vertex)
+    }
+
+    fn edge(&mut self, label: &str, out_id: u64, in_id: u64) -> io::Result<u64> {
+        self.element(
+            "edge",
+            json!({ "label": label, "outV": out_id, "inV": in_id }),
+        )
+    }
+
+    fn edge_to_many(
+        &mut self,
+        label: &str,
+        out_id: u64,
+        in_ids: &[u64],
+        extra: Option<HashMap<&str, Value>>,
+    ) -> io::Result<u64> {
+        let mut edge = json!({ "label": label, "outV": out_id, "inVs": in_ids });
+
+        for (key, value) in extra.unwrap_or_default() {
+            edge[key] = value;
+        }
+
+        self.element("edge", edge)
+    }
+
+    fn item_edge(
+        &mut self,
+        out_id: u64,
+        document_id: u64,
+        range_ids: &[u64],
+        property: Option<&str>,
+    ) -> io::Result<u64> {
+        let mut extra = HashMap::from([("document", json!(document_id))]);
+
+        if let Some(property) = property {
+            extra.insert("property", json!(property));
+        }
+
+        self.edge_to_many("item", out_id, range_ids, Some(extra))
+    }
+
+    fn element(&mut self, element_type: &str, mut element: Value) -> io::Result<u64> {
+        self.next_id += 1;
+        element["id"] = json!(self.next_id);
+        element["type"] = json!(element_type);
+
+        writeln!(self.writer, "{}", element)?;
+
+        Ok(self.next_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Position;
+
+    fn occurrence(path: &str, line: u32, definition: bool) -> Occurrence {
+        Occurrence {
+            uri: Url::parse(&format!("file:///project/{}", path)).unwrap(),
+            range: Range::new(Position::new(line, 4), Position::new(line, 9)),
+            symbol: "method greet".to_string(),
+            definition,
+            local: false,
+        }
+    }
+
+    #[test]
+    fn links_ranges_to_their_definitions_and_references() {
+        let mut output = vec![];
+        let summary = write_dump(
+            &mut output,
+            &Url::parse("file:///project").unwrap(),
+            vec![occurrence("b.rb", 1, false), occurrence("a.rb", 0, true)],
+        )
+        .unwrap();
+        let elements: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let labels: Vec<&str> = elements
+            .iter()
+            .map(|element| element["label"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            (summary.documents, summary.symbols, summary.occurrences),
+            (2, 1, 2)
+        );
+        assert_eq!(
+            labels,
+            [
+                "metaData",
+                "project",
+                "document",
+                "range",
+                "contains",
+                "document",
+                "range",
+                "contains",
+                "contains",
+                "resultSet",
+                "next",
+                "next",
+                "moniker",
+                "moniker",
+                "definitionResult",
+                "textDocument/definition",
+                "item",
+                "referenceResult",
+                "textDocument/references",
+                "item",
+                "item",
+            ]
+        );
+        assert_eq!(elements[2]["uri"], "file:///project/a.rb");
+        assert_eq!(elements[3]["start"], json!({ "line": 0, "character": 4 }));
+        assert_eq!(elements[8]["inVs"], json!([3, 6]));
+        assert_eq!(elements[12]["identifier"], "method greet");
+        assert_eq!(
+            elements[16],
+            json!({ "id": 17, "type": "edge", "label": "item", "outV": 15, "inVs": [4], "document": 3 })
+        );
+        assert_eq!(elements[19]["property"], "definitions");
+        assert_eq!(elements[20]["property"], "references");
+        assert_eq!(elements[20]["inVs"], json!([7]));
+        assert!(elements
+            .iter()
+            .enumerate()
+            .all(|(index, element)| element["id"] == json!(index + 1)));
+    }
+
+    #[test]
+    fn leaves_locals_without_monikers() {
+        assert_eq!(
+            symbol("Lvar", "user", "a.rb", &["greet".to_string()], &[]),
+            Some(("local a.rb:greet#user".to_string(), true))
+        );
+        assert!(!symbol("Super", "greet", "a.rb", &[], &[]).unwrap().1);
+        assert_eq!(symbol("Args", "", "a.rb", &[], &[]), None);
+    }
+}
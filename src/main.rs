@@ -1,259 +1,237 @@
-mod persistence;
+use fuzzy::{cancellation, cli, lsif, paths, persistence, server};
 
+use cancellation::CancellationToken;
+use cli::{Cli, Command, Export, Query, QueryKind, Transport};
 use persistence::Persistence;
-use tasklist::tasklist;
 
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use log::info;
+use std::fs::File;
+use tokio::net::TcpListener;
 use tokio::time::*;
-use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer, LspService, Server};
-
-struct Backend {
-    client: Client,
-    persistence: Arc<Mutex<Persistence>>,
-}
+use tower_lsp::Server;
 
 #[tokio::main]
 #[quit::main]
 async fn main() {
-    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = Cli::from_args(&args).unwrap_or_else(|message| {
+        eprintln!("{}\n\n{}", message, cli::HELP);
+        quit::with_code(2);
+    });
 
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    init_logger(&cli);
 
-    let persistence = Arc::new(Mutex::new(Persistence::new().unwrap()));
+    match cli.command {
+        Command::Serve(transport) => serve(transport).await,
+        Command::Index(path) => index_workspace(&path),
+        Command::Query(query) => query_index(query),
+        Command::Export(export) => export_workspace(export),
+        Command::Version => println!("fuzzy {}", cli::VERSION),
+        Command::Help => println!("{}", cli::HELP),
+    }
+}
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        persistence,
-    });
+fn init_logger(cli: &Cli) {
+    let mut builder = env_logger::Builder::from_default_env();
 
-    Server::new(stdin, stdout, socket).serve(service).await;
-}
+    if let Some(log_level) = cli.log_level {
+        builder.filter_level(log_level);
+    }
 
-#[tower_lsp::async_trait]
-impl LanguageServer for Backend {
-    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        let mut persistence = self.persistence.lock().await;
-        persistence.initialize(&params);
-        drop(persistence);
+    if let Some(log_file) = &cli.log_file {
+        let file = File::create(log_file).unwrap_or_else(|error| {
+            eprintln!("Unable to open log file {}: {}", log_file, error);
+            quit::with_code(1);
+        });
 
-        tokio::spawn(async move {
-            #[cfg(not(target_family = "windows"))]
-            loop {
-                let editor_process_id = params.process_id.unwrap_or_else(|| quit::with_code(1));
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
 
-                let editor_process_running = psutil::process::processes()
-                    .unwrap()
-                    .into_iter()
-                    .filter_map(|process| process.ok())
-                    .find(|process| process.pid() == editor_process_id);
+    builder.init();
+}
 
-                if let None = editor_process_running {
-                    quit::with_code(1);
-                }
+// Builds the same on-disk index the server uses with `allocationType: disk`,
+// so it can be warmed ahead of time or debugged without an editor.
+fn index_workspace(path: &str) {
+    let start_time = Instant::now();
+    let mut persistence = indexed_persistence(path);
 
-                tokio::time::sleep(Duration::from_secs(60)).await;
-            }
-            #[cfg(target_family = "Windows")]
-            loop {
-                use tasklist;
+    println!("Workspace files: {}", persistence.indexed_file_count());
+    println!("Documents: {}", persistence.document_count().unwrap_or(0));
 
-                let editor_process_id = params.process_id.unwrap_or_else(|| quit::with_code(1));
+    persistence.shutdown();
 
-                let editor_process_running = unsafe {
-                    let mut tl = tasklist::Tasklist::new();
-                    tl.any(|process| process.get_pid() == editor_process_id)
-                };
+    println!("Index: {}", paths::index_dir(&paths::canonicalize(path)).display());
+    println!("Time: {:.2}s", start_time.elapsed().as_secs_f64());
+}
 
-                if !editor_process_running {
-                    quit::with_code(1);
-                }
+// Writes the workspace's index as LSIF for code review tools, reusing and
+// updating the index `fuzzy index` keeps
+fn export_workspace(export: Export) {
+    let start_time = Instant::now();
+    let mut persistence = indexed_persistence(&export.workspace_path);
+    let project_root = paths::path_to_uri(&paths::canonicalize(&export.workspace_path));
 
-                tokio::time::sleep(Duration::from_secs(60)).await;
-            }
+    let occurrences = persistence.lsif_occurrences().unwrap_or_else(|error| {
+        eprintln!("Export failed: {}", error);
+        quit::with_code(1);
+    });
 
-        });
+    let export_result = File::create(&export.output_path).and_then(|file| {
+        let mut writer = std::io::BufWriter::new(file);
+        let summary = lsif::write_dump(&mut writer, &project_root, occurrences)?;
 
-        let background_persistence = Arc::clone(&self.persistence);
+        std::io::Write::flush(&mut writer)?;
 
-        tokio::spawn(async move {
-            loop {
-                let mut persistence = background_persistence.lock().await;
-                let _ = persistence.reindex_modified_files();
-                let _ = persistence.index_included_dirs_once();
-                let _ = persistence.index_gems_once();
-                drop(persistence);
+        Ok(summary)
+    });
 
-                tokio::time::sleep(Duration::from_secs(600)).await
-            }
-        });
+    let summary = export_result.unwrap_or_else(|error| {
+        eprintln!("Unable to write {}: {}", export.output_path, error);
+        quit::with_code(1);
+    });
 
-        Ok(InitializeResult {
-            server_info: None,
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Options(
-                    TextDocumentSyncOptions {
-                        open_close: Some(true),
-                        change: Some(TextDocumentSyncKind::FULL), // todo: incremental
-                        will_save: Some(false),
-                        will_save_wait_until: Some(false),
-                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
-                            include_text: Some(true),
-                        })),
-                    },
-                )),
-                definition_provider: Some(OneOf::Left(true)),
-                document_highlight_provider: Some(OneOf::Left(true)),
-                references_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
-                workspace_symbol_provider: Some(OneOf::Left(true)),
-                ..ServerCapabilities::default()
-            },
-        })
-    }
+    persistence.shutdown();
 
-    async fn shutdown(&self) -> Result<()> {
-        Ok(())
+    println!("Documents: {}", summary.documents);
+    println!("Symbols: {}", summary.symbols);
+    println!("Occurrences: {}", summary.occurrences);
+    println!("Dump: {}", export.output_path);
+    println!("Time: {:.2}s", start_time.elapsed().as_secs_f64());
+}
+
+fn indexed_persistence(path: &str) -> Persistence {
+    if !std::path::Path::new(path).is_dir() {
+        eprintln!("Unable to index {}: not a directory", path);
+        quit::with_code(1);
     }
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let mut persistence = self.persistence.lock().await;
-        let mut diagnostics: Vec<tower_lsp::lsp_types::Diagnostic> = vec![];
+    let params = InitializeParams {
+        root_uri: Some(paths::path_to_uri(&paths::canonicalize(path))),
+        initialization_options: Some(serde_json::json!({ "allocationType": "disk" })),
+        ..InitializeParams::default()
+    };
 
-        let change_diagnostics =
-            persistence.diagnostics(&params.text_document.text, &params.text_document.uri);
+    let mut persistence = Persistence::new().unwrap();
 
-        for diagnostic in change_diagnostics {
-            for unwrapped_diagnostic in diagnostic {
-                if let Some(finally_diagnostic) = unwrapped_diagnostic {
-                    diagnostics.push(finally_diagnostic.to_owned());
-                }
-            }
-        }
+    persistence.initialize(&params);
 
-        if persistence.report_diagnostics {
-            self.client
-                .publish_diagnostics(
-                    params.text_document.uri,
-                    diagnostics,
-                    Some(params.text_document.version),
-                )
-                .await;
-        }
-    }
+    let indexing_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        persistence.reindex_modified_files()?;
+        persistence.index_gems_once()
+    }));
 
-    async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let mut persistence = self.persistence.lock().await;
-
-        for content_change in &params.content_changes {
-            persistence
-                .reindex_modified_file(
-                    &self.client,
-                    &content_change.text,
-                    &params.text_document.uri,
-                )
-                .await;
+    match indexing_result {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => {
+            eprintln!("Indexing failed: {}", error);
+            quit::with_code(1);
+        }
+        // The panic message has already been printed by the panic hook
+        Err(_) => {
+            eprintln!("Indexing panicked, the index may be incomplete");
+            quit::with_code(1);
         }
     }
 
-    async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let mut persistence = self.persistence.lock().await;
-        persistence
-            .reindex_modified_file(
-                &self.client,
-                &params.text.unwrap(),
-                &params.text_document.uri,
-            )
-            .await;
-    }
-
-    async fn did_close(&self, _: DidCloseTextDocumentParams) {
-        self.client
-            .log_message(MessageType::INFO, "file closed!")
-            .await;
-    }
-
-    async fn goto_definition(
-        &self,
-        params: GotoDefinitionParams,
-    ) -> Result<Option<GotoDefinitionResponse>> {
-        let persistence = self.persistence.lock().await;
-        let definitions = || -> Option<GotoDefinitionResponse> {
-            let locations = persistence.find_definitions(params.text_document_position_params);
-            let locations = locations.unwrap();
+    persistence
+}
 
-            Some(GotoDefinitionResponse::Array(locations))
-        }();
+// Answers from the index `fuzzy index` built, without reindexing
+fn query_index(query: Query) {
+    let workspace_path = paths::canonicalize(&query.workspace_path);
 
-        Ok(definitions)
+    if !paths::index_dir(&workspace_path).join("meta.json").exists() {
+        eprintln!(
+            "No index found for {}, run `fuzzy index {}` first",
+            workspace_path, query.workspace_path
+        );
+        quit::with_code(1);
     }
 
-    async fn document_highlight(
-        &self,
-        params: DocumentHighlightParams,
-    ) -> Result<Option<Vec<DocumentHighlight>>> {
-        let persistence = self.persistence.lock().await;
-
-        let highlights_response = || -> Option<Vec<DocumentHighlight>> {
-            let highlights = persistence.find_highlights(params.text_document_position_params);
-            let highlights = highlights.unwrap();
-
-            Some(highlights)
-        }();
+    let params = InitializeParams {
+        root_uri: Some(paths::path_to_uri(&workspace_path)),
+        initialization_options: Some(serde_json::json!({ "allocationType": "disk" })),
+        ..InitializeParams::default()
+    };
 
-        Ok(highlights_response)
-    }
+    let mut persistence = Persistence::new().unwrap();
+    persistence.initialize(&params);
 
-    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        let persistence = self.persistence.lock().await;
-        let text_position = params.clone().text_document_position;
-        let text_document = &params.text_document_position.text_document;
+    let locations = match query.kind {
+        QueryKind::Definitions { path, line, column } => {
+            let text_document_position = TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(paths::path_to_uri(&paths::canonicalize(&path))),
+                Position::new(line - 1, column - 1),
+            );
 
-        let locations_response = || -> Option<Vec<Location>> {
-            let documents = persistence.find_references(text_position).unwrap();
-            let locations = persistence.documents_to_locations(text_document.uri.path(), documents);
+            persistence.find_definitions(text_document_position, &CancellationToken::default())
+        }
+        QueryKind::References(name) => persistence.find_references_by_name(&name),
+    };
 
-            Some(locations)
-        }();
+    let locations = locations.unwrap_or_else(|error| {
+        eprintln!("Query failed: {}", error);
+        quit::with_code(1);
+    });
 
-        Ok(locations_response)
+    if query.json {
+        println!("{}", serde_json::to_string_pretty(&locations).unwrap());
+    } else {
+        for location in locations {
+            println!(
+                "{}:{}:{}",
+                paths::uri_to_path(&location.uri),
+                location.range.start.line + 1,
+                location.range.start.character + 1
+            );
+        }
     }
+}
 
-    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        let persistence = self.persistence.lock().await;
-        let text_position = params.clone().text_document_position;
-        let text_document = &params.text_document_position.text_document;
-        let new_name = &params.new_name;
-
-        let workspace_edit = || -> Option<WorkspaceEdit> {
-            let references = persistence.find_references(text_position).unwrap();
-            let workspace_edit =
-                persistence.rename_tokens(text_document.uri.path(), references, new_name);
+async fn serve(transport: Transport) {
+    // A remote editor's process ID means nothing on this machine
+    let watch_editor_process = !matches!(transport, Transport::Tcp(_));
 
-            Some(workspace_edit)
-        }();
+    let (service, socket) = server::service(watch_editor_process);
 
-        Ok(workspace_edit)
-    }
+    match transport {
+        Transport::Stdio => {
+            let stdin = tokio::io::stdin();
+            let stdout = tokio::io::stdout();
 
-    async fn symbol(
-        &self,
-        params: WorkspaceSymbolParams,
-    ) -> Result<Option<Vec<SymbolInformation>>> {
-        let persistence = self.persistence.lock().await;
+            Server::new(stdin, stdout, socket).serve(service).await;
+        }
+        Transport::Tcp(address) => {
+            let listener = TcpListener::bind(&address).await.unwrap_or_else(|error| {
+                eprintln!("Unable to listen on {}: {}", address, error);
+                quit::with_code(1);
+            });
 
-        let symbol_info_response = || -> Option<Vec<SymbolInformation>> {
-            let documents = persistence
-                .find_references_in_workspace(params.query)
-                .unwrap_or_else(|_| Vec::new());
-            let symbol_info = persistence.documents_to_symbol_information(documents);
+            info!("Listening on {}", address);
 
-            Some(symbol_info)
-        }();
+            let (stream, _address) = listener.accept().await.unwrap_or_else(|error| {
+                eprintln!("Unable to accept a connection on {}: {}", address, error);
+                quit::with_code(1);
+            });
+            let (read, write) = tokio::io::split(stream);
 
-        Ok(symbol_info_response)
+            Server::new(read, write, socket).serve(service).await;
+        }
+        Transport::Pipe(name) => {
+            #[cfg(unix)]
+            let pipe = tokio::net::UnixStream::connect(&name).await;
+            #[cfg(windows)]
+            let pipe = tokio::net::windows::named_pipe::ClientOptions::new().open(&name);
+
+            let pipe = pipe.unwrap_or_else(|error| {
+                eprintln!("Unable to connect to {}: {}", name, error);
+                quit::with_code(1);
+            });
+            let (read, write) = tokio::io::split(pipe);
+
+            Server::new(read, write, socket).serve(service).await;
+        }
     }
 }
@@ -0,0 +1,150 @@
+use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+
+// Splits names into the lowercase words they're made of, so symbol search
+// can match any part of a name:
+//
+//   UserAccountsController  =>  user, accounts, controller
+//   find_by_email!          =>  find, by, email
+//   HTMLParser              =>  html, parser
+//
+// Registered on the index as `name_parts` for the `name_parts` field.
+
+pub const TOKENIZER_NAME: &str = "name_parts";
+
+#[derive(Clone)]
+pub struct NamePartsTokenizer;
+
+impl Tokenizer for NamePartsTokenizer {
+    type TokenStream<'a> = NamePartsTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> NamePartsTokenStream {
+        let tokens = word_ranges(text)
+            .into_iter()
+            .enumerate()
+            .map(|(position, (offset_from, offset_to))| Token {
+                offset_from,
+                offset_to,
+                position,
+                text: text[offset_from..offset_to].to_lowercase(),
+                position_length: 1,
+            })
+            .collect();
+
+        NamePartsTokenStream { tokens, index: 0 }
+    }
+}
+
+pub struct NamePartsTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl TokenStream for NamePartsTokenStream {
+    fn advance(&mut self) -> bool {
+        if self.index < self.tokens.len() {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+// The words of a search query, split the same way names are
+pub fn words(text: &str) -> Vec<String> {
+    word_ranges(text)
+        .into_iter()
+        .map(|(start, end)| text[start..end].to_lowercase())
+        .collect()
+}
+
+// Byte ranges of each word. Words end at punctuation and underscores, and at
+// case changes, where an acronym's last capital starts the next word.
+fn word_ranges(text: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut ranges = vec![];
+    let mut word_start: Option<usize> = None;
+
+    for (index, (offset, char)) in chars.iter().enumerate() {
+        if !char.is_alphanumeric() {
+            if let Some(start) = word_start.take() {
+                ranges.push((start, *offset));
+            }
+
+            continue;
+        }
+
+        if let Some(start) = word_start {
+            let previous = chars[index - 1].1;
+            let next = chars.get(index + 1).map(|(_offset, next)| *next);
+
+            let word_break = char.is_uppercase()
+                && (previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.map_or(false, |next| next.is_lowercase())));
+
+            if word_break {
+                ranges.push((start, *offset));
+                word_start = Some(*offset);
+            }
+        } else {
+            word_start = Some(*offset);
+        }
+    }
+
+    if let Some(start) = word_start {
+        ranges.push((start, text.len()));
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_names_into_words() {
+        assert_eq!(
+            words("UserAccountsController"),
+            ["user", "accounts", "controller"]
+        );
+        assert_eq!(words("HTTPServer"), ["http", "server"]);
+        assert_eq!(words("find_by_email!"), ["find", "by", "email"]);
+        assert_eq!(words("Base64Encoder"), ["base64", "encoder"]);
+    }
+
+    #[test]
+    fn tokenizes_words_at_their_offsets() {
+        let mut tokenizer = NamePartsTokenizer;
+        let mut token_stream = tokenizer.token_stream("HTTPServer");
+        let mut tokens = vec![];
+
+        while token_stream.advance() {
+            let token = token_stream.token();
+
+            tokens.push((
+                token.text.clone(),
+                token.offset_from,
+                token.offset_to,
+                token.position,
+            ));
+        }
+
+        assert_eq!(
+            tokens,
+            [
+                ("http".to_string(), 0, 4, 0),
+                ("server".to_string(), 4, 10, 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+
+// What's parsed from open files, so requests about a buffer that hasn't
+// changed since the last one don't parse it again. Each file keeps the
+// parse of its latest text, found by the text's hash, and editing it drops
+// the parse. The least recently used are dropped past a number of files or
+// a total size of text parsed.
+pub struct ParseCache<T> {
+    // Least recently used first
+    entries: VecDeque<Entry<T>>,
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: usize,
+}
+
+struct Entry<T> {
+    uri: Url,
+    content_hash: String,
+    bytes: usize,
+    value: Arc<T>,
+}
+
+impl<T> ParseCache<T> {
+    pub fn new(max_entries: usize, max_bytes: usize) -> ParseCache<T> {
+        ParseCache {
+            entries: VecDeque::new(),
+            max_entries,
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    pub fn get(&mut self, uri: &Url, content_hash: &str) -> Option<Arc<T>> {
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| &entry.uri == uri && entry.content_hash == content_hash)?;
+        let entry = self.entries.remove(position)?;
+        let value = Arc::clone(&entry.value);
+
+        self.entries.push_back(entry);
+
+        Some(value)
+    }
+
+    // Replaces the file's parse, `bytes` being the size of the text parsed
+    pub fn insert(&mut self, uri: &Url, content_hash: &str, bytes: usize, value: T) -> Arc<T> {
+        self.invalidate(uri);
+
+        let value = Arc::new(value);
+
+        // Parsing it again is cheaper than holding on to everything else
+        if bytes > self.max_bytes {
+            return value;
+        }
+
+        self.entries.push_back(Entry {
+            uri: uri.clone(),
+            content_hash: content_hash.to_string(),
+            bytes,
+            value: Arc::clone(&value),
+        });
+        self.bytes += bytes;
+
+        while self.entries.len() > self.max_entries || self.bytes > self.max_bytes {
+            match self.entries.pop_front() {
+                Some(entry) => self.bytes -= entry.bytes,
+                None => break,
+            }
+        }
+
+        value
+    }
+
+    pub fn invalidate(&mut self, uri: &Url) {
+        let bytes = &mut self.bytes;
+
+        self.entries.retain(|entry| {
+            let kept = &entry.uri != uri;
+
+            if !kept {
+                *bytes -= entry.bytes;
+            }
+
+            kept
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(name: &str) -> Url {
+        Url::parse(&format!("file:///app/{}.rb", name)).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_parse_of_each_files_latest_text() {
+        let mut cache = ParseCache::new(8, 1_000);
+
+        cache.insert(&uri("user"), "a", 10, "user v1");
+        cache.insert(&uri("order"), "b", 10, "order v1");
+
+        assert_eq!(cache.get(&uri("user"), "a").as_deref(), Some(&"user v1"));
+        assert_eq!(cache.get(&uri("user"), "c"), None);
+
+        cache.insert(&uri("user"), "c", 10, "user v2");
+
+        assert_eq!(cache.get(&uri("user"), "a"), None);
+        assert_eq!(cache.get(&uri("user"), "c").as_deref(), Some(&"user v2"));
+
+        cache.invalidate(&uri("order"));
+
+        assert_eq!(cache.get(&uri("order"), "b"), None);
+        assert!(cache.get(&uri("user"), "c").is_some());
+    }
+
+    #[test]
+    fn drops_the_least_recently_used_past_its_bounds() {
+        let mut cache = ParseCache::new(2, 100);
+
+        cache.insert(&uri("user"), "a", 10, "user");
+        cache.insert(&uri("order"), "b", 10, "order");
+        cache.get(&uri("user"), "a");
+        cache.insert(&uri("product"), "c", 10, "product");
+
+        assert_eq!(cache.get(&uri("order"), "b"), None);
+        assert!(cache.get(&uri("user"), "a").is_some());
+
+        cache.insert(&uri("schema"), "d", 95, "schema");
+
+        assert_eq!(cache.get(&uri("user"), "a"), None);
+        assert_eq!(cache.get(&uri("product"), "c"), None);
+        assert!(cache.get(&uri("schema"), "d").is_some());
+
+        // Bigger than the whole cache, it isn't kept
+        cache.insert(&uri("dump"), "e", 200, "dump");
+
+        assert_eq!(cache.get(&uri("dump"), "e"), None);
+        assert!(cache.get(&uri("schema"), "d").is_some());
+    }
+}
@@ -0,0 +1,262 @@
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// Rails partials, templates rendered inside other templates:
+//
+//   <%= render "shared/header" %>     app/views/shared/_header.html.erb
+//   <%= render partial: "item" %>     _item.html.erb, next to the view
+//
+// Templates are indexed as the Ruby in their tags, so `render` calls in
+// them are found like any other call, and each partial is indexed as a
+// definition at the start of its file.
+
+pub fn is_template(path: &str) -> bool {
+    path.ends_with(".erb") && (path.starts_with("app/views/") || path.contains("/app/views/"))
+}
+
+// The name a partial is rendered by, `shared/_header` for
+// `app/views/shared/_header.html.erb`
+pub fn partial_name(path: &str) -> Option<String> {
+    let view_path = view_path(path)?;
+    let (dir, file_name) = view_path.rsplit_once('/').unwrap_or(("", view_path));
+
+    if !file_name.starts_with('_') {
+        return None;
+    }
+
+    let file_name = file_name.split('.').next().unwrap_or(file_name);
+
+    if dir.is_empty() {
+        Some(file_name.to_string())
+    } else {
+        Some(format!("{}/{}", dir, file_name))
+    }
+}
+
+// Where a view or controller looks for the partials it names without a
+// directory: `orders` for `app/views/orders/show.html.erb` and for
+// `app/controllers/orders_controller.rb`
+pub fn view_dir(path: &str) -> Option<String> {
+    if let Some(view_path) = view_path(path) {
+        return view_path
+            .rsplit_once('/')
+            .map(|(dir, _file_name)| dir.to_string());
+    }
+
+    let controller_path = match path.find("app/controllers/") {
+        Some(index) if index == 0 || path[..index].ends_with('/') => {
+            &path[index + "app/controllers/".len()..]
+        }
+        _ => return None,
+    };
+
+    controller_path
+        .strip_suffix("_controller.rb")
+        .map(String::from)
+}
+
+// The path of a template under `app/views/`
+fn view_path(path: &str) -> Option<&str> {
+    if !is_template(path) {
+        return None;
+    }
+
+    let index = path.rfind("app/views/")?;
+
+    Some(&path[index + "app/views/".len()..])
+}
+
+// The partial a `render` call renders, with the string naming it:
+//
+//   render "shared/header"                  shared/_header
+//   render partial: "item", locals: {...}   _item
+//
+// Names without a directory are left for the caller to put in the view's.
+pub fn rendered_partial(args: &[Node]) -> Option<(String, &Loc)> {
+    let name_node = match args.first()? {
+        Node::Str(_) => args.first()?,
+        Node::Kwargs(Kwargs { pairs, .. }) => pairs.iter().find_map(|pair| match pair {
+            Node::Pair(Pair { key, value, .. }) => match key.as_ref() {
+                Node::Sym(Sym { name, .. }) if name.to_string_lossy() == "partial" => {
+                    Some(value.as_ref())
+                }
+                _ => None,
+            },
+            _ => None,
+        })?,
+        _ => return None,
+    };
+
+    let (name, loc) = match name_node {
+        Node::Str(Str {
+            value,
+            expression_l,
+            ..
+        }) => (value.to_string_lossy(), expression_l),
+        _ => return None,
+    };
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let partial_name = match name.rsplit_once('/') {
+        Some((dir, file_name)) => format!("{}/_{}", dir, file_name),
+        None => format!("_{}", name),
+    };
+
+    Some((partial_name, loc))
+}
+
+// The Ruby in a template's tags, where it is in the template so a
+// position in one is the same position in the other. Everything outside
+// the tags is blanked out to spaces, keeping line breaks, and each tag ends
+// in a `;` so tags on one line read as separate statements:
+//
+//   <li><%= item.name %></li>        item.name ;
+pub fn erb_ruby(text: &str) -> String {
+    let mut ruby = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(tag_start) = rest.find("<%") {
+        blank(&mut ruby, &rest[..tag_start]);
+        let tag = &rest[tag_start..];
+
+        // `<%%` is a literal `<%`
+        if tag.starts_with("<%%") {
+            blank(&mut ruby, "<%%");
+            rest = &tag[3..];
+            continue;
+        }
+
+        let open_len = if tag.starts_with("<%==") {
+            4
+        } else if tag.starts_with("<%=") || tag.starts_with("<%-") || tag.starts_with("<%#") {
+            3
+        } else {
+            2
+        };
+        let (code_end, tag_end) = match tag.find("%>") {
+            Some(close) if tag[..close].ends_with('-') && close > open_len => {
+                (close - 1, close + 2)
+            }
+            Some(close) => (close.max(open_len), close + 2),
+            None => (tag.len(), tag.len()),
+        };
+
+        let close = &tag[code_end..tag_end];
+
+        if tag.starts_with("<%#") {
+            blank(&mut ruby, &tag[..tag_end]);
+        } else {
+            blank(&mut ruby, &tag[..open_len]);
+            ruby.push_str(&tag[open_len..code_end]);
+
+            if !close.is_empty() {
+                ruby.push(';');
+                blank(&mut ruby, &close[1..]);
+            }
+        }
+
+        rest = &tag[tag_end..];
+    }
+
+    blank(&mut ruby, rest);
+
+    ruby
+}
+
+// Spaces for each character, as many as it has UTF-16 code units so the
+// columns after it don't move
+fn blank(ruby: &mut String, text: &str) {
+    for char in text.chars() {
+        match char {
+            '\n' | '\r' => ruby.push(char),
+            _ => {
+                for _ in 0..char.len_utf16() {
+                    ruby.push(' ');
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    fn partial(call: &str) -> Option<String> {
+        let ast = Parser::new(call.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+
+        match ast.as_ref() {
+            Node::Send(Send { args, .. }) => rendered_partial(args).map(|(name, _loc)| name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn keeps_the_ruby_in_tags_where_it_is() {
+        let text = r#"<ul>
+  <% items.each do |item| -%>
+    <li><%= item.name %></li>
+  <% end %><%# é %>
+</ul>
+"#;
+        let ruby = erb_ruby(text);
+
+        assert_eq!(
+            ruby.split('\n').map(str::trim_end).collect::<Vec<_>>(),
+            vec![
+                "",
+                "     items.each do |item| ;",
+                "            item.name ;",
+                "     end ;",
+                "",
+                "",
+            ]
+        );
+        assert_eq!(
+            ruby.split('\n')
+                .map(positions::utf16_len)
+                .collect::<Vec<_>>(),
+            text.split('\n')
+                .map(positions::utf16_len)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reads_the_partial_a_render_renders() {
+        assert_eq!(
+            partial("render 'shared/header'"),
+            Some("shared/_header".to_string())
+        );
+        assert_eq!(
+            partial("render partial: 'item', collection: items"),
+            Some("_item".to_string())
+        );
+        assert_eq!(partial("render json: order"), None);
+        assert_eq!(partial("render @items"), None);
+    }
+
+    #[test]
+    fn names_partials_after_their_paths() {
+        assert_eq!(
+            partial_name("/shop/app/views/shared/_header.html.erb"),
+            Some("shared/_header".to_string())
+        );
+        assert_eq!(partial_name("/shop/app/views/orders/show.html.erb"), None);
+        assert_eq!(
+            view_dir("/shop/app/views/admin/orders/show.html.erb"),
+            Some("admin/orders".to_string())
+        );
+        assert_eq!(
+            view_dir("/shop/app/controllers/admin/orders_controller.rb"),
+            Some("admin/orders".to_string())
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str;
+use tower_lsp::lsp_types::Url;
+
+// Paths are handled internally as `/` separated strings so file IDs,
+// relative paths, and workspace prefixes compare the same on every platform:
+//
+//   /home/user/app/models/user.rb
+//   c:/Users/user/app/models/user.rb
+//   //server/share/app/models/user.rb
+//
+// Buffers that aren't local files, like `untitled:Untitled-1`, a diff's
+// `git:/app/models/user.rb?{...}`, or a remote file system's, keep their URI
+// as their path. It converts back to the same URI, and is never read from
+// disk.
+
+pub fn normalize(path: &str) -> String {
+    let mut normalized = path.replace("\\", "/");
+
+    // Editors disagree on drive letter case, `C:` and `c:` are the same drive
+    if has_drive_letter(&normalized) {
+        let drive_letter = normalized[0..1].to_ascii_lowercase();
+        normalized.replace_range(0..1, &drive_letter);
+    }
+
+    while normalized.len() > 1 && normalized.ends_with("/") && !normalized.ends_with(":/") {
+        normalized.pop();
+    }
+
+    normalized
+}
+
+pub fn uri_to_path(uri: &Url) -> String {
+    if uri.scheme() != "file" {
+        return normalize(uri.as_str());
+    }
+
+    let path = percent_decode(uri.path());
+
+    let path = match uri.host_str() {
+        // UNC paths put the server in the host, `file://server/share/file.rb`
+        Some(host) if host.len() > 0 && host != "localhost" => format!("//{}{}", host, path),
+        // `file:///c:/file.rb` has a path of `/c:/file.rb`
+        _ => match path.strip_prefix("/") {
+            Some(drive_path) if has_drive_letter(drive_path) => drive_path.to_string(),
+            _ => path,
+        },
+    };
+
+    normalize(&path)
+}
+
+pub fn path_to_uri(path: &str) -> Url {
+    if is_virtual(path) {
+        if let Ok(uri) = Url::parse(path) {
+            return uri;
+        }
+    }
+
+    let path = normalize(path);
+
+    // `%`, `#` and `?` would otherwise be read as escapes, the fragment, and
+    // the query. Everything else is percent-encoded by the parser.
+    let escaped_path = path
+        .replace("%", "%25")
+        .replace("#", "%23")
+        .replace("?", "%3F");
+
+    let uri = if escaped_path.starts_with("//") {
+        format!("file:{}", escaped_path)
+    } else if has_drive_letter(&escaped_path) {
+        format!("file:///{}", escaped_path)
+    } else {
+        format!("file://{}", escaped_path)
+    };
+
+    Url::parse(&uri).unwrap()
+}
+
+// The path with `root` stripped, keeping the leading `/`. `None` when the
+// path isn't inside `root`; `/app` doesn't contain `/application/file.rb`.
+pub fn relative_to<'a>(path: &'a str, root: &str) -> Option<&'a str> {
+    let root = root.trim_end_matches("/");
+
+    path.strip_prefix(root)
+        .filter(|relative_path| relative_path.starts_with("/"))
+}
+
+// The path to a file from a directory, `../lib/shop/order.rb` from `/app/bin`
+// to `/app/lib/shop/order.rb`
+pub fn path_from(directory: &str, path: &str) -> String {
+    let directory_parts: Vec<&str> = directory.trim_end_matches("/").split("/").collect();
+    let path_parts: Vec<&str> = path.split("/").collect();
+    let shared_parts = directory_parts
+        .iter()
+        .zip(&path_parts)
+        .take_while(|(directory_part, path_part)| directory_part == path_part)
+        .count();
+
+    let mut parts = vec![".."; directory_parts.len() - shared_parts];
+    parts.extend(&path_parts[shared_parts..]);
+
+    parts.join("/")
+}
+
+// A path relative to a directory made absolute, with its `.` and `..` parts
+// resolved
+pub fn join(directory: &str, relative_path: &str) -> String {
+    let mut parts: Vec<&str> = directory.trim_end_matches("/").split("/").collect();
+
+    for part in relative_path.split("/") {
+        match part {
+            "." | "" => {}
+            ".." => {
+                if parts.len() > 1 {
+                    parts.pop();
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+// Resolves symlinks so every path to a file gives the same result. Falls back
+// to the path as given for files that no longer exist.
+pub fn canonicalize(path: &str) -> String {
+    match fs::canonicalize(path) {
+        Ok(canonical_path) => {
+            let canonical_path = normalize(&canonical_path.to_string_lossy());
+
+            // Windows canonical paths are verbatim, `\\?\C:\file.rb`
+            match canonical_path.strip_prefix("//?/") {
+                Some(verbatim_path) => normalize(verbatim_path),
+                None => canonical_path,
+            }
+        }
+        Err(_) => path.to_string(),
+    }
+}
+
+// What a file's documents are indexed under. Symlinks to a file share its
+// ID when they're resolved.
+pub fn file_path_id(path: &str, resolve_symlinks: bool) -> String {
+    let path = if resolve_symlinks {
+        canonicalize(path)
+    } else {
+        path.to_string()
+    };
+
+    blake3::hash(path.as_bytes()).to_string()
+}
+
+// Where the persistent index for a workspace is kept, e.g.
+// `~/.cache/fuzzy_ruby_server/<workspace hash>`
+pub fn index_dir(workspace_path: &str) -> PathBuf {
+    let workspace_hash = blake3::hash(normalize(workspace_path).as_bytes()).to_string();
+
+    cache_dir().join(&workspace_hash[..16])
+}
+
+// Where the stubs for an `rbs` gem's core signatures are unpacked, e.g.
+// `~/.cache/fuzzy_ruby_server/stdlib/<rbs gem hash>`
+pub fn stdlib_stub_dir(rbs_gem_path: &str) -> PathBuf {
+    let rbs_gem_hash = blake3::hash(normalize(rbs_gem_path).as_bytes()).to_string();
+
+    stdlib_dir().join(&rbs_gem_hash[..16])
+}
+
+// Where telemetry is written when it's sent to a file, see `telemetry`
+pub fn telemetry_file() -> PathBuf {
+    cache_dir().join("telemetry.jsonl")
+}
+
+pub fn stdlib_dir() -> PathBuf {
+    cache_dir().join("stdlib")
+}
+
+fn cache_dir() -> PathBuf {
+    let cache_dir = env::var("XDG_CACHE_HOME")
+        .or_else(|_| env::var("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|_| env::temp_dir());
+
+    cache_dir.join("fuzzy_ruby_server")
+}
+
+// A URI's path, starting with a scheme longer than a drive letter
+pub fn is_virtual(path: &str) -> bool {
+    match path.split_once(':') {
+        Some((scheme, _rest)) => {
+            scheme.len() > 1
+                && scheme.starts_with(|char: char| char.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || "+.-".contains(char))
+        }
+        None => false,
+    }
+}
+
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with("/") || has_drive_letter(path)
+}
+
+// Rebuilds an absolute path from the `/` separated parts stored in the index
+pub fn from_parts(parts: &[&str]) -> String {
+    let joined_parts = parts.join("/");
+
+    if has_drive_letter(&joined_parts) || is_virtual(&joined_parts) {
+        joined_parts
+    } else {
+        format!("/{}", joined_parts)
+    }
+}
+
+// Matches `/` separated paths against a glob, where `*` and `?` stay within a
+// directory and `**` crosses them: `app/**/*_controller.rb`
+pub fn glob_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                // `app/**/user.rb` includes `app/user.rb`
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    pattern.push_str("(?:.*/)?");
+                } else {
+                    pattern.push_str(".*");
+                }
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+
+    pattern.push('$');
+
+    Regex::new(&pattern)
+}
+
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes.len() == 2 || bytes[2] == b'/')
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            let hex = str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or("");
+
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_unix_paths() {
+        assert_eq!(normalize("/home/user/app/"), "/home/user/app");
+        assert_eq!(normalize("/"), "/");
+    }
+
+    #[test]
+    fn normalizes_windows_paths() {
+        assert_eq!(normalize("C:\\Users\\user\\app\\"), "c:/Users/user/app");
+        assert_eq!(normalize("c:/"), "c:/");
+    }
+
+    #[test]
+    fn normalizes_unc_paths() {
+        assert_eq!(normalize("\\\\server\\share\\app"), "//server/share/app");
+    }
+
+    #[test]
+    fn converts_unix_uris() {
+        let uri = Url::parse("file:///home/user/my%20app/user.rb").unwrap();
+
+        assert_eq!(uri_to_path(&uri), "/home/user/my app/user.rb");
+        assert_eq!(path_to_uri("/home/user/my app/user.rb"), uri);
+    }
+
+    #[test]
+    fn converts_windows_uris() {
+        let uri = Url::parse("file:///c%3A/Users/user/app/user.rb").unwrap();
+
+        assert_eq!(uri_to_path(&uri), "c:/Users/user/app/user.rb");
+        assert_eq!(
+            path_to_uri("C:\\Users\\user\\app\\user.rb").as_str(),
+            "file:///c:/Users/user/app/user.rb"
+        );
+    }
+
+    #[test]
+    fn converts_unc_uris() {
+        let uri = Url::parse("file://server/share/app/user.rb").unwrap();
+
+        assert_eq!(uri_to_path(&uri), "//server/share/app/user.rb");
+        assert_eq!(path_to_uri("//server/share/app/user.rb"), uri);
+    }
+
+    #[test]
+    fn keeps_the_uris_of_buffers_that_arent_files() {
+        for uri in [
+            "untitled:Untitled-1",
+            "git:/home/user/app/user.rb?%7B%22ref%22%3A%22HEAD%22%7D",
+            "vscode-remote://ssh-remote%2Bbox/home/user/app/user.rb",
+        ] {
+            let path = uri_to_path(&Url::parse(uri).unwrap());
+
+            assert_eq!(path, uri);
+            assert!(is_virtual(&path));
+            assert_eq!(path_to_uri(&path).as_str(), uri);
+        }
+
+        assert!(!is_virtual("c:/Users/user/app/user.rb"));
+        assert!(!is_virtual("/home/user/app/user:1.rb"));
+        assert_eq!(from_parts(&["untitled:Untitled-1"]), "untitled:Untitled-1");
+    }
+
+    #[test]
+    fn escapes_reserved_characters() {
+        let path = "/home/user/app/100%#?.rb";
+
+        assert_eq!(uri_to_path(&path_to_uri(path)), path);
+    }
+
+    #[test]
+    fn finds_relative_paths() {
+        assert_eq!(
+            relative_to("/app/models/user.rb", "/app"),
+            Some("/models/user.rb")
+        );
+        assert_eq!(
+            relative_to("/app/models/user.rb", "/app/"),
+            Some("/models/user.rb")
+        );
+        assert_eq!(relative_to("/application/user.rb", "/app"), None);
+        assert_eq!(
+            relative_to("c:/Users/user/app/user.rb", "c:/Users/user"),
+            Some("/app/user.rb")
+        );
+    }
+
+    #[test]
+    fn finds_paths_between_directories() {
+        assert_eq!(
+            path_from("/app/bin", "/app/lib/shop/order.rb"),
+            "../lib/shop/order.rb"
+        );
+        assert_eq!(path_from("/app/lib/", "/app/lib/order.rb"), "order.rb");
+        assert_eq!(
+            join("/app/bin", "../lib/./shop/order"),
+            "/app/lib/shop/order"
+        );
+        assert_eq!(join("c:/app/bin", "../lib"), "c:/app/lib");
+    }
+
+    #[test]
+    fn keeps_symlinked_workspaces_unresolved() {
+        // A workspace opened through a symlink is indexed under the symlink,
+        // matching the paths the editor sends
+        let uri = Url::parse("file:///home/user/linked_app/user.rb").unwrap();
+
+        assert_eq!(
+            relative_to(&uri_to_path(&uri), "/home/user/linked_app"),
+            Some("/user.rb")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_symlinked_files_only_for_their_ids() {
+        let dir = env::temp_dir().join(format!("fuzzy-symlinks-{}", std::process::id()));
+        let app_dir = dir.join("app");
+        let linked_dir = dir.join("linked_app");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("user.rb"), "class User; end\n").unwrap();
+        std::os::unix::fs::symlink(&app_dir, &linked_dir).unwrap();
+
+        // The temp dir can itself be behind a symlink, like macOS's /var
+        let app_path = canonicalize(&app_dir.to_string_lossy());
+        let linked_path = normalize(&linked_dir.to_string_lossy());
+        let user_path = format!("{}/user.rb", app_path);
+        let linked_user_path = format!("{}/user.rb", linked_path);
+
+        // Paths through the symlink are kept as the editor sends them
+        let path = uri_to_path(&path_to_uri(&linked_user_path));
+
+        assert_eq!(path, linked_user_path);
+        assert_eq!(relative_to(&path, &linked_path), Some("/user.rb"));
+        assert_eq!(relative_to(&path, &app_path), None);
+
+        // and share the target's ID only when symlinks are resolved
+        assert_eq!(file_path_id(&path, true), file_path_id(&user_path, true));
+        assert_ne!(file_path_id(&path, false), file_path_id(&user_path, false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_absolute_paths() {
+        assert!(is_absolute("/home/user/app"));
+        assert!(is_absolute("c:/Users/user/app"));
+        assert!(is_absolute("//server/share/app"));
+        assert!(!is_absolute("vendor/engines"));
+    }
+
+    #[test]
+    fn matches_globs() {
+        let controllers = glob_regex("app/**/*_controller.rb").unwrap();
+
+        assert!(controllers.is_match("app/controllers/users_controller.rb"));
+        assert!(controllers.is_match("app/controllers/admin/users_controller.rb"));
+        assert!(controllers.is_match("app/users_controller.rb"));
+        assert!(!controllers.is_match("lib/app/users_controller.rb"));
+
+        let migrations = glob_regex("db/migrate/*.rb").unwrap();
+
+        assert!(migrations.is_match("db/migrate/20240101000000_create_users.rb"));
+        assert!(!migrations.is_match("db/migrate/old/20200101000000_create_users.rb"));
+    }
+
+    #[test]
+    fn rebuilds_paths_from_parts() {
+        assert_eq!(
+            from_parts(&["gems", "rails", "rails.rb"]),
+            "/gems/rails/rails.rb"
+        );
+        assert_eq!(from_parts(&["c:", "gems", "rails.rb"]), "c:/gems/rails.rb");
+    }
+}
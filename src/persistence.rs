@@ -1,920 +1,7055 @@
 use filetime::FileTime;
+use ignore::{WalkBuilder, WalkState};
 use jwalk::WalkDirGeneric;
-use lib_ruby_parser::source::DecodedInput;
 use lib_ruby_parser::{nodes::*, Loc, Node, Parser, ParserOptions};
+use crate::autoload;
+use crate::cancellation::CancellationToken;
+use crate::compaction::MergeSettings;
+use crate::dsl_rules::{self, DslRule};
+use crate::encoding::{self, SourceInput};
+use crate::formatter::FormatJob;
+use crate::git;
+use crate::i18n;
+use crate::indexed_symbol::{self, IndexedSymbol, SchemaFields};
+use crate::lsif;
+use crate::name_parts::{self, NamePartsTokenizer};
+use crate::parse_cache::ParseCache;
+use crate::partials;
+use crate::paths;
+use crate::plugins::{self, Plugin, Visit};
+use crate::positions;
+use crate::project_config;
+use crate::rbs::Signatures;
+use crate::receivers;
+use crate::refactorings;
+use crate::resolver::{self, ScopeStrategy, SymbolFields, SymbolQuery, USAGE_TYPE_RESTRICTIONS};
+use crate::results;
+use crate::ruby_version::{self, RubyVersion, SyntaxFeature, RUBY_VERSION_CODE};
+use crate::stdlib;
+use crate::telemetry;
+use crate::test_runner::{self, TestBlock, TestCommands};
+use crate::warm_up::WarmUpQueue;
 use log::info;
-use phf::phf_map;
 use regex::Regex;
 use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str;
-use tantivy::collector::TopDocs;
-use tantivy::query::{BooleanQuery, BoostQuery, Occur, Query, RegexQuery, TermQuery};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::query::{
+    AllQuery, BooleanQuery, FuzzyTermQuery, Occur, Query, RangeQuery, RegexQuery, TermQuery,
+};
+use tantivy::directory::{Directory, DirectoryLock, Lock, MmapDirectory};
 use tantivy::{schema::*, ReloadPolicy, Document};
-use tantivy::{Index, IndexWriter};
+use tantivy::{Index, IndexWriter, Searcher};
 use tower_lsp::lsp_types::InitializeParams;
 use tower_lsp::lsp_types::{
-    DocumentHighlight, DocumentHighlightKind, Location, Position, Range, SymbolInformation,
-    SymbolKind, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
-};
-use tower_lsp::Client;
-
-static USAGE_TYPE_RESTRICTIONS: phf::Map<&'static str, &[&str]> = phf_map! {
-    "Alias" => &[
-        "Alias", "Def", "Defs",
-        "CSend", "Send", "Super", "ZSuper",
-    ],
-    "Const" => &[
-        "Casgn", "Class", "Module",
-        "Const"
-    ],
-    "CSend" => &[
-        "Alias", "Def", "Defs",
-        "CSend", "Send", "Super", "ZSuper",
-    ],
-    "Cvar" => &[
-        "Cvasgn",
-        "Cvar"
-    ],
-    "Gvar" => &[
-        "Gvasgn",
-        "Gvar"
-    ],
-    "Ivar" => &[
-        "Ivasgn",
-        "Ivar"
-    ],
-    "Lvar" => &[
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg",
-        "Lvar"
-    ],
-    "Send" => &[
-        "Alias", "Def", "Defs",
-        "CSend", "Send", "Super", "ZSuper",
-    ],
-    "Super" => &[
-        "Alias", "Def", "Defs",
-        "CSend", "Send", "Super", "ZSuper",
-    ],
-    "ZSuper" => &[
-        "Alias", "Def", "Defs",
-        "CSend", "Send", "Super", "ZSuper",
-    ],
+    ChangeAnnotation, CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeLens, CompletionItem, CompletionItemKind, CompletionTextEdit, DiagnosticSeverity,
+    DocumentChangeOperation, DocumentChanges, DocumentDiagnosticReport, DocumentHighlight, DocumentHighlightKind, Documentation, FileRename,
+    FullDocumentDiagnosticReport, Hover, HoverContents, InlayHint, InlayHintKind, InlayHintLabel,
+    InlineValue, InlineValueParams, InlineValueVariableLookup, InsertTextFormat, LinkedEditingRanges, Location, LocationLink, MarkupContent, MarkupKind, NumberOrString,
+    OneOf, OptionalVersionedTextDocumentIdentifier, ParameterInformation, ParameterLabel, Position, PreviousResultId, Range, ReferenceParams,
+    RelatedFullDocumentDiagnosticReport, RelatedUnchangedDocumentDiagnosticReport, RenameFile,
+    ResourceOp, ResourceOperationKind, SignatureHelp,
+    SignatureInformation, SymbolInformation, SymbolKind, TextDocumentEdit, TextDocumentIdentifier, TextDocumentPositionParams, TextEdit,
+    UnchangedDocumentDiagnosticReport, Url, WorkspaceDiagnosticReport,
+    WorkspaceDocumentDiagnosticReport, WorkspaceEdit, WorkspaceFullDocumentDiagnosticReport,
+    WorkspaceUnchangedDocumentDiagnosticReport,
 };
 
-static ASSIGNMENT_TYPE_RESTRICTIONS: phf::Map<&'static str, &[&str]> = phf_map! {
-    "Alias" => &[
-        "Alias", "CSend", "Send", "Super", "ZSuper",
-        "Def", "Defs"
-    ],
-    "Arg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Casgn" => &[
-        "Const",
-        "Casgn", "Class", "Module"
-    ],
-    "Class" => &[
-        "Const",
-        "Casgn", "Class", "Module"
-    ],
-    "Cvasgn" => &[
-        "Cvar",
-        "Cvasgn"
-    ],
-    "Def" => &[
-        "Alias", "CSend", "Send", "Super", "ZSuper",
-        "Def"
-    ],
-    "Defs" => &[
-        "Alias", "CSend", "Send", "Super", "ZSuper",
-        "Defs"
-    ],
-    "Gvasgn" => &[
-        "Gvar",
-        "Gvasgn"
-    ],
-    "Ivasgn" => &[
-        "Ivar",
-        "Ivasgn"
-    ],
-    "Kwarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Kwoptarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Kwrestarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Lvasgn" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "MatchVar" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Module" => &[
-        "Const",
-        "Casgn", "Class", "Module"
-    ],
-    "Optarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Restarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-    "Shadowarg" => &[
-        "Lvar",
-        "Arg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
-    ],
-};
+fn escape_snippet(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('$', "\\$")
+        .replace('}', "\\}")
+}
 
-#[derive(Clone)]
-pub struct IndexableDir {
-    path: String,
-    interface_only: bool,
+// Every Ruby file under `root`, and every locale file and template,
+// skipping directories that only hold dependencies or generated files
+fn ruby_file_paths(root: &str, skip_vendor: bool) -> Vec<String> {
+    let walk_dir = WalkDirGeneric::<(usize, bool)>::new(root).process_read_dir(
+        move |_depth, _path, _read_dir_state, children| {
+            children.retain(|dir_entry_result| {
+                dir_entry_result
+                    .as_ref()
+                    .map(|dir_entry| {
+                        if let Some(file_name) = dir_entry.file_name.to_str() {
+                            let ruby_file = file_name.ends_with(".rb");
+                            let yaml_file =
+                                file_name.ends_with(".yml") || file_name.ends_with(".yaml");
+                            let erb_file = file_name.ends_with(".erb");
+                            dir_entry.file_type.is_dir() || ruby_file || yaml_file || erb_file
+                        } else {
+                            false
+                        }
+                    })
+                    .unwrap_or(false)
+            });
+
+            children.iter_mut().for_each(|dir_entry_result| {
+                if let Ok(dir_entry) = dir_entry_result {
+                    if let Some(file_name) = dir_entry.file_name.to_str() {
+                        if is_skipped_dir(file_name, skip_vendor) {
+                            dir_entry.read_children_path = None;
+                        }
+                    }
+                }
+            });
+        },
+    );
+
+    let mut ruby_file_paths = Vec::new();
+
+    for entry in walk_dir {
+        if let Ok(entry) = entry {
+            let path = paths::normalize(&entry.path().to_string_lossy());
+
+            if path.ends_with(".rb") || i18n::is_locale_file(&path) || partials::is_template(&path)
+            {
+                ruby_file_paths.push(path);
+            }
+        }
+    }
+
+    ruby_file_paths
 }
 
-pub struct Persistence {
-    schema: Schema,
-    schema_fields: SchemaFields,
-    index: Option<Index>,
-    workspace_path: String,
-    last_reindex_time: i64,
-    indexed_file_paths: HashSet<String>,
-    process_id: Option<u32>,
-    no_workspace: bool,
-    gems_indexed: bool,
-    include_dirs_indexed: bool,
-    index_interface_only: bool,
-    class_scope: Vec<String>,
-    include_dirs: Vec<IndexableDir>,
-    pub report_diagnostics: bool,
+// Every Ruby, locale, and template file in a workspace folder, leaving out
+// what `.gitignore` and `.git/info/exclude` ignore, like coverage output and
+// vendored code, unless ignored files are indexed too
+fn workspace_ruby_file_paths(root: &str, index_ignored_files: bool) -> Vec<String> {
+    if index_ignored_files {
+        return ruby_file_paths(root, false);
+    }
+
+    let found_paths = Mutex::new(Vec::new());
+    let found_paths_ref = &found_paths;
+
+    WalkBuilder::new(root)
+        .hidden(false)
+        .filter_entry(|entry| {
+            let is_dir = entry.file_type().map_or(false, |file_type| file_type.is_dir());
+
+            entry.depth() == 0
+                || !is_dir
+                || !is_skipped_dir(&entry.file_name().to_string_lossy(), false)
+        })
+        .build_parallel()
+        .run(|| {
+            Box::new(move |entry| {
+                if let Ok(entry) = entry {
+                    let path = paths::normalize(&entry.path().to_string_lossy());
+
+                    if path.ends_with(".rb")
+                        || i18n::is_locale_file(&path)
+                        || partials::is_template(&path)
+                    {
+                        found_paths_ref.lock().unwrap().push(path);
+                    }
+                }
+
+                WalkState::Continue
+            })
+        });
+
+    let mut ruby_file_paths = found_paths.into_inner().unwrap();
+    ruby_file_paths.sort();
+
+    ruby_file_paths
 }
 
-struct SchemaFields {
-    file_path_id: Field,
-    file_path: Field,
-    category_field: Field,
-    fuzzy_ruby_scope_field: Field,
-    class_scope_field: Field,
-    name_field: Field,
-    node_type_field: Field,
-    line_field: Field,
-    start_column_field: Field,
-    end_column_field: Field,
-    columns_field: Field,
-    user_space_field: Field,
+fn is_excluded(path: &str, workspace_path: &str, exclude_globs: &[Regex]) -> bool {
+    match paths::relative_to(path, workspace_path) {
+        Some(relative_path) => {
+            let relative_path = relative_path.trim_start_matches("/");
+
+            exclude_globs
+                .iter()
+                .any(|exclude_glob| exclude_glob.is_match(relative_path))
+        }
+        None => false,
+    }
 }
 
-#[derive(Debug)]
-struct FuzzyNode<'a> {
-    category: &'a str,
-    fuzzy_ruby_scope: Vec<String>,
-    class_scope: Vec<String>,
-    name: String,
-    node_type: &'a str,
-    line: usize,
-    start_column: usize,
-    end_column: usize,
+fn is_skipped_dir(dir_name: &str, skip_vendor: bool) -> bool {
+    dir_name.contains("node_modules")
+        || (skip_vendor && dir_name.contains("vendor"))
+        || dir_name.contains("tmp")
+        || dir_name.contains(".git")
 }
 
-impl Persistence {
-    pub fn new() -> tantivy::Result<Persistence> {
-        let mut schema_builder = Schema::builder();
-        let schema_fields = SchemaFields {
-            file_path_id: schema_builder.add_text_field(
-                "file_path_id",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            file_path: schema_builder.add_text_field(
-                "file_path",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            category_field: schema_builder.add_text_field(
-                "category",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            fuzzy_ruby_scope_field: schema_builder.add_text_field(
-                "fuzzy_ruby_scope",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            class_scope_field: schema_builder.add_text_field(
-                "class_scope",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            name_field: schema_builder.add_text_field(
-                "name",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            node_type_field: schema_builder.add_text_field(
-                "node_type",
-                TextOptions::default()
-                    .set_indexing_options(
-                        TextFieldIndexing::default()
-                            .set_tokenizer("raw")
-                            .set_index_option(IndexRecordOption::Basic),
-                    )
-                    .set_stored(),
-            ),
-            line_field: schema_builder.add_u64_field("line", INDEXED | STORED),
-            start_column_field: schema_builder.add_u64_field("start_column", INDEXED | STORED),
-            end_column_field: schema_builder.add_u64_field("end_column", INDEXED | STORED),
-            columns_field: schema_builder.add_u64_field("columns", INDEXED | STORED),
-            user_space_field: schema_builder.add_bool_field("user_space", INDEXED | STORED),
-        };
+// The name of a method's `&block` parameter
+fn block_param_name(args: &Option<Box<Node>>) -> Option<String> {
+    match args.as_deref() {
+        Some(Node::Args(Args { args, .. })) => args.iter().find_map(|arg| match arg {
+            Node::Blockarg(Blockarg { name, .. }) => name.clone(),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
 
-        let schema = schema_builder.build();
-        let index = None;
-        let workspace_path = "unset".to_string();
-        let last_reindex_time = FileTime::from_unix_time(0, 0).seconds();
-        let indexed_file_paths = HashSet::new();
-        let process_id: Option<u32> = None;
-        let no_workspace = false;
-        let gems_indexed = false;
-        let index_interface_only = false;
-        let class_scope = vec![];
-        let report_diagnostics = true;
-        let include_dirs = Vec::new();
-        let include_dirs_indexed = false;
+// Every short name is within two edits of every other
+fn edit_distance(name: &str) -> u8 {
+    if name.chars().count() > 4 {
+        2
+    } else {
+        1
+    }
+}
 
-        Ok(Self {
-            schema,
-            schema_fields,
-            index,
-            workspace_path,
-            last_reindex_time,
-            indexed_file_paths,
-            process_id,
-            no_workspace,
-            gems_indexed,
-            index_interface_only,
-            class_scope,
-            report_diagnostics,
-            include_dirs,
-            include_dirs_indexed,
-        })
+// The name as typed plus its snake_case and CamelCase spellings, so
+// `userAccount` finds `user_account` and `UserAccount`
+fn name_variants(name: &str) -> Vec<String> {
+    let snake_case = autoload::underscore(name);
+    let camel_case = autoload::camelize(&snake_case);
+
+    let mut variants = vec![name.to_string()];
+
+    for variant in [snake_case, camel_case] {
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
     }
 
-    pub fn initialize(&mut self, params: &InitializeParams) {
-        let uri = params.root_uri.as_ref().unwrap_or_else(|| {
-            info!("root_uri wasn't given to initialize, exiting.");
-            quit::with_code(1);
-        });
+    variants
+}
 
-        self.workspace_path = uri.path().to_string();
+fn read_index_metadata(index_dir: &PathBuf) -> Option<serde_json::Value> {
+    fs::read_to_string(index_dir.join(INDEX_METADATA_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+}
 
-        let default_user_config = json!({});
-        let default_allocation_type = json!("ram");
+// Only one server writes a workspace's on-disk index, the one holding the
+// lock beside it. The lock's released when its process exits, however it
+// exits.
+fn acquire_writer_lock(index_dir: &Path) -> Option<DirectoryLock> {
+    let cache_dir = index_dir.parent()?;
+    let lock_file_name = format!("{}.lock", index_dir.file_name()?.to_string_lossy());
 
-        let user_config = &params
-            .initialization_options
-            .as_ref()
-            .unwrap_or(&default_user_config)
-            .as_object()
-            .unwrap();
-        let allocation_type = user_config
-            .get("allocationType")
-            .unwrap_or(&default_allocation_type)
-            .as_str()
-            .unwrap();
-
-        self.index = match allocation_type {
-            "ram" => Some(Index::create_in_ram(self.schema.clone())),
-            "tempdir" => Some(Index::create_from_tempdir(self.schema.clone()).unwrap()),
-            _ => {
-                info!("Unknown allocation_type, defaulting to tempdir");
-                Some(Index::create_from_tempdir(self.schema.clone()).unwrap())
-            }
-        };
+    fs::create_dir_all(cache_dir).ok()?;
 
-        if let Some(included_dirs) = user_config.get("includeDirs") {
-            if let Some(dirs) = included_dirs.as_array() {
-                let dirs = dirs
-                    .iter()
-                    .map(|v| {
-                        // v.as_str().unwrap().to_string()
-                        let dir_params = v.as_object().unwrap();
-                        let dir_path = dir_params.get("path").unwrap().as_str().unwrap();
-                        let interface_only = {
-                            let param = dir_params.get("interface_only");
-                            match param {
-                                Some(val) => val.as_bool().unwrap(),
-                                None => true,
-                            }
-                        };
+    MmapDirectory::open(cache_dir)
+        .ok()?
+        .acquire_lock(&Lock {
+            filepath: PathBuf::from(lock_file_name),
+            is_blocking: false,
+        })
+        .ok()
+}
 
-                        let dir_path = dir_path.to_string();
-                        let absolute_dir_path = if dir_path.starts_with("/") {
-                            dir_path
-                        } else {
-                            format!("{}/{}", &self.workspace_path, dir_path)
-                        };
+fn remove_index_dir(index_dir: &PathBuf) {
+    if let Err(error) = fs::remove_dir_all(index_dir) {
+        info!("Unable to remove index at {}: {}", index_dir.display(), error);
+    }
+}
 
-                        IndexableDir {
-                            path: absolute_dir_path,
-                            interface_only,
-                        }
-                    })
-                    .collect();
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
 
-                self.include_dirs = dirs;
-            };
-        }
+    for (a_index, a_char) in a.chars().enumerate() {
+        let mut row = vec![a_index + 1];
 
-        let default_index_gems = json!(true);
-        let skip_indexing_gems = !user_config
-            .get("indexGems")
-            .unwrap_or(&default_index_gems)
-            .as_bool()
-            .unwrap();
-        if skip_indexing_gems {
-            self.gems_indexed = true;
+        for (b_index, b_char) in b.iter().enumerate() {
+            let substitution = previous_row[b_index] + if a_char == *b_char { 0 } else { 1 };
+            let insertion = row[b_index] + 1;
+            let deletion = previous_row[b_index + 1] + 1;
+
+            row.push(substitution.min(insertion).min(deletion));
         }
 
-        let default_report_diagnostics = json!(true);
-        let report_diagnostics = user_config
-            .get("reportDiagnostics")
-            .unwrap_or(&default_report_diagnostics)
-            .as_bool()
-            .unwrap();
-        if !report_diagnostics {
-            self.report_diagnostics = false;
+        previous_row = row;
+    }
+
+    previous_row[b.len()]
+}
+
+fn is_comment_directive(text: &str) -> bool {
+    let text = text.trim_start();
+
+    ["frozen_string_literal:", "encoding:", "coding:", "typed:", "rubocop:", "-*-", "!"]
+        .iter()
+        .any(|directive| text.starts_with(directive))
+}
+
+// Renders YARD tags as their own lines so they don't run together:
+//
+//   Finds a user
+//   @param id [Integer] the user's ID
+//
+// becomes "Finds a user\n\n- _@param_ id [Integer] the user's ID"
+fn yard_to_markdown(documentation: &str) -> String {
+    let mut markdown = String::new();
+    let mut previous_line_was_tag = false;
+
+    for line in documentation.lines() {
+        let trimmed_line = line.trim_start();
+
+        if trimmed_line.starts_with("@") {
+            let (tag, description) = trimmed_line
+                .split_once(" ")
+                .unwrap_or((trimmed_line, ""));
+
+            if !previous_line_was_tag && markdown.len() > 0 {
+                markdown.push_str("\n");
+            }
+
+            markdown.push_str(&format!("- _{}_ {}\n", tag, description));
+            previous_line_was_tag = true;
+        } else if previous_line_was_tag && line.starts_with("  ") {
+            // Indented lines continue the tag above them
+            markdown.pop();
+            markdown.push_str(&format!(" {}\n", trimmed_line));
+        } else {
+            if previous_line_was_tag {
+                markdown.push_str("\n");
+            }
+
+            markdown.push_str(line);
+            markdown.push_str("\n");
+            previous_line_was_tag = false;
         }
     }
 
-    pub fn reindex_modified_files(&mut self) -> tantivy::Result<()> {
-        let start_time = FileTime::from_unix_time(FileTime::now().unix_seconds(), 0).seconds() - 1;
-        let last_reindex_time = self.last_reindex_time.clone();
-
-        let walk_dir = WalkDirGeneric::<(usize, bool)>::new(&self.workspace_path).process_read_dir(
-            move |_depth, _path, _read_dir_state, children| {
-                children.retain(|dir_entry_result| {
-                    dir_entry_result
-                        .as_ref()
-                        .map(|dir_entry| {
-                            if let Some(file_name) = dir_entry.file_name.to_str() {
-                                let ruby_file = file_name.ends_with(".rb");
-                                dir_entry.file_type.is_dir() || ruby_file
-                            } else {
-                                false
-                            }
-                        })
-                        .unwrap_or(false)
-                });
+    markdown.trim_end().to_string()
+}
 
-                children.iter_mut().for_each(|dir_entry_result| {
-                    if let Ok(dir_entry) = dir_entry_result {
-                        if let Some(file_name) = dir_entry.file_name.to_str() {
-                            if file_name.contains("node_modules")
-                                || file_name.contains("tmp")
-                                || file_name.contains(".git")
-                            {
-                                dir_entry.read_children_path = None;
-                            }
-                        }
-                    }
-                });
-            },
-        );
+// `sig { params(name: String).returns(User) }`
+fn is_sorbet_sig(node: &Node) -> bool {
+    match node {
+        Node::Block(Block { call, .. }) => matches!(
+            call.as_ref(),
+            Node::Send(Send { recv: None, method_name, .. }) if method_name == "sig"
+        ),
+        _ => false,
+    }
+}
+
+// How a parameter from a `Def` document reads in a method signature, `None`
+// for yielded names
+fn parameter_label(parameter: &str) -> Option<String> {
+    let (kind, name) = parameter.split_once(":")?;
+
+    let label = match kind {
+        "arg" => name.to_string(),
+        "optarg" => format!("{} = …", name),
+        "restarg" => format!("*{}", name),
+        "kwarg" => format!("{}:", name),
+        "kwoptarg" => format!("{}: …", name),
+        "kwrestarg" => format!("**{}", name),
+        "blockarg" => format!("&{}", name),
+        "forwardarg" => "...".to_string(),
+        _ => return None,
+    };
+
+    Some(label)
+}
 
-        let mut new_indexable_file_paths = HashSet::new();
-        let mut indexed_file_paths = HashSet::new();
+// `extend ActiveSupport::Concern` among a module's statements
+fn extends_concern(body: &Option<Box<Node>>) -> bool {
+    let statements: Vec<&Node> = match body.as_deref() {
+        Some(Node::Begin(Begin { statements, .. })) => statements.iter().collect(),
+        Some(node) => vec![node],
+        None => return false,
+    };
+
+    statements.into_iter().any(|statement| match statement {
+        Node::Send(Send {
+            recv: None,
+            method_name,
+            args,
+            ..
+        }) if method_name == "extend" => args
+            .iter()
+            .any(|arg| matches!(arg, Node::Const(Const { name, .. }) if name == "Concern")),
+        _ => false,
+    })
+}
 
-        for entry in walk_dir {
-            let path = entry.unwrap().path();
-            let path = path.to_str().unwrap();
-            let ruby_file = path.ends_with(".rb");
+// The concern method a block is passed to, whose body runs in the classes
+// including the concern
+fn concern_block_method(call: &Node) -> Option<&'static str> {
+    match call {
+        Node::Send(Send {
+            recv: None,
+            method_name,
+            ..
+        }) => ["included", "prepended", "class_methods"]
+            .into_iter()
+            .find(|concern_method| method_name == *concern_method),
+        _ => None,
+    }
+}
 
-            if ruby_file {
-                indexed_file_paths.insert(path.to_string());
-                self.indexed_file_paths.remove(path);
+// The name a `:symbol` or `"string"` argument gives a method
+fn method_name_argument(node: &Node) -> Option<(String, &Loc)> {
+    match node {
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => Some((name.to_string_lossy(), expression_l)),
+        Node::Str(Str {
+            value,
+            expression_l,
+            ..
+        }) => Some((value.to_string_lossy(), expression_l)),
+        _ => None,
+    }
+}
 
-                let metadata = fs::metadata(path).unwrap();
+// The keywords of a call's keyword arguments, `name` in `create(name: n)`.
+// Quoted keys and `:name =>` keys aren't written like parameters.
+fn keyword_arguments(args: &[Node]) -> Vec<(String, &Loc)> {
+    let mut keywords = vec![];
 
-                let mtime = FileTime::from_last_modification_time(&metadata);
-                let recently_modified = mtime.seconds() >= last_reindex_time;
+    for arg in args {
+        let pairs = match arg {
+            Node::Kwargs(Kwargs { pairs, .. }) => pairs,
+            _ => continue,
+        };
 
-                if recently_modified {
-                    new_indexable_file_paths.insert(path.to_string());
+        for pair in pairs {
+            if let Node::Pair(Pair { key, .. }) = pair {
+                if let Node::Sym(Sym {
+                    name,
+                    begin_l: None,
+                    expression_l,
+                    ..
+                }) = key.as_ref()
+                {
+                    keywords.push((name.to_string_lossy(), expression_l));
                 }
             }
         }
+    }
 
-        if let Some(index) = &self.index {
-            let files_added = new_indexable_file_paths.len() > 0;
-            let files_deleted = self.indexed_file_paths.len() > 0;
+    keywords
+}
 
-            if files_added || files_deleted {
-                let mut index_writer = index.writer(256_000_000).unwrap();
+// The methods a delegation defines, with the receiver they forward to:
+//
+//   def_delegator :@items, :size, :count        count, to @items
+//   def_delegators :@items, :first, :last       first and last, to @items
+//   delegate :name, to: :user, prefix: true     user_name, to user
+//   delegate [:push, :pop] => :@stack           push and pop, to @stack
+fn delegated_methods<'a>(method_name: &str, args: &'a [Node]) -> Vec<(String, &'a Loc, String)> {
+    let mut delegated = vec![];
+
+    match method_name {
+        "def_delegator" | "def_instance_delegator" => {
+            let target = match args.first().and_then(method_name_argument) {
+                Some((target, _)) => target,
+                None => return delegated,
+            };
 
-                for path in &self.indexed_file_paths {
-                    let relative_path = path.replace(&self.workspace_path, "");
+            // The optional third argument names the method it's defined as
+            if let Some((name, loc)) = args.get(2).or(args.get(1)).and_then(method_name_argument) {
+                delegated.push((name, loc, target));
+            }
+        }
+        "def_delegators" | "def_instance_delegators" => {
+            let target = match args.first().and_then(method_name_argument) {
+                Some((target, _)) => target,
+                None => return delegated,
+            };
 
-                    let file_path_id = blake3::hash(&relative_path.as_bytes());
-                    let path_term = Term::from_field_text(
-                        self.schema_fields.file_path_id,
-                        &file_path_id.to_string(),
-                    );
+            for (name, loc) in args.iter().skip(1).filter_map(method_name_argument) {
+                delegated.push((name, loc, target.clone()));
+            }
+        }
+        "delegate" => {
+            let pairs = match args.last() {
+                Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs,
+                _ => return delegated,
+            };
 
-                    index_writer.delete_term(path_term);
-                }
+            let option = |option_name: &str| {
+                pairs.iter().find_map(|pair| match pair {
+                    Node::Pair(Pair { key, value, .. }) => match method_name_argument(key) {
+                        Some((key_name, _)) if key_name == option_name => Some(value.as_ref()),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+            };
 
-                for path in &new_indexable_file_paths {
-                    let text = fs::read_to_string(&path).unwrap();
-                    let uri = Url::from_file_path(&path).unwrap();
-                    let relative_path = uri.path().replace(&self.workspace_path, "");
+            // Rails, `to:` with the methods before it
+            if let Some((target, _)) = option("to").and_then(method_name_argument) {
+                let prefix = match option("prefix") {
+                    Some(Node::True(_)) => format!("{}_", target.trim_start_matches('@')),
+                    Some(node) => match method_name_argument(node) {
+                        Some((prefix, _)) => format!("{}_", prefix),
+                        None => String::new(),
+                    },
+                    None => String::new(),
+                };
 
-                    self.reindex_modified_file_without_commit(
-                        &text,
-                        relative_path,
-                        &index_writer,
-                        true,
-                    );
+                for node in &args[..args.len() - 1] {
+                    if let Some((name, loc)) = method_name_argument(node) {
+                        delegated.push((format!("{}{}", prefix, name), loc, target.clone()));
+                    }
                 }
 
-                index_writer.commit().unwrap();
-                info!("Indexing workspace complete!");
-            } else {
-                info!("No file changes, skipping periodic reindexing.")
+                return delegated;
             }
-        }
 
-        self.last_reindex_time = start_time;
-        self.indexed_file_paths = indexed_file_paths;
+            // Forwardable, methods mapped to their receivers
+            for pair in pairs {
+                if let Node::Pair(Pair { key, value, .. }) = pair {
+                    let target = match method_name_argument(value) {
+                        Some((target, _)) => target,
+                        None => continue,
+                    };
 
-        Ok(())
-    }
+                    let names: Vec<&Node> = match key.as_ref() {
+                        Node::Array(Array { elements, .. }) => elements.iter().collect(),
+                        node => vec![node],
+                    };
 
-    pub fn index_included_dirs_once(&mut self) -> tantivy::Result<()> {
-        if self.include_dirs_indexed {
-            return Ok(());
+                    for (name, loc) in names.into_iter().filter_map(method_name_argument) {
+                        delegated.push((name, loc, target.clone()));
+                    }
+                }
+            }
         }
+        _ => {}
+    }
 
-        self.index_interface_only = true;
+    delegated
+}
 
-        if self.include_dirs.len() > 0 {
-            let index = match &self.index {
-                Some(index) => index,
-                None => {
-                    info!("missing index");
-                    quit::with_code(1);
+// The method whose argument list the line ends inside of, and which argument
+// the cursor is on: `user.update(name, ro` is `update`'s second argument
+fn open_call(line_prefix: &str) -> Option<(String, u32)> {
+    let chars: Vec<char> = line_prefix.chars().collect();
+    let mut depth = 0;
+    let mut argument_index = 0;
+
+    for (index, char) in chars.iter().enumerate().rev() {
+        match char {
+            ')' | ']' | '}' => depth += 1,
+            '(' if depth == 0 => {
+                let name_length = chars[..index]
+                    .iter()
+                    .rev()
+                    .take_while(|char| char.is_alphanumeric() || ['_', '?', '!'].contains(char))
+                    .count();
+                let name: String = chars[index - name_length..index].iter().collect();
+
+                if name.len() == 0 {
+                    return None;
                 }
-            };
 
-            let mut index_writer = index.writer(256_000_000).unwrap();
-
-            for indexable_dir in self.include_dirs.clone() {
-                let walk_dir = WalkDirGeneric::<(usize, bool)>::new(indexable_dir.path.clone())
-                    .process_read_dir(move |_depth, _path, _read_dir_state, children| {
-                        children.retain(|dir_entry_result| {
-                            dir_entry_result
-                                .as_ref()
-                                .map(|dir_entry| {
-                                    if let Some(file_name) = dir_entry.file_name.to_str() {
-                                        let ruby_file = file_name.ends_with(".rb");
-                                        dir_entry.file_type.is_dir() || ruby_file
-                                    } else {
-                                        false
-                                    }
-                                })
-                                .unwrap_or(false)
-                        });
+                return Some((name, argument_index));
+            }
+            '[' | '{' if depth == 0 => return None,
+            '(' | '[' | '{' => depth -= 1,
+            ',' if depth == 0 => argument_index += 1,
+            _ => {}
+        }
+    }
 
-                        children.iter_mut().for_each(|dir_entry_result| {
-                            if let Ok(dir_entry) = dir_entry_result {
-                                if let Some(file_name) = dir_entry.file_name.to_str() {
-                                    if file_name.contains("node_modules")
-                                        || file_name.contains("vendor")
-                                        || file_name.contains("tmp")
-                                        || file_name.contains(".git")
-                                    {
-                                        dir_entry.read_children_path = None;
-                                    }
-                                }
-                            }
-                        });
-                    });
+    None
+}
 
-                let mut indexable_file_paths = Vec::new();
+static INDEX_METADATA_FILE: &str = "fuzzy_ruby_server.json";
+
+// Methods that set `$~`, and with it `$1`, `$&`, and the other match globals
+static REGEXP_MATCH_METHODS: &[&str] = &[
+    "=~", "!~", "match", "scan", "sub", "sub!", "gsub", "gsub!",
+];
+
+static BYTES_PER_MB: u64 = 1_000_000;
+
+// tantivy's smallest per-thread writer arena is 15MB, a little is left over
+// for its bookkeeping
+static MIN_WRITER_MEMORY: usize = 20_000_000;
+
+// Bumped when what's indexed for a file changes without the schema changing,
+// so indexes kept on disk by older versions are rebuilt
+static INDEX_VERSION: u32 = 10;
+
+// Providers reporting diagnostics unless a project picks its own,
+// `privateCalls` is opt-in
+static DIAGNOSTIC_PROVIDERS: &[&str] = &["syntax", "duplicates"];
+static DUPLICATE_METHOD_CODE: &str = "duplicate-method";
+static PRIVATE_CALL_CODE: &str = "private-call";
+static STALE_DOCUMENTS_CODE: &str = "stale-documents";
+
+// Lines above and below a definition in `fuzzy/peekDefinition` snippets
+static PEEK_CONTEXT_LINES: usize = 5;
+// Open files whose parses are kept between requests, and the most text
+// they're kept for
+static PARSE_CACHE_FILES: usize = 32;
+static PARSE_CACHE_BYTES: usize = 16_000_000;
+static MAX_PEEK_CONTEXT_LINES: usize = 50;
+
+// Variables a debugger is asked to show the values of inline
+static INLINE_VALUE_TYPES: &[&str] = &["Ivar", "Ivasgn", "Lvar", "Lvasgn"];
+
+// Symbols whose references are found by name across the workspace
+static NAMED_SYMBOL_TYPES: &[&str] = &["Factory", "Partial", "Trait"];
+
+// Renamed across the workspace, wherever they're defined or used
+static CONSTANT_TYPES: &[&str] = &["Class", "Module", "Const"];
+
+// Moving a class's file when renaming it is left for the user to confirm
+static MOVE_FILE_ANNOTATION: &str = "move-file";
+
+static RUBY_KEYWORDS: &[&str] = &[
+    "BEGIN", "END", "__ENCODING__", "__END__", "__FILE__", "__LINE__", "alias", "and", "begin",
+    "break", "case", "class", "def", "defined?", "do", "else", "elsif", "end", "ensure", "false",
+    "for", "if", "in", "module", "next", "nil", "not", "or", "redo", "rescue", "retry", "return",
+    "self", "super", "then", "true", "undef", "unless", "until", "when", "while", "yield",
+];
+
+#[derive(Clone)]
+pub struct IndexableDir {
+    path: String,
+    interface_only: bool,
+}
+
+pub struct Persistence {
+    schema: Schema,
+    schema_fields: SchemaFields,
+    index: Option<Index>,
+    // Where the index is kept, `ram`, `tempdir`, or `disk`
+    allocation_type: String,
+    workspace_paths: Vec<String>,
+    last_reindex_time: i64,
+    // Path to the file_path_id it was indexed with
+    indexed_file_paths: HashMap<String, String>,
+    // Path to the blake3 hash of the contents it was indexed with
+    indexed_file_hashes: HashMap<String, String>,
+    // Workspace folder to the git revision it was last walked at
+    indexed_revisions: HashMap<String, String>,
+    // Files that differed from that revision when they were last walked, so
+    // they're indexed again even once they match it, like after a stash
+    dirty_file_paths: HashSet<String>,
+    // Files opened or edited this session, which may have been indexed with
+    // text that's in neither the revision nor the working tree
+    edited_file_paths: HashSet<String>,
+    // Files whose current text doesn't parse, their documents are from the
+    // last version that did. Marked by the indexer while holding the read
+    // lock.
+    stale_file_path_ids: Mutex<HashSet<String>>,
+    // Each time a file is indexed its documents get a new generation, and
+    // the ones from earlier generations are deleted. Generations only
+    // increase, across sessions too, so they count up from when the server
+    // started.
+    next_generation: AtomicU64,
+    // File path ID to the generation it was last indexed with, until the
+    // writer commits
+    pending_generations: Mutex<HashMap<String, u64>>,
+    // File path ID to its latest committed generation. Lookups skip
+    // documents from earlier ones that haven't been deleted yet.
+    generations: Mutex<HashMap<String, u64>>,
+    // The first walk of a session only looks at files git says changed
+    workspace_walked: bool,
+    process_id: Option<u32>,
+    no_workspace: bool,
+    gems_indexed: bool,
+    // Whether Ruby's core classes are indexed from the `rbs` gem's
+    // signatures, along with the gems
+    index_stdlib: bool,
+    include_dirs_indexed: bool,
+    index_interface_only: bool,
+    // Whether calls' arguments get hints, only parses for inlay hints need
+    // them
+    argument_hints: bool,
+    class_scope: Vec<String>,
+    // Inside `class << self`, where `def` defines class methods
+    singleton_class: bool,
+    // Inside a module extending `ActiveSupport::Concern`
+    concern: bool,
+    // How many conditions and blocks the node being serialized is in
+    conditional_depth: usize,
+    // Framework plugins, visiting what's serialized
+    plugins: Vec<Box<dyn Plugin>>,
+    // The project's Ruby, syntax newer than it is reported
+    ruby_version: Option<RubyVersion>,
+    // Reported for the file being serialized
+    newer_syntax_diagnostics: Vec<tower_lsp::lsp_types::Diagnostic>,
+    // Warnings from plugins, reported for the file being serialized
+    plugin_diagnostics: Vec<tower_lsp::lsp_types::Diagnostic>,
+    method_yields: Vec<Option<Vec<String>>>,
+    // What `yield` calls in each method being serialized, its `&block`
+    // parameter or, when the block is implicit, the method itself
+    yield_targets: Vec<String>,
+    // The last regexp match in the method being serialized, which `$1` and
+    // the other match globals read
+    last_regexp_match: Option<String>,
+    // The classes of the locals in the method being serialized, by name,
+    // None for a local assigned anything else too. None outside methods.
+    local_classes: Option<HashMap<String, Option<Vec<String>>>>,
+    numblock_calls: Vec<String>,
+    // A Sorbet `sig` waiting for the method definition that follows it
+    pending_signature: Option<String>,
+    // The comment above a `sig`, which documents the method after it
+    pending_documentation: Option<String>,
+    // Comments on lines of their own, by line, for documenting the
+    // definitions below them
+    comments: HashMap<usize, String>,
+    open_files: HashMap<Url, String>,
+    // Path to the file_path_id of each open file, resolved when it's opened
+    open_file_path_ids: HashMap<String, String>,
+    // Behind a lock so requests reading the file share it
+    parse_cache: Mutex<ParseCache<ParsedFile>>,
+    // The file last opened or edited, which results from are ranked first
+    // when a request doesn't name one
+    current_file: Option<Url>,
+    include_dirs: Vec<IndexableDir>,
+    force_rename: bool,
+    resolve_symlinks: bool,
+    // Index files git ignores, which are left out by default
+    index_ignored_files: bool,
+    // Workspace files matching these globs aren't indexed
+    exclude_globs: Vec<Regex>,
+    // Overrides detecting Rails apps from `config/application.rb`
+    rails_mode: Option<bool>,
+    diagnostic_providers: Vec<String>,
+    // Formats documents, reading the text on stdin and writing it to stdout
+    pub format_command: Option<String>,
+    // Index constants named in heredoc text, like SQL or templates
+    index_heredoc_constants: bool,
+    // Index the symbols of the project's own DSLs
+    dsl_rules: Vec<DslRule>,
+    // Match misspelled names in symbol search and goto definition
+    fuzzy_search: bool,
+    // Offer similarly named definitions when goto definition finds none
+    pub suggest_definitions: bool,
+    // Replaces the detected command for running tests from code lenses
+    test_command_template: Option<String>,
+    // The most definitions and implementations a lookup finds
+    definition_limit: usize,
+    // The most references and workspace symbols a search finds
+    reference_limit: usize,
+    // How long a lookup searches before answering with what it's found
+    pub query_time_budget: Option<Duration>,
+    // Where telemetry events go, None unless it's turned on
+    pub telemetry: Option<telemetry::Sink>,
+    // Files larger than this aren't indexed
+    max_file_size: u64,
+    // Files larger than this, usually generated ones like `db/schema.rb`,
+    // only have their definitions indexed
+    stub_file_size: u64,
+    // Shared by the threads of a writer
+    index_memory_budget: usize,
+    // How writers merge the segments edits commit
+    pub merge_settings: MergeSettings,
+    // Set when the index is kept on disk between sessions
+    index_dir: Option<PathBuf>,
+    // Held while this server writes the on-disk index. Other editor windows
+    // on the workspace read it without one.
+    writer_lock: Option<DirectoryLock>,
+    pub report_diagnostics: bool,
+    // The client asks for diagnostics with `textDocument/diagnostic` and
+    // `workspace/diagnostic`, rather than having them published
+    pub pull_diagnostics: bool,
+    // The client takes `LocationLink`s for definitions, with the usage
+    // they're found for
+    pub definition_link_support: bool,
+    // The client applies workspace edits that rename files
+    pub rename_file_support: bool,
+    // And has the user confirm the changes marked as needing it
+    pub change_annotation_support: bool,
+    // Completions insert placeholders for required arguments, otherwise
+    // just the name
+    pub snippet_support: bool,
+    // Hovers are Markdown with Ruby code blocks, otherwise plain text
+    pub markdown_hover_support: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct FuzzyNode<'a> {
+    category: &'a str,
+    fuzzy_ruby_scope: Vec<String>,
+    class_scope: Vec<String>,
+    name: String,
+    node_type: &'a str,
+    line: usize,
+    start_column: usize,
+    end_column: usize,
+    // Def/Defs: "kind:name" for each parameter, then "yield:name" for each
+    // value yielded. Hints: the argument position at the call site.
+    // Requires: `require` or `require_relative`.
+    parameters: Vec<String>,
+    // Def/Defs: the Sorbet `sig` or RBS method type
+    signature: Option<String>,
+    // Def/Defs/Class/Module: the comment block above the definition
+    documentation: Option<String>,
+    // Def: "public", "private", or "protected", when defined in a class or
+    // module body
+    visibility: Option<&'static str>,
+    // Send/CSend usages: what the method is called on, see `receivers`
+    receiver: Option<String>,
+    // Def/Defs: defined under a condition or in a block, so it may not run
+    // alongside the other definitions in its scope
+    conditional: bool,
+}
+
+pub struct WorkspaceWalk {
+    workspace_paths: Vec<String>,
+    indexed_file_paths: HashMap<String, String>,
+    indexed_file_hashes: HashMap<String, String>,
+    // Revisions to ask git for changes since, empty for a full walk
+    git_revisions: HashMap<String, String>,
+    // Changed along with what git reports
+    dirty_file_paths: HashSet<String>,
+    last_reindex_time: i64,
+    resolve_symlinks: bool,
+    index_ignored_files: bool,
+    exclude_globs: Vec<Regex>,
+}
+
+impl WorkspaceWalk {
+    // Finds files that were added, modified, or deleted since the last walk
+    pub fn changes(mut self) -> WorkspaceChanges {
+        let start_time = FileTime::from_unix_time(FileTime::now().unix_seconds(), 0).seconds() - 1;
+
+        let mut modified_file_paths = Vec::new();
+        let mut indexed_file_paths = HashMap::new();
+        let mut indexed_file_hashes = HashMap::new();
+        let mut indexed_file_path_ids = HashSet::new();
+        let mut revisions = HashMap::new();
+        let mut dirty_file_paths = HashSet::new();
+
+        for workspace_path in &self.workspace_paths {
+            let head_revision = git::head_revision(workspace_path);
+
+            let git_changed_file_paths = self
+                .git_revisions
+                .get(workspace_path)
+                .and_then(|revision| {
+                    git::changed_file_paths(workspace_path, revision, self.index_ignored_files)
+                });
+
+            // What differs from HEAD now, which is what's changed since the
+            // last walk when that was at HEAD too
+            if let Some(revision) = head_revision {
+                let dirty = match &git_changed_file_paths {
+                    Some(changed_file_paths)
+                        if self.git_revisions.get(workspace_path) == Some(&revision) =>
+                    {
+                        Some(changed_file_paths.clone())
+                    }
+                    _ => {
+                        git::changed_file_paths(workspace_path, &revision, self.index_ignored_files)
+                    }
+                };
+
+                dirty_file_paths.extend(dirty.unwrap_or_default());
+                revisions.insert(workspace_path.clone(), revision);
+            }
+
+            // Files that were dirty last time may have been put back the way
+            // they were at the revision since
+            let git_changed_file_paths = git_changed_file_paths.map(|mut changed_file_paths| {
+                changed_file_paths.extend(
+                    self.dirty_file_paths
+                        .iter()
+                        .filter(|path| paths::relative_to(path, workspace_path).is_some())
+                        .cloned(),
+                );
+
+                changed_file_paths
+            });
+
+            let walked_file_paths: Vec<String> = match git_changed_file_paths {
+                Some(changed_file_paths) => {
+                    // Everything else is as it was when it was last indexed.
+                    // Files that were open at shutdown weren't recorded, the
+                    // next full walk picks them up.
+                    let unchanged_file_paths: Vec<String> = self
+                        .indexed_file_paths
+                        .keys()
+                        .filter(|path| {
+                            paths::relative_to(path, workspace_path).is_some()
+                                && !changed_file_paths.contains(*path)
+                        })
+                        .cloned()
+                        .collect();
+
+                    for path in unchanged_file_paths {
+                        let file_path_id = self.indexed_file_paths.remove(&path).unwrap();
+
+                        if let Some(indexed_hash) = self.indexed_file_hashes.remove(&path) {
+                            indexed_file_hashes.insert(path.clone(), indexed_hash);
+                        }
+
+                        indexed_file_path_ids.insert(file_path_id.clone());
+                        indexed_file_paths.insert(path, file_path_id);
+                    }
+
+                    // Deleted files are left for the walk to find missing
+                    changed_file_paths
+                        .into_iter()
+                        .filter(|path| fs::metadata(path).is_ok())
+                        .collect()
+                }
+                None => workspace_ruby_file_paths(workspace_path, self.index_ignored_files),
+            };
+
+            for path in walked_file_paths {
+                // Left for the walk to find missing, so excluding a file
+                // removes what was indexed from it
+                if is_excluded(&path, workspace_path, &self.exclude_globs) {
+                    continue;
+                }
+
+                // Files that weren't seen by the last walk, such as those in a
+                // newly added workspace folder, are indexed regardless of mtime
+                let indexed_file_path_id = self.indexed_file_paths.remove(&path);
+                let newly_seen = indexed_file_path_id.is_none();
+                let indexed_hash = self.indexed_file_hashes.remove(&path);
+                // A path's resolved once, when it's first seen
+                let file_path_id = indexed_file_path_id
+                    .unwrap_or_else(|| paths::file_path_id(&path, self.resolve_symlinks));
+
+                // Only index the first path found to a symlinked file
+                if !indexed_file_path_ids.insert(file_path_id.clone()) {
+                    continue;
+                }
+
+                let recently_modified = match fs::metadata(&path) {
+                    Ok(metadata) => {
+                        let mtime = FileTime::from_last_modification_time(&metadata);
+                        mtime.seconds() >= self.last_reindex_time
+                    }
+                    Err(_) => continue,
+                };
+
+                let content_hash = if recently_modified || newly_seen {
+                    let content_hash = fs::read(&path)
+                        .ok()
+                        .map(|contents| blake3::hash(&contents).to_hex().to_string());
+
+                    // Touching a file or checking out a branch with the same
+                    // contents changes its mtime, but there's nothing new
+                    // to index
+                    let unchanged =
+                        !newly_seen && content_hash.is_some() && content_hash == indexed_hash;
+
+                    if !unchanged {
+                        modified_file_paths.push(path.clone());
+                    }
+
+                    content_hash
+                } else {
+                    indexed_hash
+                };
+
+                if let Some(content_hash) = content_hash {
+                    indexed_file_hashes.insert(path.clone(), content_hash);
+                }
+
+                indexed_file_paths.insert(path, file_path_id);
+            }
+        }
+
+        WorkspaceChanges {
+            modified_file_paths,
+            // Whatever wasn't walked again has been deleted
+            deleted_file_path_ids: self.indexed_file_paths.into_values().collect(),
+            workspace_paths: self.workspace_paths,
+            indexed_file_paths,
+            indexed_file_hashes,
+            revisions,
+            dirty_file_paths,
+            start_time,
+        }
+    }
+}
+
+// What's needed to find the include dirs' and gems' files
+pub struct DependencyWalk {
+    workspace_path: Option<String>,
+    include_dirs: Vec<IndexableDir>,
+    index_stdlib: bool,
+}
+
+impl DependencyWalk {
+    // Ruby files in the `includeDirs` setting, paired with whether only their
+    // interface should be indexed
+    pub fn included_file_paths(&self) -> Vec<(String, bool)> {
+        let mut included_file_paths = Vec::new();
+
+        for indexable_dir in &self.include_dirs {
+            for path in ruby_file_paths(&indexable_dir.path, true) {
+                included_file_paths.push((path, indexable_dir.interface_only));
+            }
+        }
+
+        included_file_paths
+    }
+
+    // Ruby's own source and every gem in the first workspace folder's
+    // Gemfile.lock
+    pub fn gem_file_paths(&self) -> Vec<String> {
+        let workspace_path = match &self.workspace_path {
+            Some(workspace_path) => workspace_path,
+            None => return Vec::new(),
+        };
+
+        // Four leading spaces dictates that it's a gem version
+        // https://github.com/rubygems/bundler/blob/v2.1.4/lib/bundler/lockfile_parser.rb#L174-L181
+        let gem_version = Regex::new(r"^\s{4}([a-zA-Z\d\.\-_]+)\s\(([\d\w\.\-_]+)\)").unwrap();
+        // Gems are resolved from the first workspace folder's bundle
+        let gemfile_path = format!("{}/{}", workspace_path, "Gemfile.lock");
+
+        let gemfile_contents = match fs::read_to_string(gemfile_path) {
+            Ok(gemfile_contents) => gemfile_contents,
+            Err(_) => {
+                info!("Gemfile not found, skipping indexing workspace gems.");
+                return Vec::new();
+            }
+        };
+
+        let mut gem_paths = vec![];
+        let mut base_gem_path = "unset";
+
+        let gem_home_path_result = Command::new("sh")
+            .arg("-c")
+            // .arg(format!("eval \"$(/usr/local/bin/rbenv init -)\" && cd {} && gem environment home", workspace_path))
+            .arg(format!("cd {} && gem environment home", workspace_path))
+            .output();
+
+        if let Ok(gem_home_path) = gem_home_path_result {
+            if let Ok(gem_home_path) = str::from_utf8(gem_home_path.stdout.as_slice()) {
+                base_gem_path = gem_home_path;
+            }
+
+            // Index Ruby
+            let ruby_source_path = base_gem_path.replace("gems/", "").replace("\n", "");
+
+            info!("Added Ruby source path: {}", ruby_source_path);
+            gem_paths.push(ruby_source_path);
+
+            // Index Gems
+            for line in gemfile_contents.lines() {
+                if let Some(captures) = gem_version.captures(line) {
+                    let name = captures[1].to_string();
+                    let version = captures[2].to_string();
+                    let gem_folder_name = format!("{}/gems/{}-{}", base_gem_path, name, version);
+                    // Not 100% sure where this newline is coming from. `gemfile_contents.lines()` I think.
+                    let gem_folder_name = gem_folder_name.replace("\n", "");
+
+                    info!("gem folder name: {}", gem_folder_name);
+
+                    gem_paths.push(gem_folder_name)
+                }
+            }
+        }
+
+        gem_paths
+            .iter()
+            .flat_map(|gem_path| ruby_file_paths(gem_path, true))
+            .collect()
+    }
+
+    // Stubs for Ruby's core classes, from the workspace Ruby's `rbs` gem
+    pub fn stdlib_file_paths(&self) -> Vec<String> {
+        let workspace_path = match &self.workspace_path {
+            Some(workspace_path) if self.index_stdlib => workspace_path,
+            _ => return Vec::new(),
+        };
+
+        let rbs_gem_path = match stdlib::rbs_gem_path(workspace_path) {
+            Some(rbs_gem_path) => rbs_gem_path,
+            None => return Vec::new(),
+        };
+
+        stdlib::core_stub_paths(&rbs_gem_path).unwrap_or_else(|error| {
+            info!("Unable to unpack Ruby's core documentation: {}", error);
+            Vec::new()
+        })
+    }
+}
+
+// Files found by a workspace walk that need to be indexed or removed
+pub struct WorkspaceChanges {
+    pub modified_file_paths: Vec<String>,
+    pub deleted_file_path_ids: Vec<String>,
+    workspace_paths: Vec<String>,
+    indexed_file_paths: HashMap<String, String>,
+    indexed_file_hashes: HashMap<String, String>,
+    revisions: HashMap<String, String>,
+    dirty_file_paths: HashSet<String>,
+    start_time: i64,
+}
+
+impl WorkspaceChanges {
+    pub fn is_empty(&self) -> bool {
+        self.modified_file_paths.is_empty() && self.deleted_file_path_ids.is_empty()
+    }
+
+    // The modified files, in the order the indexer takes them
+    pub fn warm_up_queue(&self) -> WarmUpQueue {
+        let mut queue = WarmUpQueue::new(self.start_time);
+
+        for path in &self.modified_file_paths {
+            let relative_path = self
+                .workspace_paths
+                .iter()
+                .filter_map(|workspace_path| paths::relative_to(path, workspace_path))
+                .min_by_key(|relative_path| relative_path.len())
+                .unwrap_or(path.as_str())
+                .trim_start_matches('/');
+            let modified = fs::metadata(path)
+                .ok()
+                .map(|metadata| FileTime::from_last_modification_time(&metadata).seconds());
+
+            queue.push(path.clone(), relative_path, modified);
+        }
+
+        queue
+    }
+}
+
+// Definitions named like a usage that has none of its own
+pub struct DefinitionSuggestions {
+    pub name: String,
+    pub suggestions: Vec<(String, Location)>,
+}
+
+// A definition found for the usage at a position. Exact ones are in the
+// usage's scope or one around it, the class it's called in or one it
+// inherits from, or are looked up by a name that's unique to them. The
+// rest are guesses by name.
+pub struct DefinitionLink {
+    pub location: Location,
+    // The usage, for editors to underline
+    pub origin_selection_range: Option<Range>,
+    pub exact: bool,
+}
+
+impl DefinitionLink {
+    pub fn location_link(&self) -> LocationLink {
+        LocationLink {
+            origin_selection_range: self.origin_selection_range,
+            target_uri: self.location.uri.clone(),
+            target_range: self.location.range,
+            target_selection_range: self.location.range,
+        }
+    }
+
+    // Exact matches apart from guesses by name, for editors to list in
+    // sections:
+    //
+    //   { "exact": [LocationLink], "fuzzy": [LocationLink] }
+    pub fn sections(links: &[DefinitionLink]) -> serde_json::Value {
+        let (exact, fuzzy): (Vec<&DefinitionLink>, Vec<&DefinitionLink>) =
+            links.iter().partition(|link| link.exact);
+        let location_links = |links: Vec<&DefinitionLink>| -> Vec<LocationLink> {
+            links.iter().map(|link| link.location_link()).collect()
+        };
+
+        json!({
+            "exact": location_links(exact),
+            "fuzzy": location_links(fuzzy),
+        })
+    }
+}
+
+// A position in a `fuzzy/batch` request, and what's asked about it
+pub struct BatchQuery {
+    pub position: TextDocumentPositionParams,
+    pub definition: bool,
+    pub highlights: bool,
+    pub hover: bool,
+}
+
+// The definitions for the usage at a position, each with whether it's an
+// exact match
+#[derive(Default)]
+struct DefinitionMatches {
+    origin_selection_range: Option<Range>,
+    documents: Vec<(Document, bool)>,
+}
+
+pub struct ParsedFile {
+    documents: Vec<FuzzyNode<'static>>,
+    pub diagnostics: Vec<tower_lsp::lsp_types::Diagnostic>,
+    // False when the parser couldn't produce an AST
+    pub parsed: bool,
+}
+
+impl FuzzyNode<'_> {
+    fn into_symbol(self, file_symbol: &IndexedSymbol) -> IndexedSymbol {
+        IndexedSymbol {
+            category: self.category.to_string(),
+            fuzzy_ruby_scope: self.fuzzy_ruby_scope,
+            class_scope: self.class_scope,
+            name: self.name,
+            node_type: self.node_type.to_string(),
+            line: self.line as u32,
+            start_column: self.start_column as u32,
+            end_column: self.end_column as u32,
+            parameters: self.parameters,
+            signature: self.signature,
+            documentation: self.documentation,
+            visibility: self.visibility.map(String::from),
+            receiver: self.receiver,
+            ..file_symbol.clone()
+        }
+    }
+}
+
+impl ParsedFile {
+    // Every extracted node, hints and tests included, for `fuzzy/debugAst`
+    // and the serializer's golden tests
+    pub fn debug_nodes(&self) -> Vec<serde_json::Value> {
+        self.documents
+            .iter()
+            .map(|document| {
+                json!({
+                    "category": document.category,
+                    "fuzzyRubyScope": document.fuzzy_ruby_scope,
+                    "classScope": document.class_scope,
+                    "name": document.name,
+                    "nodeType": document.node_type,
+                    "line": document.line,
+                    "startColumn": document.start_column,
+                    "endColumn": document.end_column,
+                    "parameters": document.parameters,
+                    "visibility": document.visibility,
+                    "receiver": document.receiver,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Persistence {
+    pub fn new() -> tantivy::Result<Persistence> {
+        let (schema, schema_fields) = indexed_symbol::schema().clone();
+        let index = None;
+        let allocation_type = "ram".to_string();
+        let workspace_paths = Vec::new();
+        let last_reindex_time = FileTime::from_unix_time(0, 0).seconds();
+        let indexed_file_paths = HashMap::new();
+        let indexed_file_hashes = HashMap::new();
+        let stale_file_path_ids = Mutex::new(HashSet::new());
+        let next_generation = AtomicU64::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_micros() as u64),
+        );
+        let pending_generations = Mutex::new(HashMap::new());
+        let generations = Mutex::new(HashMap::new());
+        let indexed_revisions = HashMap::new();
+        let dirty_file_paths = HashSet::new();
+        let edited_file_paths = HashSet::new();
+        let workspace_walked = false;
+        let process_id: Option<u32> = None;
+        let no_workspace = false;
+        let gems_indexed = false;
+        let index_stdlib = true;
+        let index_interface_only = false;
+        let argument_hints = false;
+        let class_scope = vec![];
+        let singleton_class = false;
+        let concern = false;
+        let conditional_depth = 0;
+        let plugins = plugins::enabled();
+        let ruby_version = None;
+        let newer_syntax_diagnostics = vec![];
+        let plugin_diagnostics = vec![];
+        let method_yields = vec![];
+        let yield_targets = vec![];
+        let last_regexp_match = None;
+        let local_classes = None;
+        let numblock_calls = vec![];
+        let pending_signature = None;
+        let pending_documentation = None;
+        let comments = HashMap::new();
+        let open_files = HashMap::new();
+        let open_file_path_ids = HashMap::new();
+        let parse_cache = Mutex::new(ParseCache::new(PARSE_CACHE_FILES, PARSE_CACHE_BYTES));
+        let current_file = None;
+        let report_diagnostics = true;
+        let pull_diagnostics = false;
+        let definition_link_support = false;
+        let rename_file_support = false;
+        let change_annotation_support = false;
+        let snippet_support = false;
+        let markdown_hover_support = false;
+        let force_rename = false;
+        let resolve_symlinks = true;
+        let index_ignored_files = false;
+        let exclude_globs = vec![];
+        let rails_mode = None;
+        let diagnostic_providers = DIAGNOSTIC_PROVIDERS
+            .iter()
+            .map(|provider| provider.to_string())
+            .collect();
+        let format_command = None;
+        let index_heredoc_constants = false;
+        let dsl_rules = vec![];
+        let fuzzy_search = false;
+        let suggest_definitions = false;
+        let test_command_template = None;
+        let definition_limit = 50;
+        let reference_limit = 100;
+        let query_time_budget = Some(Duration::from_millis(2000));
+        let telemetry = None;
+        let max_file_size = 10 * BYTES_PER_MB;
+        let stub_file_size = BYTES_PER_MB;
+        let index_memory_budget = 256_000_000;
+        let merge_settings = MergeSettings::default();
+        let index_dir = None;
+        let writer_lock = None;
+        let include_dirs = Vec::new();
+        let include_dirs_indexed = false;
+
+        Ok(Self {
+            schema,
+            schema_fields,
+            index,
+            allocation_type,
+            workspace_paths,
+            last_reindex_time,
+            indexed_file_paths,
+            indexed_file_hashes,
+            indexed_revisions,
+            dirty_file_paths,
+            edited_file_paths,
+            stale_file_path_ids,
+            next_generation,
+            pending_generations,
+            generations,
+            workspace_walked,
+            process_id,
+            no_workspace,
+            gems_indexed,
+            index_stdlib,
+            index_interface_only,
+            argument_hints,
+            class_scope,
+            singleton_class,
+            concern,
+            conditional_depth,
+            plugins,
+            ruby_version,
+            newer_syntax_diagnostics,
+            plugin_diagnostics,
+            method_yields,
+            yield_targets,
+            last_regexp_match,
+            local_classes,
+            numblock_calls,
+            pending_signature,
+            pending_documentation,
+            comments,
+            open_files,
+            open_file_path_ids,
+            parse_cache,
+            current_file,
+            report_diagnostics,
+            pull_diagnostics,
+            definition_link_support,
+            rename_file_support,
+            change_annotation_support,
+            snippet_support,
+            markdown_hover_support,
+            force_rename,
+            resolve_symlinks,
+            index_ignored_files,
+            exclude_globs,
+            rails_mode,
+            diagnostic_providers,
+            format_command,
+            index_heredoc_constants,
+            dsl_rules,
+            fuzzy_search,
+            suggest_definitions,
+            test_command_template,
+            definition_limit,
+            reference_limit,
+            query_time_budget,
+            telemetry,
+            max_file_size,
+            stub_file_size,
+            index_memory_budget,
+            merge_settings,
+            index_dir,
+            writer_lock,
+            include_dirs,
+            include_dirs_indexed,
+        })
+    }
+
+    pub fn initialize(&mut self, params: &InitializeParams) {
+        if let Some(workspace_folders) = &params.workspace_folders {
+            for workspace_folder in workspace_folders {
+                self.add_workspace_folder(&workspace_folder.uri);
+            }
+        }
+
+        if self.workspace_paths.is_empty() {
+            match &params.root_uri {
+                Some(uri) => self.add_workspace_folder(uri),
+                // A file opened on its own, or an untitled buffer, with only
+                // the open files to index
+                None => info!("No workspace root given, indexing open files only"),
+            }
+        }
+
+        let user_config = &project_config::merge(
+            params.initialization_options.as_ref(),
+            self.workspace_paths
+                .first()
+                .and_then(|workspace_path| project_config::load(workspace_path)),
+        );
+        self.allocation_type = project_config::str_setting(user_config, "allocationType")
+            .unwrap_or("ram")
+            .to_string();
+
+        self.index = Some(self.create_index());
+
+        if let Some(included_dirs) = user_config.get("includeDirs") {
+            if let Some(dirs) = included_dirs.as_array() {
+                let dirs = dirs
+                    .iter()
+                    .filter_map(|v| {
+                        let dir_path = match v.get("path").and_then(|path| path.as_str()) {
+                            Some(dir_path) => dir_path,
+                            None => {
+                                info!("Ignoring include dir without a path: {}", v);
+                                return None;
+                            }
+                        };
+                        let interface_only = v
+                            .get("interface_only")
+                            .and_then(|interface_only| interface_only.as_bool())
+                            .unwrap_or(true);
+
+                        let dir_path = paths::normalize(dir_path);
+                        // Relative ones are skipped without a workspace
+                        let absolute_dir_path = if paths::is_absolute(&dir_path) {
+                            dir_path
+                        } else {
+                            format!("{}/{}", self.workspace_paths.first()?, dir_path)
+                        };
+
+                        Some(IndexableDir {
+                            path: absolute_dir_path,
+                            interface_only,
+                        })
+                    })
+                    .collect();
+
+                self.include_dirs = dirs;
+            };
+        }
+
+        let skip_indexing_gems = !project_config::bool_setting(user_config, "indexGems", true);
+        if skip_indexing_gems {
+            self.gems_indexed = true;
+            self.index_stdlib = false;
+        }
+
+        let report_diagnostics =
+            project_config::bool_setting(user_config, "reportDiagnostics", true);
+        if !report_diagnostics {
+            self.report_diagnostics = false;
+        }
+
+        self.pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .map_or(false, |text_document| text_document.diagnostic.is_some());
+
+        self.definition_link_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.definition.as_ref())
+            .and_then(|definition| definition.link_support)
+            .unwrap_or(false);
+
+        self.snippet_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|completion_item| completion_item.snippet_support)
+            .unwrap_or(false);
+
+        self.markdown_hover_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.hover.as_ref())
+            .and_then(|hover| hover.content_format.as_ref())
+            .map_or(false, |content_format| {
+                content_format.contains(&MarkupKind::Markdown)
+            });
+
+        let workspace_edit = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref());
+
+        self.rename_file_support = workspace_edit.map_or(false, |workspace_edit| {
+            workspace_edit.document_changes == Some(true)
+                && workspace_edit
+                    .resource_operations
+                    .as_ref()
+                    .map_or(false, |kinds| {
+                        kinds.contains(&ResourceOperationKind::Rename)
+                    })
+        });
+        self.change_annotation_support = workspace_edit.map_or(false, |workspace_edit| {
+            workspace_edit.change_annotation_support.is_some()
+        });
+
+        self.force_rename = project_config::bool_setting(user_config, "forceRename", false);
+        self.resolve_symlinks = project_config::bool_setting(user_config, "resolveSymlinks", true);
+        self.index_ignored_files =
+            project_config::bool_setting(user_config, "indexIgnoredFiles", false);
+
+        if let Some(exclude_globs) = user_config.get("exclude").and_then(|v| v.as_array()) {
+            self.exclude_globs = exclude_globs
+                .iter()
+                .flat_map(|v| v.as_str())
+                .filter_map(|glob| match paths::glob_regex(glob.trim_start_matches("/")) {
+                    Ok(glob_regex) => Some(glob_regex),
+                    Err(error) => {
+                        info!("Ignoring exclude glob {}: {}", glob, error);
+                        None
+                    }
+                })
+                .collect();
+        }
+
+        self.rails_mode = user_config.get("rails").and_then(|v| v.as_bool());
+
+        self.ruby_version = match user_config.get("rubyVersion").and_then(|v| v.as_str()) {
+            Some(ruby_version) if !ruby_version.trim().is_empty() => {
+                RubyVersion::parse(ruby_version)
+            }
+            _ => self
+                .workspace_paths
+                .first()
+                .and_then(|workspace_path| RubyVersion::detect(workspace_path)),
+        };
+
+        if let Some(diagnostic_providers) =
+            user_config.get("diagnostics").and_then(|v| v.as_array())
+        {
+            self.diagnostic_providers = diagnostic_providers
+                .iter()
+                .flat_map(|v| v.as_str())
+                .map(String::from)
+                .collect();
+        }
+
+        self.format_command = user_config
+            .get("formatCommand")
+            .and_then(|v| v.as_str())
+            .filter(|command| !command.trim().is_empty())
+            .map(String::from);
+
+        self.index_heredoc_constants =
+            project_config::bool_setting(user_config, "indexHeredocConstants", false);
+
+        if let Some(rules) = user_config.get("dslRules") {
+            self.dsl_rules = dsl_rules::rules(rules);
+        }
+
+        #[cfg(feature = "wasm_plugins")]
+        if let Some(plugin_paths) = user_config.get("wasmPlugins").and_then(|v| v.as_array()) {
+            for plugin_path in plugin_paths.iter().filter_map(|v| v.as_str()) {
+                let plugin_path = paths::normalize(plugin_path);
+                let absolute_plugin_path = match self.workspace_paths.first() {
+                    _ if paths::is_absolute(&plugin_path) => plugin_path,
+                    Some(workspace_path) => format!("{}/{}", workspace_path, plugin_path),
+                    None => continue,
+                };
+
+                match plugins::WasmPlugin::load(std::path::Path::new(&absolute_plugin_path)) {
+                    Ok(plugin) => self.plugins.push(Box::new(plugin)),
+                    Err(error) => {
+                        info!("Unable to load plugin {}: {}", absolute_plugin_path, error)
+                    }
+                }
+            }
+        }
+
+        self.fuzzy_search = project_config::bool_setting(user_config, "fuzzySearch", false);
+
+        self.suggest_definitions = user_config
+            .get("suggestDefinitions")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        self.test_command_template = user_config
+            .get("testCommandTemplate")
+            .and_then(|v| v.as_str())
+            .filter(|template| !template.trim().is_empty())
+            .map(String::from);
+
+        if let Some(definition_limit) = user_config.get("maxDefinitions").and_then(|v| v.as_u64()) {
+            self.definition_limit = definition_limit.max(1) as usize;
+        }
+
+        if let Some(reference_limit) = user_config.get("maxReferences").and_then(|v| v.as_u64()) {
+            self.reference_limit = reference_limit.max(1) as usize;
+        }
+
+        // 0 lets lookups take as long as they need
+        if let Some(query_time_budget_ms) =
+            user_config.get("queryTimeBudgetMs").and_then(|v| v.as_u64())
+        {
+            self.query_time_budget = Some(Duration::from_millis(query_time_budget_ms))
+                .filter(|time_budget| !time_budget.is_zero());
+        }
+
+        if let Some(max_file_size_mb) =
+            user_config.get("maxFileSizeMb").and_then(|v| v.as_f64())
+        {
+            self.max_file_size = (max_file_size_mb * BYTES_PER_MB as f64) as u64;
+        }
+
+        if let Some(stub_file_size_mb) =
+            user_config.get("stubIndexFileSizeMb").and_then(|v| v.as_f64())
+        {
+            self.stub_file_size = (stub_file_size_mb * BYTES_PER_MB as f64) as u64;
+        }
+
+        if let Some(index_memory_budget_mb) =
+            user_config.get("indexMemoryBudgetMb").and_then(|v| v.as_u64())
+        {
+            self.index_memory_budget = index_memory_budget_mb.saturating_mul(BYTES_PER_MB) as usize;
+        }
+
+        if let Some(min_segments) = user_config
+            .get("indexMergeMinSegments")
+            .and_then(|v| v.as_u64())
+        {
+            self.merge_settings.min_segments = min_segments as usize;
+        }
+
+        if let Some(max_docs) = user_config
+            .get("indexMergeMaxDocs")
+            .and_then(|v| v.as_u64())
+        {
+            self.merge_settings.max_docs = max_docs as usize;
+        }
+
+        self.telemetry = telemetry::Sink::from_settings(
+            user_config.get("telemetry").and_then(|v| v.as_str()),
+            user_config.get("telemetryFile").and_then(|v| v.as_str()),
+        );
+    }
+
+    // A Persistence for parsing files away from this one, with the same
+    // settings for what's extracted from them
+    pub fn parser(&self) -> Persistence {
+        let mut parser = Persistence::new().unwrap();
+        parser.index_heredoc_constants = self.index_heredoc_constants;
+        parser.max_file_size = self.max_file_size;
+        parser.stub_file_size = self.stub_file_size;
+        parser.ruby_version = self.ruby_version;
+        parser.dsl_rules = self.dsl_rules.clone();
+        parser.plugins = self.plugins.clone();
+
+        parser
+    }
+
+    // A type only DSL rules or plugins index, looked up by name across the
+    // workspace
+    fn is_named_type(&self, node_type: &str) -> bool {
+        dsl_rules::is_dsl_type(&self.dsl_rules, node_type)
+            || self
+                .plugins
+                .iter()
+                .any(|plugin| plugin.named_types().contains(&node_type))
+    }
+
+    fn create_index(&mut self) -> Index {
+        let index = match self.allocation_type.as_str() {
+            "ram" => Index::create_in_ram(self.schema.clone()),
+            "tempdir" => Index::create_from_tempdir(self.schema.clone()).unwrap(),
+            "disk" => self.open_disk_index(),
+            _ => {
+                info!("Unknown allocation_type, defaulting to tempdir");
+                Index::create_from_tempdir(self.schema.clone()).unwrap()
+            }
+        };
+
+        index
+            .tokenizers()
+            .register(name_parts::TOKENIZER_NAME, NamePartsTokenizer);
+
+        index
+    }
+
+    // Drops everything indexed, for when the index is out of sync with the
+    // files in a way reindexing modified files won't fix. The next workspace
+    // walk indexes every file again.
+    pub fn clear_index(&mut self) {
+        self.index = None;
+        self.last_reindex_time = FileTime::from_unix_time(0, 0).seconds();
+        self.indexed_file_paths.clear();
+        self.indexed_file_hashes.clear();
+        self.stale_file_path_ids.lock().unwrap().clear();
+        self.pending_generations.lock().unwrap().clear();
+        self.generations.lock().unwrap().clear();
+        self.indexed_revisions.clear();
+        self.dirty_file_paths.clear();
+        self.include_dirs_indexed = false;
+        self.parse_cache.lock().unwrap().clear();
+
+        // Another window's server is writing a shared index, which is only
+        // opened again
+        if let Some(index_dir) = self.index_dir.take() {
+            if self.writer_lock.is_some() {
+                remove_index_dir(&index_dir);
+            }
+        }
+
+        self.index = Some(self.create_index());
+        info!("Index cleared");
+    }
+
+    // Reuses the index from the last session when there is one. Files are
+    // still walked on startup, but only those modified since the last
+    // session's shutdown are reindexed.
+    //
+    // Indexes from another version of the schema, or whose version wasn't
+    // recorded because the last session didn't shut down, are rebuilt
+    // rather than opened, since their documents can't be read as this
+    // version's.
+    fn open_disk_index(&mut self) -> Index {
+        let index_dir = match self.workspace_paths.first() {
+            Some(workspace_path) => paths::index_dir(workspace_path),
+            None => {
+                info!("No workspace to keep an index on disk for, defaulting to ram");
+                return Index::create_in_ram(self.schema.clone());
+            }
+        };
+        let metadata = read_index_metadata(&index_dir);
+        let index_fingerprint = self.index_fingerprint();
+
+        let current = metadata
+            .as_ref()
+            .and_then(|metadata| metadata["indexVersion"].as_str())
+            .map_or(false, |version| version == index_fingerprint);
+
+        self.writer_lock = self
+            .writer_lock
+            .take()
+            .or_else(|| acquire_writer_lock(&index_dir));
+
+        if self.writer_lock.is_none() {
+            return self.open_shared_index(index_dir, current, metadata);
+        }
+
+        if !current && index_dir.exists() {
+            info!(
+                "Index at {} is from another version, rebuilding",
+                index_dir.display()
+            );
+            remove_index_dir(&index_dir);
+        }
+
+        let index = match self.open_index_dir(&index_dir) {
+            Ok(index) => Ok(index),
+            Err(error) => {
+                info!(
+                    "Unable to open index at {}, rebuilding: {}",
+                    index_dir.display(),
+                    error
+                );
+                remove_index_dir(&index_dir);
+                self.open_index_dir(&index_dir)
+            }
+        };
+
+        match index {
+            Ok(index) => {
+                info!("Using index at {}", index_dir.display());
+
+                if let (true, Some(metadata)) = (current, metadata) {
+                    self.load_index_metadata(&metadata);
+                }
+
+                self.index_dir = Some(index_dir);
+
+                index
+            }
+            Err(error) => {
+                info!(
+                    "Unable to open index at {}, defaulting to tempdir: {}",
+                    index_dir.display(),
+                    error
+                );
+                Index::create_from_tempdir(self.schema.clone()).unwrap()
+            }
+        }
+    }
+
+    // Reads the index another editor window's server is writing, as it's
+    // committed. One from another version can't be read, and this server
+    // keeps its own in a tempdir instead.
+    fn open_shared_index(
+        &mut self,
+        index_dir: PathBuf,
+        current: bool,
+        metadata: Option<serde_json::Value>,
+    ) -> Index {
+        let index = match (current, metadata) {
+            (true, Some(metadata)) => match self.open_index_dir(&index_dir) {
+                Ok(index) => Some((index, metadata)),
+                Err(error) => {
+                    info!("Unable to open index at {}: {}", index_dir.display(), error);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        match index {
+            Some((index, metadata)) => {
+                info!(
+                    "Reading index at {}, written by another window",
+                    index_dir.display()
+                );
+                self.load_index_metadata(&metadata);
+                self.index_dir = Some(index_dir);
+
+                index
+            }
+            None => {
+                info!(
+                    "Index at {} is another window's, from another version, defaulting to tempdir",
+                    index_dir.display()
+                );
+                Index::create_from_tempdir(self.schema.clone()).unwrap()
+            }
+        }
+    }
+
+    // Whether the index is another window's, which this server can't write
+    pub fn reads_shared_index(&self) -> bool {
+        self.index_dir.is_some() && self.writer_lock.is_none()
+    }
+
+    // Takes over writing a shared index once the window that wrote it is
+    // closed, picking up from what it saved as it shut down. True when this
+    // server can write its index.
+    pub fn claim_index_writer(&mut self) -> bool {
+        let index_dir = match &self.index_dir {
+            Some(index_dir) if self.writer_lock.is_none() => index_dir.clone(),
+            _ => return true,
+        };
+
+        self.writer_lock = acquire_writer_lock(&index_dir);
+
+        if self.writer_lock.is_none() {
+            return false;
+        }
+
+        if let Some(metadata) = read_index_metadata(&index_dir) {
+            self.load_index_metadata(&metadata);
+        }
+
+        info!("Writing index at {}", index_dir.display());
+
+        true
+    }
+
+    fn open_index_dir(&self, index_dir: &PathBuf) -> tantivy::Result<Index> {
+        fs::create_dir_all(index_dir)
+            .map_err(|error| tantivy::TantivyError::from(error))
+            .and_then(|_| MmapDirectory::open(index_dir).map_err(|error| error.into()))
+            .and_then(|directory| Index::open_or_create(directory, self.schema.clone()))
+    }
+
+    // Identifies the schema and what's indexed with it, changing whenever a
+    // field is added or changed or INDEX_VERSION is bumped
+    fn index_fingerprint(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(&self.schema)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+
+        format!("{}-{:016x}", INDEX_VERSION, hasher.finish())
+    }
+
+    fn load_index_metadata(&mut self, metadata: &serde_json::Value) {
+        let last_reindex_time = metadata["lastReindexTime"].as_i64();
+        let files = metadata["files"].as_object();
+
+        if let (Some(last_reindex_time), Some(files)) = (last_reindex_time, files) {
+            self.last_reindex_time = last_reindex_time;
+            self.indexed_file_paths = files
+                .iter()
+                .filter_map(|(path, file_path_id)| {
+                    Some((path.to_string(), file_path_id.as_str()?.to_string()))
+                })
+                .collect();
+        }
+
+        // Indexes saved before hashes were kept fall back to mtimes alone
+        if let Some(hashes) = metadata["hashes"].as_object() {
+            self.indexed_file_hashes = hashes
+                .iter()
+                .filter_map(|(path, hash)| Some((path.to_string(), hash.as_str()?.to_string())))
+                .collect();
+        }
+
+        if let Some(revisions) = metadata["revisions"].as_object() {
+            self.indexed_revisions = revisions
+                .iter()
+                .filter_map(|(workspace_path, revision)| {
+                    Some((workspace_path.to_string(), revision.as_str()?.to_string()))
+                })
+                .collect();
+        }
+
+        if let Some(dirty_files) = metadata["dirtyFiles"].as_array() {
+            self.dirty_file_paths = dirty_files
+                .iter()
+                .filter_map(|path| Some(path.as_str()?.to_string()))
+                .collect();
+        }
+    }
+
+    // Writers are committed as soon as they're used, so this only needs to
+    // record what was indexed and release the index. The metadata is written
+    // last so an interrupted shutdown falls back to a full reindex.
+    pub fn shutdown(&mut self) {
+        if let (Some(index_dir), Some(_writer_lock)) = (&self.index_dir, &self.writer_lock) {
+            // Open files may have been indexed with unsaved changes, leaving
+            // them out has them reindexed from disk next session
+            let open_file_paths: HashSet<String> =
+                self.open_files.keys().map(paths::uri_to_path).collect();
+            let files: HashMap<&String, &String> = self
+                .indexed_file_paths
+                .iter()
+                .filter(|(path, _file_path_id)| !open_file_paths.contains(*path))
+                .collect();
+            let hashes: HashMap<&String, &String> = self
+                .indexed_file_hashes
+                .iter()
+                .filter(|(path, _hash)| !open_file_paths.contains(*path))
+                .collect();
+
+            let dirty_files: HashSet<&String> = self
+                .dirty_file_paths
+                .union(&self.edited_file_paths)
+                .collect();
+
+            let metadata = json!({
+                "indexVersion": self.index_fingerprint(),
+                "lastReindexTime": self.last_reindex_time,
+                "files": files,
+                "hashes": hashes,
+                "revisions": self.indexed_revisions,
+                "dirtyFiles": dirty_files,
+            });
+
+            if let Err(error) = fs::write(
+                index_dir.join(INDEX_METADATA_FILE),
+                metadata.to_string(),
+            ) {
+                info!("Unable to save index metadata: {}", error);
+            }
+        }
+
+        self.index = None;
+        self.writer_lock = None;
+    }
+
+    pub fn document_count(&self) -> tantivy::Result<u64> {
+        match &self.index {
+            Some(index) => Ok(index.reader()?.searcher().num_docs()),
+            None => Ok(0),
+        }
+    }
+
+    pub fn index_health(&self) -> tantivy::Result<telemetry::Event> {
+        let segments = match &self.index {
+            Some(index) => index.searchable_segment_ids()?.len(),
+            None => 0,
+        };
+
+        Ok(telemetry::Event::IndexHealth {
+            documents: self.document_count()?,
+            segments,
+            files: self.indexed_file_count(),
+        })
+    }
+
+    // What's needed to walk the workspace, copied so the walk doesn't hold
+    // up requests
+    pub fn workspace_walk(&self) -> WorkspaceWalk {
+        WorkspaceWalk {
+            workspace_paths: self.workspace_paths.clone(),
+            indexed_file_paths: self.indexed_file_paths.clone(),
+            indexed_file_hashes: self.indexed_file_hashes.clone(),
+            git_revisions: if self.workspace_walked {
+                HashMap::new()
+            } else {
+                self.indexed_revisions.clone()
+            },
+            dirty_file_paths: self.dirty_file_paths.clone(),
+            last_reindex_time: self.last_reindex_time,
+            resolve_symlinks: self.resolve_symlinks,
+            index_ignored_files: self.index_ignored_files,
+            exclude_globs: self.exclude_globs.clone(),
+        }
+    }
+
+    pub fn finish_workspace_changes(&mut self, changes: WorkspaceChanges) {
+        self.last_reindex_time = changes.start_time;
+        self.indexed_file_paths = changes.indexed_file_paths;
+        self.indexed_file_hashes = changes.indexed_file_hashes;
+        self.indexed_revisions = changes.revisions;
+        self.dirty_file_paths = changes.dirty_file_paths;
+        self.workspace_walked = true;
+    }
+
+    pub fn reindex_modified_files(&mut self) -> tantivy::Result<()> {
+        let changes = self.workspace_walk().changes();
+
+        if changes.is_empty() {
+            info!("No file changes, skipping periodic reindexing.");
+        } else if let Some(index_writer) = self.index_writer() {
+            let mut index_writer = index_writer?;
+
+            for file_path_id in &changes.deleted_file_path_ids {
+                self.delete_file(file_path_id, &index_writer);
+            }
+
+            for path in &changes.modified_file_paths {
+                if let Some(parsed_file) = self.parse_path(path, false) {
+                    self.index_parsed_file(parsed_file, path, true, &index_writer)?;
+                }
+            }
+
+            self.commit(&mut index_writer)?;
+            info!("Indexing workspace complete!");
+        }
+
+        self.finish_workspace_changes(changes);
+
+        Ok(())
+    }
+
+    pub fn indexed_file_count(&self) -> usize {
+        self.indexed_file_paths.len()
+    }
+
+    pub fn dependency_walk(&self) -> DependencyWalk {
+        DependencyWalk {
+            workspace_path: self.workspace_paths.first().cloned(),
+            include_dirs: self.include_dirs.clone(),
+            index_stdlib: self.index_stdlib,
+        }
+    }
+
+    pub fn index_included_dirs_once(&mut self) -> tantivy::Result<()> {
+        if self.include_dirs_indexed {
+            return Ok(());
+        }
+
+        let included_file_paths = self.dependency_walk().included_file_paths();
+
+        if included_file_paths.len() > 0 {
+            if let Some(index_writer) = self.index_writer() {
+                let mut index_writer = index_writer?;
+
+                for (path, interface_only) in &included_file_paths {
+                    if let Some(parsed_file) = self.parse_path(path, *interface_only) {
+                        self.index_parsed_file(parsed_file, path, false, &index_writer)?;
+                    }
+                }
+
+                self.commit(&mut index_writer)?;
+            }
+        }
+
+        self.include_dirs_indexed = true;
+
+        Ok(())
+    }
+
+    pub fn index_gems_once(&mut self) -> tantivy::Result<()> {
+        if self.gems_indexed {
+            return Ok(());
+        }
+
+        let dependency_walk = self.dependency_walk();
+
+        // Ruby's core stubs are committed on their own, after the gems
+        for file_paths in [
+            dependency_walk.gem_file_paths(),
+            dependency_walk.stdlib_file_paths(),
+        ] {
+            if file_paths.is_empty() {
+                continue;
+            }
+
+            if let Some(index_writer) = self.index_writer() {
+                let mut index_writer = index_writer?;
+
+                for path in &file_paths {
+                    if let Some(parsed_file) = self.parse_path(path, true) {
+                        self.index_parsed_file(parsed_file, path, false, &index_writer)?;
+                    }
+                }
+
+                self.commit(&mut index_writer)?;
+            }
+        }
+
+        self.gems_indexed = true;
+
+        Ok(())
+    }
+
+    // Uses as many threads as the memory budget allows, each needing at
+    // least tantivy's minimum arena
+    pub fn index_writer(&self) -> Option<tantivy::Result<IndexWriter>> {
+        if self.reads_shared_index() {
+            return None;
+        }
+
+        let memory_budget = self.index_memory_budget.max(MIN_WRITER_MEMORY);
+        let num_threads = std::thread::available_parallelism()
+            .map_or(1, |parallelism| parallelism.get())
+            .min(8)
+            .min(memory_budget / MIN_WRITER_MEMORY)
+            .max(1);
+
+        self.index.as_ref().map(|index| {
+            let index_writer = index.writer_with_num_threads(num_threads, memory_budget)?;
+            index_writer.set_merge_policy(self.merge_settings.merge_policy());
+
+            Ok(index_writer)
+        })
+    }
+
+    // A smaller writer for reindexing a single edited file
+    pub fn file_index_writer(&self) -> Option<tantivy::Result<IndexWriter>> {
+        if self.reads_shared_index() {
+            return None;
+        }
+
+        let memory_budget = self
+            .index_memory_budget
+            .clamp(MIN_WRITER_MEMORY, 30_000_000);
+
+        self.index.as_ref().map(|index| {
+            let index_writer = index.writer_with_num_threads(1, memory_budget)?;
+            index_writer.set_merge_policy(self.merge_settings.merge_policy());
+
+            Ok(index_writer)
+        })
+    }
+
+    // The index this server writes, None when another window writes it
+    pub fn writable_index(&self) -> Option<&Index> {
+        if self.reads_shared_index() {
+            return None;
+        }
+
+        self.index.as_ref()
+    }
+
+    pub fn delete_file(&self, file_path_id: &str, index_writer: &IndexWriter) {
+        let path_term = Term::from_field_text(self.schema_fields.file_path_id, file_path_id);
+
+        index_writer.delete_term(path_term);
+        self.stale_file_path_ids
+            .lock()
+            .unwrap()
+            .remove(file_path_id);
+        self.pending_generations
+            .lock()
+            .unwrap()
+            .remove(file_path_id);
+    }
+
+    // Deleting only what's older than the generation leaves the new
+    // documents alone, so they're added first and no commit can land
+    // between a file's documents being deleted and added again
+    fn delete_earlier_generations(
+        &self,
+        file_path_id: &str,
+        generation: u64,
+        index_writer: &IndexWriter,
+    ) -> tantivy::Result<()> {
+        let path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, file_path_id),
+            IndexRecordOption::Basic,
+        ));
+        let earlier_query: Box<dyn Query> = Box::new(RangeQuery::new_u64(
+            self.schema_fields.generation_field,
+            0..generation,
+        ));
+
+        index_writer.delete_query(Box::new(BooleanQuery::new(vec![
+            (Occur::Must, path_query),
+            (Occur::Must, earlier_query),
+        ])))?;
+        self.stale_file_path_ids
+            .lock()
+            .unwrap()
+            .remove(file_path_id);
+
+        Ok(())
+    }
+
+    // Commits the writer, after which lookups skip the generations it
+    // replaced
+    pub fn commit(&self, index_writer: &mut IndexWriter) -> tantivy::Result<()> {
+        index_writer.commit()?;
+        self.publish_generations();
+
+        Ok(())
+    }
+
+    // For writers committed without the lock held
+    pub fn publish_generations(&self) {
+        let pending_generations = std::mem::take(&mut *self.pending_generations.lock().unwrap());
+
+        self.generations.lock().unwrap().extend(pending_generations);
+    }
+
+    // Deletes the documents of generations lookups are skipping, so
+    // compacting the index purges them
+    pub fn delete_superseded_generations(&self, index_writer: &IndexWriter) -> tantivy::Result<()> {
+        let generations = self.generations.lock().unwrap().clone();
+
+        for (file_path_id, generation) in &generations {
+            self.delete_earlier_generations(file_path_id, *generation, index_writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_superseded(&self, symbol: &IndexedSymbol) -> bool {
+        self.generations
+            .lock()
+            .unwrap()
+            .get(&symbol.file_path_id)
+            .map_or(false, |generation| symbol.generation < *generation)
+    }
+
+    // Keeps answering from the file's documents while its text doesn't
+    // parse, and notes they're out of date. False when there's nothing
+    // indexed for the file to fall back on.
+    pub fn mark_stale(&self, path: &str) -> bool {
+        let file_path_id = self.file_path_id(path);
+
+        if !self.has_documents(&file_path_id) {
+            return false;
+        }
+
+        self.stale_file_path_ids
+            .lock()
+            .unwrap()
+            .insert(file_path_id);
+
+        true
+    }
+
+    // Shown with a stale file's syntax errors, reported along with them
+    pub fn stale_documents_diagnostic(&self) -> tower_lsp::lsp_types::Diagnostic {
+        tower_lsp::lsp_types::Diagnostic {
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            code: Some(NumberOrString::String(STALE_DOCUMENTS_CODE.to_string())),
+            message: "Definitions and references in this file are from its last version \
+                      without syntax errors, until they're fixed"
+                .to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn is_stale(&self, file_path_id: &str) -> bool {
+        self.stale_file_path_ids
+            .lock()
+            .unwrap()
+            .contains(file_path_id)
+    }
+
+    fn has_documents(&self, file_path_id: &str) -> bool {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let path_query = TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, file_path_id),
+            IndexRecordOption::Basic,
+        );
+
+        index
+            .reader()
+            .and_then(|reader| reader.searcher().search(&path_query, &Count))
+            .map_or(false, |count| count > 0)
+    }
+
+    // Parsing only needs the serializer state, not the index, so a separate
+    // Persistence can parse files while this one keeps answering requests.
+    pub fn parse_file(&mut self, text: &String, interface_only: bool) -> ParsedFile {
+        let mut documents = Vec::new();
+
+        for plugin in &mut self.plugins {
+            plugin.start_file();
+        }
+
+        self.index_interface_only = interface_only;
+        let parse_result = self.parse(text, &mut documents);
+        self.index_interface_only = false;
+
+        let (diagnostics, parsed) = match parse_result {
+            Ok(diagnostics) => (diagnostics, true),
+            Err(diagnostics) => (diagnostics, false),
+        };
+
+        ParsedFile {
+            documents,
+            diagnostics: diagnostics.into_iter().flatten().collect(),
+            parsed,
+        }
+    }
+
+    // Locale files are read for their translation keys, templates for the
+    // Ruby in their tags, and everything else is Ruby
+    pub fn parse_text(&mut self, path: &str, text: &String, interface_only: bool) -> ParsedFile {
+        if i18n::is_locale_file(path) {
+            return self.parse_locale_file(text);
+        }
+
+        if partials::is_template(path) {
+            return self.parse_template(path, text, interface_only);
+        }
+
+        self.parse_file(text, interface_only)
+    }
+
+    // A partial is defined at the start of its file
+    fn parse_template(&mut self, path: &str, text: &str, interface_only: bool) -> ParsedFile {
+        let mut parsed_file = self.parse_file(&partials::erb_ruby(text), interface_only);
+
+        if let Some(partial_name) = partials::partial_name(path) {
+            parsed_file.documents.push(FuzzyNode {
+                category: "assignment",
+                name: partial_name,
+                node_type: "Partial",
+                ..Default::default()
+            });
+        }
+
+        parsed_file
+    }
+
+    fn parse_locale_file(&self, text: &str) -> ParsedFile {
+        let documents = i18n::locale_keys(text)
+            .into_iter()
+            .map(|locale_key| FuzzyNode {
+                category: "translation",
+                fuzzy_ruby_scope: vec![locale_key.locale],
+                name: locale_key.key,
+                node_type: "I18nKey",
+                line: locale_key.line,
+                start_column: locale_key.start_column,
+                end_column: locale_key.end_column,
+                ..Default::default()
+            })
+            .collect();
+
+        ParsedFile {
+            documents,
+            diagnostics: vec![],
+            parsed: true,
+        }
+    }
+
+    // Reads a file for indexing. Files over the `maxFileSizeMb` setting are
+    // skipped, and those over `stubIndexFileSizeMb` only have their
+    // definitions indexed, so one giant generated file can't use up the
+    // writer's memory.
+    pub fn parse_path(&mut self, path: &str, interface_only: bool) -> Option<ParsedFile> {
+        let file_size = fs::metadata(path).ok()?.len();
+
+        if file_size > self.max_file_size {
+            info!("Skipping {}, it's larger than maxFileSizeMb", path);
+            return None;
+        }
+
+        let text = encoding::read_ruby_file(path).ok()?;
+
+        if file_size > self.stub_file_size {
+            let mut parsed_file = self.parse_text(path, &text, true);
+            parsed_file
+                .documents
+                .retain(|document| document.category != "usage");
+
+            Some(parsed_file)
+        } else {
+            Some(self.parse_text(path, &text, interface_only))
+        }
+    }
+
+    // Replaces the file's documents. Files that failed to parse keep their
+    // existing documents.
+    pub fn index_parsed_file(
+        &self,
+        mut parsed_file: ParsedFile,
+        path: &str,
+        user_space: bool,
+        index_writer: &IndexWriter,
+    ) -> tantivy::Result<()> {
+        if !parsed_file.parsed {
+            self.mark_stale(path);
+            return Ok(());
+        }
+
+        // Routes are only dispatched to controllers, and partials only
+        // rendered, in Rails apps
+        let rails_app = self
+            .workspace_root(path)
+            .map_or(false, |workspace_path| self.is_rails_app(workspace_path));
+
+        if !rails_app {
+            parsed_file.documents.retain(|document| {
+                document.node_type != "Route" && document.node_type != "Partial"
+            });
+        } else if let Some(view_dir) = partials::view_dir(path) {
+            // Partials named without a directory are in the view's
+            for document in &mut parsed_file.documents {
+                if document.node_type == "Partial"
+                    && document.category == "usage"
+                    && !document.name.contains('/')
+                {
+                    document.name = format!("{}/{}", view_dir, document.name);
+                }
+            }
+        }
+
+        if let Some(signatures) = Signatures::for_ruby_file(path) {
+            for document in &mut parsed_file.documents {
+                let singleton = match document.node_type {
+                    "Def" => false,
+                    "Defs" => true,
+                    _ => continue,
+                };
+
+                if document.signature.is_some() {
+                    continue;
+                }
+
+                let scope_name = document
+                    .fuzzy_ruby_scope
+                    .last()
+                    .map(String::as_str)
+                    .unwrap_or("");
+
+                document.signature = signatures
+                    .method(scope_name, singleton, &document.name)
+                    .cloned();
+            }
+        }
+
+        let file_path_id = self.file_path_id(path);
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+
+        self.add_documents(
+            parsed_file.documents,
+            path,
+            user_space,
+            generation,
+            index_writer,
+        )?;
+        self.delete_earlier_generations(&file_path_id, generation, index_writer)?;
+        self.pending_generations
+            .lock()
+            .unwrap()
+            .insert(file_path_id, generation);
+
+        Ok(())
+    }
+
+    pub fn is_rails_app(&self, workspace_path: &str) -> bool {
+        self.rails_mode
+            .unwrap_or_else(|| autoload::is_rails_app(workspace_path))
+    }
+
+    // Excluded by the project's `exclude` globs
+    pub fn is_excluded(&self, path: &str) -> bool {
+        match self.workspace_root(path) {
+            Some(workspace_path) => is_excluded(path, workspace_path, &self.exclude_globs),
+            None => false,
+        }
+    }
+
+    // Files the workspace walk doesn't index are indexed as they're opened
+    pub fn is_walked(&self, uri: &Url) -> bool {
+        !self.workspace_paths.is_empty() && !paths::is_virtual(&paths::uri_to_path(uri))
+    }
+
+    // Without a workspace, the open files are all there is
+    pub fn is_user_space(&self, path: &str) -> bool {
+        self.workspace_paths.is_empty() || self.workspace_root(path).is_some()
+    }
+
+    fn add_documents(
+        &self,
+        documents: Vec<FuzzyNode>,
+        path: &str,
+        user_space: bool,
+        generation: u64,
+        index_writer: &IndexWriter,
+    ) -> tantivy::Result<()> {
+        let file_symbol = self.file_symbol(path, user_space, generation);
+
+        for document in documents {
+            // Hints and tests are only needed while answering requests for
+            // the file
+            if ["hint", "test"].contains(&document.category) {
+                continue;
+            }
+
+            index_writer.add_document(Document::from(document.into_symbol(&file_symbol)))?;
+        }
+
+        Ok(())
+    }
+
+    // What the symbols indexed for a file share
+    fn file_symbol(&self, path: &str, user_space: bool, generation: u64) -> IndexedSymbol {
+        let file_path_id = self.file_path_id(path);
+        let workspace_folder = self.workspace_root(path).filter(|_root| user_space);
+        let relative_path = match workspace_folder {
+            Some(_root) => self.relative_path(path),
+            None => path.to_string(),
+        };
+        // A URI's slashes aren't all separators
+        let file_path: Vec<String> = if paths::is_virtual(&relative_path) {
+            vec![relative_path]
+        } else {
+            relative_path
+                .split("/")
+                .filter(|path_part| path_part.len() > 0)
+                .map(String::from)
+                .collect()
+        };
+
+        IndexedSymbol {
+            file_path_id,
+            workspace_folder: workspace_folder.map(String::from),
+            file_path,
+            user_space,
+            generation,
+            ..IndexedSymbol::default()
+        }
+    }
+
+    pub fn add_workspace_folder(&mut self, uri: &Url) {
+        let workspace_path = paths::uri_to_path(uri);
+
+        if !self.workspace_paths.contains(&workspace_path) {
+            self.workspace_paths.push(workspace_path);
+        }
+    }
+
+    // Documents from the folder are deleted by the next reindex, since its
+    // files are no longer walked.
+    pub fn remove_workspace_folder(&mut self, uri: &Url) {
+        let workspace_path = paths::uri_to_path(uri);
+
+        self.workspace_paths
+            .retain(|existing_path| existing_path != &workspace_path);
+    }
+
+    // Nested folders are matched to the innermost one
+    fn workspace_root(&self, path: &str) -> Option<&String> {
+        self.workspace_paths
+            .iter()
+            .filter(|workspace_path| paths::relative_to(path, workspace_path).is_some())
+            .max_by_key(|workspace_path| workspace_path.len())
+    }
+
+    fn relative_path(&self, path: &str) -> String {
+        match self.workspace_root(path) {
+            Some(workspace_path) => paths::relative_to(path, workspace_path)
+                .unwrap()
+                .to_string(),
+            None => path.to_string(),
+        }
+    }
+
+    // Symlinked files share an ID with their target so that they're only
+    // indexed once, unless resolving them is disabled for performance.
+    // Walked and open files keep the ID they were given when they were
+    // found, rather than resolving their path for every lookup.
+    fn file_path_id(&self, path: &str) -> String {
+        let known_file_path_id = self
+            .indexed_file_paths
+            .get(path)
+            .or_else(|| self.open_file_path_ids.get(path));
+
+        match known_file_path_id {
+            Some(file_path_id) => file_path_id.clone(),
+            None => paths::file_path_id(path, self.resolve_symlinks),
+        }
+    }
+
+    pub fn update_open_file(&mut self, uri: &Url, text: &String) {
+        let path = paths::uri_to_path(uri);
+
+        if !self.open_file_path_ids.contains_key(&path) {
+            let file_path_id = paths::file_path_id(&path, self.resolve_symlinks);
+            self.open_file_path_ids.insert(path.clone(), file_path_id);
+        }
+
+        self.edited_file_paths.insert(path);
+        self.open_files.insert(uri.clone(), text.to_string());
+        self.parse_cache.lock().unwrap().invalidate(uri);
+        self.current_file = Some(uri.clone());
+    }
+
+    // A buffer that isn't a file in the workspace, like an untitled one or
+    // a diff, whose documents are dropped when it's closed since nothing on
+    // disk indexes it again
+    pub fn is_detached_buffer(&self, uri: &Url) -> bool {
+        let path = paths::uri_to_path(uri);
+
+        paths::is_virtual(&path) && self.workspace_root(&path).is_none()
+    }
+
+    pub fn delete_path(&self, path: &str, index_writer: &IndexWriter) {
+        self.delete_file(&self.file_path_id(path), index_writer);
+    }
+
+    pub fn close_file(&mut self, uri: &Url) {
+        self.open_files.remove(uri);
+        self.open_file_path_ids.remove(&paths::uri_to_path(uri));
+        self.parse_cache.lock().unwrap().invalidate(uri);
+
+        if self.current_file.as_ref() == Some(uri) {
+            self.current_file = None;
+        }
+    }
+
+    // Orders documents from the current file first, then other open files,
+    // then files in the current file's directory, then everything else.
+    // Matches with the same name are often unrelated, and the nearest one
+    // is usually the one meant. Within each, they're ordered by path then
+    // line, rather than the index's order, which changes as segments merge.
+    fn rank_by_proximity(&self, documents: &mut Vec<Document>, current_uri: Option<&Url>) {
+        let current_path = current_uri.map(paths::uri_to_path);
+        let current_dir = current_path
+            .as_ref()
+            .and_then(|current_path| current_path.rsplit_once("/"))
+            .map(|(current_dir, _file_name)| current_dir.to_string());
+        let open_paths: HashSet<String> = self.open_files.keys().map(paths::uri_to_path).collect();
+
+        documents.sort_by_cached_key(|document| {
+            let symbol = self.indexed_symbol(document);
+            let path = symbol
+                .as_ref()
+                .map(|symbol| symbol.path())
+                .unwrap_or_default();
+            let start = symbol
+                .map(|symbol| symbol.range().start)
+                .unwrap_or_default();
+
+            let tier = if current_path.as_ref() == Some(&path) {
+                0
+            } else if open_paths.contains(&path) {
+                1
+            } else if current_dir.as_deref() == path.rsplit_once("/").map(|(dir, _)| dir) {
+                2
+            } else if !stdlib::is_stub_path(&path) {
+                3
+            } else {
+                // Ruby's core stubs come after everything the project defines
+                4
+            };
+
+            (tier, path, start)
+        });
+    }
+
+    fn file_contents(&self, uri: &Url) -> Option<String> {
+        match self.open_files.get(uri) {
+            Some(text) => Some(text.to_string()),
+            None => encoding::read_ruby_file(paths::uri_to_path(uri)).ok(),
+        }
+    }
+
+    pub fn debug_ast(&self, uri: &Url) -> Option<serde_json::Value> {
+        let text = self.file_contents(uri)?;
+
+        Some(json!(self.parsed_file(uri, &text).debug_nodes()))
+    }
+
+    // An open file's parse, kept for the requests that follow while its text
+    // is unchanged. Other files are parsed each time. Parsing is done by a
+    // parser of its own, so requests only need to read this.
+    fn parsed_file(&self, uri: &Url, text: &String) -> Arc<ParsedFile> {
+        let path = paths::uri_to_path(uri);
+
+        if !self.open_files.contains_key(uri) {
+            return Arc::new(self.parser().parse_text(&path, text, false));
+        }
+
+        let content_hash = blake3::hash(text.as_bytes()).to_hex().to_string();
+
+        if let Some(parsed_file) = self.parse_cache.lock().unwrap().get(uri, &content_hash) {
+            return parsed_file;
+        }
+
+        // Parsed without the lock, a request for the same text meanwhile
+        // parses it too and the last one is kept
+        let parsed_file = self.parser().parse_text(&path, text, false);
+
+        self.parse_cache
+            .lock()
+            .unwrap()
+            .insert(uri, &content_hash, text.len(), parsed_file)
+    }
+
+    // "Run file" above the first line of a spec or test file, and "Run test"
+    // over each test and group in it. The lenses carry the shell command,
+    // which `fuzzy.runTest` sends back to the client to run.
+    pub fn test_code_lenses(&self, uri: &Url) -> Vec<CodeLens> {
+        let path = paths::uri_to_path(uri);
+
+        let framework = match test_runner::framework(&path) {
+            Some(framework) => framework,
+            None => return Vec::new(),
+        };
+
+        // Test commands run from the workspace folder
+        let workspace_path = match self.workspace_root(&path) {
+            Some(workspace_path) => workspace_path.clone(),
+            None => return Vec::new(),
+        };
+        let relative_path = self.relative_path(&path);
+
+        let text = match self.file_contents(uri) {
+            Some(text) => text,
+            None => return Vec::new(),
+        };
+
+        let parsed_file = self.parsed_file(uri, &text);
+
+        if !parsed_file.parsed {
+            return Vec::new();
+        }
+
+        let run_lens = |title: &str, range: Range, command: String| CodeLens {
+            range,
+            command: Some(tower_lsp::lsp_types::Command {
+                title: title.to_string(),
+                command: test_runner::RUN_TEST_COMMAND.to_string(),
+                arguments: Some(vec![json!({ "command": command, "cwd": workspace_path })]),
+            }),
+            data: None,
+        };
+
+        let test_commands = TestCommands {
+            framework,
+            workspace_path: &workspace_path,
+            relative_path: &relative_path,
+            template: self.test_command_template.as_deref(),
+            rails: self.is_rails_app(&workspace_path),
+        };
+
+        let mut lenses = vec![run_lens(
+            "Run file",
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+            test_commands.file_command(),
+        )];
+
+        for document in &parsed_file.documents {
+            if document.category != "test" {
+                continue;
+            }
+
+            let test_block = TestBlock {
+                node_type: document.node_type,
+                name: &document.name,
+                line: document.line as u32 + 1,
+            };
+
+            if let Some(command) = test_commands.test_command(&test_block) {
+                let range = Range::new(
+                    Position::new(document.line as u32, document.start_column as u32),
+                    Position::new(document.line as u32, document.end_column as u32),
+                );
+
+                lenses.push(run_lens("Run test", range, command));
+            }
+        }
+
+        lenses
+    }
+
+    pub fn diagnostics(
+        &self,
+        text: &String,
+        uri: &Url,
+    ) -> tantivy::Result<Vec<Option<tower_lsp::lsp_types::Diagnostic>>> {
+        // Locale files aren't Ruby, and templates are only Ruby in their
+        // tags, which is what's parsed of them
+        let parsed_file = self.parsed_file(uri, text);
+
+        Ok(parsed_file.diagnostics.iter().cloned().map(Some).collect())
+    }
+
+    // What's reported for a file after it's parsed, the same whether it's
+    // published or pulled
+    pub fn file_diagnostics(
+        &self,
+        parsed_file: &ParsedFile,
+        stale: bool,
+    ) -> Vec<tower_lsp::lsp_types::Diagnostic> {
+        let mut diagnostics = parsed_file.diagnostics.clone();
+
+        if stale {
+            diagnostics.push(self.stale_documents_diagnostic());
+        }
+
+        match self.private_call_diagnostics(parsed_file) {
+            Ok(private_call_diagnostics) => diagnostics.extend(private_call_diagnostics),
+            Err(error) => info!("Unable to check private calls: {}", error),
+        }
+
+        self.reported_diagnostics(diagnostics)
+    }
+
+    // A pulled file's diagnostics are unchanged while its contents are, until
+    // the workspace is reindexed, which can change the private call checks
+    fn diagnostics_result_id(&self, content_hash: &str) -> String {
+        format!("{}-{}", self.last_reindex_time, content_hash)
+    }
+
+    // For `textDocument/diagnostic`, an unchanged report when the client's
+    // last result still holds
+    pub fn document_diagnostic_report(
+        &self,
+        uri: &Url,
+        previous_result_id: Option<String>,
+    ) -> DocumentDiagnosticReport {
+        let text = self.file_contents(uri).unwrap_or_default();
+        let content_hash = blake3::hash(text.as_bytes()).to_hex().to_string();
+        let result_id = self.diagnostics_result_id(&content_hash);
+
+        if previous_result_id.as_ref() == Some(&result_id) {
+            return DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                    result_id,
+                },
+            });
+        }
+
+        let path = paths::uri_to_path(uri);
+        let parsed_file = self.parser().parse_text(&path, &text, false);
+        let items = if self.report_diagnostics {
+            self.file_diagnostics(&parsed_file, self.is_stale(&self.file_path_id(&path)))
+        } else {
+            Vec::new()
+        };
+
+        DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items,
+            },
+        })
+    }
+
+    // For `workspace/diagnostic`, every indexed workspace file that isn't
+    // open. Open files are pulled on their own as they're edited. Files whose
+    // result the client already has aren't read again.
+    pub fn workspace_diagnostic_report(
+        &self,
+        previous_result_ids: Vec<PreviousResultId>,
+        cancellation: &CancellationToken,
+    ) -> WorkspaceDiagnosticReport {
+        let previous_result_ids: HashMap<Url, String> = previous_result_ids
+            .into_iter()
+            .map(|previous_result_id| (previous_result_id.uri, previous_result_id.value))
+            .collect();
+        let mut parser = self.parser();
+        let mut items = Vec::new();
+
+        let mut indexed_paths: Vec<&String> = self.indexed_file_hashes.keys().collect();
+        indexed_paths.sort();
+
+        for path in indexed_paths {
+            if cancellation.is_cancelled() || !self.report_diagnostics {
+                break;
+            }
+
+            let uri = paths::path_to_uri(path);
+
+            if self.open_files.contains_key(&uri) {
+                continue;
+            }
+
+            let result_id = self.diagnostics_result_id(&self.indexed_file_hashes[path]);
+
+            if previous_result_ids.get(&uri) == Some(&result_id) {
+                items.push(WorkspaceDocumentDiagnosticReport::Unchanged(
+                    WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version: None,
+                        unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                            result_id,
+                        },
+                    },
+                ));
+                continue;
+            }
+
+            let parsed_file = match parser.parse_path(path, false) {
+                Some(parsed_file) => parsed_file,
+                None => continue,
+            };
+            let stale = self.is_stale(&self.file_path_id(path));
+
+            items.push(WorkspaceDocumentDiagnosticReport::Full(
+                WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version: None,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: Some(result_id),
+                        items: self.file_diagnostics(&parsed_file, stale),
+                    },
+                },
+            ));
+        }
+
+        WorkspaceDiagnosticReport { items }
+    }
+
+    // Leaves out diagnostics from providers the project turned off
+    pub fn reported_diagnostics(
+        &self,
+        diagnostics: Vec<tower_lsp::lsp_types::Diagnostic>,
+    ) -> Vec<tower_lsp::lsp_types::Diagnostic> {
+        diagnostics
+            .into_iter()
+            .filter(|diagnostic| {
+                let provider = match &diagnostic.code {
+                    Some(NumberOrString::String(code)) if code == DUPLICATE_METHOD_CODE => {
+                        "duplicates"
+                    }
+                    Some(NumberOrString::String(code)) if code == PRIVATE_CALL_CODE => {
+                        "privateCalls"
+                    }
+                    _ => "syntax",
+                };
+
+                self.diagnostic_providers
+                    .iter()
+                    .any(|enabled_provider| enabled_provider == provider)
+            })
+            .collect()
+    }
+
+    // Warns on calls with an explicit receiver to methods that are only
+    // defined as private, which raise NoMethodError. Calls on `self` are
+    // allowed. Opt-in, since the receiver's class isn't known and any public
+    // method with the name is assumed to be the one called.
+    pub fn private_call_diagnostics(
+        &self,
+        parsed_file: &ParsedFile,
+    ) -> tantivy::Result<Vec<tower_lsp::lsp_types::Diagnostic>> {
+        let enabled = self
+            .diagnostic_providers
+            .iter()
+            .any(|provider| provider == "privateCalls");
+
+        let index = match &self.index {
+            Some(index) if enabled => index,
+            _ => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let mut private_methods: HashMap<&str, bool> = HashMap::new();
+        let mut diagnostics = vec![];
+
+        for document in &parsed_file.documents {
+            // Calls on anything but `self`, which private methods can't be
+            let explicit_receiver = document
+                .receiver
+                .as_deref()
+                .map_or(false, |receiver| receiver != "self");
+
+            if document.category != "usage" || document.node_type != "Send" || !explicit_receiver {
+                continue;
+            }
+
+            let private = match private_methods.get(document.name.as_str()) {
+                Some(private) => *private,
+                None => {
+                    let private = self.only_private(&searcher, &document.name)?;
+
+                    private_methods.insert(&document.name, private);
+                    private
+                }
+            };
+
+            if !private {
+                continue;
+            }
+
+            let range = Range::new(
+                Position::new(document.line as u32, document.start_column as u32),
+                Position::new(document.line as u32, document.end_column as u32),
+            );
+
+            diagnostics.push(tower_lsp::lsp_types::Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(PRIVATE_CALL_CODE.to_string())),
+                message: format!(
+                    "`{}` is private and can't be called with an explicit receiver",
+                    document.name
+                ),
+                ..Default::default()
+            });
+        }
+
+        Ok(diagnostics)
+    }
+
+    // Every definition of the name is a private instance method
+    fn only_private(&self, searcher: &Searcher, name: &str) -> tantivy::Result<bool> {
+        let query = SymbolQuery::named(name)
+            .category("assignment")
+            .build(&self.symbol_fields());
+
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(50))?;
+
+        if top_docs.is_empty() {
+            return Ok(false);
+        }
+
+        for (_score, doc_address) in top_docs {
+            let document = searcher.doc(doc_address)?;
+            let visibility = document
+                .get_first(self.schema_fields.visibility_field)
+                .and_then(Value::as_text);
+
+            if visibility != Some("private") {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Formatting a file with the `formatCommand` setting, run from its
+    // workspace folder, see `formatter`
+    pub fn format_job(&self, uri: &Url) -> Result<Option<FormatJob>, String> {
+        let format_command = match &self.format_command {
+            Some(format_command) => format_command,
+            None => return Ok(None),
+        };
+
+        let path = paths::uri_to_path(uri);
+
+        // The formatter is for Ruby files
+        if i18n::is_locale_file(&path) || partials::is_template(&path) {
+            return Ok(None);
+        }
+
+        let text = self
+            .file_contents(uri)
+            .ok_or_else(|| format!("Unable to read {}", uri))?;
+        let working_dir = self
+            .workspace_root(&path)
+            .or(self.workspace_paths.first())
+            .cloned()
+            .or_else(|| {
+                path.rsplit_once('/')
+                    .map(|(directory, _file_name)| directory.to_string())
+            })
+            .unwrap_or_default();
+        let relative_path = self.relative_path(&path);
+
+        FormatJob::new(
+            format_command,
+            relative_path.trim_start_matches("/"),
+            working_dir,
+            text,
+        )
+        .map(Some)
+    }
+
+    // Definitions with the source around them, for `fuzzy/peekDefinition`,
+    // so editors can show a preview without reading each file themselves:
+    //
+    //   {
+    //     "uri": "file:///app/models/user.rb",
+    //     "range": { "start": { "line": 12, ... }, "end": { ... } },
+    //     "snippet": { "startLine": 7, "text": "  ...\n  def full_name\n..." }
+    //   }
+    //
+    // Open files are read from the editor's text, so unsaved changes show.
+    pub fn peek_definitions(
+        &self,
+        params: TextDocumentPositionParams,
+        context_lines: Option<usize>,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<serde_json::Value>> {
+        let context_lines = context_lines
+            .unwrap_or(PEEK_CONTEXT_LINES)
+            .min(MAX_PEEK_CONTEXT_LINES);
+        let mut file_lines: HashMap<Url, Vec<String>> = HashMap::new();
+        let mut peeks = vec![];
+
+        for location in self.find_definitions(params, cancellation)? {
+            let lines = file_lines.entry(location.uri.clone()).or_insert_with(|| {
+                self.file_contents(&location.uri)
+                    .map(|text| text.lines().map(String::from).collect())
+                    .unwrap_or_default()
+            });
+
+            let start_line = (location.range.start.line as usize).saturating_sub(context_lines);
+            let end_line = (location.range.end.line as usize + context_lines + 1).min(lines.len());
+            let snippet = lines
+                .get(start_line..end_line)
+                .map(|snippet_lines| snippet_lines.join("\n"))
+                .unwrap_or_default();
+
+            peeks.push(json!({
+                "uri": location.uri,
+                "range": location.range,
+                "snippet": {
+                    "startLine": start_line,
+                    "text": snippet,
+                },
+            }));
+        }
+
+        Ok(peeks)
+    }
+
+    pub fn find_definitions(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Location>> {
+        let links = self.find_definition_links(params, cancellation)?;
+
+        Ok(links.into_iter().map(|link| link.location).collect())
+    }
+
+    // Definitions with how sure the match is, exact ones first
+    pub fn find_definition_links(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<DefinitionLink>> {
+        let definition_matches = self.definition_matches(params.clone(), cancellation)?;
+
+        self.definition_links(definition_matches, &params, cancellation)
+    }
+
+    // The matches' locations, or when there are none, the definitions
+    // found other ways
+    fn definition_links(
+        &self,
+        definition_matches: DefinitionMatches,
+        params: &TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<DefinitionLink>> {
+        let origin_selection_range = definition_matches.origin_selection_range;
+        let link = |location, exact| DefinitionLink {
+            location,
+            origin_selection_range,
+            exact,
+        };
+
+        let mut links: Vec<DefinitionLink> = definition_matches
+            .documents
+            .iter()
+            .flat_map(|(document, exact)| {
+                self.document_to_location(document)
+                    .map(|location| link(location, *exact))
+            })
+            .collect();
+
+        // Already on a definition, so the others with its name are where it's
+        // overridden or reopened
+        if links.is_empty() && !cancellation.is_cancelled() {
+            let mut documents = self.override_documents(params)?;
+
+            self.rank_by_proximity(&mut documents, Some(&params.text_document.uri));
+
+            links.extend(documents.iter().flat_map(|document| {
+                self.document_to_location(document)
+                    .map(|location| link(location, true))
+            }));
+        }
+
+        if links.is_empty() && !cancellation.is_cancelled() {
+            links = self
+                .autoload_locations(params)?
+                .into_iter()
+                .map(|location| link(location, true))
+                .collect();
+        }
+
+        if links.is_empty() && self.fuzzy_search && !cancellation.is_cancelled() {
+            if let Some(definition_suggestions) = self.definition_suggestions(params)? {
+                links = definition_suggestions
+                    .suggestions
+                    .into_iter()
+                    .map(|(_name, location)| link(location, false))
+                    .collect();
+            }
+        }
+
+        // Definitions are ranked by proximity, so they keep their order
+        // within each group
+        links.sort_by_key(|link| !link.exact);
+
+        let mut locations: Vec<Location> = Vec::with_capacity(links.len());
+
+        links.retain(|link| {
+            let seen = locations.contains(&link.location);
+            locations.push(link.location.clone());
+            !seen
+        });
+
+        Ok(links)
+    }
+
+    // The closest names to the usage at a position that have definitions,
+    // for when it has none of its own. Typos are the usual cause.
+    pub fn definition_suggestions(
+        &self,
+        params: &TextDocumentPositionParams,
+    ) -> tantivy::Result<Option<DefinitionSuggestions>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let usage = match self
+            .indexed_document(&searcher, params, "usage")?
+            .and_then(|usage_document| self.indexed_symbol(&usage_document))
+        {
+            Some(usage) => usage,
+            None => return Ok(None),
+        };
+        let usage_name = usage.name.as_str();
+        let usage_type = usage.node_type.as_str();
+
+        let assignment_types = match USAGE_TYPE_RESTRICTIONS.get(usage_type) {
+            Some(assignment_types) => assignment_types,
+            None => return Ok(None),
+        };
+
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "assignment"),
+            IndexRecordOption::Basic,
+        ));
+        let name_query: Box<dyn Query> = Box::new(FuzzyTermQuery::new(
+            Term::from_field_text(self.schema_fields.name_field, usage_name),
+            edit_distance(usage_name),
+            true,
+        ));
+
+        let mut assignment_type_queries = vec![];
+
+        for assignment_type in assignment_types.iter() {
+            let assignment_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.node_type_field, assignment_type),
+                IndexRecordOption::Basic,
+            ));
+
+            assignment_type_queries.push((Occur::Should, assignment_type_query));
+        }
+
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, category_query),
+            (Occur::Must, name_query),
+            (Occur::Must, Box::new(BooleanQuery::new(assignment_type_queries))),
+        ]);
+
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(50))?;
+        let mut suggestions: Vec<(String, Location)> = vec![];
+
+        for (_score, doc_address) in top_docs {
+            let symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+
+            let seen_name = suggestions
+                .iter()
+                .any(|(suggested_name, _location)| suggested_name == &symbol.name);
+
+            if symbol.name != usage_name && !seen_name {
+                suggestions.push((symbol.name.clone(), symbol.location()));
+            }
+        }
+
+        // Fuzzy matches all score the same, closest first
+        suggestions.sort_by_key(|(name, _location)| levenshtein_distance(name, usage_name));
+        suggestions.truncate(3);
+
+        if suggestions.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(DefinitionSuggestions {
+            name: usage_name.to_string(),
+            suggestions,
+        }))
+    }
+
+    // The top of the file a Rails app would autoload an unindexed constant
+    // from, for files that are generated or haven't been indexed yet
+    fn autoload_locations(
+        &self,
+        params: &TextDocumentPositionParams,
+    ) -> tantivy::Result<Vec<Location>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+
+        let path = paths::uri_to_path(&params.text_document.uri);
+
+        let workspace_path = match self.workspace_root(&path) {
+            Some(workspace_path) if self.is_rails_app(workspace_path) => workspace_path,
+            _ => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let usage = match self
+            .indexed_document(&searcher, params, "usage")?
+            .and_then(|usage_document| self.indexed_symbol(&usage_document))
+        {
+            Some(usage) if usage.node_type == "Const" => usage,
+            _ => return Ok(vec![]),
+        };
+
+        // The class scope is stored innermost first
+        let mut constant_path = usage.class_scope;
+
+        constant_path.reverse();
+        constant_path.push(usage.name);
+
+        let locations = autoload::constant_file_paths(workspace_path, &constant_path)
+            .iter()
+            .map(|file_path| Location::new(paths::path_to_uri(file_path), Range::default()))
+            .collect();
+
+        Ok(locations)
+    }
+
+    // Other definitions of the method, class, module, or constant defined
+    // at a position, like overrides in subclasses and monkeypatches
+    fn override_documents(
+        &self,
+        params: &TextDocumentPositionParams,
+    ) -> tantivy::Result<Vec<Document>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let definition = match self
+            .indexed_document(&searcher, params, "assignment")?
+            .and_then(|definition_document| self.indexed_symbol(&definition_document))
+        {
+            Some(definition) => definition,
+            None => return Ok(vec![]),
+        };
+        let node_type = definition.node_type.as_str();
+
+        // Locals and variables can't be overridden
+        if !["Casgn", "Class", "Def", "Defs", "Module"].contains(&node_type) {
+            return Ok(vec![]);
+        }
+
+        let query = SymbolQuery::named(&definition.name)
+            .category("assignment")
+            .node_types(&[node_type])
+            .build(&self.symbol_fields());
+
+        let definition_location = Some(definition.location());
+        let mut documents = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?
+        {
+            let document = searcher.doc(doc_address)?;
+
+            if self.document_to_location(&document) != definition_location {
+                documents.push(document);
+            }
+        }
+
+        Ok(documents)
+    }
+
+    // A searcher over what's committed, for lookups to share
+    fn searcher(&self) -> tantivy::Result<Option<Searcher>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+
+        Ok(Some(reader.searcher()))
+    }
+
+    fn symbol_fields(&self) -> SymbolFields {
+        SymbolFields {
+            category: self.schema_fields.category_field,
+            name: self.schema_fields.name_field,
+            node_type: self.schema_fields.node_type_field,
+            fuzzy_ruby_scope: self.schema_fields.fuzzy_ruby_scope_field,
+            file_path_id: self.schema_fields.file_path_id,
+        }
+    }
+
+    // The usage or assignment indexed at a position
+    fn indexed_document(
+        &self,
+        searcher: &Searcher,
+        params: &TextDocumentPositionParams,
+        category: &str,
+    ) -> tantivy::Result<Option<Document>> {
+        Ok(self
+            .indexed_documents(searcher, params, category, 1)?
+            .into_iter()
+            .next())
+    }
+
+    // A usage in an open file's cached parse, for one typed since the file
+    // was last indexed, so hovers and lookups don't wait on the indexer
+    fn parsed_usage_document(&self, params: &TextDocumentPositionParams) -> Option<Document> {
+        let uri = &params.text_document.uri;
+        let text = self.open_files.get(uri)?;
+        let parsed_file = self.parsed_file(uri, text);
+        let position = params.position;
+
+        let document = parsed_file.documents.iter().find(|document| {
+            document.category == "usage"
+                && document.line as u32 == position.line
+                && positions::touching_columns(
+                    document.start_column as u32,
+                    document.end_column as u32,
+                )
+                .contains(&position.character)
+        })?;
+
+        let path = paths::uri_to_path(uri);
+        // Newer than any generation, so it isn't taken for a superseded one
+        let file_symbol = self.file_symbol(&path, self.is_user_space(&path), u64::MAX);
+
+        Some(Document::from(document.clone().into_symbol(&file_symbol)))
+    }
+
+    // Every one indexed at a position, for names that stand for more than
+    // one symbol, like `resources :users` routing to each of its actions
+    fn indexed_documents(
+        &self,
+        searcher: &Searcher,
+        params: &TextDocumentPositionParams,
+        category: &str,
+        limit: usize,
+    ) -> tantivy::Result<Vec<Document>> {
+        let path = &paths::uri_to_path(&params.text_document.uri);
+        let position = params.position;
+        let file_path_id = self.file_path_id(path);
+
+        let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, &file_path_id),
+            IndexRecordOption::Basic,
+        ));
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, category),
+            IndexRecordOption::Basic,
+        ));
+        let line_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_u64(self.schema_fields.line_field, position.line.into()),
+            IndexRecordOption::Basic,
+        ));
+        let column_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_u64(self.schema_fields.columns_field, position.character.into()),
+            IndexRecordOption::Basic,
+        ));
+
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, file_path_query),
+            (Occur::Must, category_query),
+            (Occur::Must, line_query),
+            (Occur::Must, column_query),
+        ]);
+
+        let mut documents = vec![];
+
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            documents.push(searcher.doc(doc_address)?);
+        }
+
+        Ok(documents)
+    }
+
+    fn definition_documents(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let definition_matches = self.definition_matches(params, cancellation)?;
+
+        Ok(definition_matches
+            .documents
+            .into_iter()
+            .map(|(document, _exact)| document)
+            .collect())
+    }
+
+    fn definition_matches(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<DefinitionMatches> {
+        match self.searcher()? {
+            Some(searcher) => self.definition_matches_with(&searcher, params, cancellation),
+            None => Ok(DefinitionMatches::default()),
+        }
+    }
+
+    fn definition_matches_with(
+        &self,
+        searcher: &Searcher,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<DefinitionMatches> {
+        let mut documents = Vec::new();
+
+        let retrieved_doc = match self.indexed_document(searcher, &params, "usage")? {
+            Some(usage_document) => usage_document,
+            None => match self.parsed_usage_document(&params) {
+                Some(usage_document) => usage_document,
+                None => {
+                    info!("No usages docs found");
+                    return Ok(DefinitionMatches::default());
+                }
+            },
+        };
+        let usage = match self.indexed_symbol(&retrieved_doc) {
+            Some(usage) => usage,
+            None => return Ok(DefinitionMatches::default()),
+        };
+        let origin_selection_range = Some(usage.range());
+
+        let usage_name = usage.name.as_str();
+        let usage_type = usage.node_type.as_str();
+
+        // A keyword is looked up as a call of its method, and then among the
+        // method's keyword parameters
+        let keyword = match usage_type {
+            "KeywordArg" => Some(usage_name),
+            _ => None,
+        };
+        let (usage_name, usage_type) = match keyword {
+            Some(_) => match usage.parameters.first() {
+                Some(method_name) => (method_name.as_str(), "Send"),
+                None => return Ok(DefinitionMatches::default()),
+            },
+            None => (usage_name, usage_type),
+        };
+
+        // Routes and translation keys name what they're looked up by
+        if usage_type == "Route" {
+            let documents = self.route_action_documents(searcher, &params, cancellation)?;
+
+            return Ok(DefinitionMatches {
+                documents: documents
+                    .into_iter()
+                    .map(|document| (document, true))
+                    .collect(),
+                origin_selection_range,
+            });
+        }
+
+        // The key in each locale it's translated to
+        if usage_type == "I18nKey" {
+            let query = SymbolQuery::named(usage_name)
+                .category("translation")
+                .node_types(USAGE_TYPE_RESTRICTIONS.get(usage_type).unwrap())
+                .build(&self.symbol_fields());
+
+            for (_score, doc_address) in
+                searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?
+            {
+                documents.push(searcher.doc(doc_address)?);
+            }
+
+            self.rank_by_proximity(&mut documents, Some(&params.text_document.uri));
+
+            return Ok(DefinitionMatches {
+                documents: documents
+                    .into_iter()
+                    .map(|document| (document, true))
+                    .collect(),
+                origin_selection_range,
+            });
+        }
+
+        let definition_types = match USAGE_TYPE_RESTRICTIONS.get(usage_type) {
+            Some(definition_types) => definition_types.to_vec(),
+            // Types from DSL rules are only defined as themselves
+            None => vec![usage_type],
+        };
+
+        let mut symbol_query = SymbolQuery::named(usage_name)
+            .category("assignment")
+            .node_types(&definition_types);
+
+        let implicit_yield = usage_type == "Yield"
+            && usage.fuzzy_ruby_scope.last().map_or(false, |method_scope| {
+                method_scope.trim_start_matches("self.") == usage_name
+            });
+
+        let usage_scope = &usage.fuzzy_ruby_scope;
+        let usage_fuzzy_scope = usage_scope.iter().map(String::as_str);
+        let class_scope = usage.class_scope.iter().map(String::as_str);
+
+        // Scopes searched one after another, stopping at the first with
+        // results, when nearer definitions should hide farther ones
+        let mut scope_tiers: Vec<Vec<String>> = vec![];
+        // Types looked up by a name that's unique to their definitions
+        let named_lookup = matches!(usage_type, "Factory" | "Partial")
+            || self.is_named_type(usage_type)
+            || implicit_yield;
+
+        symbol_query = match usage_type {
+            // "Alias" => {},
+            "Const" => symbol_query
+                .scope(usage_fuzzy_scope, ScopeStrategy::Ranked)
+                .scope(class_scope, ScopeStrategy::Exact),
+            // "CSend" => {},
+            // todo: improved indexed scopes so there is a separate class scope, etc
+            // "Cvar" => {},
+            // "Gvar" => {},
+            // todo: improved indexed scopes so there is a separate class scope, etc
+            // "Ivar" => {},
+            // todo: improved to be more accurate
+            // `yield` in a method with an implicit block is named after the
+            // method, whose definition is in the scope around it
+            // Partials are named by their path, and factories and the
+            // types of DSL rules and plugins are global
+            "Factory" | "Partial" => symbol_query,
+            usage_type if self.is_named_type(usage_type) => symbol_query,
+            // Traits in the factory they're used with, then in the
+            // factories it's nested in, nearest first, then anywhere
+            "Trait" => {
+                if let Some(factory_name) = usage_scope.last() {
+                    scope_tiers.push(vec![factory_name.clone()]);
+
+                    for parent_name in self.factory_parent_names(searcher, factory_name)? {
+                        scope_tiers.push(vec![parent_name]);
+                    }
+                }
+
+                scope_tiers.push(vec![]);
+
+                symbol_query
+            }
+            "Yield" if implicit_yield => {
+                let scope_names: Vec<&str> = usage_fuzzy_scope.collect();
+                let (method_scope, outer_scope_names) = scope_names.split_last().unwrap();
+                let method_type = if method_scope.starts_with("self.") {
+                    "Defs"
+                } else {
+                    "Def"
+                };
+
+                symbol_query
+                    .node_types(&[method_type])
+                    .scope(outer_scope_names.iter().copied(), ScopeStrategy::Exact)
+            }
+            // Methods in the usage's own class, then in the classes and
+            // modules it inherits from, nearest first, then anywhere
+            "Send" | "Callback" => {
+                let class_scope: Vec<&str> = class_scope.collect();
+
+                if let Some(class_name) = class_scope.last() {
+                    scope_tiers.push(class_scope.iter().map(|name| name.to_string()).collect());
+
+                    for ancestor_name in self.ancestor_names(searcher, class_name)? {
+                        scope_tiers.push(vec![ancestor_name]);
+                    }
+
+                    scope_tiers.push(vec![]);
+                }
+
+                symbol_query.scope(usage_fuzzy_scope, ScopeStrategy::Ranked)
+            }
+            // "Super" => {},
+            // "ZSuper" => {},
+            usage_type => symbol_query.scope(usage_fuzzy_scope, scope_strategy(usage_type)),
+        };
+
+        if cancellation.is_cancelled() {
+            return Ok(DefinitionMatches::default());
+        }
+
+        if scope_tiers.is_empty() {
+            scope_tiers.push(vec![]);
+        }
+
+        let mut found_in_scope_tier = false;
+
+        // Stopping early keeps what's been found, a lookup that's run out
+        // of time answers with it
+        for scope_tier in scope_tiers {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            let query = symbol_query
+                .clone()
+                .scope(scope_tier.iter().map(String::as_str), ScopeStrategy::Exact)
+                .build(&self.symbol_fields());
+            let assignments_top_docs =
+                searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?;
+
+            for (_score, doc_address) in assignments_top_docs {
+                if cancellation.is_cancelled() {
+                    break;
+                }
+
+                documents.push(searcher.doc(doc_address)?);
+            }
+
+            if !documents.is_empty() {
+                found_in_scope_tier = !scope_tier.is_empty();
+                break;
+            }
+        }
+
+        self.rank_by_proximity(&mut documents, Some(&params.text_document.uri));
+
+        if usage_type == "Const" {
+            self.group_by_constant_path(&mut documents, usage_scope);
+        }
+
+        // Others are exact when they're defined in the usage's scope or
+        // one around it
+        let documents: Vec<(Document, bool)> = documents
+            .into_iter()
+            .map(|document| {
+                let exact = named_lookup
+                    || found_in_scope_tier
+                    || self.indexed_symbol(&document).map_or(false, |symbol| {
+                        usage_scope.starts_with(&symbol.fuzzy_ruby_scope)
+                    });
+
+                (document, exact)
+            })
+            .collect();
+
+        let documents = match keyword {
+            Some(keyword) => self.keyword_parameters(searcher, keyword, documents)?,
+            None => documents,
+        };
+
+        Ok(DefinitionMatches {
+            origin_selection_range,
+            documents,
+        })
+    }
+
+    // The keyword parameters named `keyword` of the methods a call resolves
+    // to, each as exact as its method
+    fn keyword_parameters(
+        &self,
+        searcher: &Searcher,
+        keyword: &str,
+        methods: Vec<(Document, bool)>,
+    ) -> tantivy::Result<Vec<(Document, bool)>> {
+        let mut parameters = vec![];
+
+        for (document, exact) in methods {
+            let method = match self.indexed_symbol(&document) {
+                Some(method) if method.node_type == "Def" || method.node_type == "Defs" => method,
+                _ => continue,
+            };
+
+            let query = SymbolQuery::named(keyword)
+                .category("assignment")
+                .node_types(&["Kwarg", "Kwoptarg"])
+                .file(&method.file_path_id)
+                .scope(
+                    method.fuzzy_ruby_scope.iter().map(String::as_str),
+                    ScopeStrategy::Exact,
+                )
+                .build(&self.symbol_fields());
+
+            for (_score, doc_address) in
+                searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?
+            {
+                let parameter_document = searcher.doc(doc_address)?;
+                let parameter = match self.indexed_symbol(&parameter_document) {
+                    Some(parameter) => parameter,
+                    None => continue,
+                };
+
+                // The method's own, not a block's or a method's nested in it
+                let method_parameter = match parameter.fuzzy_ruby_scope.split_last() {
+                    Some((method_scope, outer_scope)) => {
+                        outer_scope == method.fuzzy_ruby_scope.as_slice()
+                            && method_scope.trim_start_matches("self.") == method.name
+                    }
+                    None => false,
+                };
+
+                if method_parameter {
+                    parameters.push((parameter_document, exact));
+                }
+            }
+        }
+
+        Ok(parameters)
+    }
+
+    // The actions the routes at a position are dispatched to, in the route's
+    // controller or, for actions it inherits, the nearest of its ancestors
+    fn route_action_documents(
+        &self,
+        searcher: &Searcher,
+        params: &TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let mut documents = vec![];
+
+        for route_document in
+            self.indexed_documents(searcher, params, "usage", self.definition_limit)?
+        {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            let route = match self.indexed_symbol(&route_document) {
+                Some(route) if route.node_type == "Route" => route,
+                _ => continue,
+            };
+            let controller_name = match route.fuzzy_ruby_scope.last() {
+                Some(controller_name) => controller_name,
+                None => continue,
+            };
+
+            let mut scope_tiers = vec![route.fuzzy_ruby_scope.clone()];
+
+            for ancestor_name in self.ancestor_names(searcher, controller_name)? {
+                scope_tiers.push(vec![ancestor_name]);
+            }
+
+            for scope_tier in scope_tiers {
+                let query = SymbolQuery::named(&route.name)
+                    .category("assignment")
+                    .node_types(USAGE_TYPE_RESTRICTIONS.get("Route").unwrap())
+                    .scope(scope_tier.iter().map(String::as_str), ScopeStrategy::Exact)
+                    .build(&self.symbol_fields());
+                let action_top_docs =
+                    searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?;
+
+                for (_score, doc_address) in &action_top_docs {
+                    documents.push(searcher.doc(*doc_address)?);
+                }
+
+                if !action_top_docs.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        Ok(documents)
+    }
+
+    // The routes dispatched to the action defined at a position, for
+    // `fuzzy/routes`:
+    //
+    //   {
+    //     "uri": "file:///app/config/routes.rb",
+    //     "range": { "start": { "line": 4, ... }, "end": { ... } },
+    //     "route": "GET /users/:id"
+    //   }
+    pub fn action_routes(
+        &self,
+        params: &TextDocumentPositionParams,
+    ) -> tantivy::Result<Vec<serde_json::Value>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let action = match self
+            .indexed_document(&searcher, params, "assignment")?
+            .and_then(|document| self.indexed_symbol(&document))
+        {
+            Some(action) if action.node_type == "Def" => action,
+            _ => return Ok(vec![]),
+        };
+
+        let query = SymbolQuery::named(&action.name)
+            .category("usage")
+            .node_types(&["Route"])
+            .scope(
+                action.fuzzy_ruby_scope.iter().map(String::as_str),
+                ScopeStrategy::Exact,
+            )
+            .build(&self.symbol_fields());
+
+        let mut routes = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            let route = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(route) => route,
+                None => continue,
+            };
+
+            // Not the action of a namespaced controller with the same name
+            if route.fuzzy_ruby_scope != action.fuzzy_ruby_scope {
+                continue;
+            }
+
+            let description = route.parameters.first().cloned().unwrap_or_default();
+
+            routes.push((route.location(), description));
+        }
+
+        routes.sort_by(|(a, _), (b, _)| {
+            (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start))
+        });
+
+        Ok(routes
+            .into_iter()
+            .map(|(location, description)| {
+                json!({
+                    "uri": location.uri,
+                    "range": location.range,
+                    "route": description,
+                })
+            })
+            .collect())
+    }
+
+    // Keeps the files a class or module is opened in together, so reopened
+    // classes read as one definition. The constant Ruby would resolve the
+    // usage to comes first, looking in the nearest enclosing namespace out:
+    // in `Billing::Invoice`, `Item` is `Billing::Invoice::Item`, then
+    // `Billing::Item`, then `Item`. Other constants with the name follow.
+    fn group_by_constant_path(&self, documents: &mut Vec<Document>, usage_scope: &[String]) {
+        let mut first_positions: HashMap<String, usize> = HashMap::new();
+
+        for (position, document) in documents.iter().enumerate() {
+            first_positions
+                .entry(self.constant_path(document))
+                .or_insert(position);
+        }
+
+        documents.sort_by_cached_key(|document| {
+            let definition_scope: Vec<String> = document
+                .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                .flat_map(Value::as_text)
+                .map(String::from)
+                .collect();
+            let lexical_distance = if usage_scope.starts_with(&definition_scope) {
+                usage_scope.len() - definition_scope.len()
+            } else {
+                usize::MAX
+            };
+
+            let first_position = first_positions[&self.constant_path(document)];
+
+            (lexical_distance, first_position)
+        });
+    }
+
+    // How many files open the class or module, 1 when it isn't reopened or
+    // the document is something else
+    fn reopened_file_count(&self, document: &Document, documents: &[Document]) -> usize {
+        let node_type = document
+            .get_first(self.schema_fields.node_type_field)
+            .and_then(Value::as_text);
+
+        if !matches!(node_type, Some("Class" | "Module")) {
+            return 1;
+        }
+
+        let constant_path = self.constant_path(document);
+
+        documents
+            .iter()
+            .filter(|other_document| self.constant_path(other_document) == constant_path)
+            .flat_map(|other_document| {
+                other_document
+                    .get_first(self.schema_fields.file_path_id)
+                    .and_then(Value::as_text)
+            })
+            .collect::<HashSet<&str>>()
+            .len()
+    }
+
+    // `Billing::Invoice` for `class Invoice` in `module Billing`
+    fn constant_path(&self, document: &Document) -> String {
+        document
+            .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+            .chain(document.get_all(self.schema_fields.name_field))
+            .flat_map(Value::as_text)
+            .collect::<Vec<&str>>()
+            .join("::")
+    }
+
+    // The classes and modules a class inherits from, includes, or prepends,
+    // nearest first. Reopened classes can add ancestors in any file.
+    fn ancestor_names(
+        &self,
+        searcher: &Searcher,
+        class_name: &str,
+    ) -> tantivy::Result<Vec<String>> {
+        let mut ancestor_names: Vec<String> = vec![];
+        let mut pending = VecDeque::from([class_name.to_string()]);
+
+        while let Some(descendant) = pending.pop_front() {
+            let query = BooleanQuery::new(vec![
+                (
+                    Occur::Must,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(self.schema_fields.category_field, "inheritance"),
+                        IndexRecordOption::Basic,
+                    )) as Box<dyn Query>,
+                ),
+                (
+                    Occur::Must,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(
+                            self.schema_fields.fuzzy_ruby_scope_field,
+                            &descendant,
+                        ),
+                        IndexRecordOption::Basic,
+                    )),
+                ),
+            ]);
+
+            for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(100))? {
+                let inheritance_doc = searcher.doc(doc_address)?;
+
+                // Only what the class itself inherits, not classes nested in it
+                let inheriting_class = inheritance_doc
+                    .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                    .flat_map(Value::as_text)
+                    .last();
+
+                if inheriting_class != Some(descendant.as_str()) {
+                    continue;
+                }
+
+                let ancestor_name = inheritance_doc
+                    .get_first(self.schema_fields.name_field)
+                    .and_then(Value::as_text)
+                    .unwrap_or_default()
+                    .to_string();
+
+                if ancestor_name != class_name && !ancestor_names.contains(&ancestor_name) {
+                    ancestor_names.push(ancestor_name.clone());
+                    pending.push_back(ancestor_name);
+                }
+            }
+        }
+
+        Ok(ancestor_names)
+    }
+
+    // The factories a factory is nested in or has as its `parent:`, nearest
+    // first
+    fn factory_parent_names(
+        &self,
+        searcher: &Searcher,
+        factory_name: &str,
+    ) -> tantivy::Result<Vec<String>> {
+        let mut parent_names: Vec<String> = vec![];
+        let mut factory_name = factory_name.to_string();
+
+        loop {
+            let query = SymbolQuery::named(&factory_name)
+                .category("assignment")
+                .node_types(&["Factory"])
+                .build(&self.symbol_fields());
+
+            let parent_name = match searcher.search(&query, &TopDocs::with_limit(1))?.first() {
+                Some((_score, doc_address)) => self
+                    .indexed_symbol(&searcher.doc(*doc_address)?)
+                    .and_then(|factory| factory.fuzzy_ruby_scope.last().cloned()),
+                None => None,
+            };
+
+            match parent_name {
+                Some(parent_name) if !parent_names.contains(&parent_name) => {
+                    parent_names.push(parent_name.clone());
+                    factory_name = parent_name;
+                }
+                _ => return Ok(parent_names),
+            }
+        }
+    }
+
+    pub fn find_implementations(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> tantivy::Result<Vec<Location>> {
+        let path = &paths::uri_to_path(&params.text_document.uri);
+
+        let position = params.position;
+
+        if let Some(index) = &self.index {
+            let reader = index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()?;
+
+            let searcher = reader.searcher();
+            let file_path_id = self.file_path_id(path);
+
+            let text_query = |field: Field, text: &str| -> Box<dyn Query> {
+                Box::new(TermQuery::new(
+                    Term::from_field_text(field, text),
+                    IndexRecordOption::Basic,
+                ))
+            };
+            let method_types_query = || -> Box<dyn Query> {
+                Box::new(BooleanQuery::new(vec![
+                    (
+                        Occur::Should,
+                        text_query(self.schema_fields.node_type_field, "Def"),
+                    ),
+                    (
+                        Occur::Should,
+                        text_query(self.schema_fields.node_type_field, "Defs"),
+                    ),
+                ]))
+            };
+
+            let line_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_u64(self.schema_fields.line_field, position.line.into()),
+                IndexRecordOption::Basic,
+            ));
+            let column_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_u64(self.schema_fields.columns_field, position.character.into()),
+                IndexRecordOption::Basic,
+            ));
+
+            let query = BooleanQuery::new(vec![
+                (
+                    Occur::Must,
+                    text_query(self.schema_fields.file_path_id, &file_path_id.to_string()),
+                ),
+                (Occur::Must, line_query),
+                (Occur::Must, column_query),
+                (
+                    Occur::MustNot,
+                    text_query(self.schema_fields.category_field, "inheritance"),
+                ),
+            ]);
+
+            let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+
+            let mut locations = Vec::new();
+
+            if top_docs.len() == 0 {
+                info!("No implementation docs found");
+                return Ok(locations);
+            }
+
+            let retrieved_symbol = match self.indexed_symbol(&searcher.doc(top_docs[0].1)?) {
+                Some(retrieved_symbol) => retrieved_symbol,
+                None => return Ok(locations),
+            };
+            let method_name = retrieved_symbol.name.as_str();
+            let node_type = retrieved_symbol.node_type.as_str();
+
+            if !["Alias", "CSend", "Def", "Defs", "Send", "Super", "ZSuper"].contains(&node_type) {
+                return Ok(locations);
+            }
+
+            // Classes and modules the method is defined in. A definition
+            // belongs to the last scope it was found in, while a call could
+            // be to any definition with the same name.
+            let mut owners = HashSet::new();
+
+            if retrieved_symbol.category == "assignment" {
+                if let Some(owner) = retrieved_symbol.fuzzy_ruby_scope.last() {
+                    owners.insert(owner.to_string());
+                }
+            } else {
+                let query = BooleanQuery::new(vec![
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.category_field, "assignment"),
+                    ),
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.name_field, method_name),
+                    ),
+                    (Occur::Must, method_types_query()),
+                ]);
+
+                for (_score, doc_address) in
+                    searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?
+                {
+                    let definition_doc = searcher.doc(doc_address)?;
+
+                    if let Some(owner) = definition_doc
+                        .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                        .flat_map(Value::as_text)
+                        .last()
+                    {
+                        owners.insert(owner.to_string());
+                    }
+                }
+            }
+
+            // Walk down the inheritance graph collecting every class that
+            // subclasses, includes, or prepends one of the owners.
+            let mut descendants = HashSet::new();
+            let mut pending: Vec<String> = owners.iter().cloned().collect();
+
+            while let Some(ancestor) = pending.pop() {
+                let query = BooleanQuery::new(vec![
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.category_field, "inheritance"),
+                    ),
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.name_field, &ancestor),
+                    ),
+                ]);
+
+                for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(100))? {
+                    let inheritance_doc = searcher.doc(doc_address)?;
+
+                    if let Some(descendant) = inheritance_doc
+                        .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                        .flat_map(Value::as_text)
+                        .last()
+                    {
+                        let descendant = descendant.to_string();
+
+                        if !owners.contains(&descendant) && descendants.insert(descendant.clone())
+                        {
+                            pending.push(descendant);
+                        }
+                    }
+                }
+            }
+
+            for descendant in &descendants {
+                let query = BooleanQuery::new(vec![
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.category_field, "assignment"),
+                    ),
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.name_field, method_name),
+                    ),
+                    (Occur::Must, method_types_query()),
+                    (
+                        Occur::Must,
+                        text_query(self.schema_fields.fuzzy_ruby_scope_field, descendant),
+                    ),
+                ]);
+
+                for (_score, doc_address) in
+                    searcher.search(&query, &TopDocs::with_limit(self.definition_limit))?
+                {
+                    let implementation_doc = searcher.doc(doc_address)?;
+
+                    let defined_in_descendant = implementation_doc
+                        .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                        .flat_map(Value::as_text)
+                        .last()
+                        == Some(descendant.as_str());
+
+                    if defined_in_descendant {
+                        locations.extend(self.document_to_location(&implementation_doc));
+                    }
+                }
+            }
+
+            results::sort_locations(&mut locations);
+
+            Ok(locations)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    // The variables in the visible range a debugger can show the values of,
+    // for `textDocument/inlineValue`. Lines after where execution stopped
+    // haven't run yet, so they're left out.
+    pub fn inline_values(&self, params: &InlineValueParams) -> tantivy::Result<Vec<InlineValue>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(Vec::new()),
+        };
+
+        let first_line = params.range.start.line;
+        let last_line = params
+            .range
+            .end
+            .line
+            .min(params.context.stopped_location.end.line);
+
+        if first_line > last_line {
+            return Ok(Vec::new());
+        }
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let file_path_id = self.file_path_id(&paths::uri_to_path(&params.text_document.uri));
+        let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, &file_path_id),
+            IndexRecordOption::Basic,
+        ));
+        let lines_query: Box<dyn Query> = Box::new(RangeQuery::new_u64(
+            self.schema_fields.line_field,
+            first_line.into()..u64::from(last_line) + 1,
+        ));
+        let variable_type_queries = INLINE_VALUE_TYPES
+            .iter()
+            .map(|node_type| -> (Occur, Box<dyn Query>) {
+                (
+                    Occur::Should,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(self.schema_fields.node_type_field, node_type),
+                        IndexRecordOption::Basic,
+                    )),
+                )
+            })
+            .collect();
+
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, file_path_query),
+            (Occur::Must, lines_query),
+            (Occur::Must, Box::new(BooleanQuery::new(variable_type_queries))),
+        ]);
+
+        let mut variables = Vec::new();
+
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(1000))? {
+            if let Some(symbol) = self.indexed_symbol(&searcher.doc(doc_address)?) {
+                variables.push((symbol.range(), symbol.name));
+            }
+        }
+
+        variables.sort_by_key(|(range, _name)| (range.start, range.end));
+        variables.dedup_by(|(range, _), (previous_range, _)| range == previous_range);
+
+        Ok(variables
+            .into_iter()
+            .map(|(range, name)| {
+                InlineValue::VariableLookup(InlineValueVariableLookup {
+                    range,
+                    variable_name: Some(name),
+                    case_sensitive_lookup: true,
+                })
+            })
+            .collect())
+    }
+
+    pub fn inlay_hints(&self, uri: &Url, range: Range) -> tantivy::Result<Vec<InlayHint>> {
+        let text = match self.file_contents(uri) {
+            Some(text) => text,
+            None => return Ok(Vec::new()),
+        };
+
+        // Parsed again with its calls' arguments, which the parses kept for
+        // other requests leave out
+        let mut parser = self.parser();
+        parser.argument_hints = true;
+        let parsed_file = parser.parse_text(&paths::uri_to_path(uri), &text, false);
+
+        if !parsed_file.parsed {
+            return Ok(Vec::new());
+        }
+
+        if let Some(index) = &self.index {
+            let reader = index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()?;
+
+            let searcher = reader.searcher();
+            let mut method_parameters: HashMap<String, Option<Vec<String>>> = HashMap::new();
+            let mut hints = Vec::new();
+
+            for document in &parsed_file.documents {
+                let line = document.line as u32;
+
+                if document.category != "hint" || line < range.start.line || line > range.end.line
+                {
+                    continue;
+                }
+
+                if !method_parameters.contains_key(&document.name) {
+                    let parameters = self.unambiguous_parameters(&searcher, &document.name)?;
+                    method_parameters.insert(document.name.clone(), parameters);
+                }
+
+                let parameters = match &method_parameters[&document.name] {
+                    Some(parameters) => parameters,
+                    None => continue,
+                };
+
+                let position: usize = document.parameters[0].parse().unwrap();
+
+                let parameter_kinds: &[&str] = match document.node_type {
+                    "Argument" => &["arg", "optarg"],
+                    "Numblock" => &["yield"],
+                    _ => &[],
+                };
+
+                let parameter_name = parameters
+                    .iter()
+                    .filter_map(|parameter| parameter.split_once(":"))
+                    .filter(|(kind, _name)| parameter_kinds.contains(kind))
+                    .nth(position)
+                    .map(|(_kind, name)| name);
+
+                let parameter_name = match parameter_name {
+                    Some(name) if name != "_" => name,
+                    _ => continue,
+                };
+
+                let hint = if document.node_type == "Argument" {
+                    InlayHint {
+                        position: Position::new(line, document.start_column as u32),
+                        label: InlayHintLabel::String(format!("{}:", parameter_name)),
+                        kind: Some(InlayHintKind::PARAMETER),
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: None,
+                        padding_right: Some(true),
+                        data: None,
+                    }
+                } else {
+                    InlayHint {
+                        position: Position::new(line, document.end_column as u32),
+                        label: InlayHintLabel::String(parameter_name.to_string()),
+                        kind: Some(InlayHintKind::PARAMETER),
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: Some(true),
+                        padding_right: None,
+                        data: None,
+                    }
+                };
+
+                hints.push(hint);
+            }
+
+            Ok(hints)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    // Parameters of the only method definition with the given name, or None
+    // when the name is ambiguous or not defined.
+    fn unambiguous_parameters(
+        &self,
+        searcher: &Searcher,
+        method_name: &str,
+    ) -> tantivy::Result<Option<Vec<String>>> {
+        let query = self.method_definitions_query(method_name);
+        let definitions = searcher.search(&query, &TopDocs::with_limit(2))?;
+
+        if definitions.len() != 1 {
+            return Ok(None);
+        }
+
+        let definition = searcher.doc(definitions[0].1)?;
+        let parameters = definition
+            .get_all(self.schema_fields.parameters_field)
+            .flat_map(Value::as_text)
+            .map(|parameter| parameter.to_string())
+            .collect();
+
+        Ok(Some(parameters))
+    }
+
+    fn method_definitions_query(&self, method_name: &str) -> BooleanQuery {
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "assignment"),
+            IndexRecordOption::Basic,
+        ));
+        let name_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.name_field, method_name),
+            IndexRecordOption::Basic,
+        ));
+
+        let mut method_type_queries = vec![];
+
+        for method_type in ["Def", "Defs"] {
+            let method_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.node_type_field, method_type),
+                IndexRecordOption::Basic,
+            ));
+
+            method_type_queries.push((Occur::Should, method_type_query));
+        }
+
+        BooleanQuery::new(vec![
+            (Occur::Must, category_query),
+            (Occur::Must, name_query),
+            (Occur::Must, Box::new(BooleanQuery::new(method_type_queries))),
+        ])
+    }
+
+    // The definition under the cursor, with its Sorbet or RBS signature when
+    // one was indexed
+    pub fn hover(&self, params: TextDocumentPositionParams) -> tantivy::Result<Option<Hover>> {
+        let documents = self.definition_documents(params, &CancellationToken::default())?;
+
+        Ok(self.definition_hover(&documents))
+    }
+
+    fn definition_hover(&self, documents: &[Document]) -> Option<Hover> {
+        let document = documents
+            .iter()
+            .find(|document| {
+                document
+                    .get_first(self.schema_fields.signature_field)
+                    .is_some()
+            })
+            .or(documents.first());
+
+        let document = match document {
+            Some(document) => document,
+            None => return None,
+        };
+
+        // Clients without Markdown get the same sections as plain text
+        let markdown = self.markdown_hover_support;
+        let code = |code: &str| {
+            if markdown {
+                format!("```ruby\n{}\n```", code)
+            } else {
+                code.to_string()
+            }
+        };
+        let separator = if markdown { "\n---\n" } else { "\n\n" };
+
+        let mut value = code(&self.declaration(document));
+
+        let reopened_file_count = self.reopened_file_count(document, documents);
+
+        if reopened_file_count > 1 {
+            value.push_str(&format!("\nDefined in {} files", reopened_file_count));
+        }
+
+        if let Some(signature) = document
+            .get_first(self.schema_fields.signature_field)
+            .and_then(Value::as_text)
+        {
+            value.push_str(&format!("\n{}", code(signature)));
+        }
+
+        if let Some(documentation) = document
+            .get_first(self.schema_fields.documentation_field)
+            .and_then(Value::as_text)
+        {
+            let documentation = if markdown {
+                yard_to_markdown(documentation)
+            } else {
+                documentation.to_string()
+            };
+
+            value.push_str(&format!("{}{}", separator, documentation));
+        }
+
+        if let Some(symbol) = self.indexed_symbol(document) {
+            if self.is_stale(&symbol.file_path_id) {
+                let relative_path = self.relative_path(&symbol.path());
+                let note = if markdown {
+                    format!(
+                        "_From the last version of `{}` without syntax errors._",
+                        relative_path
+                    )
+                } else {
+                    format!(
+                        "From the last version of {} without syntax errors.",
+                        relative_path
+                    )
+                };
+
+                value.push_str(&format!("{}{}", separator, note));
+            }
+        }
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: if markdown {
+                    MarkupKind::Markdown
+                } else {
+                    MarkupKind::PlainText
+                },
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    // Every method with the name of the call the cursor is in, since the
+    // receiver's class usually isn't known
+    pub fn signature_help(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> tantivy::Result<Option<SignatureHelp>> {
+        let position = params.position;
+
+        let text = match self.file_contents(&params.text_document.uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+
+        let line_prefix = positions::line_prefix(&text, position);
+
+        let (method_name, active_parameter) = match open_call(line_prefix) {
+            Some(open_call) => open_call,
+            None => return Ok(None),
+        };
+
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let query = self.method_definitions_query(&method_name);
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+
+        let mut signatures = vec![];
+
+        for (_score, doc_address) in top_docs {
+            let document = searcher.doc(doc_address)?;
+
+            let parameter_labels: Vec<String> = document
+                .get_all(self.schema_fields.parameters_field)
+                .flat_map(Value::as_text)
+                .filter_map(parameter_label)
+                .collect();
+
+            let documentation = document
+                .get_first(self.schema_fields.signature_field)
+                .and_then(Value::as_text)
+                .map(|signature| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("```ruby\n{}\n```", signature),
+                    })
+                });
+
+            signatures.push(SignatureInformation {
+                label: format!("{}({})", method_name, parameter_labels.join(", ")),
+                documentation,
+                parameters: Some(
+                    parameter_labels
+                        .into_iter()
+                        .map(|label| ParameterInformation {
+                            label: ParameterLabel::Simple(label),
+                            documentation: None,
+                        })
+                        .collect(),
+                ),
+                active_parameter: None,
+            });
+        }
+
+        if signatures.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(SignatureHelp {
+            signatures,
+            active_signature: Some(0),
+            active_parameter: Some(active_parameter),
+        }))
+    }
+
+    // How a definition reads in Ruby, `def self.find(id)` or `class User`
+    fn declaration(&self, document: &Document) -> String {
+        let name = document
+            .get_first(self.schema_fields.name_field)
+            .and_then(Value::as_text)
+            .unwrap_or("");
+        let node_type = document
+            .get_first(self.schema_fields.node_type_field)
+            .and_then(Value::as_text)
+            .unwrap_or("");
+
+        let parameter_labels: Vec<String> = document
+            .get_all(self.schema_fields.parameters_field)
+            .flat_map(Value::as_text)
+            .filter_map(parameter_label)
+            .collect();
+
+        let parameters = if parameter_labels.is_empty() {
+            String::new()
+        } else {
+            format!("({})", parameter_labels.join(", "))
+        };
+
+        let visibility = match document
+            .get_first(self.schema_fields.visibility_field)
+            .and_then(Value::as_text)
+        {
+            Some("private") => "private ",
+            Some("protected") => "protected ",
+            _ => "",
+        };
+
+        match node_type {
+            "Def" => format!("{}def {}{}", visibility, name, parameters),
+            "Defs" => format!("def self.{}{}", name, parameters),
+            "Class" => format!("class {}", name),
+            "Module" => format!("module {}", name),
+            _ => name.to_string(),
+        }
+    }
+
+    pub fn find_highlights(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> tantivy::Result<Vec<DocumentHighlight>> {
+        if let Ok(search_results) = self.find_references(params, &CancellationToken::default()) {
+            Ok(self.document_highlights(&search_results))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn document_highlights(&self, search_results: &[Document]) -> Vec<DocumentHighlight> {
+        let mut highlights = Vec::new();
+
+        for search_result in search_results {
+            let symbol = match self.indexed_symbol(search_result) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+            let range = symbol.range();
+
+            // Defining a method, class, or module doesn't write a value
+            // the way assigning a variable or constant does
+            let kind = match (symbol.category.as_str(), symbol.node_type.as_str()) {
+                (_, "Alias" | "Class" | "Def" | "Defs" | "Module") => DocumentHighlightKind::TEXT,
+                ("assignment", _) => DocumentHighlightKind::WRITE,
+                _ => DocumentHighlightKind::READ,
+            };
+
+            let document_highlight = DocumentHighlight {
+                range,
+                kind: Some(kind),
+            };
+
+            highlights.push(document_highlight);
+        }
+
+        // Op-assignments are indexed as both a read and a write of the
+        // same range, the write is what's shown
+        results::merge_highlights(highlights)
+    }
+
+    // Answers for each query in a `fuzzy/batch` request, in order:
+    //
+    //   {
+    //     "definition": { "exact": [LocationLink], "fuzzy": [LocationLink] },
+    //     "highlights": [DocumentHighlight],
+    //     "hover": Hover
+    //   }
+    //
+    // with only what the query asks for. The queries share a searcher, and
+    // a position's definitions and hover are resolved once for both. When
+    // cancelled, the queries answered so far are.
+    pub fn batch(
+        &self,
+        queries: &[BatchQuery],
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<serde_json::Value>> {
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(vec![json!({}); queries.len()]),
+        };
+
+        let mut answers = vec![];
+
+        for query in queries {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            let mut answer = serde_json::Map::new();
+
+            if query.definition || query.hover {
+                let definition_matches =
+                    self.definition_matches_with(&searcher, query.position.clone(), cancellation)?;
+
+                if query.hover {
+                    let documents: Vec<Document> = definition_matches
+                        .documents
+                        .iter()
+                        .map(|(document, _exact)| document.clone())
+                        .collect();
+
+                    answer.insert("hover".into(), json!(self.definition_hover(&documents)));
+                }
+
+                if query.definition {
+                    let links =
+                        self.definition_links(definition_matches, &query.position, cancellation)?;
+
+                    answer.insert("definition".into(), DefinitionLink::sections(&links));
+                }
+            }
+
+            if query.highlights {
+                let documents =
+                    self.find_references_with(&searcher, query.position.clone(), cancellation)?;
+
+                let highlights = self.document_highlights(&documents);
+
+                answer.insert("highlights".into(), json!(highlights));
+            }
+
+            answers.push(serde_json::Value::Object(answer));
+        }
+
+        Ok(answers)
+    }
+
+    // Editing a local variable's name edits the rest of its occurrences in
+    // the method along with it, found the same way as highlights
+    pub fn linked_editing_ranges(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> tantivy::Result<Option<LinkedEditingRanges>> {
+        let position = params.position;
+        let documents = self.find_references(params, &CancellationToken::default())?;
+
+        let token = match self
+            .document_at_position(&documents, position)
+            .and_then(|token| self.indexed_symbol(token))
+        {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let local_types = [
+            "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg",
+            "Restarg", "Shadowarg", "Lvar",
+        ];
+
+        if !local_types.contains(&token.node_type.as_str()) {
+            return Ok(None);
+        }
+
+        let mut ranges = Vec::new();
+        let symbols = documents
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document));
+
+        for symbol in symbols {
+            // `yield` uses the block parameter but doesn't spell its name
+            if symbol.node_type == "Yield" {
+                continue;
+            }
+
+            let range = symbol.range();
+
+            if !ranges.contains(&range) {
+                ranges.push(range);
+            }
+        }
+
+        Ok(Some(LinkedEditingRanges {
+            ranges,
+            word_pattern: Some("[a-z_][A-Za-z0-9_]*".to_string()),
+        }))
+    }
+
+    fn document_at_position<'a>(
+        &self,
+        documents: &'a [Document],
+        position: Position,
+    ) -> Option<&'a Document> {
+        documents.iter().find(|document| {
+            self.indexed_symbol(document)
+                .map_or(false, |symbol| symbol.contains(position))
+        })
+    }
+
+    pub fn find_references(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        match self.searcher()? {
+            Some(searcher) => self.find_references_with(&searcher, params, cancellation),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn find_references_with(
+        &self,
+        searcher: &Searcher,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let path = &paths::uri_to_path(&params.text_document.uri);
+
+        let position = params.position;
+
+        let character_position = position.character;
+        let character_line = position.line;
+        let file_path_id = self.file_path_id(path);
+
+        let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, &file_path_id.to_string()),
+            IndexRecordOption::Basic,
+        ));
+        let line_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_u64(self.schema_fields.line_field, character_line.into()),
+            IndexRecordOption::Basic,
+        ));
+        let column_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_u64(self.schema_fields.columns_field, character_position.into()),
+            IndexRecordOption::Basic,
+        ));
+        let inheritance_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "inheritance"),
+            IndexRecordOption::Basic,
+        ));
+
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, file_path_query),
+            (Occur::Must, line_query),
+            (Occur::Must, column_query),
+            (Occur::MustNot, inheritance_query),
+        ]);
+
+        let usage_top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+
+        if usage_top_docs.len() == 0 {
+            info!("No highlight usages docs found");
+            return Ok(Vec::new());
+        }
+
+        let usage = match self.indexed_symbol(&searcher.doc(usage_top_docs[0].1)?) {
+            Some(usage) => usage,
+            None => return Ok(Vec::new()),
+        };
+        let usage_name = usage.name.as_str();
+        let token_type = usage.node_type.as_str();
+
+        // Types from DSL rules and plugins only occur as themselves
+        let occurrence_types = if self.is_named_type(token_type) {
+            vec![token_type]
+        } else {
+            resolver::occurrence_types(token_type)
+        };
+
+        if occurrence_types.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query = SymbolQuery::named(usage_name)
+            .file(&file_path_id)
+            .node_types(&occurrence_types)
+            .scope(
+                usage.fuzzy_ruby_scope.iter().map(String::as_str),
+                resolver::scope_strategy(token_type),
+            )
+            .build(&self.symbol_fields());
+
+        if cancellation.is_cancelled() {
+            return Ok(Vec::new());
+        }
+
+        let results = searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?;
+
+        let mut documents = Vec::new();
+
+        for (_score, doc_address) in results {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            documents.push(searcher.doc(doc_address).unwrap())
+        }
+
+        Ok(documents)
+    }
+
+    // The symbol's occurrences in the file, without its definitions unless
+    // the declaration is included, which adds the ones in other files too
+    pub fn reference_locations(
+        &self,
+        params: ReferenceParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Location>> {
+        let uri = &params.text_document_position.text_document.uri;
+        let include_declaration = params.context.include_declaration;
+
+        if let Some(locations) =
+            self.translation_locations(&params.text_document_position, include_declaration)?
+        {
+            return Ok(locations);
+        }
+
+        let mut documents =
+            self.find_references(params.text_document_position.clone(), cancellation)?;
+
+        // Calls in other files on a receiver named after its class, and
+        // callbacks, like a subclass's `before_action`, call the method at
+        // the position too
+        let method = self
+            .document_at_position(&documents, params.text_document_position.position)
+            .and_then(|document| self.indexed_symbol(document))
+            .filter(|symbol| {
+                symbol.category == "assignment"
+                    && (symbol.node_type == "Def" || symbol.node_type == "Defs")
+            });
+
+        // Partials, factories, traits, and the symbols of DSL rules are used
+        // across the workspace, and found from any use or declaration of
+        // them. A partial is also found from anywhere in its file without a
+        // symbol.
+        let named_symbol = match documents
+            .first()
+            .and_then(|document| self.indexed_symbol(document))
+        {
+            Some(symbol)
+                if NAMED_SYMBOL_TYPES.contains(&symbol.node_type.as_str())
+                    || self.is_named_type(&symbol.node_type) =>
+            {
+                Some((symbol.node_type, symbol.name))
+            }
+            Some(_) => None,
+            None => partials::partial_name(&paths::uri_to_path(uri))
+                .map(|partial_name| ("Partial".to_string(), partial_name)),
+        };
+
+        if let Some((node_type, name)) = named_symbol {
+            return self.named_locations(&node_type, &name, "assignment", include_declaration);
+        }
+
+        if !include_declaration {
+            documents.retain(|document| {
+                document
+                    .get_first(self.schema_fields.category_field)
+                    .and_then(Value::as_text)
+                    != Some("assignment")
+            });
+        }
+
+        let mut locations = self.documents_to_locations(uri, documents);
+
+        if let Some(method) = &method {
+            if method.node_type == "Def" {
+                locations.extend(self.callback_locations(method)?);
+            }
+
+            locations.extend(self.call_locations(method)?);
+            results::sort_locations(&mut locations);
+        }
+
+        if !include_declaration || cancellation.is_cancelled() {
+            return Ok(locations);
+        }
+
+        let file_path_id = self.file_path_id(&paths::uri_to_path(uri));
+
+        for document in self.definition_documents(params.text_document_position, cancellation)? {
+            // Already found in the file itself
+            if document
+                .get_first(self.schema_fields.file_path_id)
+                .and_then(Value::as_text)
+                == Some(file_path_id.as_str())
+            {
+                continue;
+            }
+
+            let location = match self.document_to_location(&document) {
+                Some(location) => location,
+                None => continue,
+            };
+
+            locations.push(location);
+        }
+
+        results::sort_locations(&mut locations);
+
+        Ok(locations)
+    }
+
+    // The callbacks calling a method, in its class or ones inheriting from
+    // it, wherever they're declared
+    fn callback_locations(&self, method: &IndexedSymbol) -> tantivy::Result<Vec<Location>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+        let class_name = match method.fuzzy_ruby_scope.last() {
+            Some(class_name) => class_name,
+            None => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let query = SymbolQuery::named(&method.name)
+            .category("usage")
+            .node_types(&["Callback"])
+            .build(&self.symbol_fields());
+
+        let mut locations = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            let callback = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(callback) => callback,
+                None => continue,
+            };
+            let callback_class = match callback.class_scope.last() {
+                Some(callback_class) => callback_class,
+                None => continue,
+            };
+
+            if callback_class == class_name
+                || self
+                    .ancestor_names(&searcher, callback_class)?
+                    .contains(class_name)
+            {
+                locations.push(callback.location());
+            }
+        }
+
+        Ok(locations)
+    }
+
+    // The calls to a method across the workspace whose receiver names its
+    // class, so `save` isn't found on every model. Calls on `self` are in
+    // the class or ones inheriting from it, and calls on anything else,
+    // like `orders.last`, can't be told apart and are left out.
+    fn call_locations(&self, method: &IndexedSymbol) -> tantivy::Result<Vec<Location>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+        let class_name = match method.fuzzy_ruby_scope.last() {
+            Some(class_name) => class_name,
+            None => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let query = SymbolQuery::named(&method.name)
+            .category("usage")
+            .node_types(&["Send", "CSend"])
+            .build(&self.symbol_fields());
+
+        let mut locations = vec![];
+        let mut descendants: HashMap<String, bool> = HashMap::new();
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            let call = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(call) => call,
+                None => continue,
+            };
+            let receiver = match &call.receiver {
+                Some(receiver) => receiver,
+                None => continue,
+            };
+
+            // The call's scope ends with the method it's made in
+            let call_class = call
+                .fuzzy_ruby_scope
+                .iter()
+                .rev()
+                .find(|name| name.starts_with(|char: char| char.is_uppercase()));
+
+            let names_class = if receiver == "self" {
+                match call_class {
+                    Some(call_class) if call_class == class_name => true,
+                    Some(call_class) => match descendants.get(call_class) {
+                        Some(descendant) => *descendant,
+                        None => {
+                            let descendant = self
+                                .ancestor_names(&searcher, call_class)?
+                                .contains(class_name);
+                            descendants.insert(call_class.clone(), descendant);
+                            descendant
+                        }
+                    },
+                    None => false,
+                }
+            } else if method.node_type == "Defs" {
+                receivers::names_class(receiver, &method.fuzzy_ruby_scope)
+            } else {
+                receivers::names_instance(receiver, &method.fuzzy_ruby_scope)
+            };
+
+            if names_class {
+                locations.push(call.location());
+            }
+        }
+
+        Ok(locations)
+    }
+
+    // A translation key's lookups across the workspace, from the key in a
+    // locale file or from one of its lookups, and the key in each locale
+    // when the declaration is included. None when there's no key at the
+    // position.
+    fn translation_locations(
+        &self,
+        params: &TextDocumentPositionParams,
+        include_declaration: bool,
+    ) -> tantivy::Result<Option<Vec<Location>>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let mut documents = self.indexed_documents(&searcher, params, "translation", 1)?;
+        documents.extend(self.indexed_documents(
+            &searcher,
+            params,
+            "usage",
+            self.definition_limit,
+        )?);
+
+        let key = match documents
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .find(|symbol| symbol.node_type == "I18nKey")
+        {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        self.named_locations("I18nKey", &key.name, "translation", include_declaration)
+            .map(Some)
+    }
+
+    // The uses of a symbol named the same wherever it's used, like a
+    // partial or a factory, across the workspace, and its declarations when
+    // they're included
+    fn named_locations(
+        &self,
+        node_type: &str,
+        name: &str,
+        declaration_category: &str,
+        include_declaration: bool,
+    ) -> tantivy::Result<Vec<Location>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let categories = if include_declaration {
+            vec!["usage", declaration_category]
+        } else {
+            vec!["usage"]
+        };
+
+        let mut locations = vec![];
+
+        for category in categories {
+            let query = SymbolQuery::named(name)
+                .category(category)
+                .node_types(&[node_type])
+                .build(&self.symbol_fields());
+
+            for (_score, doc_address) in
+                searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+            {
+                if let Some(location) = self.document_to_location(&searcher.doc(doc_address)?) {
+                    locations.push(location);
+                }
+            }
+        }
+
+        results::sort_locations(&mut locations);
+
+        Ok(locations)
+    }
+
+    // What renaming the symbol at a position edits, its occurrences in the
+    // file and, for a keyword parameter, the keywords passing it at calls to
+    // its method. Renaming from one of those keywords renames the parameter.
+    // Instance variables are renamed in their class instead, in every file
+    // it's opened in, and locals only in their own method or block.
+    pub fn rename_documents(
+        &self,
+        params: TextDocumentPositionParams,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(vec![]),
+        };
+        let position = params.position;
+
+        let keyword = self
+            .indexed_document(&searcher, &params, "usage")?
+            .and_then(|document| self.indexed_symbol(&document))
+            .filter(|symbol| symbol.node_type == "KeywordArg");
+
+        let mut documents = vec![];
+
+        let parameter = match keyword {
+            Some(_) => self
+                .definition_matches_with(&searcher, params, cancellation)?
+                .documents
+                .into_iter()
+                .find_map(|(document, _exact)| self.indexed_symbol(&document)),
+            // The parameter itself or a read of it in the method
+            None => {
+                documents = self.find_references_with(&searcher, params, cancellation)?;
+
+                let token = self
+                    .document_at_position(&documents, position)
+                    .and_then(|document| self.indexed_symbol(document));
+
+                match token {
+                    Some(token) if token.node_type == "Ivar" || token.node_type == "Ivasgn" => {
+                        return self.instance_variable_documents(&searcher, &token, cancellation);
+                    }
+                    // Classes and modules wherever they're used, not
+                    // constants holding values
+                    Some(token) if CONSTANT_TYPES.contains(&token.node_type.as_str()) => {
+                        let constant_documents = self.constant_documents(&searcher, &token.name)?;
+                        let defined_here = constant_documents.iter().any(|document| {
+                            self.indexed_symbol(document).map_or(false, |symbol| {
+                                symbol.category == "assignment" && symbol.node_type != "Const"
+                            })
+                        });
+
+                        if defined_here {
+                            return Ok(constant_documents);
+                        }
+                    }
+                    // Locals in their own method or block only, not in a
+                    // method defined in it
+                    Some(token)
+                        if resolver::scope_strategy(&token.node_type) == ScopeStrategy::Exact =>
+                    {
+                        self.retain_scope(&mut documents, &token.fuzzy_ruby_scope);
+                    }
+                    _ => {}
+                }
+
+                documents
+                    .iter()
+                    .filter_map(|document| self.indexed_symbol(document))
+                    .find(|symbol| {
+                        symbol.category == "assignment"
+                            && (symbol.node_type == "Kwarg" || symbol.node_type == "Kwoptarg")
+                    })
+            }
+        };
+
+        let parameter = match parameter {
+            Some(parameter) => parameter,
+            None => return Ok(documents),
+        };
+
+        if keyword.is_some() {
+            let parameter_position = TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(parameter.location().uri),
+                parameter.range().start,
+            );
+
+            documents = self.find_references_with(&searcher, parameter_position, cancellation)?;
+            self.retain_scope(&mut documents, &parameter.fuzzy_ruby_scope);
+        }
+
+        documents.extend(self.keyword_argument_documents(&searcher, &parameter, cancellation)?);
+
+        Ok(documents)
+    }
+
+    // Finding a local's occurrences matches every scope name of its method,
+    // which a method defined inside it has too
+    fn retain_scope(&self, documents: &mut Vec<Document>, scope: &[String]) {
+        documents.retain(|document| {
+            self.indexed_symbol(document)
+                .map_or(false, |symbol| symbol.fuzzy_ruby_scope == scope)
+        });
+    }
+
+    // An instance variable's occurrences in its class, wherever it's opened.
+    // The class's instance methods share theirs, and its body and class
+    // methods have their own.
+    fn instance_variable_documents(
+        &self,
+        searcher: &Searcher,
+        instance_variable: &IndexedSymbol,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let class_scope = self.class_scope_of(&instance_variable.fuzzy_ruby_scope);
+        let class_level = |scope: &[String]| {
+            scope
+                .get(class_scope.len())
+                .map_or(true, |method_scope| method_scope.starts_with("self."))
+        };
+        let instance_variable_class_level = class_level(&instance_variable.fuzzy_ruby_scope);
+
+        let query = SymbolQuery::named(&instance_variable.name)
+            .node_types(&["Ivar", "Ivasgn"])
+            .scope(class_scope.iter().map(String::as_str), ScopeStrategy::Exact)
+            .build(&self.symbol_fields());
+
+        let mut documents = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            let document = searcher.doc(doc_address)?;
+            let occurrence = match self.indexed_symbol(&document) {
+                Some(occurrence) => occurrence,
+                None => continue,
+            };
+
+            if self.class_scope_of(&occurrence.fuzzy_ruby_scope) == class_scope
+                && class_level(&occurrence.fuzzy_ruby_scope) == instance_variable_class_level
+            {
+                documents.push(document);
+            }
+        }
+
+        Ok(documents)
+    }
+
+    // The keywords passing a keyword parameter at the calls resolving to its
+    // method, across the workspace
+    fn keyword_argument_documents(
+        &self,
+        searcher: &Searcher,
+        parameter: &IndexedSymbol,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        let method_name = match parameter.fuzzy_ruby_scope.last() {
+            Some(method_scope) => method_scope.trim_start_matches("self."),
+            None => return Ok(vec![]),
+        };
+
+        let query = SymbolQuery::named(&parameter.name)
+            .category("usage")
+            .node_types(&["KeywordArg"])
+            .build(&self.symbol_fields());
+
+        let mut documents = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            if cancellation.is_cancelled() {
+                break;
+            }
+
+            let document = searcher.doc(doc_address)?;
+            let keyword = match self.indexed_symbol(&document) {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+
+            if keyword.parameters.first().map(String::as_str) != Some(method_name) {
+                continue;
+            }
+
+            let keyword_position = TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(keyword.location().uri),
+                keyword.range().start,
+            );
+            let passes_parameter = self
+                .definition_matches_with(searcher, keyword_position, cancellation)?
+                .documents
+                .iter()
+                .filter_map(|(document, _exact)| self.indexed_symbol(document))
+                .any(|definition| {
+                    definition.file_path_id == parameter.file_path_id
+                        && definition.range() == parameter.range()
+                });
+
+            if passes_parameter {
+                documents.push(document);
+            }
+        }
+
+        Ok(documents)
+    }
+
+    pub fn prepare_rename(&self, params: TextDocumentPositionParams) -> Result<Range, String> {
+        let position = params.position;
+        let file_path_id = self.file_path_id(&paths::uri_to_path(&params.text_document.uri));
+        let documents = self
+            .rename_documents(params, &CancellationToken::default())
+            .map_err(|error| error.to_string())?;
+        let symbols: Vec<IndexedSymbol> = documents
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .collect();
+
+        // Keywords at calls in other files can be at the same position
+        let token = match symbols
+            .iter()
+            .find(|symbol| symbol.file_path_id == file_path_id && symbol.contains(position))
+        {
+            Some(token) => token,
+            None => return Err("There is no renameable symbol at the cursor.".to_string()),
+        };
+
+        let name = token.name.as_str();
+        let node_type = token.node_type.as_str();
+
+        // `super` is indexed as a usage of the enclosing method, but the
+        // token itself is the keyword.
+        if node_type == "Super" || node_type == "ZSuper" || RUBY_KEYWORDS.contains(&name) {
+            return Err(format!("`{}` is a keyword and can't be renamed.", name));
+        }
+
+        // `yield` is indexed as a usage of the method's `&block` parameter,
+        // or of the method when the block is implicit
+        if node_type == "Yield" {
+            return Err("`yield` is a keyword and can't be renamed.".to_string());
+        }
+
+        if node_type == "I18nKey" {
+            return Err("Translation keys can't be renamed.".to_string());
+        }
+
+        if node_type == "Partial" {
+            return Err("Partials are renamed by renaming their file.".to_string());
+        }
+
+        if node_type == "Factory" || node_type == "Trait" {
+            return Err("Factories and traits can't be renamed.".to_string());
+        }
+
+        if self.is_named_type(node_type) {
+            return Err("Symbols from DSL rules and plugins can't be renamed.".to_string());
+        }
+
+        if node_type == "ForwardArg" || node_type == "ForwardedArgs" {
+            return Err("`...` can't be renamed.".to_string());
+        }
+
+        if node_type == "RegexpMatch" || node_type == "NthRef" || node_type == "BackRef" {
+            return Err("Regexp match globals can't be renamed.".to_string());
+        }
+
+        // Instance variables are assigned in any method of their class
+        if !self.force_rename && node_type != "Ivar" && node_type != "Ivasgn" {
+            let assignment_scopes: HashSet<String> = symbols
+                .iter()
+                .filter(|symbol| symbol.category == "assignment")
+                .map(|symbol| symbol.fuzzy_ruby_scope.join("::"))
+                .collect();
+
+            if assignment_scopes.len() > 1 {
+                return Err(format!(
+                    "`{}` is defined in {} different scopes, set `forceRename` to rename it anyway.",
+                    name,
+                    assignment_scopes.len()
+                ));
+            }
+        }
+
+        Ok(token.range())
+    }
+
+    pub fn find_rename_conflict(
+        &self,
+        params: TextDocumentPositionParams,
+        new_name: &str,
+    ) -> tantivy::Result<Option<String>> {
+        if RUBY_KEYWORDS.contains(&new_name) {
+            return Ok(Some(format!("`{}` is a Ruby keyword.", new_name)));
+        }
+
+        let method_types = ["Alias", "Def", "Defs"];
+        let position = params.position;
+        let documents = self.find_references(params, &CancellationToken::default())?;
+
+        let token = self
+            .document_at_position(&documents, position)
+            .and_then(|document| self.indexed_symbol(document));
+
+        if let Some(token) = token {
+            if resolver::scope_strategy(&token.node_type) == ScopeStrategy::Exact {
+                return self.find_local_conflict(&token, new_name);
+            }
+
+            let constant_name = new_name.starts_with(|char: char| char.is_ascii_uppercase());
+
+            if CONSTANT_TYPES.contains(&token.node_type.as_str()) && !constant_name {
+                return Ok(Some(format!(
+                    "`{}` isn't a constant name, it must start with a capital letter.",
+                    new_name
+                )));
+            }
+        }
+
+        // Scopes the method being renamed is defined in, e.g. ["Admin", "User"]
+        let definition_scopes: HashSet<Vec<String>> = documents
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .filter(|symbol| {
+                symbol.category == "assignment" && method_types.contains(&symbol.node_type.as_str())
+            })
+            .map(|symbol| symbol.fuzzy_ruby_scope)
+            .collect();
+
+        if definition_scopes.len() == 0 {
+            return Ok(None);
+        }
+
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+
+        let searcher = reader.searcher();
+
+        for definition_scope in &definition_scopes {
+            let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.category_field, "assignment"),
+                IndexRecordOption::Basic,
+            ));
+            let name_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.name_field, new_name),
+                IndexRecordOption::Basic,
+            ));
+
+            let mut method_type_queries = vec![];
+
+            for method_type in method_types {
+                let method_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.node_type_field, method_type),
+                    IndexRecordOption::Basic,
+                ));
+
+                method_type_queries.push((Occur::Should, method_type_query));
+            }
+
+            let mut queries = vec![
+                (Occur::Must, category_query),
+                (Occur::Must, name_query),
+                (Occur::Must, Box::new(BooleanQuery::new(method_type_queries))),
+            ];
+
+            for scope_name in definition_scope {
+                let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.fuzzy_ruby_scope_field, scope_name),
+                    IndexRecordOption::Basic,
+                ));
+
+                queries.push((Occur::Must, scope_query));
+            }
+
+            let results =
+                searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(50))?;
+
+            for (_score, doc_address) in results {
+                let existing_doc = searcher.doc(doc_address)?;
+
+                let same_scope = existing_doc
+                    .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                    .flat_map(Value::as_text)
+                    .eq(definition_scope.iter().map(String::as_str));
+
+                if same_scope {
+                    let location = match self.document_to_location(&existing_doc) {
+                        Some(location) => location,
+                        None => continue,
+                    };
+                    let scope_description = if definition_scope.len() > 0 {
+                        definition_scope.join("::")
+                    } else {
+                        "the top level scope".to_string()
+                    };
+
+                    return Ok(Some(format!(
+                        "`{}` is already defined in {} at {}:{}.",
+                        new_name,
+                        scope_description,
+                        paths::uri_to_path(&location.uri),
+                        location.range.start.line + 1
+                    )));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Renaming a local to one already assigned in its method or block would
+    // merge the two
+    fn find_local_conflict(
+        &self,
+        local: &IndexedSymbol,
+        new_name: &str,
+    ) -> tantivy::Result<Option<String>> {
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(None),
+        };
+
+        let query = SymbolQuery::named(new_name)
+            .category("assignment")
+            .node_types(&resolver::occurrence_types(&local.node_type))
+            .file(&local.file_path_id)
+            .scope(
+                local.fuzzy_ruby_scope.iter().map(String::as_str),
+                ScopeStrategy::Exact,
+            )
+            .build(&self.symbol_fields());
+
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(50))? {
+            let existing = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(existing) => existing,
+                None => continue,
+            };
+
+            if existing.fuzzy_ruby_scope != local.fuzzy_ruby_scope {
+                continue;
+            }
+
+            let scope_description = match local.fuzzy_ruby_scope.last() {
+                Some(scope_name) => format!("`{}`", scope_name),
+                None => "the top level scope".to_string(),
+            };
+
+            return Ok(Some(format!(
+                "`{}` is already a local variable in {} at line {}.",
+                new_name,
+                scope_description,
+                existing.range().start.line + 1
+            )));
+        }
+
+        Ok(None)
+    }
+
+    pub fn find_references_in_workspace(
+        &self,
+        query: String,
+        cancellation: &CancellationToken,
+    ) -> tantivy::Result<Vec<Document>> {
+        if let Some(index) = &self.index {
+            let reader = index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()?;
+
+            let searcher = reader.searcher();
+
+            let user_space_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_bool(self.schema_fields.user_space_field, true),
+                IndexRecordOption::Basic,
+            ));
+
+            let prefix_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
+                format!("{}.*", query).as_str(),
+                self.schema_fields.name_field,
+            )?);
+
+            let mut name_queries = vec![(Occur::Should, prefix_query)];
+
+            // `accounts contr` matches `UserAccountsController`, each word
+            // of the query starting a different part of the name
+            let query_words = name_parts::words(&query);
+
+            if !query_words.is_empty() {
+                let mut part_queries = vec![];
+
+                for word in query_words {
+                    let part_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
+                        format!("{}.*", regex::escape(&word)).as_str(),
+                        self.schema_fields.name_parts_field,
+                    )?);
+
+                    part_queries.push((Occur::Must, part_query));
+                }
+
+                name_queries.push((Occur::Should, Box::new(BooleanQuery::new(part_queries))));
+            }
+
+            if self.fuzzy_search && query.len() > 0 {
+                for name_variant in name_variants(&query) {
+                    let fuzzy_query: Box<dyn Query> = Box::new(FuzzyTermQuery::new_prefix(
+                        Term::from_field_text(self.schema_fields.name_field, &name_variant),
+                        edit_distance(&name_variant),
+                        true,
+                    ));
+
+                    name_queries.push((Occur::Should, fuzzy_query));
+                }
+            }
+
+            let name_query: Box<dyn Query> = Box::new(BooleanQuery::new(name_queries));
+
+            let mut allowed_type_queries = vec![];
+            let allowed_types = ["Alias", "Casgn", "Class", "Def", "Defs", "Gvasgn", "Module"];
+
+            for allowed_type in allowed_types {
+                let assignment_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.node_type_field, allowed_type),
+                    IndexRecordOption::Basic,
+                ));
+
+                allowed_type_queries.push((Occur::Should, assignment_type_query));
+            }
+
+            let allowed_types_query = BooleanQuery::new(allowed_type_queries);
+
+            let queries = vec![
+                (Occur::Must, user_space_query),
+                (Occur::Must, name_query),
+                (Occur::Must, Box::new(allowed_types_query)),
+            ];
+
+            if cancellation.is_cancelled() {
+                return Ok(Vec::new());
+            }
+
+            let results = searcher.search(
+                &BooleanQuery::new(queries),
+                &TopDocs::with_limit(self.reference_limit),
+            )?;
+
+            let mut documents = Vec::new();
+
+            for (_score, doc_address) in results {
+                if cancellation.is_cancelled() {
+                    break;
+                }
+
+                documents.push(searcher.doc(doc_address).unwrap())
+            }
+
+            self.rank_by_proximity(&mut documents, self.current_file.as_ref());
+
+            Ok(documents)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    // Every usage of a name across the index, for the `query refs` command
+    pub fn find_references_by_name(&self, name: &str) -> tantivy::Result<Vec<Location>> {
+        if let Some(index) = &self.index {
+            let reader = index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()?;
+
+            let searcher = reader.searcher();
+
+            let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.category_field, "usage"),
+                IndexRecordOption::Basic,
+            ));
+            let name_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.name_field, name),
+                IndexRecordOption::Basic,
+            ));
+
+            let queries = vec![(Occur::Must, category_query), (Occur::Must, name_query)];
+
+            let results =
+                searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(1000))?;
+
+            let mut locations = Vec::new();
+
+            for (_score, doc_address) in results {
+                let document = searcher.doc(doc_address)?;
+                locations.extend(self.document_to_location(&document));
+            }
+
+            results::sort_locations(&mut locations);
+
+            Ok(locations)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    // Documents matching structured filters, for `fuzzy/search`. Every filter
+    // is optional:
+    //
+    //   {
+    //     "name": "create_.*",            regex the whole name matches
+    //     "nodeTypes": ["Def", "Defs"],
+    //     "category": "assignment",       or "usage"
+    //     "pathGlob": "app/**/*.rb",      relative to the workspace folder
+    //     "scope": "Admin::Users",        class scope the document is in
+    //     "includeDependencies": false,
+    //     "limit": 1000
+    //   }
+    pub fn search(&self, filters: &serde_json::Value) -> tantivy::Result<Vec<serde_json::Value>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(Vec::new()),
+        };
+
+        let searcher = index.reader()?.searcher();
+        let mut queries: Vec<(Occur, Box<dyn Query>)> = vec![];
+
+        if let Some(name) = filters.get("name").and_then(|v| v.as_str()) {
+            queries.push((
+                Occur::Must,
+                Box::new(RegexQuery::from_pattern(name, self.schema_fields.name_field)?),
+            ));
+        }
+
+        if let Some(node_types) = filters.get("nodeTypes").and_then(|v| v.as_array()) {
+            let node_type_queries: Vec<(Occur, Box<dyn Query>)> = node_types
+                .iter()
+                .flat_map(|v| v.as_str())
+                .map(|node_type| {
+                    let node_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                        Term::from_field_text(self.schema_fields.node_type_field, node_type),
+                        IndexRecordOption::Basic,
+                    ));
+
+                    (Occur::Should, node_type_query)
+                })
+                .collect();
+
+            queries.push((Occur::Must, Box::new(BooleanQuery::new(node_type_queries))));
+        }
+
+        if let Some(category) = filters.get("category").and_then(|v| v.as_str()) {
+            queries.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.category_field, category),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let scope: Vec<String> = match filters.get("scope").and_then(|v| v.as_str()) {
+            Some(scope) => scope
+                .split("::")
+                .filter(|name| name.len() > 0)
+                .map(String::from)
+                .collect(),
+            None => vec![],
+        };
+
+        // Narrows the search, the order of the names is checked below
+        for scope_name in &scope {
+            queries.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.class_scope_field, scope_name),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let include_dependencies = filters
+            .get("includeDependencies")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        if !include_dependencies {
+            queries.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_bool(self.schema_fields.user_space_field, true),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let path_glob = match filters.get("pathGlob").and_then(|v| v.as_str()) {
+            Some(path_glob) => Some(paths::glob_regex(path_glob).map_err(|error| {
+                tantivy::TantivyError::InvalidArgument(format!("Invalid pathGlob: {}", error))
+            })?),
+            None => None,
+        };
+
+        let limit = filters
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1000) as usize;
+
+        let query: Box<dyn Query> = if queries.is_empty() {
+            Box::new(AllQuery)
+        } else {
+            Box::new(BooleanQuery::new(queries))
+        };
+
+        let mut doc_addresses: Vec<_> = searcher
+            .search(&query, &DocSetCollector)?
+            .into_iter()
+            .collect();
+        doc_addresses.sort();
+
+        let mut results = vec![];
+
+        for doc_address in doc_addresses {
+            if results.len() >= limit {
+                break;
+            }
+
+            let document = searcher.doc(doc_address)?;
+
+            let class_scope: Vec<&str> = document
+                .get_all(self.schema_fields.class_scope_field)
+                .flat_map(Value::as_text)
+                .collect();
+
+            if !class_scope.starts_with(&scope.iter().map(String::as_str).collect::<Vec<&str>>())
+            {
+                continue;
+            }
+
+            // Workspace documents store their path relative to the folder
+            let path = document
+                .get_all(self.schema_fields.file_path)
+                .flat_map(Value::as_text)
+                .collect::<Vec<&str>>()
+                .join("/");
+
+            if let Some(path_glob) = &path_glob {
+                if !path_glob.is_match(&path) {
+                    continue;
+                }
+            }
+
+            let text_field = |field: Field| {
+                document
+                    .get_first(field)
+                    .and_then(Value::as_text)
+                    .unwrap_or("")
+            };
+
+            let fuzzy_ruby_scope: Vec<&str> = document
+                .get_all(self.schema_fields.fuzzy_ruby_scope_field)
+                .flat_map(Value::as_text)
+                .collect();
+
+            results.push(json!({
+                "name": text_field(self.schema_fields.name_field),
+                "nodeType": text_field(self.schema_fields.node_type_field),
+                "category": text_field(self.schema_fields.category_field),
+                "location": self.document_to_location(&document),
+                "classScope": class_scope,
+                "scope": fuzzy_ruby_scope,
+                "userSpace": document
+                    .get_first(self.schema_fields.user_space_field)
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+                "stale": self.is_stale(text_field(self.schema_fields.file_path_id)),
+            }));
+        }
+
+        Ok(results)
+    }
+
+    // Every workspace document as an LSIF occurrence, for the `export`
+    // command. Dependencies are left out, they aren't part of the project.
+    pub fn lsif_occurrences(&self) -> tantivy::Result<Vec<lsif::Occurrence>> {
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(Vec::new()),
+        };
+
+        let searcher = index.reader()?.searcher();
+        let user_space_query = TermQuery::new(
+            Term::from_field_bool(self.schema_fields.user_space_field, true),
+            IndexRecordOption::Basic,
+        );
+
+        let mut occurrences = Vec::new();
+
+        for doc_address in searcher.search(&user_space_query, &DocSetCollector)? {
+            let indexed_symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(indexed_symbol) => indexed_symbol,
+                None => continue,
+            };
+            let category = indexed_symbol.category.as_str();
+
+            if category != "assignment" && category != "usage" {
+                continue;
+            }
+
+            let symbol = lsif::symbol(
+                &indexed_symbol.node_type,
+                &indexed_symbol.name,
+                &indexed_symbol.path(),
+                &indexed_symbol.fuzzy_ruby_scope,
+                &indexed_symbol.class_scope,
+            );
+
+            if let Some((symbol, local)) = symbol {
+                let location = indexed_symbol.location();
+
+                occurrences.push(lsif::Occurrence {
+                    uri: location.uri,
+                    range: location.range,
+                    symbol,
+                    definition: category == "assignment",
+                    local,
+                });
+            }
+        }
+
+        Ok(occurrences)
+    }
+
+    // Renaming `user_profile.rb` to `member_profile.rb` renames the
+    // `UserProfile` class or module defined in it, and its usages, to
+    // `MemberProfile`, keeping the name Zeitwerk expects for the file.
+    // Edits are made to the files before they're renamed, so they're keyed
+    // by the old URIs.
+    pub fn file_rename_edit(
+        &self,
+        file_renames: &[FileRename],
+    ) -> tantivy::Result<Option<WorkspaceEdit>> {
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+
+        let index = match &self.index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let text_query = |field: Field, text: &str| -> Box<dyn Query> {
+            Box::new(TermQuery::new(
+                Term::from_field_text(field, text),
+                IndexRecordOption::Basic,
+            ))
+        };
+
+        for file_rename in file_renames {
+            let (old_uri, new_uri) =
+                match (Url::parse(&file_rename.old_uri), Url::parse(&file_rename.new_uri)) {
+                    (Ok(old_uri), Ok(new_uri)) => (old_uri, new_uri),
+                    _ => continue,
+                };
+
+            let old_path = paths::uri_to_path(&old_uri);
+            let constant_name = |path: &str| -> Option<String> {
+                let file_name = path.rsplit("/").next()?;
+
+                Some(autoload::camelize(file_name.strip_suffix(".rb")?))
+            };
+
+            let (old_name, new_name) =
+                match (constant_name(&old_path), constant_name(&paths::uri_to_path(&new_uri))) {
+                    (Some(old_name), Some(new_name)) if old_name != new_name => {
+                        (old_name, new_name)
+                    }
+                    _ => continue,
+                };
+
+            // Only files that define the constant their name implies
+            let definition_query = BooleanQuery::new(vec![
+                (
+                    Occur::Must,
+                    text_query(self.schema_fields.file_path_id, &self.file_path_id(&old_path)),
+                ),
+                (
+                    Occur::Must,
+                    text_query(self.schema_fields.category_field, "assignment"),
+                ),
+                (Occur::Must, text_query(self.schema_fields.name_field, &old_name)),
+                (
+                    Occur::Must,
+                    Box::new(BooleanQuery::new(vec![
+                        (
+                            Occur::Should,
+                            text_query(self.schema_fields.node_type_field, "Class"),
+                        ),
+                        (
+                            Occur::Should,
+                            text_query(self.schema_fields.node_type_field, "Module"),
+                        ),
+                    ])),
+                ),
+            ]);
+
+            if searcher.search(&definition_query, &Count)? == 0 {
+                continue;
+            }
+
+            for document in self.constant_documents(&searcher, &old_name)? {
+                let location = match self.document_to_location(&document) {
+                    Some(location) => location,
+                    None => continue,
+                };
+                let edits = changes.entry(location.uri).or_default();
+
+                // A definition's name can be indexed as a usage as well
+                if edits.iter().any(|edit| edit.range == location.range) {
+                    continue;
+                }
+
+                edits.push(TextEdit::new(location.range, new_name.clone()));
+            }
+        }
+
+        if changes.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(WorkspaceEdit::new(changes)))
+        }
+    }
+
+    // The workspace's classes, modules, and constants with the name, defined
+    // or used
+    fn constant_documents(
+        &self,
+        searcher: &Searcher,
+        name: &str,
+    ) -> tantivy::Result<Vec<Document>> {
+        let mut node_type_queries: Vec<(Occur, Box<dyn Query>)> = vec![];
+
+        for node_type in CONSTANT_TYPES {
+            node_type_queries.push((
+                Occur::Should,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.node_type_field, node_type),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let name_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.name_field, name),
+            IndexRecordOption::Basic,
+        ));
+        let user_space_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_bool(self.schema_fields.user_space_field, true),
+            IndexRecordOption::Basic,
+        ));
+
+        let constant_query = BooleanQuery::new(vec![
+            (Occur::Must, name_query),
+            (Occur::Must, user_space_query),
+            (Occur::Must, Box::new(BooleanQuery::new(node_type_queries))),
+        ]);
+
+        let mut documents = vec![];
+
+        for (_score, doc_address) in
+            searcher.search(&constant_query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            documents.push(searcher.doc(doc_address)?);
+        }
+
+        Ok(documents)
+    }
+
+    // Renaming a class or module defined in a file named after it, in a
+    // Rails app, moves the file to where Zeitwerk loads the new name from
+    pub fn constant_file_rename(
+        &self,
+        documents: &[Document],
+        new_name: &str,
+    ) -> Option<RenameFile> {
+        if !self.rename_file_support {
+            return None;
+        }
+
+        let definitions = documents
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .filter(|symbol| {
+                symbol.category == "assignment"
+                    && (symbol.node_type == "Class" || symbol.node_type == "Module")
+            });
+
+        for definition in definitions {
+            let path = paths::uri_to_path(&definition.location().uri);
+            let workspace_path = match self.workspace_root(&path) {
+                Some(workspace_path) if self.is_rails_app(workspace_path) => workspace_path,
+                _ => continue,
+            };
+
+            let mut constant_path = definition.fuzzy_ruby_scope.clone();
+            constant_path.push(definition.name.clone());
+
+            // Reopened elsewhere, like in a concern or a monkeypatch
+            if !autoload::constant_file_paths(workspace_path, &constant_path).contains(&path) {
+                continue;
+            }
+
+            let directory = match path.rsplit_once('/') {
+                Some((directory, _file_name)) => directory,
+                None => continue,
+            };
+            let new_path = format!("{}/{}.rb", directory, autoload::underscore(new_name));
+
+            if Path::new(&new_path).exists() {
+                return None;
+            }
+
+            return Some(RenameFile {
+                old_uri: paths::path_to_uri(&path),
+                new_uri: paths::path_to_uri(&new_path),
+                options: None,
+                annotation_id: None,
+            });
+        }
+
+        None
+    }
+
+    // The rename's edits, then the file moved, so the edits to it are made
+    // where it was
+    pub fn with_file_rename(
+        &self,
+        workspace_edit: WorkspaceEdit,
+        mut rename_file: RenameFile,
+    ) -> WorkspaceEdit {
+        let mut changes: Vec<(Url, Vec<TextEdit>)> = workspace_edit
+            .changes
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_uri, edits)| !edits.is_empty())
+            .collect();
+        changes.sort_by(|(uri, _edits), (other_uri, _other_edits)| uri.cmp(other_uri));
+
+        let mut operations: Vec<DocumentChangeOperation> = changes
+            .into_iter()
+            .map(|(uri, edits)| {
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                    edits: edits.into_iter().map(OneOf::Left).collect(),
+                })
+            })
+            .collect();
+
+        let mut change_annotations = None;
+
+        if self.change_annotation_support {
+            rename_file.annotation_id = Some(MOVE_FILE_ANNOTATION.to_string());
+            change_annotations = Some(HashMap::from([(
+                MOVE_FILE_ANNOTATION.to_string(),
+                ChangeAnnotation {
+                    label: "Move the file to the new name's path".to_string(),
+                    needs_confirmation: Some(true),
+                    description: None,
+                },
+            )]));
+        }
 
-                for entry in walk_dir {
-                    let path = entry.unwrap().path();
-                    let path = path.to_str().unwrap();
-                    let ruby_file = path.ends_with(".rb");
+        operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(rename_file)));
 
-                    if ruby_file {
-                        indexable_file_paths.push(path.to_string());
-                    }
-                }
+        WorkspaceEdit {
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            change_annotations,
+        }
+    }
 
-                self.index_interface_only = indexable_dir.interface_only;
+    // Refactorings of the selection, only of the kinds asked for when the
+    // client says
+    pub fn code_actions(
+        &self,
+        params: &CodeActionParams,
+    ) -> tantivy::Result<Vec<CodeActionOrCommand>> {
+        let uri = &params.text_document.uri;
+        let mut actions = vec![];
 
-                for path in &indexable_file_paths {
-                    if let Ok(text) = fs::read_to_string(&path) {
-                        let uri = Url::from_file_path(&path).unwrap();
-                        let relative_path = uri.path().replace(&self.workspace_path, "");
+        if let Some(action) = self.extract_method_action(uri, params.range)? {
+            actions.push(action);
+        }
 
-                        self.reindex_modified_file_without_commit(
-                            &text,
-                            relative_path,
-                            &index_writer,
-                            false,
-                        );
-                    }
-                }
-            }
+        if let Some(action) = self.inline_variable_action(uri, params.range.start)? {
+            actions.push(action);
+        }
 
-            index_writer.commit().unwrap();
+        if let Some(action) = self.add_require_action(uri, params.range.start)? {
+            actions.push(action);
         }
 
-        self.include_dirs_indexed = true;
-        self.index_interface_only = false;
+        if let Some(action) = self.create_method_action(uri, params.range.start)? {
+            actions.push(action);
+        }
 
-        Ok(())
-    }
+        if let Some(only) = &params.context.only {
+            actions.retain(|action: &CodeAction| {
+                let kind = action.kind.as_ref().map_or("", |kind| kind.as_str());
 
-    pub fn index_gems_once(&mut self) -> tantivy::Result<()> {
-        if self.gems_indexed {
-            return Ok(());
+                only.iter().any(|only_kind| {
+                    kind == only_kind.as_str()
+                        || kind.starts_with(&format!("{}.", only_kind.as_str()))
+                })
+            });
         }
 
-        self.index_interface_only = true;
+        Ok(actions
+            .into_iter()
+            .map(CodeActionOrCommand::CodeAction)
+            .collect())
+    }
 
-        // Four leading spaces dictates that it's a gem version
-        // https://github.com/rubygems/bundler/blob/v2.1.4/lib/bundler/lockfile_parser.rb#L174-L181
-        let gem_version = Regex::new(r"^\s{4}([a-zA-Z\d\.\-_]+)\s\(([\d\w\.\-_]+)\)").unwrap();
-        let gemfile_path = format!("{}/{}", &self.workspace_path, "Gemfile.lock");
-
-        if let Ok(gemfile_contents) = fs::read_to_string(gemfile_path) {
-            let mut gem_paths = vec![];
-            let mut base_gem_path = "unset";
-
-            let gem_home_path_result = Command::new("sh")
-                .arg("-c")
-                // .arg(format!("eval \"$(/usr/local/bin/rbenv init -)\" && cd {} && gem environment home", &self.workspace_path))
-                .arg(format!(
-                    "cd {} && gem environment home",
-                    &self.workspace_path
-                ))
-                .output();
+    // Moves the selected statements into a new method after theirs. Locals
+    // they read that are assigned before them become its parameters, and the
+    // ones they assign that are read after them what it returns.
+    fn extract_method_action(
+        &self,
+        uri: &Url,
+        range: Range,
+    ) -> tantivy::Result<Option<CodeAction>> {
+        let text = match self.file_contents(uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let extraction = match refactorings::extraction(&text, range) {
+            Some(extraction) => extraction,
+            None => return Ok(None),
+        };
 
-            if let Ok(gem_home_path) = gem_home_path_result {
-                if let Ok(gem_home_path) = str::from_utf8(gem_home_path.stdout.as_slice()) {
-                    base_gem_path = gem_home_path;
-                }
+        let file_path_id = self.file_path_id(&paths::uri_to_path(uri));
+        let mut locals = self.method_locals(&file_path_id, &extraction.method_lines)?;
+        locals.sort_by_key(|local| local.range().start);
 
-                // Index Ruby
-                let ruby_source_path = base_gem_path.replace("gems/", "").replace("\n", "");
+        let selected = |local: &IndexedSymbol| {
+            local.range().start >= extraction.range.start
+                && local.range().end <= extraction.range.end
+        };
 
-                info!("Added Ruby source path: {}", ruby_source_path);
-                gem_paths.push(ruby_source_path);
+        let mut parameters: Vec<&str> = vec![];
+        let mut returned: Vec<&str> = vec![];
 
-                // Index Gems
-                for line in gemfile_contents.lines() {
-                    if let Some(captures) = gem_version.captures(line) {
-                        let name = captures[1].to_string();
-                        let version = captures[2].to_string();
-                        let gem_folder_name =
-                            format!("{}/gems/{}-{}", base_gem_path, name, version);
-                        // Not 100% sure where this newline is coming from. `gemfile_contents.lines()` I think.
-                        let gem_folder_name = gem_folder_name.replace("\n", "");
+        for local in locals.iter().filter(|local| selected(local)) {
+            let name = local.name.as_str();
 
-                        info!("gem folder name: {}", gem_folder_name);
+            if local.node_type == "Lvar" && !parameters.contains(&name) {
+                let assigned_before = locals.iter().any(|other| {
+                    other.name == name
+                        && other.category == "assignment"
+                        && other.range().start < extraction.range.start
+                });
 
-                        gem_paths.push(gem_folder_name)
-                    }
+                if assigned_before {
+                    parameters.push(name);
                 }
             }
 
-            let index = match &self.index {
-                Some(index) => index,
-                None => {
-                    info!("missing index");
-                    quit::with_code(1);
+            if local.node_type == "Lvasgn" && !returned.contains(&name) {
+                let read_after = locals.iter().any(|other| {
+                    other.name == name
+                        && other.node_type == "Lvar"
+                        && other.range().start > extraction.range.end
+                });
+
+                if read_after {
+                    returned.push(name);
                 }
-            };
+            }
+        }
 
-            let mut index_writer = index.writer(256_000_000).unwrap();
-
-            for gem_path in gem_paths {
-                let walk_dir = WalkDirGeneric::<(usize, bool)>::new(gem_path.clone())
-                    .process_read_dir(move |_depth, _path, _read_dir_state, children| {
-                        children.retain(|dir_entry_result| {
-                            dir_entry_result
-                                .as_ref()
-                                .map(|dir_entry| {
-                                    if let Some(file_name) = dir_entry.file_name.to_str() {
-                                        let ruby_file = file_name.ends_with(".rb");
-                                        dir_entry.file_type.is_dir() || ruby_file
-                                    } else {
-                                        false
-                                    }
-                                })
-                                .unwrap_or(false)
-                        });
+        let method_name = "extracted_method";
+        let arguments = if parameters.is_empty() {
+            String::new()
+        } else {
+            format!("({})", parameters.join(", "))
+        };
+        let call = if returned.is_empty() {
+            format!("{}{}", method_name, arguments)
+        } else {
+            format!("{} = {}{}", returned.join(", "), method_name, arguments)
+        };
 
-                        children.iter_mut().for_each(|dir_entry_result| {
-                            if let Ok(dir_entry) = dir_entry_result {
-                                if let Some(file_name) = dir_entry.file_name.to_str() {
-                                    if file_name.contains("node_modules")
-                                        || file_name.contains("vendor")
-                                        || file_name.contains("tmp")
-                                        || file_name.contains(".git")
-                                    {
-                                        dir_entry.read_children_path = None;
-                                    }
-                                }
-                            }
-                        });
-                    });
+        let indent = &extraction.method_indent;
+        let body_indent = format!("{}  ", indent);
+        let singleton = if extraction.singleton { "self." } else { "" };
+        let mut lines = vec![
+            String::new(),
+            format!("{}def {}{}{}", indent, singleton, method_name, arguments),
+        ];
+
+        for line in &extraction.body {
+            if line.is_empty() {
+                lines.push(String::new());
+            } else {
+                lines.push(format!("{}{}", body_indent, line));
+            }
+        }
 
-                let mut indexable_file_paths = Vec::new();
+        match returned.as_slice() {
+            [] => {}
+            [name] => lines.push(format!("{}{}", body_indent, name)),
+            names => lines.push(format!("{}[{}]", body_indent, names.join(", "))),
+        }
 
-                for entry in walk_dir {
-                    let path = entry.unwrap().path();
-                    let path = path.to_str().unwrap();
-                    let ruby_file = path.ends_with(".rb");
+        lines.push(format!("{}end", indent));
 
-                    if ruby_file {
-                        indexable_file_paths.push(path.to_string());
-                    }
-                }
+        let method_end = Range::new(extraction.method_end, extraction.method_end);
+        let edits = vec![
+            TextEdit::new(extraction.range, call),
+            TextEdit::new(method_end, format!("\n{}", lines.join("\n"))),
+        ];
 
-                for path in &indexable_file_paths {
-                    if let Ok(text) = fs::read_to_string(&path) {
-                        let uri = Url::from_file_path(&path).unwrap();
-                        let relative_path = uri.path().replace(&self.workspace_path, "");
+        Ok(Some(CodeAction {
+            title: "Extract method".to_string(),
+            kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+            ..Default::default()
+        }))
+    }
 
-                        self.reindex_modified_file_without_commit(
-                            &text,
-                            relative_path,
-                            &index_writer,
-                            false,
-                        );
-                    }
-                }
+    // Replaces a local's reads with the value it's assigned, and deletes the
+    // assignment. Only for locals assigned once and read in the same scope
+    // after it.
+    fn inline_variable_action(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> tantivy::Result<Option<CodeAction>> {
+        let text = match self.file_contents(uri) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let inlining = match refactorings::inlining(&text, position) {
+            Some(inlining) => inlining,
+            None => return Ok(None),
+        };
+
+        let params =
+            TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri.clone()), position);
+        let occurrences: Vec<IndexedSymbol> = self
+            .find_references(params, &CancellationToken::default())?
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .collect();
+
+        let assignment = match occurrences
+            .iter()
+            .find(|occurrence| occurrence.contains(position))
+        {
+            Some(assignment) if assignment.node_type == "Lvasgn" => assignment,
+            _ => return Ok(None),
+        };
+
+        let mut edits = vec![];
+
+        for occurrence in &occurrences {
+            // A method defined in this one has locals of its own
+            if occurrence == assignment
+                || occurrence.fuzzy_ruby_scope != assignment.fuzzy_ruby_scope
+            {
+                continue;
             }
 
-            index_writer.commit().unwrap();
-        } else {
-            info!("Gemfile not found, skipping indexing workspace gems.");
+            let inlinable = occurrence.node_type == "Lvar"
+                && occurrence.range().start >= inlining.assignment.end;
+
+            // Assigned again, or read before it's assigned, like in a loop
+            if !inlinable {
+                return Ok(None);
+            }
+
+            edits.push(TextEdit::new(occurrence.range(), inlining.value.clone()));
         }
 
-        self.gems_indexed = true;
-        self.index_interface_only = false;
+        if edits.is_empty() {
+            return Ok(None);
+        }
 
-        Ok(())
+        edits.push(TextEdit::new(inlining.assignment, String::new()));
+
+        Ok(Some(CodeAction {
+            title: format!("Inline local variable `{}`", inlining.name),
+            kind: Some(CodeActionKind::REFACTOR_INLINE),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+            ..Default::default()
+        }))
     }
 
-    pub fn reindex_modified_file_without_commit(
-        &mut self,
-        text: &String,
-        relative_path: String,
-        index_writer: &IndexWriter,
-        user_space: bool,
-    ) -> tantivy::Result<Vec<Option<tower_lsp::lsp_types::Diagnostic>>> {
-        if let Some(_) = &self.index {
-            let mut documents = Vec::new();
+    // Requires the file defining the constant at the position, outside
+    // Rails apps where it's autoloaded, when it's the only one that does and
+    // isn't required yet. Files in `lib/` are required by their path under
+    // it from outside it, the rest relative to the file.
+    fn add_require_action(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> tantivy::Result<Option<CodeAction>> {
+        let path = paths::uri_to_path(uri);
+        let workspace_path = match self.workspace_root(&path) {
+            Some(workspace_path) if !self.is_rails_app(workspace_path) => workspace_path,
+            _ => return Ok(None),
+        };
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(None),
+        };
 
-            let diagnostics = match self.parse(text, &mut documents) {
-                Ok(diagnostics) => diagnostics,
-                Err(diagnostics) => {
-                    // Return early so existing documents are not deleted when
-                    // there is a syntax error
-                    return Ok(diagnostics);
-                }
-            };
+        let params =
+            TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri.clone()), position);
+        let constant = match self
+            .indexed_document(&searcher, &params, "usage")?
+            .and_then(|document| self.indexed_symbol(&document))
+        {
+            Some(constant) if constant.node_type == "Const" => constant,
+            _ => return Ok(None),
+        };
 
-            let file_path_id = blake3::hash(&relative_path.as_bytes());
+        let definitions: Vec<IndexedSymbol> = self
+            .constant_documents(&searcher, &constant.name)?
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .filter(|symbol| symbol.category == "assignment" && symbol.node_type != "Const")
+            .collect();
 
-            for document in documents {
-                let mut fuzzy_doc = Document::default();
+        let definition_path = match definitions.as_slice() {
+            [definition] => definition.path(),
+            _ => return Ok(None),
+        };
+        let directory = match path.rsplit_once('/') {
+            Some((directory, _file_name)) => directory,
+            None => return Ok(None),
+        };
 
-                fuzzy_doc.add_text(self.schema_fields.file_path_id, &file_path_id.to_string());
+        if definition_path == path {
+            return Ok(None);
+        }
 
-                for path_part in relative_path.split("/") {
-                    if path_part.len() > 0 {
-                        fuzzy_doc.add_text(self.schema_fields.file_path, path_part);
-                    }
-                }
+        let requires = self.file_requires(&searcher, &constant.file_path_id)?;
+        let required = requires.iter().any(|require| {
+            let required_path = match require.parameters.first().map(String::as_str) {
+                Some("require_relative") => paths::join(directory, &require.name),
+                _ => return definition_path.ends_with(&format!("/{}.rb", require.name)),
+            };
 
-                for fuzzy_scope in document.fuzzy_ruby_scope {
-                    fuzzy_doc.add_text(self.schema_fields.fuzzy_ruby_scope_field, fuzzy_scope);
-                }
+            definition_path == required_path || definition_path == format!("{}.rb", required_path)
+        });
 
-                for class_scope in document.class_scope {
-                    fuzzy_doc.add_text(self.schema_fields.class_scope_field, class_scope);
-                }
+        if required {
+            return Ok(None);
+        }
 
-                fuzzy_doc.add_text(
-                    self.schema_fields.category_field,
-                    document.category.to_string(),
-                );
-                fuzzy_doc.add_text(self.schema_fields.name_field, document.name);
-                fuzzy_doc.add_text(self.schema_fields.node_type_field, document.node_type);
-                fuzzy_doc.add_u64(
-                    self.schema_fields.line_field,
-                    document.line.try_into().unwrap(),
-                );
-                fuzzy_doc.add_u64(
-                    self.schema_fields.start_column_field,
-                    document.start_column.try_into().unwrap(),
-                );
-                fuzzy_doc.add_u64(
-                    self.schema_fields.end_column_field,
-                    document.end_column.try_into().unwrap(),
-                );
-                fuzzy_doc.add_bool(self.schema_fields.user_space_field, user_space);
+        let lib_path = format!("{}/lib", workspace_path);
+        let feature = definition_path
+            .strip_suffix(".rb")
+            .unwrap_or(&definition_path);
+        let require_line = match (
+            paths::relative_to(feature, &lib_path),
+            paths::relative_to(&path, &lib_path),
+        ) {
+            (Some(lib_feature), None) => {
+                format!("require \"{}\"", lib_feature.trim_start_matches('/'))
+            }
+            _ => format!(
+                "require_relative \"{}\"",
+                paths::path_from(directory, feature)
+            ),
+        };
 
-                let start_col = document.start_column;
-                let end_col = document.end_column;
-                let col_range = start_col..(end_col + 1);
-                for col in col_range {
-                    fuzzy_doc.add_u64(self.schema_fields.columns_field, col as u64);
+        // After the file's requires, or its magic comments when it has none
+        let edit = match requires.iter().map(|require| require.line).max() {
+            Some(line) => TextEdit::new(
+                Range::new(Position::new(line + 1, 0), Position::new(line + 1, 0)),
+                format!("{}\n", require_line),
+            ),
+            None => {
+                let text = self.file_contents(uri).unwrap_or_default();
+                let comment_lines = text
+                    .lines()
+                    .take_while(|line| line.starts_with('#'))
+                    .count();
+                let mut line = comment_lines as u32;
+
+                // Past the blank line after them
+                if comment_lines > 0 && text.lines().nth(comment_lines) == Some("") {
+                    line += 1;
                 }
 
-                index_writer.add_document(fuzzy_doc)?;
+                TextEdit::new(
+                    Range::new(Position::new(line, 0), Position::new(line, 0)),
+                    format!("{}\n\n", require_line),
+                )
             }
+        };
 
-            Ok(diagnostics)
-        } else {
-            Ok(vec![])
-        }
+        Ok(Some(CodeAction {
+            title: format!("Add `{}`", require_line),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(
+                uri.clone(),
+                vec![edit],
+            )]))),
+            ..Default::default()
+        }))
     }
 
-    pub async fn reindex_modified_file(&mut self, client: &Client, text: &String, uri: &Url) {
-        let mut documents = Vec::new();
-        let diagnostics = match self.parse(text, &mut documents) {
-            Ok(diagnostics) => diagnostics,
-            Err(diagnostics) => {
-                // Return early so existing documents are not deleted when
-                // there is a syntax error
-                // return Ok(diagnostics);
-                diagnostics
+    fn file_requires(
+        &self,
+        searcher: &Searcher,
+        file_path_id: &str,
+    ) -> tantivy::Result<Vec<IndexedSymbol>> {
+        let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, file_path_id),
+            IndexRecordOption::Basic,
+        ));
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "require"),
+            IndexRecordOption::Basic,
+        ));
+
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, file_path_query),
+            (Occur::Must, category_query),
+        ]);
+
+        let mut requires = vec![];
+
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(1_000))? {
+            if let Some(require) = self.indexed_symbol(&searcher.doc(doc_address)?) {
+                requires.push(require);
             }
-        };
+        }
 
-        if self.report_diagnostics {
-            let mut reported_diagnostics = vec![];
+        Ok(requires)
+    }
 
-            for diagnostic in &diagnostics {
-                for unwrapped_diagnostic in diagnostic {
-                    reported_diagnostics.push(unwrapped_diagnostic.clone());
-                }
-            }
+    // Stubs a method called on self, or on a receiver whose class is known,
+    // that isn't defined anywhere, last in the class's body
+    fn create_method_action(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> tantivy::Result<Option<CodeAction>> {
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(None),
+        };
 
-            client
-                .publish_diagnostics(uri.clone(), reported_diagnostics, None)
-                .await;
-            // .await;
-        }
+        let params =
+            TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri.clone()), position);
+        let call = match self
+            .indexed_document(&searcher, &params, "usage")?
+            .and_then(|document| self.indexed_symbol(&document))
+        {
+            Some(call)
+                if call.node_type == "Send"
+                    && call
+                        .name
+                        .starts_with(|char: char| char.is_alphabetic() || char == '_')
+                    && !call.name.ends_with('=') =>
+            {
+                call
+            }
+            _ => return Ok(None),
+        };
 
-        if diagnostics.len() > 0 {
-            return;
+        if !self
+            .definition_documents(params, &CancellationToken::default())?
+            .is_empty()
+        {
+            return Ok(None);
         }
 
-        if let Some(index) = &self.index {
-            let mut index_writer = index.writer_with_num_threads(1, 30_000_000).unwrap();
+        // Instance variables and locals are named after their class, unless
+        // a local's known to hold one
+        let receiver = call.receiver.as_deref().unwrap_or("unknown");
+        let class_path: Vec<String> = match receiver {
+            "self" => self.class_scope_of(&call.fuzzy_ruby_scope),
+            "unknown" => return Ok(None),
+            _ => match receiver
+                .strip_prefix("#<")
+                .and_then(|hint| hint.strip_suffix('>'))
+            {
+                Some(instance_class) => instance_class.split("::").map(String::from).collect(),
+                None if receiver.starts_with(|char: char| char == '@' || char.is_lowercase()) => {
+                    vec![autoload::camelize(receiver.trim_start_matches('@'))]
+                }
+                None => return Ok(None),
+            },
+        };
+        let class_name = match class_path.last() {
+            Some(class_name) => class_name,
+            None => return Ok(None),
+        };
 
-            let user_space: bool;
-            let relative_path: String;
+        let definitions: Vec<IndexedSymbol> = self
+            .constant_documents(&searcher, class_name)?
+            .iter()
+            .filter_map(|document| self.indexed_symbol(document))
+            .filter(|symbol| {
+                let mut definition_path = symbol.fuzzy_ruby_scope.clone();
+                definition_path.push(symbol.name.clone());
+
+                symbol.category == "assignment"
+                    && symbol.node_type != "Const"
+                    && receivers::names_class(&class_path.join("::"), &definition_path)
+            })
+            .collect();
+
+        // A class reopened across files gets it in the calling file, or else
+        // the one named after it
+        let file_name = format!("/{}.rb", autoload::underscore(class_name));
+        let definition = match definitions
+            .iter()
+            .find(|definition| definition.file_path_id == call.file_path_id)
+            .or_else(|| {
+                definitions
+                    .iter()
+                    .find(|definition| definition.path().ends_with(&file_name))
+            })
+            .or_else(|| definitions.first())
+        {
+            Some(definition) => definition,
+            None => return Ok(None),
+        };
 
-            if uri.path().contains(&self.workspace_path) {
-                user_space = true;
-                relative_path = uri.path().replace(&self.workspace_path, "");
-            } else {
-                user_space = false;
-                relative_path = uri.path().to_string();
-            }
+        let definition_uri = if definition.file_path_id == call.file_path_id {
+            uri.clone()
+        } else {
+            definition.location().uri
+        };
+        let body_end = match self
+            .file_contents(&definition_uri)
+            .and_then(|text| refactorings::body_end(&text, definition.line))
+        {
+            Some(body_end) => body_end,
+            None => return Ok(None),
+        };
 
-            let file_path_id = blake3::hash(&relative_path.as_bytes());
+        // Calls on self from a class method are to another one
+        let singleton = receiver == "self"
+            && call
+                .fuzzy_ruby_scope
+                .last()
+                .map_or(false, |method_scope| method_scope.starts_with("self."));
+        let mut stub = format!(
+            "{}def {}{}\n{}end\n",
+            body_end.indent,
+            if singleton { "self." } else { "" },
+            call.name,
+            body_end.indent
+        );
 
-            let file_path_id_term =
-                Term::from_field_text(self.schema_fields.file_path_id, &file_path_id.to_string());
+        if !body_end.empty {
+            stub.insert(0, '\n');
+        }
 
-            index_writer.delete_term(file_path_id_term);
+        let mut definition_path = definition.fuzzy_ruby_scope.clone();
+        definition_path.push(definition.name.clone());
 
-            for document in documents {
-                let mut fuzzy_doc = Document::default();
+        Ok(Some(CodeAction {
+            title: format!(
+                "Create method `{}` in {}",
+                call.name,
+                definition_path.join("::")
+            ),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(
+                definition_uri,
+                vec![TextEdit::new(
+                    Range::new(body_end.position, body_end.position),
+                    stub,
+                )],
+            )]))),
+            ..Default::default()
+        }))
+    }
 
-                fuzzy_doc.add_text(self.schema_fields.file_path_id, &file_path_id.to_string());
+    // The locals of the method on the lines, its parameters' and blocks'
+    // included
+    fn method_locals(
+        &self,
+        file_path_id: &str,
+        lines: &RangeInclusive<u32>,
+    ) -> tantivy::Result<Vec<IndexedSymbol>> {
+        let searcher = match self.searcher()? {
+            Some(searcher) => searcher,
+            None => return Ok(vec![]),
+        };
 
-                for path_part in relative_path.split("/") {
-                    if path_part.len() > 0 {
-                        fuzzy_doc.add_text(self.schema_fields.file_path, path_part);
-                    }
-                }
+        let mut node_type_queries: Vec<(Occur, Box<dyn Query>)> = vec![];
 
-                for fuzzy_scope in document.fuzzy_ruby_scope {
-                    fuzzy_doc.add_text(self.schema_fields.fuzzy_ruby_scope_field, fuzzy_scope);
-                }
+        for node_type in resolver::occurrence_types("Lvar") {
+            node_type_queries.push((
+                Occur::Should,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.node_type_field, node_type),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
 
-                for class_scope in document.class_scope {
-                    fuzzy_doc.add_text(self.schema_fields.class_scope_field, class_scope);
-                }
+        let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.file_path_id, file_path_id),
+            IndexRecordOption::Basic,
+        ));
 
-                fuzzy_doc.add_text(
-                    self.schema_fields.category_field,
-                    document.category.to_string(),
-                );
-                fuzzy_doc.add_text(self.schema_fields.name_field, document.name);
-                fuzzy_doc.add_text(self.schema_fields.node_type_field, document.node_type);
-                fuzzy_doc.add_u64(
-                    self.schema_fields.line_field,
-                    document.line.try_into().unwrap(),
-                );
-                fuzzy_doc.add_u64(
-                    self.schema_fields.start_column_field,
-                    document.start_column.try_into().unwrap(),
-                );
-                fuzzy_doc.add_u64(
-                    self.schema_fields.end_column_field,
-                    document.end_column.try_into().unwrap(),
-                );
-                fuzzy_doc.add_bool(self.schema_fields.user_space_field, user_space);
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, file_path_query),
+            (Occur::Must, Box::new(BooleanQuery::new(node_type_queries))),
+        ]);
 
-                let start_col = document.start_column;
-                let end_col = document.end_column;
-                let col_range = start_col..(end_col + 1);
-                for col in col_range {
-                    fuzzy_doc.add_u64(self.schema_fields.columns_field, col as u64);
-                }
+        let mut locals = vec![];
 
-                index_writer.add_document(fuzzy_doc).unwrap();
+        for (_score, doc_address) in
+            searcher.search(&query, &TopDocs::with_limit(self.reference_limit))?
+        {
+            match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(local) if lines.contains(&local.line) => locals.push(local),
+                _ => continue,
             }
-
-            index_writer.commit().unwrap();
         }
-    }
 
-    pub fn diagnostics(
-        &mut self,
-        text: &String,
-        _uri: &Url,
-    ) -> tantivy::Result<Vec<Option<tower_lsp::lsp_types::Diagnostic>>> {
-        let mut documents = Vec::new();
-        match self.parse(text, &mut documents) {
-            Ok(diagnostics) => Ok(diagnostics),
-            Err(diagnostics) => Ok(diagnostics),
-        }
+        Ok(locals)
     }
 
-    pub fn find_definitions(
+    pub fn completions(
         &self,
         params: TextDocumentPositionParams,
-    ) -> tantivy::Result<Vec<Location>> {
-        let path = params.text_document.uri.path();
-        let relative_path = path.replace(&self.workspace_path, "");
-
+    ) -> tantivy::Result<Vec<CompletionItem>> {
+        let uri = &params.text_document.uri;
         let position = params.position;
 
+        let text = match self.file_contents(uri) {
+            Some(text) => text,
+            None => return Ok(Vec::new()),
+        };
+
+        let line_prefix = positions::line_prefix(&text, position);
+
+        // Walk backwards from the cursor to the start of the token being typed
+        let token_start = line_prefix
+            .char_indices()
+            .rev()
+            .take_while(|(_index, char)| char.is_alphanumeric() || *char == '_' || *char == '@')
+            .last()
+            .map_or(line_prefix.len(), |(index, _char)| index);
+        let token = &line_prefix[token_start..];
+        let token_start_column = positions::utf16_len(&line_prefix[..token_start]);
+
         if let Some(index) = &self.index {
             let reader = index
                 .reader_builder()
@@ -922,613 +7057,544 @@ impl Persistence {
                 .try_into()?;
 
             let searcher = reader.searcher();
-            let character_position = position.character;
-            let character_line = position.line;
-            let file_path_id = blake3::hash(&relative_path.as_bytes());
-
-            let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.file_path_id, &file_path_id.to_string()),
-                IndexRecordOption::Basic,
-            ));
-            let category_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.category_field, "usage"),
-                IndexRecordOption::Basic,
-            ));
-            let line_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_u64(self.schema_fields.line_field, character_line.into()),
-                IndexRecordOption::Basic,
-            ));
-            let column_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_u64(self.schema_fields.columns_field, character_position.into()),
-                IndexRecordOption::Basic,
-            ));
-
-            let query = BooleanQuery::new(vec![
-                (Occur::Must, file_path_query),
-                (Occur::Must, category_query),
-                (Occur::Must, line_query),
-                (Occur::Must, column_query),
-            ]);
-
-            let usage_top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
-
-            let mut locations = Vec::new();
-
-            if usage_top_docs.len() == 0 {
-                info!("No usages docs found");
-                return Ok(locations);
+            let file_path_id = self.file_path_id(&paths::uri_to_path(uri));
+            let scope = self.enclosing_scope(&searcher, &file_path_id, position)?;
+            let replace_range =
+                Range::new(Position::new(position.line, token_start_column), position);
+
+            if let Some(receiver_text) = line_prefix[..token_start].strip_suffix('.') {
+                let receiver_path = self.receiver_constant_path(receiver_text);
+                let items =
+                    self.method_completions(&searcher, token, &receiver_path, replace_range)?;
+
+                // A constant with no indexed class methods is likely defined
+                // outside the index, so any method is a better guess than none
+                if items.is_empty() && receiver_path.is_some() {
+                    self.method_completions(&searcher, token, &None, replace_range)
+                } else {
+                    Ok(items)
+                }
+            } else if token.starts_with("@") {
+                self.variable_completions(&searcher, token, &scope, replace_range)
+            } else {
+                self.name_completions(&searcher, token, &scope, replace_range)
             }
+        } else {
+            Ok(Vec::new())
+        }
+    }
 
-            let doc_address = usage_top_docs[0].1;
-            let retrieved_doc = searcher.doc(doc_address)?;
+    // Parses `Foo` or `Foo::Bar` immediately before the `.` into its parts.
+    // Any other receiver (locals, calls, literals) can't be resolved here.
+    fn receiver_constant_path(&self, receiver_text: &str) -> Option<Vec<String>> {
+        let receiver_start = receiver_text
+            .char_indices()
+            .rev()
+            .take_while(|(_index, char)| char.is_alphanumeric() || *char == '_' || *char == ':')
+            .last()
+            .map_or(receiver_text.len(), |(index, _char)| index);
+        let receiver = receiver_text[receiver_start..].trim_start_matches("::");
+
+        let parts: Vec<String> = receiver.split("::").map(|part| part.to_string()).collect();
+        let is_constant_path = parts
+            .iter()
+            .all(|part| part.starts_with(char::is_uppercase));
+
+        if is_constant_path {
+            Some(parts)
+        } else {
+            None
+        }
+    }
 
-            let category_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.category_field, "assignment"),
-                IndexRecordOption::Basic,
-            ));
+    // Class methods of the receiver constant when there is one, otherwise
+    // every method in the project.
+    fn method_completions(
+        &self,
+        searcher: &Searcher,
+        token: &str,
+        receiver_path: &Option<Vec<String>>,
+        replace_range: Range,
+    ) -> tantivy::Result<Vec<CompletionItem>> {
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "assignment"),
+            IndexRecordOption::Basic,
+        ));
+
+        let mut queries = vec![(Occur::Must, category_query)];
+
+        if token.len() > 0 {
+            let name_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
+                format!("{}.*", regex::escape(token)).as_str(),
+                self.schema_fields.name_field,
+            )?);
 
-            let usage_name = retrieved_doc
-                .get_first(self.schema_fields.name_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
-            let usage_type = retrieved_doc
-                .get_first(self.schema_fields.node_type_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
+            queries.push((Occur::Must, name_query));
+        }
 
-            let name_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.name_field, usage_name),
+        if let Some(receiver_path) = receiver_path {
+            let node_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.node_type_field, "Defs"),
                 IndexRecordOption::Basic,
             ));
 
-            let mut assignment_type_queries = vec![];
+            queries.push((Occur::Must, node_type_query));
 
-            for possible_assignment_type in USAGE_TYPE_RESTRICTIONS.get(usage_type).unwrap().iter()
-            {
-                let assignment_type_query: Box<dyn Query> = Box::new(TermQuery::new(
-                    Term::from_field_text(
-                        self.schema_fields.node_type_field,
-                        possible_assignment_type,
-                    ),
+            for scope_name in receiver_path {
+                let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.fuzzy_ruby_scope_field, scope_name),
                     IndexRecordOption::Basic,
                 ));
 
-                assignment_type_queries.push((Occur::Should, assignment_type_query));
+                queries.push((Occur::Must, scope_query));
             }
+        } else {
+            let mut method_type_queries = vec![];
 
-            let assignment_type_query = BooleanQuery::new(assignment_type_queries);
-
-            let mut queries = vec![
-                (Occur::Must, category_query),
-                (Occur::Must, name_query),
-                (Occur::Must, Box::new(assignment_type_query)),
-            ];
-
-            let usage_fuzzy_scope =
-                retrieved_doc.get_all(self.schema_fields.fuzzy_ruby_scope_field);
-
-            match usage_type {
-                // "Alias" => {},
-                "Const" => {
-                    for scope_name in usage_fuzzy_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Should, scope_query));
-                    }
-
-                    let class_scope = retrieved_doc.get_all(self.schema_fields.class_scope_field);
-
-                    for scope_name in class_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Must, scope_query));
-                    }
-                }
-                // "CSend" => {},
-                // todo: improved indexed scopes so there is a separate class scope, etc
-                // "Cvar" => {},
-                // "Gvar" => {},
-                // todo: improved indexed scopes so there is a separate class scope, etc
-                // "Ivar" => {},
-                // todo: improved to be more accurate
-                "Arg" | "Kwarg" | "Kwoptarg" | "Kwrestarg" | "Lvasgn" | "MatchVar" | "Optarg"
-                | "Restarg" | "Shadowarg" | "Lvar" => {
-                    for scope_name in usage_fuzzy_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Must, scope_query));
-                    }
-                }
-                //
-                "Send" => {
-                    let class_scope = retrieved_doc.get_all(self.schema_fields.class_scope_field);
-
-                    let mut usage_scope_fallback = true;
-
-                    for scope_name in class_scope {
-                        usage_scope_fallback = false;
-
-                        let scope_query = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        let boosted_scope_query: Box<dyn Query> =
-                            Box::new(BoostQuery::new(scope_query, 10000.0));
-
-                        // queries.push((Occur::Should, scope_query));
-                        // queries.push((Occur::Should, boosted_scope_query));
-
-                        // This probably would be better as just a boosted
-                        // query, but it's not working for some reason.
-                        queries.push((Occur::Must, boosted_scope_query));
-                    }
-
-                    if usage_scope_fallback {
-                        for scope_name in usage_fuzzy_scope {
-                            let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                                Term::from_field_text(
-                                    self.schema_fields.fuzzy_ruby_scope_field,
-                                    scope_name.as_text().unwrap(),
-                                ),
-                                IndexRecordOption::Basic,
-                            ));
-
-                            queries.push((Occur::Should, scope_query));
-                        }
-                    }
-                }
-                // "Super" => {},
-                // "ZSuper" => {},
-                _ => {
-                    for scope_name in usage_fuzzy_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Should, scope_query));
-                    }
-                }
-            };
+            for node_type in ["Alias", "Def", "Defs"] {
+                let node_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(self.schema_fields.node_type_field, node_type),
+                    IndexRecordOption::Basic,
+                ));
 
-            let query = BooleanQuery::new(queries);
-            let assignments_top_docs = searcher.search(&query, &TopDocs::with_limit(50))?;
+                method_type_queries.push((Occur::Should, node_type_query));
+            }
 
-            for (_score, doc_address) in assignments_top_docs {
-                let retrieved_doc = searcher.doc(doc_address)?;
+            queries.push((
+                Occur::Must,
+                Box::new(BooleanQuery::new(method_type_queries)),
+            ));
+        }
 
-                let file_path: String = retrieved_doc
-                    .get_all(self.schema_fields.file_path)
-                    .flat_map(Value::as_text)
-                    .collect::<Vec<&str>>()
-                    .join("/");
+        let results = searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(200))?;
 
-                let absolute_file_path: String;
+        let mut items = Vec::new();
+        let mut seen_names = HashSet::new();
 
-                let user_space = retrieved_doc
-                    .get_first(self.schema_fields.user_space_field)
-                    .unwrap()
-                    .as_bool()
-                    .unwrap() as bool;
+        for (_score, doc_address) in results {
+            let symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
 
-                if user_space {
-                    absolute_file_path = format!("{}/{}", &self.workspace_path, &file_path);
-                } else {
-                    absolute_file_path = format!("/{}", &file_path);
-                }
-
-                let doc_uri = Url::from_file_path(&absolute_file_path).unwrap();
-
-                let start_line = retrieved_doc
-                    .get_first(self.schema_fields.line_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let start_column = retrieved_doc
-                    .get_first(self.schema_fields.start_column_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let start_position = Position::new(start_line, start_column);
-                let end_column = retrieved_doc
-                    .get_first(self.schema_fields.end_column_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let end_position = Position::new(start_line, end_column);
-
-                let doc_range = Range::new(start_position, end_position);
-                let location = Location::new(doc_uri, doc_range);
+            // The scope terms only say the parts appear somewhere in the
+            // scope, `Foo.` shouldn't offer methods from `Foo::Bar`
+            if let Some(receiver_path) = receiver_path {
+                if !symbol.fuzzy_ruby_scope.ends_with(receiver_path) {
+                    continue;
+                }
+            }
 
-                locations.push(location);
+            if !seen_names.insert(symbol.name.clone()) {
+                continue;
             }
 
-            Ok(locations)
-        } else {
-            Ok(vec![])
+            let location = symbol.location();
+            let snippet = self.method_snippet(&symbol);
+            let name = symbol.name;
+
+            items.push(CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::METHOD),
+                detail: Some(format!(
+                    "{}:{}",
+                    self.relative_path(&paths::uri_to_path(&location.uri)),
+                    location.range.start.line + 1
+                )),
+                filter_text: Some(name.clone()),
+                insert_text_format: snippet.as_ref().map(|_| InsertTextFormat::SNIPPET),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    replace_range,
+                    snippet.unwrap_or(name),
+                ))),
+                ..CompletionItem::default()
+            });
         }
-    }
 
-    pub fn find_highlights(
-        &self,
-        params: TextDocumentPositionParams,
-    ) -> tantivy::Result<Vec<DocumentHighlight>> {
-        if let Ok(search_results) = self.find_references(params) {
-            let mut highlights = Vec::new();
-
-            for search_result in &search_results {
-                let start_line = search_result
-                    .get_first(self.schema_fields.line_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let start_column = search_result
-                    .get_first(self.schema_fields.start_column_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let start_position = Position::new(start_line, start_column);
-                let end_column = search_result
-                    .get_first(self.schema_fields.end_column_field)
-                    .unwrap()
-                    .as_u64()
-                    .unwrap() as u32;
-                let end_position = Position::new(start_line, end_column);
-
-                let range = Range::new(start_position, end_position);
-
-                let category = search_result
-                    .get_first(self.schema_fields.category_field)
-                    .unwrap()
-                    .as_text()
-                    .unwrap();
+        Ok(items)
+    }
 
-                let kind = if category == "assignment" {
-                    Some(DocumentHighlightKind::WRITE)
-                } else {
-                    Some(DocumentHighlightKind::READ)
-                };
+    // Builds `name(${1:arg}, key: ${2:key})` from the required parameters
+    // stored on a `Def`/`Defs` document. Optional parameters are left for the
+    // user to add, and methods without required parameters insert plainly.
+    fn method_snippet(&self, symbol: &IndexedSymbol) -> Option<String> {
+        if !self.snippet_support {
+            return None;
+        }
 
-                let document_highlight = DocumentHighlight { range, kind };
+        if symbol.node_type != "Def" && symbol.node_type != "Defs" {
+            return None;
+        }
+
+        let mut placeholders = vec![];
 
-                highlights.push(document_highlight);
+        for parameter in &symbol.parameters {
+            let tab_stop = placeholders.len() + 1;
+
+            if let Some(arg_name) = parameter.strip_prefix("arg:") {
+                placeholders.push(format!("${{{}:{}}}", tab_stop, escape_snippet(arg_name)));
+            } else if let Some(kwarg_name) = parameter.strip_prefix("kwarg:") {
+                placeholders.push(format!(
+                    "{}: ${{{}:{}}}",
+                    kwarg_name,
+                    tab_stop,
+                    escape_snippet(kwarg_name)
+                ));
             }
+        }
 
-            Ok(highlights)
+        if placeholders.is_empty() {
+            None
         } else {
-            Ok(Vec::new())
+            Some(format!(
+                "{}({})",
+                escape_snippet(&symbol.name),
+                placeholders.join(", ")
+            ))
         }
     }
 
-    pub fn find_references(
+    // Instance and class variables assigned anywhere in the enclosing class,
+    // including other files that reopen it.
+    fn variable_completions(
         &self,
-        params: TextDocumentPositionParams,
-    ) -> tantivy::Result<Vec<Document>> {
-        let path = params.text_document.uri.path();
-        let relative_path = path.replace(&self.workspace_path, "");
-
-        let position = params.position;
-
-        if let Some(index) = &self.index {
-            let reader = index
-                .reader_builder()
-                .reload_policy(ReloadPolicy::OnCommit)
-                .try_into()?;
-
-            let searcher = reader.searcher();
-            let character_position = position.character;
-            let character_line = position.line;
-            let file_path_id = blake3::hash(&relative_path.as_bytes());
+        searcher: &Searcher,
+        token: &str,
+        scope: &Vec<String>,
+        replace_range: Range,
+    ) -> tantivy::Result<Vec<CompletionItem>> {
+        let (node_type, kind) = if token.starts_with("@@") {
+            ("Cvasgn", CompletionItemKind::VARIABLE)
+        } else {
+            ("Ivasgn", CompletionItemKind::FIELD)
+        };
 
-            let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.file_path_id, &file_path_id.to_string()),
-                IndexRecordOption::Basic,
-            ));
-            let line_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_u64(self.schema_fields.line_field, character_line.into()),
-                IndexRecordOption::Basic,
-            ));
-            let column_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_u64(self.schema_fields.columns_field, character_position.into()),
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "assignment"),
+            IndexRecordOption::Basic,
+        ));
+        let node_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.node_type_field, node_type),
+            IndexRecordOption::Basic,
+        ));
+        let name_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
+            format!("{}.*", regex::escape(token)).as_str(),
+            self.schema_fields.name_field,
+        )?);
+
+        let mut queries = vec![
+            (Occur::Must, category_query),
+            (Occur::Must, node_type_query),
+            (Occur::Must, name_query),
+        ];
+
+        for scope_name in self.class_scope_of(scope) {
+            let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.fuzzy_ruby_scope_field, &scope_name),
                 IndexRecordOption::Basic,
             ));
 
-            let query = BooleanQuery::new(vec![
-                (Occur::Must, file_path_query),
-                (Occur::Must, line_query),
-                (Occur::Must, column_query),
-            ]);
+            queries.push((Occur::Must, scope_query));
+        }
 
-            let usage_top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+        let results = searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(200))?;
 
-            if usage_top_docs.len() == 0 {
-                info!("No highlight usages docs found");
-                return Ok(Vec::new());
+        let mut items = Vec::new();
+        let mut seen_names = HashSet::new();
+        let mut file_contents: HashMap<Url, Option<String>> = HashMap::new();
+
+        for (_score, doc_address) in results {
+            let symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+
+            if !seen_names.insert(symbol.name.clone()) {
+                continue;
             }
 
-            let doc_address = usage_top_docs[0].1;
-            let retrieved_doc = searcher.doc(doc_address)?;
+            let location = symbol.location();
+            let name = symbol.name;
+            let contents = file_contents
+                .entry(location.uri.clone())
+                .or_insert_with(|| self.file_contents(&location.uri));
+            let defining_line = contents.as_ref().and_then(|contents| {
+                contents
+                    .lines()
+                    .nth(location.range.start.line as usize)
+                    .map(|line| line.trim().to_string())
+            });
+
+            items.push(CompletionItem {
+                label: name.clone(),
+                kind: Some(kind),
+                detail: defining_line,
+                filter_text: Some(name.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(replace_range, name))),
+                ..CompletionItem::default()
+            });
+        }
 
-            let usage_name = retrieved_doc
-                .get_first(self.schema_fields.name_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
-            let token_type = retrieved_doc
-                .get_first(self.schema_fields.node_type_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
+        Ok(items)
+    }
 
-            let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.file_path_id, &file_path_id.to_string()),
-                IndexRecordOption::Basic,
-            ));
+    fn name_completions(
+        &self,
+        searcher: &Searcher,
+        token: &str,
+        scope: &Vec<String>,
+        replace_range: Range,
+    ) -> tantivy::Result<Vec<CompletionItem>> {
+        if token.len() == 0 {
+            return Ok(Vec::new());
+        }
 
-            let name_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_text(self.schema_fields.name_field, usage_name),
+        let local_types = [
+            "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg",
+            "Restarg", "Shadowarg",
+        ];
+        let global_types = ["Alias", "Casgn", "Class", "Def", "Defs", "Gvasgn", "Module"];
+
+        let category_query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(self.schema_fields.category_field, "assignment"),
+            IndexRecordOption::Basic,
+        ));
+        let name_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
+            format!("{}.*", regex::escape(token)).as_str(),
+            self.schema_fields.name_field,
+        )?);
+
+        let mut node_type_queries = vec![];
+
+        for node_type in local_types.iter().chain(global_types.iter()) {
+            let node_type_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.node_type_field, node_type),
                 IndexRecordOption::Basic,
             ));
 
-            let mut highlight_token_queries = vec![];
-
-            for possible_assignment_type in USAGE_TYPE_RESTRICTIONS
-                .get(token_type)
-                .unwrap_or(&[].as_slice())
-                .iter()
-            {
-                let assignment_type_query: Box<dyn Query> = Box::new(TermQuery::new(
-                    Term::from_field_text(
-                        self.schema_fields.node_type_field,
-                        possible_assignment_type,
-                    ),
-                    IndexRecordOption::Basic,
-                ));
-
-                highlight_token_queries.push((Occur::Should, assignment_type_query));
-            }
-            for possible_usage_type in ASSIGNMENT_TYPE_RESTRICTIONS
-                .get(token_type)
-                .unwrap_or(&[].as_slice())
-                .iter()
-            {
-                let usage_type_query: Box<dyn Query> = Box::new(TermQuery::new(
-                    Term::from_field_text(self.schema_fields.node_type_field, possible_usage_type),
-                    IndexRecordOption::Basic,
-                ));
+            node_type_queries.push((Occur::Should, node_type_query));
+        }
 
-                highlight_token_queries.push((Occur::Should, usage_type_query));
-            }
+        let queries = vec![
+            (Occur::Must, category_query),
+            (Occur::Must, name_query),
+            (
+                Occur::Must,
+                Box::new(BooleanQuery::new(node_type_queries)) as Box<dyn Query>,
+            ),
+        ];
 
-            let token_type_query = BooleanQuery::new(highlight_token_queries);
+        let results = searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(100))?;
 
-            let mut queries = vec![
-                (Occur::Must, file_path_query),
-                (Occur::Must, name_query),
-                (Occur::Must, Box::new(token_type_query)),
-            ];
+        let mut items = Vec::new();
+        let mut seen_names = HashSet::new();
 
-            let usage_fuzzy_scope =
-                retrieved_doc.get_all(self.schema_fields.fuzzy_ruby_scope_field);
-
-            match token_type {
-                // "Alias" => {},
-                // "Const" => {},
-                // "CSend" => {},
-                // todo: improved indexed scopes so there is a separate class scope, etc
-                // "Cvar" => {},
-                // "Gvar" => {},
-                // todo: improved indexed scopes so there is a separate class scope, etc
-                // "Ivar" => {},
-                // todo: improved to be more accurate
-
-                // same values as local assignment type restrictions, for
-                // example "Lvasgn" in ASSIGNMENT_TYPE_RESTRICTIONS
-                "Arg" | "Kwarg" | "Kwoptarg" | "Kwrestarg" | "Lvasgn" | "MatchVar" | "Optarg"
-                | "Restarg" | "Shadowarg" | "Lvar" => {
-                    for scope_name in usage_fuzzy_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Must, scope_query));
-                    }
-                }
-                // "Send" => {},
-                // "Super" => {},
-                // "ZSuper" => {},
-                _ => {
-                    for scope_name in usage_fuzzy_scope {
-                        let scope_query: Box<dyn Query> = Box::new(TermQuery::new(
-                            Term::from_field_text(
-                                self.schema_fields.fuzzy_ruby_scope_field,
-                                scope_name.as_text().unwrap(),
-                            ),
-                            IndexRecordOption::Basic,
-                        ));
-
-                        queries.push((Occur::Should, scope_query));
-                    }
-                }
+        for (_score, doc_address) in results {
+            let symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                Some(symbol) => symbol,
+                None => continue,
             };
 
-            let results =
-                searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(100))?;
+            // Locals are only visible from the scope they're defined in
+            if local_types.contains(&symbol.node_type.as_str()) && &symbol.fuzzy_ruby_scope != scope
+            {
+                continue;
+            }
 
-            let mut documents = Vec::new();
+            let kind = match symbol.node_type.as_str() {
+                "Alias" | "Def" | "Defs" => CompletionItemKind::METHOD,
+                "Casgn" => CompletionItemKind::CONSTANT,
+                "Class" => CompletionItemKind::CLASS,
+                "Module" => CompletionItemKind::MODULE,
+                _ => CompletionItemKind::VARIABLE,
+            };
 
-            for (_score, doc_address) in results {
-                documents.push(searcher.doc(doc_address).unwrap())
+            if !seen_names.insert((symbol.name.clone(), kind)) {
+                continue;
             }
 
-            Ok(documents)
-        } else {
-            Ok(Vec::new())
+            let location = symbol.location();
+            let snippet = self.method_snippet(&symbol);
+            let name = symbol.name;
+
+            items.push(CompletionItem {
+                label: name.clone(),
+                kind: Some(kind),
+                detail: Some(format!(
+                    "{}:{}",
+                    self.relative_path(&paths::uri_to_path(&location.uri)),
+                    location.range.start.line + 1
+                )),
+                filter_text: Some(name.clone()),
+                insert_text_format: snippet.as_ref().map(|_| InsertTextFormat::SNIPPET),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    replace_range,
+                    snippet.unwrap_or(name),
+                ))),
+                ..CompletionItem::default()
+            });
         }
+
+        Ok(items)
     }
 
-    pub fn find_references_in_workspace(
+    // Scope of the closest indexed node at or before the position. The index
+    // is used rather than parsing since the file rarely parses mid-edit.
+    fn enclosing_scope(
         &self,
-        query: String,
-    ) -> tantivy::Result<Vec<Document>> {
-        if let Some(index) = &self.index {
-            let reader = index
-                .reader_builder()
-                .reload_policy(ReloadPolicy::OnCommit)
-                .try_into()?;
-
-            let searcher = reader.searcher();
+        searcher: &Searcher,
+        file_path_id: &str,
+        position: Position,
+    ) -> tantivy::Result<Vec<String>> {
+        let first_line = position.line.saturating_sub(100);
 
-            let user_space_query: Box<dyn Query> = Box::new(TermQuery::new(
-                Term::from_field_bool(self.schema_fields.user_space_field, true),
+        for line in (first_line..=position.line).rev() {
+            let file_path_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_text(self.schema_fields.file_path_id, file_path_id),
+                IndexRecordOption::Basic,
+            ));
+            let line_query: Box<dyn Query> = Box::new(TermQuery::new(
+                Term::from_field_u64(self.schema_fields.line_field, line.into()),
                 IndexRecordOption::Basic,
             ));
 
-            let name_query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(
-                format!("{}.*", query).as_str(),
-                self.schema_fields.name_field,
-            )?);
-
-            let mut allowed_type_queries = vec![];
-            let allowed_types = ["Alias", "Casgn", "Class", "Def", "Defs", "Gvasgn", "Module"];
+            let query = BooleanQuery::new(vec![
+                (Occur::Must, file_path_query),
+                (Occur::Must, line_query),
+            ]);
 
-            for allowed_type in allowed_types {
-                let assignment_type_query: Box<dyn Query> = Box::new(TermQuery::new(
-                    Term::from_field_text(self.schema_fields.node_type_field, allowed_type),
-                    IndexRecordOption::Basic,
-                ));
+            let mut closest: Option<(u32, Vec<String>)> = None;
 
-                allowed_type_queries.push((Occur::Should, assignment_type_query));
-            }
+            for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(100))? {
+                let symbol = match self.indexed_symbol(&searcher.doc(doc_address)?) {
+                    Some(symbol) => symbol,
+                    None => continue,
+                };
 
-            let allowed_types_query = BooleanQuery::new(allowed_type_queries);
+                if line == position.line && symbol.start_column > position.character {
+                    continue;
+                }
 
-            let queries = vec![
-                (Occur::Must, user_space_query),
-                (Occur::Must, name_query),
-                (Occur::Must, Box::new(allowed_types_query)),
-            ];
+                if let Some((closest_column, _scope)) = &closest {
+                    if symbol.start_column < *closest_column {
+                        continue;
+                    }
+                }
 
-            let results =
-                searcher.search(&BooleanQuery::new(queries), &TopDocs::with_limit(100))?;
+                let mut scope = symbol.fuzzy_ruby_scope;
 
-            let mut documents = Vec::new();
+                // The method body is scoped to the method's name
+                if symbol.category == "assignment" && symbol.node_type == "Def" {
+                    scope.push(symbol.name);
+                } else if symbol.category == "assignment" && symbol.node_type == "Defs" {
+                    scope.push(format!("self.{}", symbol.name));
+                }
 
-            for (_score, doc_address) in results {
-                documents.push(searcher.doc(doc_address).unwrap())
+                closest = Some((symbol.start_column, scope));
             }
 
-            Ok(documents)
-        } else {
-            Ok(Vec::new())
+            if let Some((_column, scope)) = closest {
+                return Ok(scope);
+            }
         }
+
+        Ok(Vec::new())
     }
 
-    pub fn documents_to_locations(
-        &self,
-        path: &str,
-        documents: Vec<Document>,
-    ) -> Vec<Location> {
-        let mut locations = Vec::new();
+    // Drops trailing method names from a scope, leaving the classes and
+    // modules. Constants are the only capitalized scope names.
+    fn class_scope_of(&self, scope: &Vec<String>) -> Vec<String> {
+        let class_scope_len = scope
+            .iter()
+            .rposition(|scope_name| scope_name.starts_with(char::is_uppercase))
+            .map_or(0, |index| index + 1);
 
-        for document in documents {
-            let doc_uri = Url::from_file_path(path).unwrap();
+        scope[..class_scope_len].to_vec()
+    }
 
-            let start_line = document
-                .get_first(self.schema_fields.line_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_column = document
-                .get_first(self.schema_fields.start_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_position = Position::new(start_line, start_column);
-            let end_column = document
-                .get_first(self.schema_fields.end_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let end_position = Position::new(start_line, end_column);
+    pub fn documents_to_locations(&self, uri: &Url, documents: Vec<Document>) -> Vec<Location> {
+        let mut locations = Vec::new();
 
-            let doc_range = Range::new(start_position, end_position);
-            let location = Location::new(doc_uri, doc_range);
+        for document in documents {
+            let symbol = match self.indexed_symbol(&document) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+            let location = Location::new(uri.clone(), symbol.range());
 
-            locations.push(location);
+            if !locations.contains(&location) {
+                locations.push(location);
+            }
         }
 
         locations
     }
 
+    // Workspace documents store their path relative to the folder they were
+    // indexed from, everything else stores an absolute path.
+    fn document_path(&self, document: &Document) -> Option<String> {
+        self.indexed_symbol(document).map(|symbol| symbol.path())
+    }
+
+    fn document_to_location(&self, document: &Document) -> Option<Location> {
+        self.indexed_symbol(document)
+            .map(|symbol| symbol.location())
+    }
+
+    // None for documents missing a field, which are logged and left out of
+    // results rather than failing the request, and for documents from a
+    // file's superseded generation
+    fn indexed_symbol(&self, document: &Document) -> Option<IndexedSymbol> {
+        match IndexedSymbol::try_from(document) {
+            Ok(symbol) if self.is_superseded(&symbol) => None,
+            Ok(symbol) => Some(symbol),
+            Err(error) => {
+                info!("Skipping {}", error);
+                None
+            }
+        }
+    }
+
     pub fn rename_tokens(
         &self,
-        path: &str,
+        uri: &Url,
         documents: Vec<Document>,
         new_name: &String,
     ) -> WorkspaceEdit {
-        let mut edits = Vec::new();
+        let file_path_id = self.file_path_id(&paths::uri_to_path(uri));
+        let mut map: HashMap<Url, Vec<TextEdit>> = HashMap::new();
 
         for document in documents {
-            let start_line = document
-                .get_first(self.schema_fields.line_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_column = document
-                .get_first(self.schema_fields.start_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_position = Position::new(start_line, start_column);
-            let end_column = document
-                .get_first(self.schema_fields.end_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let end_position = Position::new(start_line, end_column);
+            let symbol = match self.indexed_symbol(&document) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
 
-            edits.push(TextEdit::new(
-                Range::new(start_position, end_position),
-                new_name.clone(),
-            ));
-        }
+            // The `yield` keyword stays as it is when the block is renamed
+            if symbol.node_type == "Yield" {
+                continue;
+            }
+
+            // Edits to the file being renamed in use the editor's URI for it
+            let edit_uri = if symbol.file_path_id == file_path_id {
+                uri.clone()
+            } else {
+                symbol.location().uri
+            };
+            let edits = map.entry(edit_uri).or_default();
+            let range = symbol.range();
+
+            // Edits can't overlap, and op-assignments index their receiver
+            // twice
+            if edits.iter().any(|edit: &TextEdit| edit.range == range) {
+                continue;
+            }
 
-        let mut map = HashMap::new();
-        let uri = Url::from_file_path(&path).unwrap();
+            edits.push(TextEdit::new(range, new_name.clone()));
+        }
 
-        map.insert(uri, edits);
+        // The file being renamed in has an entry even when nothing's renamed
+        map.entry(uri.clone()).or_default();
 
         let workspace_edit = WorkspaceEdit::new(map);
 
@@ -1542,45 +7608,12 @@ impl Persistence {
         let mut symbol_infos = Vec::new();
 
         for document in documents {
-            let doc_path: Vec<&str> = document
-                .get_all(self.schema_fields.file_path)
-                .map(|v| v.as_text().unwrap())
-                .collect();
-            let doc_path = doc_path.join("/");
-            let absolute_file_path = format!("{}/{}", &self.workspace_path, &doc_path);
-            let doc_uri = Url::from_file_path(absolute_file_path).unwrap();
-
-            let name = document
-                .get_first(self.schema_fields.name_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
-
-            let start_line = document
-                .get_first(self.schema_fields.line_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_column = document
-                .get_first(self.schema_fields.start_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let start_position = Position::new(start_line, start_column);
-            let end_column = document
-                .get_first(self.schema_fields.end_column_field)
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
-            let end_position = Position::new(start_line, end_column);
-
-            let doc_type = document
-                .get_first(self.schema_fields.node_type_field)
-                .unwrap()
-                .as_text()
-                .unwrap();
+            let symbol = match self.indexed_symbol(&document) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
 
-            let symbol_kind = match doc_type {
+            let symbol_kind = match symbol.node_type.as_str() {
                 "Alias" => SymbolKind::METHOD,
                 "Casgn" => SymbolKind::CLASS,
                 "Class" => SymbolKind::CLASS,
@@ -1588,83 +7621,186 @@ impl Persistence {
                 "Defs" => SymbolKind::METHOD,
                 "Gvasgn" => SymbolKind::VARIABLE,
                 "Module" => SymbolKind::MODULE,
+                "Partial" => SymbolKind::FILE,
                 _ => SymbolKind::VARIABLE,
             };
 
-            let doc_range = Range::new(start_position, end_position);
-            let symbol_location = Location::new(doc_uri, doc_range);
+            // Shown beside the name, public methods are left unmarked
+            let container_name = symbol
+                .visibility
+                .clone()
+                .filter(|visibility| visibility != "public");
 
             let symbol_info = SymbolInformation {
-                name: name.to_string(),
+                location: symbol.location(),
+                name: symbol.name,
                 kind: symbol_kind,
                 tags: None,
                 deprecated: None,
-                location: symbol_location,
-                container_name: None,
+                container_name,
             };
 
             symbol_infos.push(symbol_info);
         }
 
-        symbol_infos
+        symbol_infos
+    }
+
+    fn parse(
+        &mut self,
+        contents: &String,
+        documents: &mut Vec<FuzzyNode>,
+    ) -> Result<
+        Vec<Option<tower_lsp::lsp_types::Diagnostic>>,
+        Vec<Option<tower_lsp::lsp_types::Diagnostic>>,
+    > {
+        let options = ParserOptions {
+            buffer_name: "(eval)".to_string(),
+            decoder: Some(encoding::parser_decoder()),
+            record_tokens: false,
+            ..Default::default()
+        };
+        let parser = Parser::new(contents.to_string(), options);
+        let parser_result = parser.do_parse();
+        let input = SourceInput::new(contents);
+
+        let mut diagnostics = vec![];
+
+        for parser_diagnostic in parser_result.diagnostics {
+            diagnostics.push(self.lsp_diagnostic(parser_diagnostic, &input));
+        }
+
+        let ast = match parser_result.ast {
+            Some(a) => *a,
+            None => return Err(diagnostics),
+        };
+
+        self.comments.clear();
+        self.last_regexp_match = None;
+        self.newer_syntax_diagnostics.clear();
+        self.plugin_diagnostics.clear();
+
+        for comment in &parser_result.comments {
+            if !input.starts_line(comment.location.begin) {
+                continue;
+            }
+
+            if let Some(text) = input.source(&comment.location).strip_prefix("#") {
+                let line = input.line(comment.location.begin);
+                let text = text.strip_prefix(" ").unwrap_or(text);
+
+                self.comments.insert(line, text.trim_end().to_string());
+            }
+        }
+
+        let mut scope = Vec::new();
+
+        self.serialize(&ast, documents, &mut scope, &input);
+        self.comments.clear();
+
+        for diagnostic in self.duplicate_method_diagnostics(documents) {
+            diagnostics.push(Some(diagnostic));
+        }
+
+        for diagnostic in self.newer_syntax_diagnostics.drain(..) {
+            diagnostics.push(Some(diagnostic));
+        }
+
+        for diagnostic in self.plugin_diagnostics.drain(..) {
+            diagnostics.push(Some(diagnostic));
+        }
+
+        Ok(diagnostics)
+    }
+
+    // Warns on methods defined twice in the same scope of a file, since the
+    // later definition silently replaces the earlier one. Those under a
+    // condition or in a block are left out, like a method defined in both
+    // branches of an `if`, or in sibling `context` or `Class.new` blocks.
+    fn duplicate_method_diagnostics(
+        &self,
+        documents: &Vec<FuzzyNode>,
+    ) -> Vec<tower_lsp::lsp_types::Diagnostic> {
+        let mut first_definitions: HashMap<(&str, &Vec<String>, &String), usize> = HashMap::new();
+        let mut diagnostics = vec![];
+
+        for document in documents {
+            let method_definition = document.category == "assignment"
+                && (document.node_type == "Def" || document.node_type == "Defs")
+                && !document.conditional;
+
+            if !method_definition {
+                continue;
+            }
+
+            let key = (
+                document.node_type,
+                &document.fuzzy_ruby_scope,
+                &document.name,
+            );
+
+            match first_definitions.get(&key) {
+                Some(first_line) if *first_line != document.line => {
+                    let range = Range::new(
+                        Position::new(document.line as u32, document.start_column as u32),
+                        Position::new(document.line as u32, document.end_column as u32),
+                    );
+
+                    diagnostics.push(tower_lsp::lsp_types::Diagnostic {
+                        range,
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        code: Some(NumberOrString::String(DUPLICATE_METHOD_CODE.to_string())),
+                        message: format!(
+                            "`{}` is already defined on line {}",
+                            document.name,
+                            first_line + 1
+                        ),
+                        ..Default::default()
+                    });
+                }
+                Some(_first_line) => {}
+                None => {
+                    first_definitions.insert(key, document.line);
+                }
+            }
+        }
+
+        diagnostics
     }
 
-    fn parse(
-        &mut self,
-        contents: &String,
-        documents: &mut Vec<FuzzyNode>,
-    ) -> Result<
-        Vec<Option<tower_lsp::lsp_types::Diagnostic>>,
-        Vec<Option<tower_lsp::lsp_types::Diagnostic>>,
-    > {
-        let options = ParserOptions {
-            buffer_name: "(eval)".to_string(),
-            record_tokens: false,
-            ..Default::default()
-        };
-        let parser = Parser::new(contents.to_string(), options);
-        let parser_result = parser.do_parse();
-        let input = parser_result.input;
+    // The comments directly above a line, without magic comments and
+    // directives
+    fn doc_comment(&self, line: usize) -> Option<String> {
+        let mut comment_lines = vec![];
+        let mut current_line = line;
 
-        let mut diagnostics = vec![];
+        while current_line > 0 {
+            current_line -= 1;
 
-        for parser_diagnostic in parser_result.diagnostics {
-            diagnostics.push(self.lsp_diagnostic(parser_diagnostic, &input));
+            match self.comments.get(&current_line) {
+                Some(text) => comment_lines.push(text.as_str()),
+                None => break,
+            }
         }
 
-        let ast = match parser_result.ast {
-            Some(a) => *a,
-            None => return Err(diagnostics),
-        };
-
-        let mut scope = Vec::new();
-
-        self.serialize(&ast, documents, &mut scope, &input);
+        comment_lines.reverse();
+        comment_lines.retain(|text| !is_comment_directive(text));
 
-        Ok(diagnostics)
+        if comment_lines.is_empty() {
+            None
+        } else {
+            Some(comment_lines.join("\n"))
+        }
     }
 
     fn lsp_diagnostic(
         &mut self,
         parser_diagnostic: lib_ruby_parser::Diagnostic,
-        input: &DecodedInput,
+        input: &SourceInput,
     ) -> Option<tower_lsp::lsp_types::Diagnostic> {
         let diagnostic = || -> Option<tower_lsp::lsp_types::Diagnostic> {
-            let (begin_lineno, start_column) =
-                input.line_col_for_pos(parser_diagnostic.loc.begin).unwrap();
-            let (end_lineno, end_column) =
-                input.line_col_for_pos(parser_diagnostic.loc.end).unwrap();
-            let start_position = Position::new(
-                begin_lineno.try_into().unwrap(),
-                start_column.try_into().unwrap(),
-            );
-            let end_position = Position::new(
-                end_lineno.try_into().unwrap(),
-                end_column.try_into().unwrap(),
-            );
-
             Some(tower_lsp::lsp_types::Diagnostic::new_simple(
-                Range::new(start_position, end_position),
+                input.range(&parser_diagnostic.loc),
                 parser_diagnostic.message.render(),
             ))
         }();
@@ -1672,19 +7808,90 @@ impl Persistence {
         diagnostic
     }
 
+    // Reports syntax newer than the project's Ruby, in workspace files
+    fn check_syntax_version(&mut self, feature: &SyntaxFeature, loc: &Loc, input: &SourceInput) {
+        let ruby_version = match self.ruby_version {
+            Some(ruby_version) if ruby_version < feature.since && !self.index_interface_only => {
+                ruby_version
+            }
+            _ => return,
+        };
+
+        self.newer_syntax_diagnostics
+            .push(tower_lsp::lsp_types::Diagnostic {
+                range: input.range(loc),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(RUBY_VERSION_CODE.to_string())),
+                message: format!(
+                    "Ruby {} doesn't support {}, added in Ruby {}",
+                    ruby_version, feature.description, feature.since
+                ),
+                ..Default::default()
+            });
+    }
+
+    fn check_range_syntax(
+        &mut self,
+        left: &Option<Box<Node>>,
+        right: &Option<Box<Node>>,
+        operator_l: &Loc,
+        input: &SourceInput,
+    ) {
+        if left.is_none() {
+            self.check_syntax_version(&ruby_version::BEGINLESS_RANGES, operator_l, input);
+        } else if right.is_none() {
+            self.check_syntax_version(&ruby_version::ENDLESS_RANGES, operator_l, input);
+        }
+    }
+
     fn serialize(
         &mut self,
         node: &Node,
         documents: &mut Vec<FuzzyNode>,
         fuzzy_scope: &mut Vec<String>,
-        input: &DecodedInput,
+        input: &SourceInput,
+    ) {
+        let conditional = matches!(
+            node,
+            Node::If(_)
+                | Node::IfMod(_)
+                | Node::IfTernary(_)
+                | Node::Case(_)
+                | Node::CaseMatch(_)
+                | Node::While(_)
+                | Node::WhilePost(_)
+                | Node::Until(_)
+                | Node::UntilPost(_)
+                | Node::For(_)
+                | Node::And(_)
+                | Node::Or(_)
+                | Node::Rescue(_)
+                | Node::Block(_)
+                | Node::Numblock(_)
+        );
+
+        if conditional {
+            self.conditional_depth += 1;
+        }
+
+        self.serialize_node(node, documents, fuzzy_scope, input);
+
+        if conditional {
+            self.conditional_depth -= 1;
+        }
+    }
+
+    fn serialize_node(
+        &mut self,
+        node: &Node,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
     ) {
         match &node {
             Node::Alias(Alias { to, from, .. }) => {
                 if let Node::Sym(sym) = *to.to_owned() {
-                    let (lineno, begin_pos) =
-                        input.line_col_for_pos(sym.expression_l.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(sym.expression_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&sym.expression_l);
 
                     documents.push(FuzzyNode {
                         category: "assignment",
@@ -1695,13 +7902,12 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        ..Default::default()
                     });
                 }
 
                 if let Node::Sym(sym) = *from.to_owned() {
-                    let (lineno, begin_pos) =
-                        input.line_col_for_pos(sym.expression_l.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(sym.expression_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&sym.expression_l);
 
                     documents.push(FuzzyNode {
                         category: "usage",
@@ -1712,6 +7918,7 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        ..Default::default()
                     });
                 }
             }
@@ -1723,12 +7930,12 @@ impl Persistence {
 
             Node::AndAsgn(AndAsgn { recv, value, .. }) => {
                 self.serialize(recv, documents, fuzzy_scope, input);
+                self.serialize_op_assign_read(recv, documents, fuzzy_scope, input);
                 self.serialize(value, documents, fuzzy_scope, input);
             }
 
             Node::Arg(Arg { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -1739,6 +7946,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -1770,16 +7978,68 @@ impl Persistence {
                 }
             }
 
-            // Node::BackRef(BackRef { .. }) => {}
+            Node::BackRef(BackRef { expression_l, .. }) => {
+                self.serialize_match_global("BackRef", expression_l, documents, fuzzy_scope, input);
+            }
+
             Node::Begin(Begin { statements, .. }) => {
                 for child_node in statements {
                     self.serialize(child_node, documents, fuzzy_scope, input);
+
+                    // A sig only applies to the statement right after it
+                    if !is_sorbet_sig(child_node) {
+                        self.pending_signature = None;
+                        self.pending_documentation = None;
+                    }
                 }
             }
 
             Node::Block(Block {
-                call, args, body, ..
+                call,
+                args,
+                body,
+                expression_l,
+                ..
             }) => {
+                if is_sorbet_sig(node) {
+                    let sig = input.source(expression_l);
+
+                    self.pending_signature =
+                        Some(sig.split_whitespace().collect::<Vec<&str>>().join(" "));
+
+                    let sig_line = input.line(expression_l.begin);
+                    self.pending_documentation = self.doc_comment(sig_line);
+                }
+
+                if self.visit_block_plugins(call, body.as_deref(), documents, fuzzy_scope, input) {
+                    return;
+                }
+
+                // A concern's `included` block runs in the class including
+                // it, so its associations, scopes, and methods are indexed
+                // like the concern's own. Methods in `class_methods` are
+                // the including class's class methods.
+                if let Some(concern_method) = concern_block_method(call).filter(|_| self.concern) {
+                    if !self.index_interface_only {
+                        self.serialize(call, documents, fuzzy_scope, input);
+                    }
+
+                    let outer_singleton_class = std::mem::replace(
+                        &mut self.singleton_class,
+                        concern_method == "class_methods",
+                    );
+                    let first_body_document = documents.len();
+
+                    if let Some(child_node) = body {
+                        self.serialize(child_node, documents, fuzzy_scope, input);
+                    }
+
+                    self.add_visibility(body, documents, first_body_document, fuzzy_scope);
+                    self.singleton_class = outer_singleton_class;
+
+                    return;
+                }
+
                 if self.index_interface_only {
                     return;
                 }
@@ -1790,12 +8050,50 @@ impl Persistence {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
 
+                for plugin in &mut self.plugins {
+                    plugin.enter_block(call);
+                }
+
                 if let Some(child_node) = body {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
+
+                for plugin in &mut self.plugins {
+                    plugin.exit_block();
+                }
+            }
+
+            Node::Blockarg(Blockarg {
+                name,
+                name_l,
+                expression_l,
+            }) => {
+                if name.is_none() {
+                    self.check_syntax_version(
+                        &ruby_version::ANONYMOUS_BLOCK_ARGUMENTS,
+                        expression_l,
+                        input,
+                    );
+                }
+
+                // Ruby 3.1's anonymous `&` has no name to refer to
+                if let (Some(name), Some(name_l)) = (name, name_l) {
+                    let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
+
+                    documents.push(FuzzyNode {
+                        category: "assignment",
+                        fuzzy_ruby_scope: fuzzy_scope.clone(),
+                        class_scope: vec![],
+                        name: name.to_string(),
+                        node_type: "Blockarg",
+                        line: lineno,
+                        start_column: begin_pos,
+                        end_column: end_pos,
+                        ..Default::default()
+                    });
+                }
             }
 
-            // Node::Blockarg(Blockarg { .. }) => {}
             Node::BlockPass(BlockPass { value, .. }) => {
                 if let Some(child_node) = value {
                     self.serialize(child_node, documents, fuzzy_scope, input);
@@ -1831,8 +8129,11 @@ impl Persistence {
                 expr,
                 in_bodies,
                 else_body,
+                keyword_l,
                 ..
             }) => {
+                self.check_syntax_version(&ruby_version::PATTERN_MATCHING, keyword_l, input);
+
                 self.serialize(expr, documents, fuzzy_scope, input);
 
                 for child_node in in_bodies {
@@ -1860,8 +8161,7 @@ impl Persistence {
                 };
                 let node_class_scope = self.build_class_scope(&const_node);
 
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -1872,6 +8172,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 if let Some(child_node) = scope {
@@ -1891,19 +8192,21 @@ impl Persistence {
                 ..
             }) => {
                 if let Node::Const(const_node) = *name.to_owned() {
-                    // loop over names and add to fuzzy/class_scope
+                    // Each segment of a compound name like `Foo::Bar` is a
+                    // usage in the scope the class is defined in
+                    if let Some(scope_node) = &const_node.scope {
+                        self.serialize(scope_node, documents, fuzzy_scope, input);
+                    }
+
                     let node_class_scope = self.build_class_scope(&const_node);
                     let class_scope_len = node_class_scope.len();
 
-                    for ancestor_name in node_class_scope {
+                    // Innermost first, pushed outermost first
+                    for ancestor_name in node_class_scope.into_iter().rev() {
                         fuzzy_scope.push(ancestor_name);
                     }
 
-                    let (lineno, begin_pos) = input
-                        .line_col_for_pos(const_node.expression_l.begin)
-                        .unwrap();
-                    let (_lineno, end_pos) =
-                        input.line_col_for_pos(const_node.expression_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&const_node.name_l);
                     let class_name = const_node.name.to_string();
 
                     let document = FuzzyNode {
@@ -1916,31 +8219,54 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        documentation: self.doc_comment(lineno),
+                        ..Default::default()
                     };
 
                     documents.push(document);
 
                     fuzzy_scope.push(class_name.to_string());
                     self.class_scope.push(class_name);
-
-                    if let Some(scope_node) = const_node.scope {
-                        self.serialize(&scope_node, documents, fuzzy_scope, input);
-                    }
+                    let outer_singleton_class = std::mem::replace(&mut self.singleton_class, false);
+                    let outer_concern = std::mem::replace(&mut self.concern, false);
 
                     if let Some(superclass_node) = superclass {
+                        if let Node::Const(superclass_const) = superclass_node.as_ref() {
+                            let (lineno, begin_pos, end_pos) =
+                                input.line_span(&superclass_const.name_l);
+
+                            documents.push(FuzzyNode {
+                                category: "inheritance",
+                                fuzzy_ruby_scope: fuzzy_scope.clone(),
+                                class_scope: self.build_class_scope(superclass_const),
+                                name: superclass_const.name.to_string(),
+                                node_type: "Superclass",
+                                line: lineno,
+                                start_column: begin_pos,
+                                end_column: end_pos,
+                                ..Default::default()
+                            });
+                        }
+
                         self.serialize(superclass_node, documents, fuzzy_scope, input);
                     }
 
+                    let first_body_document = documents.len();
+
                     for child_node in body {
                         self.serialize(child_node, documents, fuzzy_scope, input);
                     }
 
+                    self.add_visibility(body, documents, first_body_document, fuzzy_scope);
+
                     for _ in 0..class_scope_len {
                         fuzzy_scope.pop();
                     }
 
                     fuzzy_scope.pop();
                     self.class_scope.pop();
+                    self.singleton_class = outer_singleton_class;
+                    self.concern = outer_concern;
                 }
             }
 
@@ -1960,8 +8286,7 @@ impl Persistence {
                 };
                 let node_class_scope = self.build_class_scope(&const_node);
 
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 let document = FuzzyNode {
                     category: "usage",
@@ -1972,6 +8297,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 };
 
                 documents.push(document);
@@ -1996,8 +8322,7 @@ impl Persistence {
                 ..
             }) => {
                 if let Some(loc) = selector_l {
-                    let (lineno, begin_pos) = input.line_col_for_pos(loc.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(loc.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&loc);
 
                     documents.push(FuzzyNode {
                         category: "usage",
@@ -2008,6 +8333,8 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        receiver: Some(self.receiver_hint(Some(recv.as_ref()))),
+                        ..Default::default()
                     });
                 }
 
@@ -2019,8 +8346,7 @@ impl Persistence {
             }
 
             Node::Cvar(Cvar { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "usage",
@@ -2031,6 +8357,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -2040,8 +8367,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2052,6 +8378,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 if let Some(child_node) = value {
@@ -2064,27 +8391,67 @@ impl Persistence {
                 args,
                 body,
                 name_l,
+                assignment_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                if let Some(assignment_l) = assignment_l {
+                    self.check_syntax_version(&ruby_version::ENDLESS_METHODS, assignment_l, input);
+                }
+
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
+
+                // Methods in `class << self` are indexed like `def self.name`
+                let node_type = if self.singleton_class { "Defs" } else { "Def" };
+                let document_index = documents.len();
 
                 documents.push(FuzzyNode {
                     category: "assignment",
                     fuzzy_ruby_scope: fuzzy_scope.clone(),
                     class_scope: vec![],
                     name: name.to_string(),
-                    node_type: "Def",
+                    node_type,
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    parameters: self.describe_parameters(args),
+                    signature: self.pending_signature.take(),
+                    documentation: self
+                        .pending_documentation
+                        .take()
+                        .or_else(|| self.doc_comment(lineno)),
+                    conditional: self.conditional_depth > 0,
+                    ..Default::default()
                 });
 
                 if self.index_interface_only {
                     return;
                 }
 
-                fuzzy_scope.push(name.to_string());
+                // Minitest runs every method whose name starts with `test_`
+                if name.starts_with("test_") && !self.singleton_class {
+                    documents.push(FuzzyNode {
+                        category: "test",
+                        fuzzy_ruby_scope: fuzzy_scope.clone(),
+                        name: name.to_string(),
+                        node_type: "Def",
+                        line: lineno,
+                        start_column: begin_pos,
+                        end_column: end_pos,
+                        ..Default::default()
+                    });
+                }
+
+                if self.singleton_class {
+                    fuzzy_scope.push(format!("self.{}", name));
+                } else {
+                    fuzzy_scope.push(name.to_string());
+                }
+
+                self.method_yields.push(None);
+                self.yield_targets
+                    .push(block_param_name(args).unwrap_or_else(|| name.to_string()));
+                let outer_regexp_match = self.last_regexp_match.take();
+                let outer_local_classes = self.local_classes.replace(HashMap::new());
 
                 if let Some(child_node) = args {
                     self.serialize(child_node, documents, fuzzy_scope, input);
@@ -2094,6 +8461,17 @@ impl Persistence {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
 
+                if let Some(Some(yielded_names)) = self.method_yields.pop() {
+                    for yielded_name in yielded_names {
+                        documents[document_index]
+                            .parameters
+                            .push(format!("yield:{}", yielded_name));
+                    }
+                }
+
+                self.yield_targets.pop();
+                self.last_regexp_match = outer_regexp_match;
+                self.local_classes = outer_local_classes;
                 fuzzy_scope.pop();
             }
 
@@ -2106,10 +8484,16 @@ impl Persistence {
                 args,
                 body,
                 name_l,
+                assignment_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                if let Some(assignment_l) = assignment_l {
+                    self.check_syntax_version(&ruby_version::ENDLESS_METHODS, assignment_l, input);
+                }
+
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
+
+                let document_index = documents.len();
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2120,6 +8504,14 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    parameters: self.describe_parameters(args),
+                    signature: self.pending_signature.take(),
+                    documentation: self
+                        .pending_documentation
+                        .take()
+                        .or_else(|| self.doc_comment(lineno)),
+                    conditional: self.conditional_depth > 0,
+                    ..Default::default()
                 });
 
                 if self.index_interface_only {
@@ -2130,6 +8522,11 @@ impl Persistence {
                 scope_name.push_str(name);
 
                 fuzzy_scope.push(scope_name);
+                self.method_yields.push(None);
+                self.yield_targets
+                    .push(block_param_name(args).unwrap_or_else(|| name.to_string()));
+                let outer_regexp_match = self.last_regexp_match.take();
+                let outer_local_classes = self.local_classes.replace(HashMap::new());
 
                 if let Some(child_node) = args {
                     self.serialize(child_node, documents, fuzzy_scope, input);
@@ -2139,6 +8536,17 @@ impl Persistence {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
 
+                if let Some(Some(yielded_names)) = self.method_yields.pop() {
+                    for yielded_name in yielded_names {
+                        documents[document_index]
+                            .parameters
+                            .push(format!("yield:{}", yielded_name));
+                    }
+                }
+
+                self.yield_targets.pop();
+                self.last_regexp_match = outer_regexp_match;
+                self.local_classes = outer_local_classes;
                 fuzzy_scope.pop();
             }
 
@@ -2176,7 +8584,14 @@ impl Persistence {
                 }
             }
 
-            Node::Erange(Erange { left, right, .. }) => {
+            Node::Erange(Erange {
+                left,
+                right,
+                operator_l,
+                ..
+            }) => {
+                self.check_range_syntax(left, right, operator_l, input);
+
                 if let Some(child_node) = left {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
@@ -2209,11 +8624,42 @@ impl Persistence {
                 }
             }
 
-            // Node::ForwardArg(ForwardArg { .. }) => {}
-            // Node::ForwardedArgs(ForwardedArgs { .. }) => {}
+            Node::ForwardArg(ForwardArg { expression_l }) => {
+                self.check_syntax_version(&ruby_version::ARGUMENT_FORWARDING, expression_l, input);
+
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
+
+                documents.push(FuzzyNode {
+                    category: "assignment",
+                    fuzzy_ruby_scope: fuzzy_scope.clone(),
+                    class_scope: vec![],
+                    name: "...".to_string(),
+                    node_type: "ForwardArg",
+                    line: lineno,
+                    start_column: begin_pos,
+                    end_column: end_pos,
+                    ..Default::default()
+                });
+            }
+
+            Node::ForwardedArgs(ForwardedArgs { expression_l }) => {
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
+
+                documents.push(FuzzyNode {
+                    category: "usage",
+                    fuzzy_ruby_scope: fuzzy_scope.clone(),
+                    class_scope: vec![],
+                    name: "...".to_string(),
+                    node_type: "ForwardedArgs",
+                    line: lineno,
+                    start_column: begin_pos,
+                    end_column: end_pos,
+                    ..Default::default()
+                });
+            }
+
             Node::Gvar(Gvar { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "usage",
@@ -2224,6 +8670,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -2233,8 +8680,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2245,6 +8691,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 if let Some(child_node) = value {
@@ -2266,6 +8713,7 @@ impl Persistence {
 
             Node::Heredoc(Heredoc { parts, .. }) => {
                 for child_node in parts {
+                    self.serialize_heredoc_constants(child_node, documents, fuzzy_scope, input);
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
             }
@@ -2329,12 +8777,27 @@ impl Persistence {
                 self.serialize(if_false, documents, fuzzy_scope, input);
             }
 
-            Node::Index(lib_ruby_parser::nodes::Index { recv, indexes, .. }) => {
+            Node::Index(lib_ruby_parser::nodes::Index {
+                recv,
+                indexes,
+                expression_l,
+                ..
+            }) => {
                 self.serialize(recv, documents, fuzzy_scope, input);
 
+                let first_index_document = documents.len();
+
                 for child_node in indexes {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
+
+                self.serialize_dsl_symbols(
+                    expression_l,
+                    first_index_document,
+                    documents,
+                    fuzzy_scope,
+                    input,
+                );
             }
 
             Node::IndexAsgn(IndexAsgn {
@@ -2372,7 +8835,14 @@ impl Persistence {
             }
 
             // Node::Int(Int { .. }) => {}
-            Node::Irange(Irange { left, right, .. }) => {
+            Node::Irange(Irange {
+                left,
+                right,
+                operator_l,
+                ..
+            }) => {
+                self.check_range_syntax(left, right, operator_l, input);
+
                 if let Some(child_node) = left {
                     self.serialize(child_node, documents, fuzzy_scope, input);
                 }
@@ -2383,8 +8853,7 @@ impl Persistence {
             }
 
             Node::Ivar(Ivar { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "usage",
@@ -2395,6 +8864,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -2404,8 +8874,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2416,6 +8885,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 if let Some(child_node) = value {
@@ -2424,8 +8894,7 @@ impl Persistence {
             }
 
             Node::Kwarg(Kwarg { name, name_l, .. }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2436,6 +8905,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -2458,8 +8928,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2470,6 +8939,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 self.serialize(default, documents, fuzzy_scope, input);
@@ -2478,8 +8948,7 @@ impl Persistence {
             Node::Kwrestarg(Kwrestarg { name, name_l, .. }) => {
                 if let Some(node_name) = name {
                     if let Some(loc) = name_l {
-                        let (lineno, begin_pos) = input.line_col_for_pos(loc.begin).unwrap();
-                        let (_lineno, end_pos) = input.line_col_for_pos(loc.end).unwrap();
+                        let (lineno, begin_pos, end_pos) = input.line_span(&loc);
 
                         documents.push(FuzzyNode {
                             category: "assignment",
@@ -2490,6 +8959,7 @@ impl Persistence {
                             line: lineno,
                             start_column: begin_pos,
                             end_column: end_pos,
+                            ..Default::default()
                         });
                     }
                 }
@@ -2502,8 +8972,7 @@ impl Persistence {
             // Node::Lambda(Lambda { .. }) => {}
             // Node::Line(Line { .. }) => {}
             Node::Lvar(Lvar { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "usage",
@@ -2514,7 +8983,30 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
+
+                // `_1`, `_2`, etc. inside a numbered parameter block
+                if let Some(method_name) = self.numblock_calls.last() {
+                    let numbered_param = name
+                        .strip_prefix("_")
+                        .and_then(|number| number.parse::<usize>().ok());
+
+                    if let Some(number) = numbered_param {
+                        documents.push(FuzzyNode {
+                            category: "hint",
+                            fuzzy_ruby_scope: fuzzy_scope.clone(),
+                            class_scope: vec![],
+                            name: method_name.to_string(),
+                            node_type: "Numblock",
+                            line: lineno,
+                            start_column: begin_pos,
+                            end_column: end_pos,
+                            parameters: vec![(number - 1).to_string()],
+                            ..Default::default()
+                        });
+                    }
+                }
             }
 
             Node::Lvasgn(Lvasgn {
@@ -2523,8 +9015,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2535,13 +9026,23 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 if let Some(child_node) = value {
                     self.serialize(child_node, documents, fuzzy_scope, input);
+                    self.assign_local_class(name, child_node);
                 }
             }
 
+            // `a, (b, *c), @d, self.e, f[0] = values` destructures into an
+            // `Mlhs` of targets, each an assignment node without a value:
+            // `Lvasgn`, `Ivasgn`, `Gvasgn`, `Cvasgn`, and `Casgn` index their
+            // name as an assignment, and `Splat` and nested `Mlhs` hold more
+            // targets. Setters and `IndexAsgn` don't assign a name, they're
+            // left out of the assignments and indexed as the calls they are,
+            // `self.e` as a usage of `e=` and `f[0]` as its receiver and
+            // index.
             Node::Masgn(Masgn { lhs, rhs, .. }) => {
                 self.serialize(lhs, documents, fuzzy_scope, input);
                 self.serialize(rhs, documents, fuzzy_scope, input);
@@ -2559,15 +9060,36 @@ impl Persistence {
 
             Node::MatchCurrentLine(MatchCurrentLine { re, .. }) => {
                 self.serialize(re, documents, fuzzy_scope, input);
+                self.serialize_regexp_match(re.expression(), documents, fuzzy_scope, input);
             }
 
             // Node::MatchNilPattern(MatchNilPattern { .. }) => {}
-            Node::MatchPattern(MatchPattern { value, pattern, .. }) => {
+            Node::MatchPattern(MatchPattern {
+                value,
+                pattern,
+                operator_l,
+                ..
+            }) => {
+                self.check_syntax_version(
+                    &ruby_version::ONE_LINE_PATTERN_MATCHING,
+                    operator_l,
+                    input,
+                );
                 self.serialize(value, documents, fuzzy_scope, input);
                 self.serialize(pattern, documents, fuzzy_scope, input);
             }
 
-            Node::MatchPatternP(MatchPatternP { value, pattern, .. }) => {
+            Node::MatchPatternP(MatchPatternP {
+                value,
+                pattern,
+                operator_l,
+                ..
+            }) => {
+                self.check_syntax_version(
+                    &ruby_version::ONE_LINE_PATTERN_MATCHING,
+                    operator_l,
+                    input,
+                );
                 self.serialize(value, documents, fuzzy_scope, input);
                 self.serialize(pattern, documents, fuzzy_scope, input);
             }
@@ -2579,8 +9101,7 @@ impl Persistence {
             }
 
             Node::MatchVar(MatchVar { name, name_l, .. }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2591,12 +9112,20 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
-            Node::MatchWithLvasgn(MatchWithLvasgn { re, value, .. }) => {
+            Node::MatchWithLvasgn(MatchWithLvasgn {
+                re,
+                value,
+                operator_l,
+                ..
+            }) => {
                 self.serialize(re, documents, fuzzy_scope, input);
                 self.serialize(value, documents, fuzzy_scope, input);
+                self.serialize_named_captures(re, documents, fuzzy_scope, input);
+                self.serialize_regexp_match(operator_l, documents, fuzzy_scope, input);
             }
 
             Node::Mlhs(Mlhs { items, .. }) => {
@@ -2607,18 +9136,18 @@ impl Persistence {
 
             Node::Module(Module { name, body, .. }) => {
                 if let Node::Const(const_node) = *name.to_owned() {
+                    if let Some(scope_node) = &const_node.scope {
+                        self.serialize(scope_node, documents, fuzzy_scope, input);
+                    }
+
                     let node_class_scope = self.build_class_scope(&const_node);
                     let class_scope_len = node_class_scope.len();
 
-                    for ancestor_name in node_class_scope {
+                    for ancestor_name in node_class_scope.into_iter().rev() {
                         fuzzy_scope.push(ancestor_name);
                     }
 
-                    let (lineno, begin_pos) = input
-                        .line_col_for_pos(const_node.expression_l.begin)
-                        .unwrap();
-                    let (_lineno, end_pos) =
-                        input.line_col_for_pos(const_node.expression_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&const_node.name_l);
                     let class_name = const_node.name.to_string();
 
                     documents.push(FuzzyNode {
@@ -2631,21 +9160,32 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        documentation: self.doc_comment(lineno),
+                        ..Default::default()
                     });
 
                     fuzzy_scope.push(class_name.to_string());
                     self.class_scope.push(class_name);
+                    let outer_singleton_class = std::mem::replace(&mut self.singleton_class, false);
+                    let outer_concern = std::mem::replace(&mut self.concern, extends_concern(body));
+
+                    let first_body_document = documents.len();
 
                     for child_node in body {
                         self.serialize(child_node, documents, fuzzy_scope, input);
                     }
 
+                    self.add_visibility(body, documents, first_body_document, fuzzy_scope);
+                    self.add_module_functions(body, documents, first_body_document, fuzzy_scope);
+
                     for _ in 0..class_scope_len {
                         fuzzy_scope.pop();
                     }
 
                     fuzzy_scope.pop();
                     self.class_scope.pop();
+                    self.singleton_class = outer_singleton_class;
+                    self.concern = outer_concern;
                 }
             }
 
@@ -2656,14 +9196,33 @@ impl Persistence {
             }
 
             // Node::Nil(Nil { .. }) => {}
-            // Node::NthRef(NthRef { .. }) => {}
-            Node::Numblock(Numblock { call, body, .. }) => {
+            Node::NthRef(NthRef { expression_l, .. }) => {
+                self.serialize_match_global("NthRef", expression_l, documents, fuzzy_scope, input);
+            }
+
+            Node::Numblock(Numblock {
+                call,
+                body,
+                begin_l,
+                ..
+            }) => {
+                self.check_syntax_version(&ruby_version::NUMBERED_PARAMETERS, begin_l, input);
                 self.serialize(call, documents, fuzzy_scope, input);
+
+                let method_name = match call.as_ref() {
+                    Node::Send(Send { method_name, .. }) => method_name.to_string(),
+                    Node::CSend(CSend { method_name, .. }) => method_name.to_string(),
+                    _ => "".to_string(),
+                };
+
+                self.numblock_calls.push(method_name);
                 self.serialize(body, documents, fuzzy_scope, input);
+                self.numblock_calls.pop();
             }
 
             Node::OpAsgn(OpAsgn { recv, value, .. }) => {
                 self.serialize(recv, documents, fuzzy_scope, input);
+                self.serialize_op_assign_read(recv, documents, fuzzy_scope, input);
                 self.serialize(value, documents, fuzzy_scope, input);
             }
 
@@ -2673,8 +9232,7 @@ impl Persistence {
                 name_l,
                 ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(name_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(name_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -2685,6 +9243,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
 
                 self.serialize(default, documents, fuzzy_scope, input);
@@ -2697,6 +9256,7 @@ impl Persistence {
 
             Node::OrAsgn(OrAsgn { recv, value, .. }) => {
                 self.serialize(recv, documents, fuzzy_scope, input);
+                self.serialize_op_assign_read(recv, documents, fuzzy_scope, input);
                 self.serialize(value, documents, fuzzy_scope, input);
             }
 
@@ -2776,8 +9336,7 @@ impl Persistence {
             Node::Restarg(Restarg { name, name_l, .. }) => {
                 if let Some(name_str) = name {
                     if let Some(loc) = name_l {
-                        let (lineno, begin_pos) = input.line_col_for_pos(loc.begin).unwrap();
-                        let (_lineno, end_pos) = input.line_col_for_pos(loc.end).unwrap();
+                        let (lineno, begin_pos, end_pos) = input.line_span(&loc);
 
                         documents.push(FuzzyNode {
                             category: "assignment",
@@ -2788,6 +9347,7 @@ impl Persistence {
                             line: lineno,
                             start_column: begin_pos,
                             end_column: end_pos,
+                            ..Default::default()
                         });
                     }
                 }
@@ -2803,9 +9363,16 @@ impl Persistence {
             Node::SClass(SClass { expr, body, .. }) => {
                 self.serialize(expr, documents, fuzzy_scope, input);
 
+                // `class << SomeConstant` opens another object's singleton
+                // class, its methods aren't this scope's class methods
+                let outer_singleton_class = self.singleton_class;
+                self.singleton_class = matches!(expr.as_ref(), Node::Self_(_));
+
                 for node in body {
                     self.serialize(node, documents, fuzzy_scope, input);
                 }
+
+                self.singleton_class = outer_singleton_class;
             }
 
             // Node::Self_(Self_ { .. }) => {}
@@ -2814,6 +9381,7 @@ impl Persistence {
                 method_name,
                 args,
                 selector_l,
+                expression_l,
                 ..
             }) => {
                 let class_scope = if let Some(recv_node) = recv {
@@ -2825,6 +9393,13 @@ impl Persistence {
                             full_class_scope.append(self.build_class_scope(&const_node).as_mut());
                             full_class_scope
                         }
+                        // Methods called on a local holding an instance are
+                        // looked up in its class like methods called on the
+                        // class
+                        Node::Lvar(Lvar { name, .. }) => match self.local_class(name) {
+                            Some(class_path) => class_path.iter().rev().cloned().collect(),
+                            None => vec![],
+                        },
                         _ => vec![],
                     }
                 } else {
@@ -2832,8 +9407,7 @@ impl Persistence {
                 };
 
                 if let Some(loc) = selector_l {
-                    let (lineno, begin_pos) = input.line_col_for_pos(loc.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(loc.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&loc);
 
                     documents.push(FuzzyNode {
                         category: "usage",
@@ -2844,25 +9418,128 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        receiver: Some(self.receiver_hint(recv.as_deref())),
+                        ..Default::default()
                     });
                 }
 
+                let named_method = method_name
+                    .chars()
+                    .next()
+                    .map_or(false, |char| char.is_alphabetic() || char == '_');
+
+                if named_method && self.argument_hints {
+                    for (argument_index, node) in args.iter().enumerate() {
+                        // Positions after a splat can't be matched to parameters
+                        if let Node::Splat(_) | Node::Kwargs(_) | Node::BlockPass(_) = node {
+                            break;
+                        }
+
+                        let (lineno, begin_pos, _end_pos) = input.line_span(node.expression());
+
+                        documents.push(FuzzyNode {
+                            category: "hint",
+                            fuzzy_ruby_scope: fuzzy_scope.clone(),
+                            class_scope: vec![],
+                            name: method_name.to_string(),
+                            node_type: "Argument",
+                            line: lineno,
+                            start_column: begin_pos,
+                            end_column: begin_pos,
+                            parameters: vec![argument_index.to_string()],
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                if named_method && !self.index_interface_only {
+                    // Keywords are usages of the keyword parameters of the
+                    // method called, `initialize` when it's `new`
+                    let parameters_method = match method_name.as_str() {
+                        "new" => "initialize",
+                        method_name => method_name,
+                    };
+
+                    for (keyword, loc) in keyword_arguments(args) {
+                        let (lineno, begin_pos, end_pos) = input.line_span(loc);
+
+                        documents.push(FuzzyNode {
+                            category: "usage",
+                            fuzzy_ruby_scope: fuzzy_scope.clone(),
+                            class_scope: class_scope.clone(),
+                            name: keyword,
+                            node_type: "KeywordArg",
+                            line: lineno,
+                            start_column: begin_pos,
+                            end_column: end_pos,
+                            parameters: vec![parameters_method.to_string()],
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                let first_argument_document = documents.len();
+
                 for node in args {
                     self.serialize(node, documents, fuzzy_scope, input);
                 }
 
+                self.serialize_dsl_symbols(
+                    expression_l,
+                    first_argument_document,
+                    documents,
+                    fuzzy_scope,
+                    input,
+                );
+
+                self.visit_call_plugins(
+                    node,
+                    first_argument_document,
+                    documents,
+                    fuzzy_scope,
+                    input,
+                );
+
+                if REGEXP_MATCH_METHODS.contains(&method_name.as_str()) {
+                    if let Some(loc) = selector_l {
+                        self.serialize_regexp_match(loc, documents, fuzzy_scope, input);
+                    }
+                }
+
                 match method_name.as_str() {
                     // Ruby
+                    // Files required, for telling whether a file's constants
+                    // are loaded
+                    "require" | "require_relative" if recv.is_none() => {
+                        if let Some(Node::Str(Str {
+                            value,
+                            expression_l,
+                            ..
+                        })) = args.first()
+                        {
+                            let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
+
+                            documents.push(FuzzyNode {
+                                category: "require",
+                                fuzzy_ruby_scope: fuzzy_scope.clone(),
+                                name: value.to_string_lossy(),
+                                node_type: "Require",
+                                line: lineno,
+                                start_column: begin_pos,
+                                end_column: end_pos,
+                                parameters: vec![method_name.to_string()],
+                                ..Default::default()
+                            });
+                        }
+                    }
                     "attr_accessor" => {
                         for node in args {
                             match node {
                                 Node::Sym(Sym {
                                     name, expression_l, ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -2873,6 +9550,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
 
                                     documents.push(FuzzyNode {
@@ -2884,6 +9562,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 _ => {}
@@ -2896,10 +9575,8 @@ impl Persistence {
                                 Node::Sym(Sym {
                                     name, expression_l, ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -2910,6 +9587,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 _ => {}
@@ -2922,10 +9600,8 @@ impl Persistence {
                                 Node::Sym(Sym {
                                     name, expression_l, ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -2936,6 +9612,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 _ => {}
@@ -2948,10 +9625,8 @@ impl Persistence {
                                 Node::Sym(Sym {
                                     name, expression_l, ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -2962,6 +9637,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 Node::Str(Str {
@@ -2969,10 +9645,8 @@ impl Persistence {
                                     expression_l,
                                     ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -2983,24 +9657,98 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 _ => {}
                             }
                         }
                     }
+                    "include" | "prepend" => {
+                        if recv.is_some() {
+                            return;
+                        }
+
+                        let node_type = if method_name == "include" {
+                            "Include"
+                        } else {
+                            "Prepend"
+                        };
+
+                        for node in args {
+                            if let Node::Const(const_node) = node {
+                                let (lineno, begin_pos, end_pos) =
+                                    input.line_span(&const_node.name_l);
+
+                                documents.push(FuzzyNode {
+                                    category: "inheritance",
+                                    fuzzy_ruby_scope: fuzzy_scope.clone(),
+                                    class_scope: self.build_class_scope(const_node),
+                                    name: const_node.name.to_string(),
+                                    node_type,
+                                    line: lineno,
+                                    start_column: begin_pos,
+                                    end_column: end_pos,
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                    }
+
+                    // Forwardable, and Rails' `delegate ... to:`
+                    "def_delegator"
+                    | "def_delegators"
+                    | "def_instance_delegator"
+                    | "def_instance_delegators"
+                    | "delegate" => {
+                        if recv.is_some() {
+                            return;
+                        }
+
+                        for (name, loc, target) in delegated_methods(method_name, args) {
+                            let (lineno, begin_pos, end_pos) = input.line_span(&loc);
+
+                            documents.push(FuzzyNode {
+                                category: "assignment",
+                                fuzzy_ruby_scope: fuzzy_scope.clone(),
+                                class_scope: class_scope.clone(),
+                                name,
+                                node_type: "Def",
+                                line: lineno,
+                                start_column: begin_pos,
+                                end_column: end_pos,
+                                documentation: Some(format!("Delegated to `{}`", target)),
+                                ..Default::default()
+                            });
+                        }
+                    }
 
                     // Rails
+                    "scope" if recv.is_none() && matches!(args.get(1), Some(Node::Block(_))) => {
+                        if let Some((name, loc)) = args.first().and_then(method_name_argument) {
+                            let (lineno, begin_pos, end_pos) = input.line_span(&loc);
+
+                            documents.push(FuzzyNode {
+                                category: "assignment",
+                                fuzzy_ruby_scope: fuzzy_scope.clone(),
+                                class_scope: class_scope.clone(),
+                                name,
+                                node_type: "Defs",
+                                line: lineno,
+                                start_column: begin_pos,
+                                end_column: end_pos,
+                                ..Default::default()
+                            });
+                        }
+                    }
                     "belongs_to" | "has_one" | "has_many" | "has_and_belongs_to_many" => {
                         if let Some(node) = args.first() {
                             match node {
                                 Node::Sym(Sym {
                                     name, expression_l, ..
                                 }) => {
-                                    let (lineno, begin_pos) =
-                                        input.line_col_for_pos(expression_l.begin).unwrap();
-                                    let (_lineno, end_pos) =
-                                        input.line_col_for_pos(expression_l.end).unwrap();
+                                    let (lineno, begin_pos, end_pos) =
+                                        input.line_span(&expression_l);
 
                                     documents.push(FuzzyNode {
                                         category: "assignment",
@@ -3011,6 +9759,7 @@ impl Persistence {
                                         line: lineno,
                                         start_column: begin_pos,
                                         end_column: end_pos,
+                                        ..Default::default()
                                     });
                                 }
                                 _ => {}
@@ -3048,8 +9797,7 @@ impl Persistence {
             }
 
             Node::Shadowarg(Shadowarg { name, expression_l }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "assignment",
@@ -3060,6 +9808,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -3074,8 +9823,7 @@ impl Persistence {
                 args, keyword_l, ..
             }) => {
                 if let Some(last_scope_name) = fuzzy_scope.last() {
-                    let (lineno, begin_pos) = input.line_col_for_pos(keyword_l.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(keyword_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&keyword_l);
 
                     documents.push(FuzzyNode {
                         category: "usage",
@@ -3086,6 +9834,7 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        ..Default::default()
                     });
                 }
 
@@ -3097,8 +9846,7 @@ impl Persistence {
             Node::Sym(Sym {
                 name, expression_l, ..
             }) => {
-                let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                 documents.push(FuzzyNode {
                     category: "usage",
@@ -3109,6 +9857,7 @@ impl Persistence {
                     line: lineno,
                     start_column: begin_pos,
                     end_column: end_pos,
+                    ..Default::default()
                 });
             }
 
@@ -3161,6 +9910,7 @@ impl Persistence {
 
             Node::XHeredoc(XHeredoc { parts, .. }) => {
                 for node in parts {
+                    self.serialize_heredoc_constants(node, documents, fuzzy_scope, input);
                     self.serialize(node, documents, fuzzy_scope, input);
                 }
             }
@@ -3171,7 +9921,37 @@ impl Persistence {
                 }
             }
 
-            Node::Yield(Yield { args, .. }) => {
+            Node::Yield(Yield {
+                args, keyword_l, ..
+            }) => {
+                let yielded_names: Vec<String> =
+                    args.iter().map(|node| self.yielded_name(node)).collect();
+
+                // A usage of the block it calls, so goto definition goes to
+                // the `&block` parameter or the method's definition
+                if let Some(yield_target) = self.yield_targets.last() {
+                    let (lineno, begin_pos, end_pos) = input.line_span(&keyword_l);
+
+                    documents.push(FuzzyNode {
+                        category: "usage",
+                        fuzzy_ruby_scope: fuzzy_scope.clone(),
+                        class_scope: vec![],
+                        name: yield_target.to_string(),
+                        node_type: "Yield",
+                        line: lineno,
+                        start_column: begin_pos,
+                        end_column: end_pos,
+                        ..Default::default()
+                    });
+                }
+
+                // Only the first `yield` in a method describes its block
+                if let Some(method_yield) = self.method_yields.last_mut() {
+                    if method_yield.is_none() {
+                        *method_yield = Some(yielded_names);
+                    }
+                }
+
                 for node in args {
                     self.serialize(node, documents, fuzzy_scope, input);
                 }
@@ -3179,8 +9959,7 @@ impl Persistence {
 
             Node::ZSuper(ZSuper { expression_l, .. }) => {
                 if let Some(last_scope_name) = fuzzy_scope.last() {
-                    let (lineno, begin_pos) = input.line_col_for_pos(expression_l.begin).unwrap();
-                    let (_lineno, end_pos) = input.line_col_for_pos(expression_l.end).unwrap();
+                    let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
 
                     documents.push(FuzzyNode {
                         category: "usage",
@@ -3191,6 +9970,7 @@ impl Persistence {
                         line: lineno,
                         start_column: begin_pos,
                         end_column: end_pos,
+                        ..Default::default()
                     });
                 }
             }
@@ -3199,9 +9979,619 @@ impl Persistence {
         };
     }
 
+    // Marks the body's methods with the visibility `private`, `protected`,
+    // and `public` give them, either for every method after a bare one, by
+    // name, or wrapping a `def`. Later markers win, like they do at runtime.
+    fn add_visibility(
+        &self,
+        body: &Option<Box<Node>>,
+        documents: &mut Vec<FuzzyNode>,
+        first_body_document: usize,
+        scope: &Vec<String>,
+    ) {
+        let statements: Vec<&Node> = match body.as_deref() {
+            Some(Node::Begin(Begin { statements, .. })) => statements.iter().collect(),
+            Some(node) => vec![node],
+            None => return,
+        };
+
+        let mut default_visibility = "public";
+        let mut visibilities: HashMap<String, &'static str> = HashMap::new();
+
+        for statement in statements {
+            match statement {
+                Node::Send(Send {
+                    recv: None,
+                    method_name,
+                    args,
+                    ..
+                }) => {
+                    let visibility = match method_name.as_str() {
+                        "private" => "private",
+                        "protected" => "protected",
+                        "public" => "public",
+                        _ => continue,
+                    };
+
+                    if args.is_empty() {
+                        default_visibility = visibility;
+                    }
+
+                    for arg in args {
+                        match arg {
+                            Node::Sym(Sym { name, .. }) => {
+                                visibilities.insert(name.to_string_lossy(), visibility);
+                            }
+                            Node::Str(Str { value, .. }) => {
+                                visibilities.insert(value.to_string_lossy(), visibility);
+                            }
+                            Node::Def(Def { name, .. }) => {
+                                visibilities.insert(name.to_string(), visibility);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Node::Def(Def { name, .. }) => {
+                    visibilities.insert(name.to_string(), default_visibility);
+                }
+                _ => {}
+            }
+        }
+
+        // Methods the body doesn't define keep what they're given, like the
+        // ones in a concern's `included` block
+        for document in &mut documents[first_body_document..] {
+            if document.node_type == "Def" && &document.fuzzy_ruby_scope == scope {
+                if let Some(visibility) = visibilities.get(&document.name) {
+                    document.visibility = Some(visibility);
+                }
+            }
+        }
+    }
+
+    // `module_function` and `extend self` make a module's instance methods
+    // callable on the module itself, so they're also indexed as `Defs`
+    fn add_module_functions(
+        &self,
+        body: &Option<Box<Node>>,
+        documents: &mut Vec<FuzzyNode>,
+        first_body_document: usize,
+        module_scope: &Vec<String>,
+    ) {
+        let statements: Vec<&Node> = match body.as_deref() {
+            Some(Node::Begin(Begin { statements, .. })) => statements.iter().collect(),
+            Some(node) => vec![node],
+            None => return,
+        };
+
+        let mut extend_self = false;
+        // A bare `module_function` applies to every method after it
+        let mut following_methods = false;
+        let mut module_function_names = HashSet::new();
+
+        for statement in statements {
+            match statement {
+                Node::Send(Send {
+                    recv: None,
+                    method_name,
+                    args,
+                    ..
+                }) if method_name == "module_function" => {
+                    if args.is_empty() {
+                        following_methods = true;
+                    }
+
+                    for arg in args {
+                        match arg {
+                            Node::Sym(Sym { name, .. }) => {
+                                module_function_names.insert(name.to_string_lossy());
+                            }
+                            Node::Def(Def { name, .. }) => {
+                                module_function_names.insert(name.to_string());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Node::Send(Send {
+                    recv: None,
+                    method_name,
+                    args,
+                    ..
+                }) if method_name == "extend" && matches!(args.as_slice(), [Node::Self_(_)]) => {
+                    extend_self = true;
+                }
+                Node::Def(Def { name, .. }) if following_methods => {
+                    module_function_names.insert(name.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        if !extend_self && module_function_names.is_empty() {
+            return;
+        }
+
+        let module_functions: Vec<FuzzyNode> = documents[first_body_document..]
+            .iter()
+            .filter(|document| {
+                document.node_type == "Def"
+                    && &document.fuzzy_ruby_scope == module_scope
+                    && (extend_self || module_function_names.contains(&document.name))
+            })
+            .map(|document| FuzzyNode {
+                node_type: "Defs",
+                visibility: None,
+                ..document.clone()
+            })
+            .collect();
+
+        documents.extend(module_functions);
+    }
+
+    // The symbols the project's DSL rules find in a call, in place of the
+    // usages its arguments are indexed as where they overlap
+    fn serialize_dsl_symbols(
+        &self,
+        call_loc: &Loc,
+        first_argument_document: usize,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &[String],
+        input: &SourceInput,
+    ) {
+        if self.dsl_rules.is_empty() {
+            return;
+        }
+
+        let dsl_symbols = dsl_rules::symbols(&self.dsl_rules, input.source(call_loc));
+
+        if dsl_symbols.is_empty() {
+            return;
+        }
+
+        let symbol_spans: Vec<(usize, usize, usize)> = dsl_symbols
+            .iter()
+            .map(|dsl_symbol| {
+                input.line_span(&Loc {
+                    begin: call_loc.begin + dsl_symbol.start,
+                    end: call_loc.begin + dsl_symbol.end,
+                })
+            })
+            .collect();
+
+        let argument_documents = documents.split_off(first_argument_document);
+
+        documents.extend(argument_documents.into_iter().filter(|document| {
+            document.node_type != "Send"
+                || !symbol_spans.iter().any(|(line, start_column, end_column)| {
+                    document.line == *line
+                        && document.start_column < *end_column
+                        && *start_column < document.end_column
+                })
+        }));
+
+        for (dsl_symbol, (lineno, begin_pos, end_pos)) in dsl_symbols.into_iter().zip(symbol_spans)
+        {
+            documents.push(FuzzyNode {
+                category: if dsl_symbol.definition {
+                    "assignment"
+                } else {
+                    "usage"
+                },
+                fuzzy_ruby_scope: fuzzy_scope.to_vec(),
+                class_scope: vec![],
+                name: dsl_symbol.name,
+                node_type: dsl_symbol.node_type,
+                line: lineno,
+                start_column: begin_pos,
+                end_column: end_pos,
+                ..Default::default()
+            });
+        }
+    }
+
+    // Runs the plugins over a block, true when one takes it over
+    fn visit_block_plugins(
+        &mut self,
+        call: &Node,
+        body: Option<&Node>,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &[String],
+        input: &SourceInput,
+    ) -> bool {
+        let mut visit = Visit::new(fuzzy_scope, input, self.index_interface_only);
+        let taken_over = self
+            .plugins
+            .iter_mut()
+            .any(|plugin| plugin.visit_block(call, body, &mut visit));
+
+        self.add_plugin_symbols(visit, documents.len(), documents, input);
+
+        taken_over
+    }
+
+    // Runs the plugins over a call, its arguments indexed from
+    // `first_argument_document` on
+    fn visit_call_plugins(
+        &mut self,
+        node: &Node,
+        first_argument_document: usize,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &[String],
+        input: &SourceInput,
+    ) {
+        let send = match node {
+            Node::Send(send) => send,
+            _ => return,
+        };
+
+        let mut visit = Visit::new(fuzzy_scope, input, self.index_interface_only);
+
+        for plugin in &mut self.plugins {
+            plugin.visit_call(send, &mut visit);
+        }
+
+        self.add_plugin_symbols(visit, first_argument_document, documents, input);
+    }
+
+    // Indexes what plugins found, in place of the argument usages they
+    // replace, and reports their warnings
+    fn add_plugin_symbols(
+        &mut self,
+        visit: Visit,
+        first_argument_document: usize,
+        documents: &mut Vec<FuzzyNode>,
+        input: &SourceInput,
+    ) {
+        if !visit.replaced_usages.is_empty() {
+            let replaced_spans: Vec<(usize, usize, usize)> = visit
+                .replaced_usages
+                .iter()
+                .map(|loc| input.line_span(loc))
+                .collect();
+
+            let argument_documents = documents.split_off(first_argument_document);
+
+            documents.extend(argument_documents.into_iter().filter(|document| {
+                let span = (document.line, document.start_column, document.end_column);
+
+                document.node_type != "Send" || !replaced_spans.contains(&span)
+            }));
+        }
+
+        for symbol in visit.symbols {
+            let (lineno, begin_pos, end_pos) = input.line_span(&symbol.loc);
+
+            documents.push(FuzzyNode {
+                category: symbol.category,
+                fuzzy_ruby_scope: symbol.fuzzy_ruby_scope,
+                class_scope: symbol.class_scope,
+                name: symbol.name,
+                node_type: symbol.node_type,
+                line: lineno,
+                start_column: begin_pos,
+                end_column: end_pos,
+                parameters: symbol.parameters,
+                documentation: symbol.documentation,
+                ..Default::default()
+            });
+        }
+
+        for (loc, message) in visit.warnings {
+            self.plugin_diagnostics
+                .push(tower_lsp::lsp_types::Diagnostic {
+                    range: input.range(&loc),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    message,
+                    ..Default::default()
+                });
+        }
+    }
+
+    // Each match is an assignment of the match globals, keyed by where it
+    // happened so `$1` links to the match before it rather than every match
+    // in the method
+    fn serialize_regexp_match(
+        &mut self,
+        loc: &Loc,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
+    ) {
+        let range = input.range(loc);
+        let (lineno, begin_pos) = (range.start.line as usize, range.start.character as usize);
+        // Locations span a single line, multiline regexps are marked at
+        // their start
+        let end_pos = if range.end.line == range.start.line {
+            range.end.character as usize
+        } else {
+            begin_pos + 1
+        };
+
+        let name = format!("$~:{}:{}", lineno, begin_pos);
+
+        documents.push(FuzzyNode {
+            category: "assignment",
+            fuzzy_ruby_scope: fuzzy_scope.clone(),
+            class_scope: vec![],
+            name: name.clone(),
+            node_type: "RegexpMatch",
+            line: lineno,
+            start_column: begin_pos,
+            end_column: end_pos,
+            ..Default::default()
+        });
+
+        self.last_regexp_match = Some(name);
+    }
+
+    // `$1`, `$~`, `$&`, and the like, read from the last match
+    fn serialize_match_global(
+        &mut self,
+        node_type: &'static str,
+        expression_l: &Loc,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
+    ) {
+        let (lineno, begin_pos, end_pos) = input.line_span(&expression_l);
+
+        documents.push(FuzzyNode {
+            category: "usage",
+            fuzzy_ruby_scope: fuzzy_scope.clone(),
+            class_scope: vec![],
+            name: self
+                .last_regexp_match
+                .clone()
+                .unwrap_or_else(|| "$~".to_string()),
+            node_type,
+            line: lineno,
+            start_column: begin_pos,
+            end_column: end_pos,
+            ..Default::default()
+        });
+    }
+
+    // `/(?<year>\d+)-(?<month>\d+)/ =~ date` assigns the locals `year` and
+    // `month`, as long as the regexp has no interpolation
+    fn serialize_named_captures(
+        &mut self,
+        re: &Node,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
+    ) {
+        static NAMED_CAPTURE: OnceLock<Regex> = OnceLock::new();
+        let named_capture =
+            NAMED_CAPTURE.get_or_init(|| Regex::new(r"\(\?<([A-Za-z_][A-Za-z0-9_]*)>").unwrap());
+
+        let parts = match re {
+            Node::Regexp(Regexp { parts, .. }) => parts,
+            _ => return,
+        };
+
+        for part in parts {
+            let expression_l = match part {
+                Node::Str(Str { expression_l, .. }) => expression_l,
+                _ => return,
+            };
+
+            for captures in named_capture.captures_iter(input.source(expression_l)) {
+                let name = captures.get(1).unwrap();
+                let begin = expression_l.begin + name.start();
+
+                let (lineno, begin_pos, end_pos) = input.line_span(&Loc {
+                    begin,
+                    end: begin + name.len(),
+                });
+
+                documents.push(FuzzyNode {
+                    category: "assignment",
+                    fuzzy_ruby_scope: fuzzy_scope.clone(),
+                    class_scope: vec![],
+                    name: name.as_str().to_string(),
+                    node_type: "MatchVar",
+                    line: lineno,
+                    start_column: begin_pos,
+                    end_column: end_pos,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    // `x += 1`, `@user ||= find_user`, and `$debug &&= false` read the
+    // variable as well as assign it, so a usage is added at the same place
+    // as the receiver's assignment.
+    //
+    // The receiver itself is an `Lvasgn`, `Ivasgn`, `Gvasgn`, `Cvasgn`, or
+    // `Casgn` without a value, which its own branch of `serialize` indexes as
+    // an assignment, so `@user ||= find_user` is a definition of `@user`.
+    fn serialize_op_assign_read(
+        &mut self,
+        recv: &Node,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
+    ) {
+        let (node_type, name, name_l, class_scope) = match recv {
+            Node::Lvasgn(Lvasgn { name, name_l, .. }) => ("Lvar", name, name_l, vec![]),
+            Node::Ivasgn(Ivasgn { name, name_l, .. }) => ("Ivar", name, name_l, vec![]),
+            Node::Gvasgn(Gvasgn { name, name_l, .. }) => ("Gvar", name, name_l, vec![]),
+            Node::Cvasgn(Cvasgn { name, name_l, .. }) => ("Cvar", name, name_l, vec![]),
+            Node::Casgn(Casgn {
+                scope,
+                name,
+                name_l,
+                ..
+            }) => ("Const", name, name_l, self.build_scope_class_scope(scope)),
+            _ => return,
+        };
+
+        let (lineno, begin_pos, end_pos) = input.line_span(&name_l);
+
+        documents.push(FuzzyNode {
+            category: "usage",
+            fuzzy_ruby_scope: fuzzy_scope.clone(),
+            class_scope,
+            name: name.to_string(),
+            node_type,
+            line: lineno,
+            start_column: begin_pos,
+            end_column: end_pos,
+            ..Default::default()
+        });
+    }
+
+    // Adds a `Const` usage for each constant named in a heredoc's text, so
+    // `FROM #{table} JOIN Admin::User` is found by references to `User`.
+    // Interpolations are serialized like any other code.
+    fn serialize_heredoc_constants(
+        &mut self,
+        node: &Node,
+        documents: &mut Vec<FuzzyNode>,
+        fuzzy_scope: &mut Vec<String>,
+        input: &SourceInput,
+    ) {
+        if !self.index_heredoc_constants {
+            return;
+        }
+
+        let expression_l = match node {
+            Node::Str(Str { expression_l, .. }) => expression_l,
+            _ => return,
+        };
+
+        // SQL keywords are all caps, so only CamelCase names are indexed
+        static CONSTANT_PATH: OnceLock<Regex> = OnceLock::new();
+        let constant_path = CONSTANT_PATH.get_or_init(|| {
+            Regex::new(r"\b[A-Z][A-Za-z0-9_]*[a-z][A-Za-z0-9_]*(?:::[A-Z][A-Za-z0-9_]*)*").unwrap()
+        });
+
+        for constant_match in constant_path.find_iter(input.source(expression_l)) {
+            let mut begin = expression_l.begin + constant_match.start();
+            let mut class_scope = vec![];
+
+            for name in constant_match.as_str().split("::") {
+                let (lineno, begin_pos, end_pos) = input.line_span(&Loc {
+                    begin,
+                    end: begin + name.len(),
+                });
+
+                documents.push(FuzzyNode {
+                    category: "usage",
+                    fuzzy_ruby_scope: fuzzy_scope.clone(),
+                    // Innermost first, matching `build_class_scope`
+                    class_scope: class_scope.iter().rev().cloned().collect(),
+                    name: name.to_string(),
+                    node_type: "Const",
+                    line: lineno,
+                    start_column: begin_pos,
+                    end_column: end_pos,
+                    ..Default::default()
+                });
+
+                class_scope.push(name.to_string());
+                begin += name.len() + "::".len();
+            }
+        }
+    }
+
+    fn describe_parameters(&self, args: &Option<Box<Node>>) -> Vec<String> {
+        let mut parameters = vec![];
+
+        if let Some(args_node) = args {
+            if let Node::Args(Args { args, .. }) = args_node.as_ref() {
+                for node in args {
+                    let parameter = match node {
+                        Node::Arg(Arg { name, .. }) => format!("arg:{}", name),
+                        Node::Optarg(Optarg { name, .. }) => format!("optarg:{}", name),
+                        Node::Restarg(Restarg { name, .. }) => {
+                            format!("restarg:{}", name.as_deref().unwrap_or(""))
+                        }
+                        Node::Kwarg(Kwarg { name, .. }) => format!("kwarg:{}", name),
+                        Node::Kwoptarg(Kwoptarg { name, .. }) => format!("kwoptarg:{}", name),
+                        Node::Kwrestarg(Kwrestarg { name, .. }) => {
+                            format!("kwrestarg:{}", name.as_deref().unwrap_or(""))
+                        }
+                        Node::Blockarg(Blockarg { name, .. }) => {
+                            format!("blockarg:{}", name.as_deref().unwrap_or(""))
+                        }
+                        Node::ForwardArg(_) => "forwardarg:...".to_string(),
+                        _ => continue,
+                    };
+
+                    parameters.push(parameter);
+                }
+            }
+        }
+
+        parameters
+    }
+
+    fn yielded_name(&self, node: &Node) -> String {
+        match node {
+            Node::Lvar(Lvar { name, .. }) => name.to_string(),
+            Node::Ivar(Ivar { name, .. }) => name.trim_start_matches("@").to_string(),
+            Node::Send(Send {
+                recv: None,
+                method_name,
+                args,
+                ..
+            }) if args.len() == 0 => method_name.to_string(),
+            _ => "_".to_string(),
+        }
+    }
+
+    // Flow-insensitive: a local assigned instances of one class anywhere in
+    // the method holds one from then on, and one also assigned anything
+    // else, or another class, doesn't. Assigning `nil` leaves it as it is.
+    fn assign_local_class(&mut self, name: &str, value: &Node) {
+        let local_classes = match &mut self.local_classes {
+            Some(local_classes) => local_classes,
+            None => return,
+        };
+
+        if let Node::Nil(_) = value {
+            return;
+        }
+
+        let class_path = receivers::constructed_class(value);
+
+        local_classes
+            .entry(name.to_string())
+            .and_modify(|known_class| {
+                if *known_class != class_path {
+                    *known_class = None;
+                }
+            })
+            .or_insert(class_path);
+    }
+
+    fn local_class(&self, name: &str) -> Option<&Vec<String>> {
+        self.local_classes.as_ref()?.get(name)?.as_ref()
+    }
+
+    fn receiver_hint(&self, recv: Option<&Node>) -> String {
+        match recv {
+            Some(Node::Lvar(Lvar { name, .. })) => match self.local_class(name) {
+                Some(class_path) => receivers::instance_hint(class_path),
+                None => receivers::receiver_hint(recv),
+            },
+            _ => receivers::receiver_hint(recv),
+        }
+    }
+
     fn build_class_scope(&self, const_node: &Const) -> Vec<String> {
+        self.build_scope_class_scope(&const_node.scope)
+    }
+
+    // The names a constant is looked up in, from the scope it's written
+    // with, innermost first
+    fn build_scope_class_scope(&self, scope: &Option<Box<Node>>) -> Vec<String> {
         let mut node_class_scope = vec![];
-        let mut current_node = &const_node.scope;
+        let mut current_node = scope;
 
         loop {
             match current_node {
@@ -3237,3 +10627,78 @@ impl Persistence {
         node_class_scope
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `line:start-end category type name class::scope` for each document
+    fn documents(source: &str) -> Vec<String> {
+        let mut persistence = Persistence::new().unwrap();
+
+        persistence
+            .parse_file(&source.to_string(), false)
+            .documents
+            .iter()
+            .map(|document| {
+                let class_scope = document.class_scope.join("::");
+                let description = format!(
+                    "{}:{}-{} {} {} {} {}",
+                    document.line,
+                    document.start_column,
+                    document.end_column,
+                    document.category,
+                    document.node_type,
+                    document.name,
+                    class_scope
+                );
+
+                description.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn indexes_op_assign_receivers_as_assignments_and_reads() {
+        assert_eq!(
+            documents("@foo ||= 1"),
+            vec!["0:0-4 assignment Ivasgn @foo", "0:0-4 usage Ivar @foo"]
+        );
+        assert_eq!(
+            documents("FOO ||= 1"),
+            vec!["0:0-3 assignment Casgn FOO", "0:0-3 usage Const FOO"]
+        );
+        assert_eq!(
+            documents("Config::LIMIT ||= 10"),
+            vec![
+                "0:8-13 assignment Casgn LIMIT Config",
+                "0:0-6 usage Const Config",
+                "0:8-13 usage Const LIMIT Config",
+            ]
+        );
+        assert_eq!(
+            documents("x = 0\nx += 1"),
+            vec![
+                "0:0-1 assignment Lvasgn x",
+                "1:0-1 assignment Lvasgn x",
+                "1:0-1 usage Lvar x",
+            ]
+        );
+    }
+
+    #[test]
+    fn indexes_destructured_names_as_assignments() {
+        assert_eq!(
+            documents("a, (b, *c), @d, self.e, f[0] = values"),
+            vec![
+                "0:0-1 assignment Lvasgn a",
+                "0:4-5 assignment Lvasgn b",
+                "0:8-9 assignment Lvasgn c",
+                "0:12-14 assignment Ivasgn @d",
+                "0:21-22 usage Send e=",
+                "0:24-25 usage Send f",
+                "0:31-37 usage Send values",
+            ]
+        );
+    }
+}
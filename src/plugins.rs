@@ -0,0 +1,171 @@
+use crate::encoding::SourceInput;
+use lib_ruby_parser::{nodes, Loc, Node};
+
+#[cfg(feature = "factory_bot")]
+mod factory_bot;
+#[cfg(feature = "rails")]
+mod rails;
+#[cfg(feature = "rspec")]
+mod rspec;
+#[cfg(feature = "wasm_plugins")]
+mod wasm;
+
+#[cfg(feature = "wasm_plugins")]
+pub use wasm::WasmPlugin;
+
+// Visitors for frameworks' DSLs, run over each parsed file alongside the
+// serializer. The serializer indexes the Ruby, and hands each plugin the
+// blocks and calls it comes across to index what they mean to a framework:
+//
+//   rails        routes, schema columns, callbacks, translations, partials
+//   rspec        test blocks to run from code lenses, Minitest's included
+//   factory_bot  factories and traits
+//
+// Each is compiled in behind the feature named after it, all of them by
+// default. What they index is looked up like any other symbol, by the node
+// types the resolver knows them to define and use. With the `wasm_plugins`
+// feature, projects can load their own, see `wasm`.
+
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    // A copy without the state of the file being serialized, for parsing
+    // files elsewhere
+    fn fresh(&self) -> Box<dyn Plugin>;
+
+    // Before each file is serialized
+    fn start_file(&mut self) {}
+
+    // A block, before anything in it is indexed. Returning true takes it
+    // over, and neither its call nor its body is indexed as Ruby.
+    fn visit_block(&mut self, _call: &Node, _body: Option<&Node>, _visit: &mut Visit) -> bool {
+        false
+    }
+
+    // Around a block's body, for DSLs nesting what's in a block in the call
+    // it's passed to
+    fn enter_block(&mut self, _call: &Node) {}
+
+    fn exit_block(&mut self) {}
+
+    // A method call, after its arguments are indexed
+    fn visit_call(&mut self, _send: &nodes::Send, _visit: &mut Visit) {}
+
+    // Node types only this plugin indexes, which aren't the server's own,
+    // looked up by name across the workspace
+    fn named_types(&self) -> &[&'static str] {
+        &[]
+    }
+}
+
+impl Clone for Box<dyn Plugin> {
+    fn clone(&self) -> Self {
+        self.fresh()
+    }
+}
+
+// What a plugin found visiting a node
+pub struct Visit<'a> {
+    pub fuzzy_scope: &'a [String],
+    pub input: &'a SourceInput<'a>,
+    // Include dirs are only indexed for their definitions
+    pub interface_only: bool,
+    pub symbols: Vec<Symbol>,
+    // Where the serializer indexed an argument as a call, like a symbol
+    // naming a factory, which the plugin's symbols are indexed instead of
+    pub replaced_usages: Vec<Loc>,
+    pub warnings: Vec<(Loc, String)>,
+}
+
+impl<'a> Visit<'a> {
+    pub fn new(
+        fuzzy_scope: &'a [String],
+        input: &'a SourceInput<'a>,
+        interface_only: bool,
+    ) -> Visit<'a> {
+        Visit {
+            fuzzy_scope,
+            input,
+            interface_only,
+            symbols: vec![],
+            replaced_usages: vec![],
+            warnings: vec![],
+        }
+    }
+
+    pub fn replace_usage(&mut self, loc: &Loc) {
+        self.replaced_usages.push(Loc {
+            begin: loc.begin,
+            end: loc.end,
+        });
+    }
+
+    pub fn warn(&mut self, loc: &Loc, message: String) {
+        self.warnings.push((
+            Loc {
+                begin: loc.begin,
+                end: loc.end,
+            },
+            message,
+        ));
+    }
+}
+
+#[derive(Debug)]
+pub struct Symbol {
+    // "assignment", "usage", or "test"
+    pub category: &'static str,
+    pub node_type: &'static str,
+    pub name: String,
+    pub fuzzy_ruby_scope: Vec<String>,
+    pub class_scope: Vec<String>,
+    pub loc: Loc,
+    pub parameters: Vec<String>,
+    pub documentation: Option<String>,
+}
+
+impl Symbol {
+    pub fn new(category: &'static str, node_type: &'static str, name: String, loc: &Loc) -> Symbol {
+        Symbol {
+            category,
+            node_type,
+            name,
+            fuzzy_ruby_scope: vec![],
+            class_scope: vec![],
+            loc: Loc {
+                begin: loc.begin,
+                end: loc.end,
+            },
+            parameters: vec![],
+            documentation: None,
+        }
+    }
+}
+
+// The plugins compiled in
+pub fn enabled() -> Vec<Box<dyn Plugin>> {
+    #[allow(unused_mut)]
+    let mut plugins: Vec<Box<dyn Plugin>> = vec![];
+
+    #[cfg(feature = "rails")]
+    plugins.push(Box::new(rails::RailsPlugin::default()));
+    #[cfg(feature = "rspec")]
+    plugins.push(Box::new(rspec::RSpecPlugin));
+    #[cfg(feature = "factory_bot")]
+    plugins.push(Box::new(factory_bot::FactoryBotPlugin::default()));
+
+    plugins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(feature = "rails", feature = "rspec", feature = "factory_bot"))]
+    fn enables_the_default_plugins() {
+        let names: Vec<&str> = enabled().iter().map(|plugin| plugin.name()).collect();
+
+        assert_eq!(names, vec!["rails", "rspec", "factory_bot"]);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{Plugin, Symbol, Visit};
+use crate::factories;
+use lib_ruby_parser::{nodes, Node};
+
+// FactoryBot's factories and traits, declared in `FactoryBot.define` and
+// built by name in specs
+#[derive(Default)]
+pub struct FactoryBotPlugin {
+    // For each block being serialized, the factories it's nested in, when
+    // it's in a define block
+    factory_scopes: Vec<Option<Vec<String>>>,
+}
+
+impl Plugin for FactoryBotPlugin {
+    fn name(&self) -> &str {
+        "factory_bot"
+    }
+
+    fn fresh(&self) -> Box<dyn Plugin> {
+        Box::new(FactoryBotPlugin::default())
+    }
+
+    // Factories in a block are nested in the `factory` it's passed to
+    fn enter_block(&mut self, call: &Node) {
+        let factory_scope = match self.factory_scopes.last() {
+            Some(Some(factory_scope)) => Some(factories::nested_scope(factory_scope, call)),
+            _ if factories::is_define_block(call) => Some(vec![]),
+            _ => None,
+        };
+
+        self.factory_scopes.push(factory_scope);
+    }
+
+    fn exit_block(&mut self) {
+        self.factory_scopes.pop();
+    }
+
+    // Factories and traits are named by symbols, indexed instead of the
+    // calls the symbols would otherwise be
+    fn visit_call(&mut self, send: &nodes::Send, visit: &mut Visit) {
+        let recv = send.recv.as_deref();
+        let mut factory_symbols = factories::usages(recv, &send.method_name, &send.args);
+
+        if let (Some(Some(factory_scope)), None) = (self.factory_scopes.last(), recv) {
+            factory_symbols.extend(factories::declarations(
+                factory_scope,
+                &send.method_name,
+                &send.args,
+            ));
+        }
+
+        for factory_symbol in factory_symbols {
+            let category = if factory_symbol.declaration {
+                "assignment"
+            } else {
+                "usage"
+            };
+
+            visit.symbols.push(Symbol {
+                fuzzy_ruby_scope: factory_symbol.scope,
+                ..Symbol::new(
+                    category,
+                    factory_symbol.node_type,
+                    factory_symbol.name,
+                    factory_symbol.loc,
+                )
+            });
+            visit.replace_usage(factory_symbol.loc);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Plugin, Symbol, Visit};
+use crate::callbacks;
+use crate::i18n;
+use crate::partials;
+use crate::routes::{self, RouteScope};
+use crate::schema;
+use lib_ruby_parser::{nodes, Node};
+
+// Routes, schema columns, callbacks, translation lookups, and partials.
+// Routes and partials are only kept for files in Rails apps, when they're
+// added to the index.
+#[derive(Default)]
+pub struct RailsPlugin {
+    // For each block being serialized, where the routes in it are nested,
+    // when it's in `Rails.application.routes.draw`
+    route_scopes: Vec<Option<RouteScope>>,
+}
+
+impl Plugin for RailsPlugin {
+    fn name(&self) -> &str {
+        "rails"
+    }
+
+    fn fresh(&self) -> Box<dyn Plugin> {
+        Box::new(RailsPlugin::default())
+    }
+
+    // The columns in `db/schema.rb` are read and written through methods of
+    // their tables' models. Files that size are often only indexed for their
+    // definitions, which these are.
+    fn visit_block(&mut self, call: &Node, body: Option<&Node>, visit: &mut Visit) -> bool {
+        if !schema::is_define_block(call) {
+            return false;
+        }
+
+        for column in schema::columns(body) {
+            let documentation = Some(column.description());
+
+            for name in [column.name.clone(), format!("{}=", column.name)] {
+                visit.symbols.push(Symbol {
+                    fuzzy_ruby_scope: vec![column.model_name.clone()],
+                    documentation: documentation.clone(),
+                    ..Symbol::new("assignment", "Def", name, column.loc)
+                });
+            }
+        }
+
+        true
+    }
+
+    // Routes in a block are nested in the one it's passed to, like a
+    // `namespace` or `resources`
+    fn enter_block(&mut self, call: &Node) {
+        let route_scope = match self.route_scopes.last() {
+            Some(Some(route_scope)) => Some(routes::nested_scope(route_scope, call)),
+            _ if routes::is_draw_block(call) => Some(RouteScope::default()),
+            _ => None,
+        };
+
+        self.route_scopes.push(route_scope);
+    }
+
+    fn exit_block(&mut self) {
+        self.route_scopes.pop();
+    }
+
+    fn visit_call(&mut self, send: &nodes::Send, visit: &mut Visit) {
+        let recv = send.recv.as_deref();
+        let method_name = send.method_name.as_str();
+        let args = &send.args;
+
+        // The symbols a callback is given are calls to methods in the class
+        // body's class
+        if recv.is_none() && callbacks::is_callback(method_name) {
+            for (name, loc) in callbacks::callback_methods(args) {
+                visit.symbols.push(Symbol {
+                    fuzzy_ruby_scope: visit.fuzzy_scope.to_vec(),
+                    class_scope: visit.fuzzy_scope.to_vec(),
+                    ..Symbol::new("usage", "Callback", name, loc)
+                });
+                visit.replace_usage(loc);
+            }
+        }
+
+        if let (Some(Some(route_scope)), None) = (self.route_scopes.last(), recv) {
+            for route in routes::routes(route_scope, method_name, args) {
+                visit.symbols.push(Symbol {
+                    fuzzy_ruby_scope: route.controller_path(),
+                    parameters: vec![route.description()],
+                    ..Symbol::new("usage", "Route", route.action.clone(), route.loc)
+                });
+            }
+        }
+
+        if i18n::is_translate_call(recv, method_name) {
+            if let Some((key, loc)) = i18n::translation_key(args, visit.fuzzy_scope) {
+                visit.symbols.push(Symbol {
+                    fuzzy_ruby_scope: visit.fuzzy_scope.to_vec(),
+                    ..Symbol::new("usage", "I18nKey", key, loc)
+                });
+            }
+        }
+
+        if let (None, "render") = (recv, method_name) {
+            if let Some((partial_name, loc)) = partials::rendered_partial(args) {
+                visit.symbols.push(Symbol {
+                    fuzzy_ruby_scope: visit.fuzzy_scope.to_vec(),
+                    ..Symbol::new("usage", "Partial", partial_name, loc)
+                });
+            }
+        }
+    }
+}
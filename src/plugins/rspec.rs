@@ -0,0 +1,63 @@
+use super::{Plugin, Symbol, Visit};
+use crate::test_runner;
+use lib_ruby_parser::{nodes::*, Node};
+
+// `it "..." do`, `describe User do`, `RSpec.describe`, and the like, and
+// Minitest's `test "..." do`, indexed for code lenses to run them. The
+// description is kept as the name, Minitest selects tests by it.
+pub struct RSpecPlugin;
+
+impl Plugin for RSpecPlugin {
+    fn name(&self) -> &str {
+        "rspec"
+    }
+
+    fn fresh(&self) -> Box<dyn Plugin> {
+        Box::new(RSpecPlugin)
+    }
+
+    fn visit_block(&mut self, call: &Node, _body: Option<&Node>, visit: &mut Visit) -> bool {
+        if visit.interface_only {
+            return false;
+        }
+
+        let (recv, method_name, args, selector_l) = match call {
+            Node::Send(Send {
+                recv,
+                method_name,
+                args,
+                selector_l: Some(selector_l),
+                ..
+            }) => (recv, method_name, args, selector_l),
+            _ => return false,
+        };
+
+        let rspec_receiver = match recv {
+            None => true,
+            Some(recv) => matches!(
+                &**recv,
+                Node::Const(Const { scope: None, name, .. }) if name == "RSpec"
+            ),
+        };
+
+        let test_method = match test_runner::test_block_method(method_name) {
+            Some(test_method) if rspec_receiver => test_method,
+            _ => return false,
+        };
+
+        let description = match args.first() {
+            Some(Node::Str(Str { value, .. })) => value.to_string_lossy(),
+            Some(Node::Const(Const { expression_l, .. })) => {
+                visit.input.source(expression_l).to_string()
+            }
+            _ => String::new(),
+        };
+
+        visit.symbols.push(Symbol {
+            fuzzy_ruby_scope: visit.fuzzy_scope.to_vec(),
+            ..Symbol::new("test", test_method, description, selector_l)
+        });
+
+        false
+    }
+}
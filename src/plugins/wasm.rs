@@ -0,0 +1,645 @@
+use super::{Plugin, Symbol, Visit};
+use crate::resolver::{ASSIGNMENT_TYPE_RESTRICTIONS, USAGE_TYPE_RESTRICTIONS};
+use lib_ruby_parser::{nodes::*, Loc, Node};
+use log::info;
+use serde_json::{json, Value};
+use std::path::Path;
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+// Plugins compiled to WebAssembly, loaded from `wasm_plugins` in the
+// project's config:
+//
+//   wasm_plugins = ["tools/fuzzy/dry_container.wasm"]
+//
+// A guest exports its `memory` and:
+//
+//   fuzzy_alloc(len: i32) -> i32             room for the host to write to
+//   fuzzy_manifest() -> i64                  its name and the types it indexes
+//   fuzzy_visit(ptr: i32, len: i32) -> i64   what it found visiting a node
+//
+// The i64s are a pointer and length in the guest's memory, `ptr << 32 | len`,
+// zero for nothing. Both ways are JSON, versioned with `apiVersion`. The
+// manifest is `{ "name": "dry", "types": ["ContainerKey"] }`, and each visit
+// is one of:
+//
+//   { "event": "block", "call": node, "body": node, "scope": [..], "interfaceOnly": bool }
+//   { "event": "enterBlock", "call": node }
+//   { "event": "exitBlock" }
+//   { "event": "call", "call": node, "scope": [..], "interfaceOnly": bool }
+//
+// where a node is its `type`, its `begin` and `end` byte offsets into the
+// file, its `source`, and for the nodes DSLs are made of, what's in them.
+// What's found is returned as:
+//
+//   {
+//     "symbols": [{ "kind": "definition", "type": "ContainerKey", "name": "services.mailer",
+//                   "scope": [], "begin": 9, "end": 26 }],
+//     "replacedUsages": [{ "begin": 9, "end": 26 }],
+//     "warnings": [{ "begin": 0, "end": 8, "message": "..." }],
+//     "takeOver": false
+//   }
+//
+// Symbols are only indexed with the types the guest declared, or the
+// server's own, and only inside the node visited.
+//
+// Each event sent to the guest gets a budget of fuel, and its memory is capped,
+// so a guest that loops or keeps growing its memory traps instead of
+// hanging the indexer. Like any trap, that skips it for the rest of the file.
+
+pub const API_VERSION: u64 = 1;
+
+// Roughly how many instructions a guest can run per event
+const FUEL_PER_CALL: u64 = 100_000_000;
+
+const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+pub struct WasmPlugin {
+    name: String,
+    // Plugins are loaded once a session, and documents are indexed with
+    // static types
+    named_types: Vec<&'static str>,
+    module: Module,
+    // None once the guest traps, it's skipped for the rest of the file
+    guest: Option<Guest>,
+}
+
+struct Guest {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    visit: TypedFunc<(i32, i32), i64>,
+    // All the fuel the store's been given, what's left is this less what
+    // it's consumed
+    fuel_added: u64,
+}
+
+impl WasmPlugin {
+    pub fn load(path: &Path) -> wasmtime::Result<WasmPlugin> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+
+        let module = Module::from_file(&Engine::new(&config)?, path)?;
+        let mut store = new_store(module.engine())?;
+        let instance = Instance::new(&mut store, &module, &[])?;
+
+        let manifest_fn = instance.get_typed_func::<(), i64>(&mut store, "fuzzy_manifest")?;
+        let mut guest = Guest::new(store, &instance)?;
+        guest.refuel()?;
+        let manifest_ptr = manifest_fn.call(&mut guest.store, ())?;
+        let manifest = guest.read_json(manifest_ptr)?;
+
+        let name = match manifest.get("name").and_then(Value::as_str) {
+            Some(name) => name.to_string(),
+            None => path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+        };
+
+        let named_types = manifest
+            .get("types")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter(|node_type| !node_type.trim().is_empty())
+            .filter(|node_type| server_type(node_type).is_none())
+            .map(|node_type| &*Box::leak(node_type.to_string().into_boxed_str()))
+            .collect();
+
+        Ok(WasmPlugin {
+            name,
+            named_types,
+            module,
+            guest: Some(guest),
+        })
+    }
+
+    // The guest's response to an event, None when it has nothing to say
+    fn send(&mut self, event: Value) -> Option<Value> {
+        let guest = self.guest.as_mut()?;
+
+        match guest.send(event) {
+            Ok(response) => response,
+            Err(error) => {
+                info!("WASM plugin {} failed, skipping it: {}", self.name, error);
+                self.guest = None;
+                None
+            }
+        }
+    }
+
+    fn instantiate(&self) -> Option<Guest> {
+        let guest = new_store(self.module.engine()).and_then(|mut store| {
+            let instance = Instance::new(&mut store, &self.module, &[])?;
+            Guest::new(store, &instance)
+        });
+
+        if let Err(error) = &guest {
+            info!("Unable to instantiate WASM plugin {}: {}", self.name, error);
+        }
+
+        guest.ok()
+    }
+
+    // Indexes what the guest found inside the node visited
+    fn add_response(&self, response: &Value, node_loc: &Loc, visit: &mut Visit) {
+        let inside = |entry: &Value| {
+            let offset = |key: &str| entry.get(key).and_then(Value::as_u64).map(|n| n as usize);
+
+            match (offset("begin"), offset("end")) {
+                (Some(begin), Some(end))
+                    if node_loc.begin <= begin && begin <= end && end <= node_loc.end =>
+                {
+                    Some(Loc { begin, end })
+                }
+                _ => None,
+            }
+        };
+        let entries = |key: &str| {
+            response
+                .get(key)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+        };
+
+        for entry in entries("symbols") {
+            let loc = match inside(entry) {
+                Some(loc) => loc,
+                None => continue,
+            };
+
+            let category = match entry.get("kind").and_then(Value::as_str) {
+                Some("definition") => "assignment",
+                Some("usage") => "usage",
+                _ => continue,
+            };
+
+            let node_type = match entry.get("type").and_then(Value::as_str) {
+                Some(node_type) => match self.node_type(node_type) {
+                    Some(node_type) => node_type,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            let name = match entry.get("name").and_then(Value::as_str) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            };
+
+            let fuzzy_ruby_scope = match entry.get("scope").and_then(Value::as_array) {
+                Some(scope) => scope
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect(),
+                None => visit.fuzzy_scope.to_vec(),
+            };
+
+            visit.symbols.push(Symbol {
+                fuzzy_ruby_scope,
+                documentation: entry
+                    .get("documentation")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                ..Symbol::new(category, node_type, name, &loc)
+            });
+        }
+
+        for entry in entries("replacedUsages") {
+            if let Some(loc) = inside(entry) {
+                visit.replace_usage(&loc);
+            }
+        }
+
+        for entry in entries("warnings") {
+            let message = entry.get("message").and_then(Value::as_str);
+
+            if let (Some(loc), Some(message)) = (inside(entry), message) {
+                visit.warn(&loc, format!("{}: {}", self.name, message));
+            }
+        }
+    }
+
+    fn node_type(&self, node_type: &str) -> Option<&'static str> {
+        match self
+            .named_types
+            .iter()
+            .find(|named_type| **named_type == node_type)
+        {
+            Some(named_type) => Some(*named_type),
+            None => server_type(node_type),
+        }
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fresh(&self) -> Box<dyn Plugin> {
+        Box::new(WasmPlugin {
+            name: self.name.clone(),
+            named_types: self.named_types.clone(),
+            module: self.module.clone(),
+            guest: self.instantiate(),
+        })
+    }
+
+    // A guest that trapped gets a new instance for the next file
+    fn start_file(&mut self) {
+        if self.guest.is_none() {
+            self.guest = self.instantiate();
+        }
+    }
+
+    fn visit_block(&mut self, call: &Node, body: Option<&Node>, visit: &mut Visit) -> bool {
+        let event = json!({
+            "apiVersion": API_VERSION,
+            "event": "block",
+            "call": node_json(call, visit),
+            "body": body.map(|body| node_json(body, visit)),
+            "scope": visit.fuzzy_scope,
+            "interfaceOnly": visit.interface_only,
+        });
+
+        let response = match self.send(event) {
+            Some(response) => response,
+            None => return false,
+        };
+
+        // What's found is in the call or its body, both inside the block
+        let block_loc = Loc {
+            begin: call.expression().begin,
+            end: body.map_or(call.expression().end, |body| body.expression().end),
+        };
+        self.add_response(&response, &block_loc, visit);
+
+        response.get("takeOver").and_then(Value::as_bool) == Some(true)
+    }
+
+    fn enter_block(&mut self, call: &Node) {
+        let call_loc = call.expression();
+
+        self.send(json!({
+            "apiVersion": API_VERSION,
+            "event": "enterBlock",
+            "call": { "type": call.str_type(), "begin": call_loc.begin, "end": call_loc.end },
+        }));
+    }
+
+    fn exit_block(&mut self) {
+        self.send(json!({ "apiVersion": API_VERSION, "event": "exitBlock" }));
+    }
+
+    fn visit_call(&mut self, send: &Send, visit: &mut Visit) {
+        let event = json!({
+            "apiVersion": API_VERSION,
+            "event": "call",
+            "call": send_json(send, visit),
+            "scope": visit.fuzzy_scope,
+            "interfaceOnly": visit.interface_only,
+        });
+
+        if let Some(response) = self.send(event) {
+            self.add_response(&response, &send.expression_l, visit);
+        }
+    }
+
+    fn named_types(&self) -> &[&'static str] {
+        &self.named_types
+    }
+}
+
+impl Guest {
+    fn new(mut store: Store<StoreLimits>, instance: &Instance) -> wasmtime::Result<Guest> {
+        let memory = match instance.get_memory(&mut store, "memory") {
+            Some(memory) => memory,
+            None => return Err(wasmtime::Error::msg("`memory` isn't exported")),
+        };
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "fuzzy_alloc")?;
+        let visit = instance.get_typed_func::<(i32, i32), i64>(&mut store, "fuzzy_visit")?;
+
+        Ok(Guest {
+            store,
+            memory,
+            alloc,
+            visit,
+            fuel_added: FUEL_PER_CALL,
+        })
+    }
+
+    // Tops the fuel back up to an event's budget, so what one event leaves
+    // unused doesn't carry over to the next
+    fn refuel(&mut self) -> wasmtime::Result<()> {
+        let consumed = self.store.fuel_consumed().unwrap_or(0);
+        let fuel = (consumed + FUEL_PER_CALL).saturating_sub(self.fuel_added);
+
+        self.store.add_fuel(fuel)?;
+        self.fuel_added += fuel;
+
+        Ok(())
+    }
+
+    fn send(&mut self, event: Value) -> wasmtime::Result<Option<Value>> {
+        let bytes = event.to_string().into_bytes();
+        let len = bytes.len() as i32;
+
+        self.refuel()?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &bytes)?;
+
+        let response_ptr = self.visit.call(&mut self.store, (ptr, len))?;
+
+        if response_ptr == 0 {
+            return Ok(None);
+        }
+
+        self.read_json(response_ptr).map(Some)
+    }
+
+    // The JSON at a packed pointer and length
+    fn read_json(&mut self, packed: i64) -> wasmtime::Result<Value> {
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & 0xffff_ffff) as usize;
+
+        let mut bytes = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut bytes)?;
+
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+// A store with an event's worth of fuel for instantiating the guest. Growing
+// memory past the cap traps rather than failing quietly, so the guest is
+// skipped instead of carrying on without the memory it asked for.
+fn new_store(engine: &Engine) -> wasmtime::Result<Store<StoreLimits>> {
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_BYTES)
+        .trap_on_grow_failure(true)
+        .build();
+
+    let mut store = Store::new(engine, limits);
+    store.limiter(|limits| limits);
+    store.add_fuel(FUEL_PER_CALL)?;
+
+    Ok(store)
+}
+
+// A type the server has of its own, like `Send` for method calls
+fn server_type(node_type: &str) -> Option<&'static str> {
+    USAGE_TYPE_RESTRICTIONS
+        .get_key(node_type)
+        .or_else(|| ASSIGNMENT_TYPE_RESTRICTIONS.get_key(node_type))
+        .copied()
+}
+
+// A node for guests, with what's in it for the nodes DSLs are made of:
+// calls, literals, constants, arrays, hashes, and statements
+fn node_json(node: &Node, visit: &Visit) -> Value {
+    let loc = node.expression();
+
+    let mut json = json!({
+        "type": node.str_type(),
+        "begin": loc.begin,
+        "end": loc.end,
+        "source": visit.input.source(loc),
+    });
+
+    let contents = match node {
+        Node::Send(send) => return send_json(send, visit),
+        Node::Sym(Sym { name, .. }) => json!({ "value": name.to_string_lossy() }),
+        Node::Str(Str { value, .. }) => json!({ "value": value.to_string_lossy() }),
+        Node::Const(Const { scope, name, .. }) => json!({
+            "scope": scope.as_deref().map(|scope| node_json(scope, visit)),
+            "name": name,
+        }),
+        Node::Array(Array { elements, .. }) => json!({ "elements": nodes_json(elements, visit) }),
+        Node::Hash(Hash { pairs, .. }) | Node::Kwargs(Kwargs { pairs, .. }) => {
+            json!({ "pairs": nodes_json(pairs, visit) })
+        }
+        Node::Pair(Pair { key, value, .. }) => json!({
+            "key": node_json(key, visit),
+            "value": node_json(value, visit),
+        }),
+        Node::Begin(Begin { statements, .. }) => {
+            json!({ "statements": nodes_json(statements, visit) })
+        }
+        _ => return json,
+    };
+
+    if let (Some(json), Value::Object(contents)) = (json.as_object_mut(), contents) {
+        json.extend(contents);
+    }
+
+    json
+}
+
+fn send_json(send: &Send, visit: &Visit) -> Value {
+    json!({
+        "type": "send",
+        "begin": send.expression_l.begin,
+        "end": send.expression_l.end,
+        "source": visit.input.source(&send.expression_l),
+        "receiver": send.recv.as_deref().map(|recv| node_json(recv, visit)),
+        "method": send.method_name,
+        "arguments": nodes_json(&send.args, visit),
+    })
+}
+
+fn nodes_json(nodes: &[Node], visit: &Visit) -> Vec<Value> {
+    nodes.iter().map(|node| node_json(node, visit)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::SourceInput;
+    use lib_ruby_parser::{Parser, ParserOptions};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Indexes `register("...")` as a definition of a `ContainerKey`, taking
+    // the name from the first argument's source without its quotes. The
+    // response is built in the guest's memory from the event it's sent.
+    const REGISTER_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"name\":\"container\",\"types\":[\"ContainerKey\"]}")
+          (data (i32.const 64) "\"method\":\"register\"")
+          (func (export "fuzzy_manifest") (result i64)
+            (i64.const 45))
+          (func (export "fuzzy_alloc") (param i32) (result i32)
+            (i32.const 4096))
+          (func (export "fuzzy_visit") (param i32 i32) (result i64)
+            (if (i32.eqz (call $contains (local.get 0) (local.get 1)))
+              (then (return (i64.const 0))))
+            (i64.or
+              (i64.shl (i64.const 1024) (i64.const 32))
+              (i64.const 283)))
+          (func $contains (param $ptr i32) (param $len i32) (result i32)
+            (local $i i32) (local $j i32)
+            (block $done
+              (loop $search
+                (br_if $done (i32.gt_u (i32.add (local.get $i) (i32.const 19)) (local.get $len)))
+                (local.set $j (i32.const 0))
+                (block $mismatch
+                  (loop $compare
+                    (br_if $mismatch
+                      (i32.ne
+                        (i32.load8_u (i32.add (i32.add (local.get $ptr) (local.get $i)) (local.get $j)))
+                        (i32.load8_u (i32.add (i32.const 64) (local.get $j)))))
+                    (local.set $j (i32.add (local.get $j) (i32.const 1)))
+                    (br_if $compare (i32.lt_u (local.get $j) (i32.const 19)))
+                    (return (i32.const 1))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $search)))
+            (i32.const 0))
+          (data (i32.const 1024) "{\"symbols\":[{\"kind\":\"definition\",\"type\":\"ContainerKey\",\"name\":\"services.mailer\",\"begin\":10,\"end\":25},{\"kind\":\"usage\",\"type\":\"Unknown\",\"name\":\"x\",\"begin\":0,\"end\":1},{\"kind\":\"usage\",\"type\":\"Send\",\"name\":\"y\",\"begin\":0,\"end\":400}],\"warnings\":[{\"begin\":0,\"end\":8,\"message\":\"registered\"}]}"))
+    "#;
+
+    // Guests that never return, and that grow their memory past the cap
+    const RUNAWAY_PLUGINS: [&str; 2] = [
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"name\":\"loop\"}")
+          (func (export "fuzzy_manifest") (result i64)
+            (i64.const 15))
+          (func (export "fuzzy_alloc") (param i32) (result i32)
+            (i32.const 1024))
+          (func (export "fuzzy_visit") (param i32 i32) (result i64)
+            (loop $forever
+              (br $forever))
+            (i64.const 0)))
+        "#,
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"name\":\"grow\"}")
+          (func (export "fuzzy_manifest") (result i64)
+            (i64.const 15))
+          (func (export "fuzzy_alloc") (param i32) (result i32)
+            (i32.const 1024))
+          (func (export "fuzzy_visit") (param i32 i32) (result i64)
+            (drop (memory.grow (i32.const 2048)))
+            (i64.const 0)))
+        "#,
+    ];
+
+    // Traps on events shorter than 64 bytes, and finds nothing in the rest
+    const PICKY_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"name\":\"picky\"}")
+          (data (i32.const 64) "{}")
+          (func (export "fuzzy_manifest") (result i64)
+            (i64.const 16))
+          (func (export "fuzzy_alloc") (param i32) (result i32)
+            (i32.const 1024))
+          (func (export "fuzzy_visit") (param i32 i32) (result i64)
+            (if (i32.lt_u (local.get 1) (i32.const 64))
+              (then unreachable))
+            (i64.or
+              (i64.shl (i64.const 64) (i64.const 32))
+              (i64.const 2))))
+    "#;
+
+    // Tests load plugins at the same time, each from a file of its own
+    static PLUGIN_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    fn load_plugin(wat: &str) -> WasmPlugin {
+        let path = std::env::temp_dir().join(format!(
+            "fuzzy-plugin-{}-{}.wat",
+            std::process::id(),
+            PLUGIN_FILES.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::write(&path, wat).unwrap();
+
+        let plugin = WasmPlugin::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        plugin
+    }
+
+    #[test]
+    fn reads_the_manifest() {
+        let plugin = load_plugin(REGISTER_PLUGIN);
+
+        assert_eq!(plugin.name(), "container");
+        assert_eq!(plugin.named_types(), &["ContainerKey"]);
+    }
+
+    #[test]
+    fn indexes_the_symbols_a_guest_finds_inside_the_call() {
+        let source = "register(\"services.mailer\")";
+        let ast = Parser::new(source.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+        let send = match ast.as_ref() {
+            Node::Send(send) => send,
+            _ => unreachable!(),
+        };
+
+        let input = SourceInput::new(source);
+        let mut visit = Visit::new(&[], &input, false);
+        let mut plugin = load_plugin(REGISTER_PLUGIN);
+        plugin.visit_call(send, &mut visit);
+
+        let symbols: Vec<String> = visit
+            .symbols
+            .iter()
+            .map(|symbol| {
+                format!(
+                    "{} {} {} @ {}",
+                    symbol.category,
+                    symbol.node_type,
+                    symbol.name,
+                    input.source(&symbol.loc)
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            symbols,
+            vec!["assignment ContainerKey services.mailer @ services.mailer"]
+        );
+        assert_eq!(visit.warnings.len(), 1);
+        assert_eq!(visit.warnings[0].1, "container: registered");
+    }
+
+    #[test]
+    fn skips_guests_that_run_out_of_fuel_or_memory() {
+        for wat in RUNAWAY_PLUGINS {
+            let mut plugin = load_plugin(wat);
+
+            assert_eq!(plugin.send(json!({ "event": "exitBlock" })), None);
+            assert!(plugin.guest.is_none(), "{} wasn't skipped", plugin.name());
+        }
+    }
+
+    #[test]
+    fn uses_a_guest_that_trapped_again_for_the_next_file() {
+        let mut plugin = load_plugin(PICKY_PLUGIN);
+
+        assert_eq!(plugin.send(json!({ "event": "exitBlock" })), None);
+        assert!(plugin.guest.is_none());
+
+        plugin.start_file();
+
+        let event = json!({
+            "event": "call",
+            "source": "register(\"services.mailer\", Mailer.new)",
+        });
+
+        assert_eq!(plugin.send(event), Some(json!({})));
+    }
+}
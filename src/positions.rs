@@ -0,0 +1,267 @@
+use lib_ruby_parser::Loc;
+use std::ops::RangeInclusive;
+use tower_lsp::lsp_types::{Position, Range};
+
+// Positions in a file are counted three ways, and every conversion between
+// them goes through here:
+//
+//   parser   byte offsets into the UTF-8 text. A `Loc` starts at its first
+//            byte and ends after its last, so `end` is exclusive.
+//   LSP      lines from 0, and characters counted in UTF-16 code units from
+//            the start of the line. A range ends after its last character,
+//            so its end is exclusive too.
+//   index    a symbol's line, start column and end column, the LSP range of
+//            its name. Names are indexed on one line.
+//
+// Lines end at `\n`, and a `\r` before it is part of the line ending rather
+// than the line. A tab is one character like any other, editors only expand
+// it for display, so it's one column here.
+//
+// A cursor touching either end of a name is on it: after typing `user|` the
+// cursor is at the name's end column, which a go to definition still finds.
+// That makes the columns a symbol is looked up by inclusive at both ends,
+// unlike its range.
+
+// The columns a cursor can be at to be on a name, its end included
+pub fn touching_columns(start_column: u32, end_column: u32) -> RangeInclusive<u32> {
+    start_column..=end_column
+}
+
+pub fn utf16_len(text: &str) -> u32 {
+    text.chars().map(|char| char.len_utf16() as u32).sum()
+}
+
+// The byte offset of a character in a line. Characters past the end of the
+// line are at its end, and one between the halves of a surrogate pair is at
+// the start of its character.
+pub fn byte_offset(line_text: &str, character: u32) -> usize {
+    let mut column = 0;
+
+    for (index, char) in line_text.char_indices() {
+        column += char.len_utf16() as u32;
+
+        if column > character {
+            return index;
+        }
+    }
+
+    line_text.len()
+}
+
+// The text of a line, without its line ending
+pub fn line_text(text: &str, line: u32) -> Option<&str> {
+    text.split('\n')
+        .nth(line as usize)
+        .map(|line_text| line_text.strip_suffix('\r').unwrap_or(line_text))
+}
+
+// The text of the position's line before it, what's been typed so far when
+// the position is the cursor
+pub fn line_prefix(text: &str, position: Position) -> &str {
+    let line_text = line_text(text, position.line).unwrap_or("");
+
+    &line_text[..byte_offset(line_text, position.character)]
+}
+
+// Where each line starts in a text, for converting many positions in it
+pub struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(index, _)| index + 1));
+
+        LineIndex { text, line_starts }
+    }
+
+    // The position of a byte offset. Offsets past the end of the text are at
+    // its end, and one inside a character is at the start of it.
+    pub fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.text.len());
+
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let line_start = self.line_starts[line];
+
+        Position::new(line as u32, utf16_len(&self.text[line_start..offset]))
+    }
+
+    // The byte offset of a position, the inverse of `position` for
+    // positions on a character boundary
+    pub fn offset(&self, position: Position) -> usize {
+        let line_start = match self.line_starts.get(position.line as usize) {
+            Some(line_start) => *line_start,
+            None => return self.text.len(),
+        };
+        let line_text = self.line(position.line).unwrap_or("");
+
+        line_start + byte_offset(line_text, position.character)
+    }
+
+    // The text of a line, without its line ending
+    pub fn line(&self, line: u32) -> Option<&'a str> {
+        let line_start = *self.line_starts.get(line as usize)?;
+        let line_end = match self.line_starts.get(line as usize + 1) {
+            Some(next_line_start) => next_line_start - 1,
+            None => self.text.len(),
+        };
+        let line_text = &self.text[line_start..line_end];
+
+        Some(line_text.strip_suffix('\r').unwrap_or(line_text))
+    }
+
+    pub fn range(&self, loc: &Loc) -> Range {
+        Range::new(self.position(loc.begin), self.position(loc.end))
+    }
+
+    // A location as an indexed symbol's line, start column and end column.
+    // One that continues onto later lines, like a multiline string, ends at
+    // the end of its first line.
+    pub fn line_span(&self, loc: &Loc) -> (usize, usize, usize) {
+        let start = self.position(loc.begin);
+        let end = self.position(loc.end.max(loc.begin));
+
+        let end_column = if end.line == start.line {
+            end.character
+        } else {
+            utf16_len(self.line(start.line).unwrap_or(""))
+        };
+
+        (
+            start.line as usize,
+            start.character as usize,
+            end_column as usize,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn counts_columns_in_utf16_code_units() {
+        let text = "name = \"café 🎉\"\n\tuser.save";
+        let lines = LineIndex::new(text);
+
+        // é is 2 bytes and 1 code unit, 🎉 is 4 bytes and 2 code units
+        let closing_quote = text.rfind('"').unwrap();
+        assert_eq!(lines.position(closing_quote), Position::new(0, 15));
+
+        // The tab is one column
+        let save = text.find("save").unwrap();
+        assert_eq!(lines.position(save), Position::new(1, 6));
+        assert_eq!(lines.offset(Position::new(1, 6)), save);
+    }
+
+    #[test]
+    fn keeps_carriage_returns_out_of_lines() {
+        let text = "first\r\nsecond\r\n";
+        let lines = LineIndex::new(text);
+
+        assert_eq!(lines.line(0), Some("first"));
+        assert_eq!(lines.line(1), Some("second"));
+        assert_eq!(lines.line(2), Some(""));
+        assert_eq!(lines.line(3), None);
+        assert_eq!(lines.offset(Position::new(0, 40)), 5);
+        assert_eq!(line_text(text, 1), Some("second"));
+    }
+
+    #[test]
+    fn clamps_positions_outside_the_text() {
+        let text = "a🎉b";
+        let lines = LineIndex::new(text);
+
+        // Inside the emoji's bytes, and between its surrogate halves
+        assert_eq!(lines.position(2), Position::new(0, 1));
+        assert_eq!(lines.offset(Position::new(0, 2)), 1);
+        assert_eq!(lines.position(100), Position::new(0, 4));
+        assert_eq!(lines.offset(Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn ends_multiline_spans_on_their_first_line() {
+        let text = "x = <<~SQL\n  select 1\nSQL\n";
+        let lines = LineIndex::new(text);
+        let loc = Loc {
+            begin: 4,
+            end: text.len() - 1,
+        };
+
+        assert_eq!(lines.line_span(&loc), (0, 4, 10));
+        assert_eq!(
+            lines.range(&loc),
+            Range::new(Position::new(0, 4), Position::new(2, 3))
+        );
+    }
+
+    #[test]
+    fn takes_the_typed_prefix_of_a_line() {
+        let text = "def total\n  prix_réduit.ro\nend";
+
+        assert_eq!(line_prefix(text, Position::new(1, 16)), "  prix_réduit.ro");
+        assert_eq!(line_prefix(text, Position::new(1, 100)), "  prix_réduit.ro");
+        assert_eq!(touching_columns(2, 13).count(), 12);
+    }
+
+    // Lines of ASCII, tabs, and characters from 1 to 4 bytes long
+    fn ruby_text() -> impl Strategy<Value = String> {
+        prop::collection::vec(
+            prop::sample::select(vec!['a', ' ', '\t', '\n', 'é', 'λ', '中', '🎉', '\r']),
+            0..200,
+        )
+        .prop_map(|chars| chars.into_iter().collect())
+    }
+
+    proptest! {
+        #[test]
+        fn offsets_round_trip_through_positions(text in ruby_text()) {
+            let lines = LineIndex::new(&text);
+
+            for (offset, _char) in text.char_indices() {
+                let position = lines.position(offset);
+
+                // The `\n` of a `\r\n` is inside the line ending
+                if text[..offset].ends_with('\r') && text[offset..].starts_with('\n') {
+                    continue;
+                }
+
+                prop_assert_eq!(lines.offset(position), offset);
+            }
+        }
+
+        #[test]
+        fn positions_follow_the_text_in_order(text in ruby_text()) {
+            let lines = LineIndex::new(&text);
+            let mut previous = Position::new(0, 0);
+
+            for (offset, _char) in text.char_indices().chain([(text.len(), ' ')]) {
+                let position = lines.position(offset);
+
+                prop_assert!(position >= previous);
+                previous = position;
+            }
+        }
+
+        #[test]
+        fn spans_stay_on_one_line(text in ruby_text(), begin in 0usize..200, len in 0usize..50) {
+            let lines = LineIndex::new(&text);
+            let begin = begin.min(text.len());
+            let loc = Loc { begin, end: (begin + len).min(text.len()) };
+
+            let (line, start_column, end_column) = lines.line_span(&loc);
+            // With any `\r` it ends in, which a location can include
+            let line_len = utf16_len(text.split('\n').nth(line).unwrap_or("")) as usize;
+
+            prop_assert!(start_column <= end_column);
+            prop_assert!(end_column <= line_len.max(start_column));
+        }
+    }
+}
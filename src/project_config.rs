@@ -0,0 +1,191 @@
+use log::info;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+// Settings checked in with a project, so everyone working on it gets the
+// same behavior without configuring their editor. Keys are the same as the
+// editor's settings, in snake_case or camelCase:
+//
+//   index_gems = false
+//   exclude = ["db/schema.rb", "spec/fixtures/**"]
+//   rails = true
+//   diagnostics = ["syntax"]
+//
+// Editors send every setting, defaults included, so the file's settings
+// take precedence over the editor's.
+//
+// Settings that run commands, write files, load code, or read outside the
+// project are ignored in the file, since opening a project shouldn't be
+// enough to run what it says, unless the editor's settings turn on
+// `trustProjectConfig`.
+
+pub const FILE_NAME: &str = ".fuzzy-ruby-server.toml";
+
+const TRUSTED_KEYS: &[&str] = &[
+    "formatCommand",
+    "testCommandTemplate",
+    "telemetryFile",
+    "wasmPlugins",
+    "includeDirs",
+    "trustProjectConfig",
+];
+
+pub fn load(workspace_path: &str) -> Option<Map<String, Value>> {
+    let config_path = Path::new(workspace_path).join(FILE_NAME);
+    let text = fs::read_to_string(&config_path).ok()?;
+
+    let table = match text.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(error) => {
+            info!("Unable to read {}: {}", config_path.display(), error);
+            return None;
+        }
+    };
+
+    let options = table
+        .into_iter()
+        .filter_map(|(key, value)| {
+            Some((lower_camel_case(&key), serde_json::to_value(value).ok()?))
+        })
+        .collect();
+
+    Some(options)
+}
+
+// Project settings over the editor's initialization options
+pub fn merge(
+    initialization_options: Option<&Value>,
+    project_options: Option<Map<String, Value>>,
+) -> Map<String, Value> {
+    let mut options = initialization_options
+        .and_then(|options| options.as_object())
+        .cloned()
+        .unwrap_or_default();
+    let trusted = options
+        .get("trustProjectConfig")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    for (key, value) in project_options.unwrap_or_default() {
+        if TRUSTED_KEYS.contains(&key.as_str()) && (!trusted || key == "trustProjectConfig") {
+            info!(
+                "Ignoring `{}` in {}, turn on the `trustProjectConfig` setting to use it",
+                key, FILE_NAME
+            );
+            continue;
+        }
+
+        options.insert(key, value);
+    }
+
+    options
+}
+
+// A boolean setting, or its default when it's missing or isn't a boolean
+pub fn bool_setting(options: &Map<String, Value>, key: &str, default: bool) -> bool {
+    typed_setting(options, key, Value::as_bool).unwrap_or(default)
+}
+
+pub fn str_setting<'a>(options: &'a Map<String, Value>, key: &str) -> Option<&'a str> {
+    typed_setting(options, key, Value::as_str)
+}
+
+// Settings of the wrong type are ignored rather than trusted, with a warning
+fn typed_setting<'a, T>(
+    options: &'a Map<String, Value>,
+    key: &str,
+    as_type: fn(&'a Value) -> Option<T>,
+) -> Option<T> {
+    let value = options.get(key)?;
+    let setting = as_type(value);
+
+    if setting.is_none() {
+        info!("Ignoring `{}` setting of the wrong type: {}", key, value);
+    }
+
+    setting
+}
+
+// `index_gems` is `indexGems`, camelCase keys are left as they are
+fn lower_camel_case(key: &str) -> String {
+    let mut words = key.split("_");
+    let first_word = words.next().unwrap_or("").to_string();
+
+    words.fold(first_word, |mut camel_case, word| {
+        let mut chars = word.chars();
+
+        if let Some(first_char) = chars.next() {
+            camel_case.extend(first_char.to_uppercase());
+            camel_case.push_str(chars.as_str());
+        }
+
+        camel_case
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project_options(options: Value) -> Option<Map<String, Value>> {
+        options.as_object().cloned()
+    }
+
+    #[test]
+    fn overrides_the_editors_settings() {
+        let options = merge(
+            Some(&json!({ "indexGems": true, "rails": false })),
+            project_options(json!({ "indexGems": false })),
+        );
+
+        assert_eq!(options["indexGems"], json!(false));
+        assert_eq!(options["rails"], json!(false));
+    }
+
+    #[test]
+    fn ignores_commands_unless_the_project_is_trusted() {
+        let project = json!({
+            "formatCommand": "./format",
+            "wasmPlugins": ["plugin.wasm"],
+            "includeDirs": [{ "path": "/opt/gems" }],
+            "trustProjectConfig": true,
+            "exclude": ["db/schema.rb"],
+        });
+
+        let options = merge(
+            Some(&json!({ "formatCommand": "rufo" })),
+            project_options(project.clone()),
+        );
+
+        assert_eq!(options["formatCommand"], json!("rufo"));
+        assert_eq!(options.get("wasmPlugins"), None);
+        assert_eq!(options.get("includeDirs"), None);
+        assert_eq!(options.get("trustProjectConfig"), None);
+        assert_eq!(options["exclude"], json!(["db/schema.rb"]));
+
+        let options = merge(
+            Some(&json!({ "trustProjectConfig": true })),
+            project_options(project),
+        );
+
+        assert_eq!(options["formatCommand"], json!("./format"));
+        assert_eq!(options["wasmPlugins"], json!(["plugin.wasm"]));
+    }
+
+    #[test]
+    fn falls_back_on_settings_of_the_wrong_type() {
+        let options = project_options(json!({
+            "indexGems": "no",
+            "forceRename": true,
+            "allocationType": 1,
+        }))
+        .unwrap();
+
+        assert!(bool_setting(&options, "indexGems", true));
+        assert!(bool_setting(&options, "forceRename", false));
+        assert!(!bool_setting(&options, "fuzzySearch", false));
+        assert_eq!(str_setting(&options, "allocationType"), None);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fs;
+
+// Method signatures from the `.rbs` file next to a Ruby file, e.g.
+// `app/models/user.rbs` for `app/models/user.rb`:
+//
+//   class User < ApplicationRecord
+//     def self.find_by_email: (String email) -> User?
+//     def full_name: () -> String
+//                  | (Symbol format) -> String
+//   end
+//
+// Only what's needed for hovers is read. Methods are keyed by the innermost
+// class or module name, whether they're singleton methods, and their name, to
+// match how `Def` and `Defs` documents are scoped.
+
+#[derive(Default)]
+pub struct Signatures {
+    methods: HashMap<(String, bool, String), String>,
+}
+
+impl Signatures {
+    pub fn for_ruby_file(path: &str) -> Option<Signatures> {
+        let rbs_path = format!("{}.rbs", path.strip_suffix(".rb")?);
+        let text = fs::read_to_string(rbs_path).ok()?;
+
+        Some(Signatures::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> Signatures {
+        let mut signatures = Signatures::default();
+        // The names of the classes and modules each open `end` belongs to,
+        // `None` for other blocks like interfaces
+        let mut scopes: Vec<Option<String>> = vec![];
+        let mut last_method = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+
+            if line.starts_with("#") || line.is_empty() {
+                continue;
+            }
+
+            if let Some(overload) = line.strip_prefix("|") {
+                // Overloads continue the previous method on the next line
+                if let Some(key) = &last_method {
+                    if let Some(signature) = signatures.methods.get_mut(key) {
+                        signature.push_str(&format!("\n  | {}", overload.trim()));
+                    }
+                }
+
+                continue;
+            }
+
+            last_method = None;
+
+            let keyword = line.split_whitespace().next().unwrap_or("");
+
+            match keyword {
+                "class" | "module" => {
+                    let name = line[keyword.len()..]
+                        .split(|char: char| char == '<' || char == '[' || char.is_whitespace())
+                        .find(|part| part.len() > 0)
+                        .unwrap_or("")
+                        .rsplit("::")
+                        .next()
+                        .unwrap_or("")
+                        .to_string();
+
+                    scopes.push(Some(name));
+                }
+                "interface" => scopes.push(None),
+                "end" => {
+                    scopes.pop();
+                }
+                "def" => {
+                    let definition = line["def".len()..].trim_start();
+                    let (name, types) = match definition.split_once(":") {
+                        Some((name, types)) => (name.trim(), types.trim()),
+                        None => continue,
+                    };
+
+                    let (singleton, name) = match name.strip_prefix("self?.") {
+                        Some(name) => (true, name),
+                        None => match name.strip_prefix("self.") {
+                            Some(name) => (true, name),
+                            None => (false, name),
+                        },
+                    };
+
+                    let scope_name = match scopes.last() {
+                        Some(Some(scope_name)) => scope_name.to_string(),
+                        Some(None) => continue,
+                        None => String::new(),
+                    };
+
+                    let key = (scope_name, singleton, name.to_string());
+
+                    signatures
+                        .methods
+                        .insert(key.clone(), format!("def {}: {}", name, types));
+
+                    last_method = Some(key);
+                }
+                _ => {}
+            }
+        }
+
+        signatures
+    }
+
+    pub fn method(&self, scope_name: &str, singleton: bool, name: &str) -> Option<&String> {
+        self.methods
+            .get(&(scope_name.to_string(), singleton, name.to_string()))
+    }
+}
+
+// Ruby with the classes, modules, methods, and constants an RBS file
+// declares, each under the comment documenting it, for indexing types that
+// have no Ruby source, like the core classes written in C:
+//
+//   # Returns the number of elements.
+//   def length: () -> Integer
+//
+// becomes
+//
+//   # Returns the number of elements.
+//   def length(*); end
+//
+// Everything else, like interfaces, type aliases, and annotations, is left
+// out. The signatures themselves are read from the `.rbs` file kept beside
+// the stub.
+pub fn ruby_stub(text: &str) -> String {
+    let mut stub = String::new();
+    let mut comment: Vec<&str> = vec![];
+    // Whether each open `end` closes a class or module that's in the stub
+    let mut scopes: Vec<bool> = vec![];
+
+    for line in text.lines() {
+        let line = line.trim();
+        let indent = "  ".repeat(scopes.len());
+
+        if let Some(comment_line) = line.strip_prefix("#") {
+            comment.push(comment_line);
+            continue;
+        }
+
+        let documentation = std::mem::take(&mut comment);
+        let keyword = line.split_whitespace().next().unwrap_or("");
+
+        let declaration = match keyword {
+            "class" | "module" => {
+                let (name, superclass) = declaration_names(&line[keyword.len()..]);
+
+                scopes.push(true);
+
+                match superclass {
+                    Some(superclass) if keyword == "class" => {
+                        format!("class {} < {}", name, superclass)
+                    }
+                    _ => format!("{} {}", keyword, name),
+                }
+            }
+            "interface" => {
+                scopes.push(false);
+                continue;
+            }
+            "end" => {
+                if scopes.pop() == Some(true) {
+                    stub.push_str(&format!("{}end\n", "  ".repeat(scopes.len())));
+                }
+
+                continue;
+            }
+            _ if scopes.last() == Some(&false) => continue,
+            "include" | "extend" | "prepend" => {
+                let (name, _) = declaration_names(&line[keyword.len()..]);
+
+                format!("{} {}", keyword, name)
+            }
+            "private" | "public" if line == keyword => keyword.to_string(),
+            "def" | "private" | "public" => match stub_method(line) {
+                Some(method) => method,
+                None => continue,
+            },
+            "alias" => match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [_, new_name, old_name] => format!(
+                    "alias_method :\"{}\", :\"{}\"",
+                    new_name.trim_start_matches("self."),
+                    old_name.trim_start_matches("self.")
+                ),
+                _ => continue,
+            },
+            "attr_reader" | "attr_writer" | "attr_accessor" => {
+                match line[keyword.len()..].split_once(":") {
+                    Some((name, _type)) => format!("{} :{}", keyword, name.trim()),
+                    None => continue,
+                }
+            }
+            // `VERSION: String`, but not a continued signature
+            _ => match line.split_once(":") {
+                Some((name, constant_type))
+                    if is_constant_name(name) && !constant_type.starts_with(":") =>
+                {
+                    format!("{} = nil", name)
+                }
+                _ => continue,
+            },
+        };
+
+        for comment_line in documentation {
+            stub.push_str(&format!("{}#{}\n", indent, comment_line));
+        }
+
+        stub.push_str(&format!("{}{}\n", indent, declaration));
+    }
+
+    stub
+}
+
+// The class or module name and superclass of a declaration, without type
+// parameters or a module's self type: `Hash[K, V] < Object` is `Hash` and
+// `Object`
+fn declaration_names(declaration: &str) -> (String, Option<String>) {
+    let without_self_type = match declaration.split_once(" : ") {
+        Some((name, _self_type)) => name,
+        None => declaration,
+    };
+    let (name, superclass) = match without_self_type.split_once("<") {
+        Some((name, superclass)) => (name, Some(superclass)),
+        None => (without_self_type, None),
+    };
+
+    let bare_name = |name: &str| {
+        name.split('[')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .trim_start_matches("::")
+            .to_string()
+    };
+
+    (
+        bare_name(name),
+        superclass.map(bare_name).filter(|name| name.len() > 0),
+    )
+}
+
+fn is_constant_name(name: &str) -> bool {
+    name.starts_with(|char: char| char.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|char| char.is_alphanumeric() || char == '_')
+}
+
+// `def self.read: (String path) -> String` as `def self.read(*); end`.
+// Methods RBS declares as both, `self?.`, are defined both ways.
+fn stub_method(line: &str) -> Option<String> {
+    let (visibility, definition) = match line.split_once(" def ") {
+        Some((visibility, definition)) => (Some(visibility.trim()), definition),
+        None => (None, line.strip_prefix("def ")?),
+    };
+
+    let name = definition.split_once(":")?.0.trim().trim_matches('`');
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let method = match name.strip_prefix("self?.") {
+        Some(name) => format!("def self.{}(*); end; def {}(*); end", name, name),
+        None => format!("def {}(*); end", name),
+    };
+
+    Some(match visibility {
+        Some(visibility) => format!("{} {}", visibility, method),
+        None => method,
+    })
+}
@@ -0,0 +1,233 @@
+use crate::autoload;
+use lib_ruby_parser::{nodes::*, Node};
+
+// What a method is called on, kept with each call so its call sites can be
+// told apart without knowing their types:
+//
+//   save                    self
+//   self.save               self
+//   @order.save             @order
+//   order.save              order
+//   current_order.save      current_order
+//   Billing::Invoice.find   Billing::Invoice
+//   orders.last.save        unknown
+//
+// Locals assigned an instance of a class in a method, like `order =
+// Order.find(id)`, hold one for the rest of it, and calls on them are
+// hinted with the class instead, `#<Order>`.
+//
+// A method's calls across the workspace are the ones on receivers named
+// after its class: `order`, `@order`, `current_order`, or `#<Order>` for
+// `Order#save`, and `Order` or `Shop::Order` for `Shop::Order.find`.
+
+// Class methods returning an instance of the class
+static CONSTRUCTOR_METHODS: &[&str] = &[
+    "create",
+    "create!",
+    "find",
+    "find_by",
+    "find_by!",
+    "find_or_create_by",
+    "find_or_create_by!",
+    "find_or_initialize_by",
+    "first",
+    "first!",
+    "last",
+    "last!",
+    "new",
+    "take",
+    "take!",
+];
+
+pub fn receiver_hint(recv: Option<&Node>) -> String {
+    let hint = match recv {
+        None | Some(Node::Self_(_)) => Some("self".to_string()),
+        Some(Node::Ivar(Ivar { name, .. })) | Some(Node::Lvar(Lvar { name, .. })) => {
+            Some(name.to_string())
+        }
+        // Attribute readers and other calls without receivers or arguments
+        // read like variables
+        Some(Node::Send(Send {
+            recv: None,
+            method_name,
+            args,
+            ..
+        })) if args.is_empty() => Some(method_name.to_string()),
+        Some(Node::Const(const_node)) => constant_path(const_node),
+        _ => None,
+    };
+
+    hint.unwrap_or_else(|| "unknown".to_string())
+}
+
+// The hint for calls on a local holding an instance of the class
+pub fn instance_hint(class_path: &[String]) -> String {
+    format!("#<{}>", class_path.join("::"))
+}
+
+// The class a value is an instance of when it's constructed or looked up
+// by a class method, `["Billing", "Invoice"]` for `Billing::Invoice.new`
+pub fn constructed_class(value: &Node) -> Option<Vec<String>> {
+    match value {
+        Node::Send(Send {
+            recv: Some(recv),
+            method_name,
+            ..
+        }) if CONSTRUCTOR_METHODS.contains(&method_name.as_str()) => match recv.as_ref() {
+            Node::Const(const_node) => {
+                constant_path(const_node).map(|path| path.split("::").map(String::from).collect())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Whether calls on the receiver are calls to an instance method of the
+// class, its constant path split into names
+pub fn names_instance(hint: &str, class_path: &[String]) -> bool {
+    if let Some(instance_class) = hint
+        .strip_prefix("#<")
+        .and_then(|hint| hint.strip_suffix('>'))
+    {
+        return names_class(instance_class, class_path);
+    }
+
+    let class_name = match class_path.last() {
+        Some(class_name) => class_name,
+        None => return false,
+    };
+    let variable = hint.trim_start_matches('@');
+
+    if variable.is_empty() || !variable.starts_with(|char: char| char.is_lowercase()) {
+        return false;
+    }
+
+    let underscored = autoload::underscore(class_name);
+
+    variable == underscored || variable.ends_with(&format!("_{}", underscored))
+}
+
+// Whether calls on the receiver are calls to a singleton method of the
+// class, its constant path split into names. Nested constants can be named
+// from inside their namespace.
+pub fn names_class(hint: &str, class_path: &[String]) -> bool {
+    let names: Vec<&str> = hint.split("::").collect();
+
+    !class_path.is_empty()
+        && names.len() <= class_path.len()
+        && class_path[class_path.len() - names.len()..]
+            .iter()
+            .zip(&names)
+            .all(|(class_name, name)| class_name == name)
+}
+
+// `Billing::Invoice`, None when a part of it isn't a constant
+fn constant_path(const_node: &Const) -> Option<String> {
+    let mut names = vec![const_node.name.as_str()];
+    let mut scope = const_node.scope.as_deref();
+
+    loop {
+        match scope {
+            None | Some(Node::Cbase(_)) => break,
+            Some(Node::Const(Const {
+                name,
+                scope: outer_scope,
+                ..
+            })) => {
+                names.push(name.as_str());
+                scope = outer_scope.as_deref();
+            }
+            Some(_) => return None,
+        }
+    }
+
+    names.reverse();
+
+    Some(names.join("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    fn hint(call: &str) -> String {
+        let ast = Parser::new(format!("order = nil\n{}", call), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+
+        let statements = match ast.as_ref() {
+            Node::Begin(Begin { statements, .. }) => statements,
+            _ => panic!("expected two statements"),
+        };
+
+        match &statements[1] {
+            Node::Send(Send { recv, .. }) => receiver_hint(recv.as_deref()),
+            Node::CSend(CSend { recv, .. }) => receiver_hint(Some(recv.as_ref())),
+            node => panic!("not a call: {:?}", node),
+        }
+    }
+
+    fn class_path(path: &str) -> Vec<String> {
+        path.split("::").map(String::from).collect()
+    }
+
+    #[test]
+    fn normalizes_receivers() {
+        assert_eq!(hint("save"), "self");
+        assert_eq!(hint("self.save"), "self");
+        assert_eq!(hint("@order.save"), "@order");
+        assert_eq!(hint("order.save"), "order");
+        assert_eq!(hint("order&.save"), "order");
+        assert_eq!(hint("current_order.save"), "current_order");
+        assert_eq!(hint("::Billing::Invoice.find(1)"), "Billing::Invoice");
+        assert_eq!(hint("orders.last.save"), "unknown");
+        assert_eq!(hint("find_order(1).save"), "unknown");
+    }
+
+    #[test]
+    fn matches_receivers_named_after_a_class() {
+        let order = class_path("Shop::Order");
+        let line_item = class_path("LineItem");
+
+        assert!(names_instance("order", &order));
+        assert!(names_instance("@order", &order));
+        assert!(names_instance("current_order", &order));
+        assert!(names_instance("line_item", &line_item));
+        assert!(!names_instance("reorder", &order));
+        assert!(!names_instance("item", &line_item));
+        assert!(!names_instance("Order", &order));
+        assert!(!names_instance("unknown", &order));
+        assert!(names_instance("#<Order>", &order));
+        assert!(!names_instance("#<Product>", &order));
+
+        assert!(names_class("Order", &order));
+        assert!(names_class("Shop::Order", &order));
+        assert!(!names_class("Admin::Order", &order));
+        assert!(!names_class("Order", &line_item));
+        assert!(!names_class("order", &order));
+    }
+
+    #[test]
+    fn reads_the_class_of_constructed_values() {
+        let class_of = |value: &str| {
+            let ast = Parser::new(value.to_string(), ParserOptions::default())
+                .do_parse()
+                .ast
+                .unwrap();
+
+            constructed_class(&ast).map(|class_path| instance_hint(&class_path))
+        };
+
+        assert_eq!(class_of("Order.new"), Some("#<Order>".to_string()));
+        assert_eq!(
+            class_of("Billing::Invoice.find_by!(number: 1)"),
+            Some("#<Billing::Invoice>".to_string())
+        );
+        assert_eq!(class_of("Order.where(paid: true)"), None);
+        assert_eq!(class_of("order.dup"), None);
+        assert_eq!(class_of("orders.first"), None);
+    }
+}
@@ -0,0 +1,434 @@
+use crate::encoding;
+use crate::positions::LineIndex;
+use lib_ruby_parser::{nodes::*, Node, Parser, ParserOptions};
+use std::ops::RangeInclusive;
+use tower_lsp::lsp_types::{Position, Range};
+
+// The code actions rewriting a method, worked out from its syntax tree:
+//
+//   extract method   statements moved into a new method after theirs, and
+//                    called in their place
+//   inline variable  a local's reads replaced by the value it's assigned,
+//                    and the assignment deleted
+//   create method    a stub for a missing method at the end of its class
+//
+// What the statements read and assign is left to the index, which has every
+// local's occurrences.
+
+// Mean something else once moved out of the method they're in
+static CONTEXT_KEYWORDS: &[&str] = &[
+    "__method__",
+    "binding",
+    "break",
+    "next",
+    "redo",
+    "retry",
+    "return",
+    "super",
+    "yield",
+];
+
+// Whole statements of a method's body, to move into a new method
+#[derive(Debug)]
+pub struct Extraction {
+    // The statements, replaced by calling the new method
+    pub range: Range,
+    // Their lines, less the indentation of the first
+    pub body: Vec<String>,
+    // The method they're in, which the new one goes after
+    pub method_indent: String,
+    pub method_lines: RangeInclusive<u32>,
+    pub method_end: Position,
+    // `def self.name`, which the new method is defined like
+    pub singleton: bool,
+}
+
+// The statements on the selection's lines, when they're all in one method
+// and none are cut partway
+pub fn extraction(text: &str, selection: Range) -> Option<Extraction> {
+    if selection.start == selection.end {
+        return None;
+    }
+
+    let ast = parse(text)?;
+    let line_index = LineIndex::new(text);
+
+    // A selection ending at the start of a line ends on the line before
+    let end_line = if selection.end.character == 0 && selection.end.line > selection.start.line {
+        selection.end.line - 1
+    } else {
+        selection.end.line
+    };
+    let begin = line_index.offset(Position::new(selection.start.line, 0));
+    let end = line_index.offset(Position::new(end_line, u32::MAX));
+
+    let method = enclosing_method(&ast, begin, end)?;
+    let (body, singleton) = match method {
+        Node::Def(Def {
+            body,
+            end_l: Some(_),
+            ..
+        }) => (body, false),
+        Node::Defs(Defs {
+            body,
+            end_l: Some(_),
+            ..
+        }) => (body, true),
+        // Endless methods are one expression
+        _ => return None,
+    };
+
+    let mut selected = vec![];
+
+    for statement in statements(body.as_deref()) {
+        let loc = statement.expression();
+
+        if loc.begin >= begin && loc.end <= end {
+            selected.push(loc);
+        } else if loc.begin < end && loc.end > begin {
+            return None;
+        }
+    }
+
+    let (first, last) = (selected.first()?, selected.last()?);
+    let source = &text[first.begin..last.end];
+
+    if mentions_context(source) {
+        return None;
+    }
+
+    let statement_indent = indentation(text, first.begin)?;
+    let mut body = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        if index == 0 || line.trim().is_empty() {
+            body.push(line.trim_end().to_string());
+            continue;
+        }
+
+        // Less indented lines are likely a heredoc's, whose text would change
+        match line.strip_prefix(statement_indent) {
+            Some(line) => body.push(line.to_string()),
+            None => return None,
+        }
+    }
+
+    let method_loc = method.expression();
+
+    Some(Extraction {
+        range: Range::new(
+            line_index.position(first.begin),
+            line_index.position(last.end),
+        ),
+        body,
+        method_indent: indentation(text, method_loc.begin)?.to_string(),
+        method_lines: line_index.position(method_loc.begin).line
+            ..=line_index.position(method_loc.end).line,
+        method_end: line_index.position(method_loc.end),
+        singleton,
+    })
+}
+
+// A local assigned on a line of its own in a method, to replace with its
+// value
+#[derive(Debug)]
+pub struct Inlining {
+    pub name: String,
+    // The assignment's line, deleted
+    pub assignment: Range,
+    pub value: String,
+}
+
+// The assignment whose local's name is at the position, when its value is
+// simple enough to read in its place
+pub fn inlining(text: &str, position: Position) -> Option<Inlining> {
+    let ast = parse(text)?;
+    let line_index = LineIndex::new(text);
+    let offset = line_index.offset(position);
+
+    let body = match enclosing_method(&ast, offset, offset)? {
+        Node::Def(Def { body, .. }) | Node::Defs(Defs { body, .. }) => body,
+        _ => return None,
+    };
+
+    for statement in statements(body.as_deref()) {
+        let (name, name_l, value) = match statement {
+            Node::Lvasgn(Lvasgn {
+                name,
+                name_l,
+                value: Some(value),
+                ..
+            }) => (name, name_l, value),
+            _ => continue,
+        };
+
+        if offset < name_l.begin || offset > name_l.end {
+            continue;
+        }
+
+        let value_loc = value.expression();
+        let value_text = &text[value_loc.begin..value_loc.end];
+
+        if !is_simple(value) || value_text.contains('\n') {
+            return None;
+        }
+
+        let loc = statement.expression();
+        let indent = indentation(text, loc.begin)?;
+        let line_end = text[loc.end..]
+            .find('\n')
+            .map_or(text.len(), |index| loc.end + index);
+
+        // Nothing else on its line
+        if !text[loc.end..line_end].trim().is_empty() {
+            return None;
+        }
+
+        let next_line_start = (line_end + 1).min(text.len());
+
+        return Some(Inlining {
+            name: name.to_string(),
+            assignment: Range::new(
+                line_index.position(loc.begin - indent.len()),
+                line_index.position(next_line_start),
+            ),
+            value: value_text.to_string(),
+        });
+    }
+
+    None
+}
+
+// Where a class or module's body ends, for new methods to go last in it
+#[derive(Debug)]
+pub struct BodyEnd {
+    // The start of the line with its `end`
+    pub position: Position,
+    // Its methods' indentation
+    pub indent: String,
+    pub empty: bool,
+}
+
+// The end of the class or module defined on the line, when its `end` is on
+// a line of its own
+pub fn body_end(text: &str, line: u32) -> Option<BodyEnd> {
+    let ast = parse(text)?;
+    let line_index = LineIndex::new(text);
+    let begin = line_index.offset(Position::new(line, 0));
+    let end = line_index.offset(Position::new(line, u32::MAX));
+
+    let (body, end_l) = match definition_on(&ast, begin, end)? {
+        Node::Class(Class { body, end_l, .. }) | Node::Module(Module { body, end_l, .. }) => {
+            (body, end_l)
+        }
+        _ => return None,
+    };
+
+    Some(BodyEnd {
+        position: Position::new(line_index.position(end_l.begin).line, 0),
+        indent: format!("{}  ", indentation(text, end_l.begin)?),
+        empty: body.is_none(),
+    })
+}
+
+// Read the same wherever it's put, without parentheses: variables,
+// constants, literals, and calls without arguments on them
+fn is_simple(value: &Node) -> bool {
+    match value {
+        Node::Lvar(_)
+        | Node::Ivar(_)
+        | Node::Cvar(_)
+        | Node::Gvar(_)
+        | Node::Const(_)
+        | Node::Self_(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_)
+        | Node::Int(_)
+        | Node::Float(_)
+        | Node::Sym(_)
+        | Node::Str(_) => true,
+        // Unary operators like `!` would take in more of where it's read
+        Node::Send(Send {
+            recv,
+            method_name,
+            args,
+            ..
+        }) => {
+            args.is_empty()
+                && method_name.starts_with(|char: char| char.is_alphabetic() || char == '_')
+                && recv.as_deref().map_or(true, is_simple)
+        }
+        _ => false,
+    }
+}
+
+fn parse(text: &str) -> Option<Box<Node>> {
+    let options = ParserOptions {
+        decoder: Some(encoding::parser_decoder()),
+        ..Default::default()
+    };
+
+    Parser::new(text.to_string(), options).do_parse().ast
+}
+
+// The method defined in a class or module body, or at the top level, whose
+// body has the offsets
+fn enclosing_method(node: &Node, begin: usize, end: usize) -> Option<&Node> {
+    let loc = node.expression();
+
+    if loc.begin > begin || loc.end < end {
+        return None;
+    }
+
+    match node {
+        Node::Def(_) | Node::Defs(_) => Some(node),
+        Node::Begin(Begin { statements, .. }) => statements
+            .iter()
+            .find_map(|statement| enclosing_method(statement, begin, end)),
+        Node::Class(Class { body, .. })
+        | Node::Module(Module { body, .. })
+        | Node::SClass(SClass { body, .. }) => enclosing_method(body.as_deref()?, begin, end),
+        _ => None,
+    }
+}
+
+// The class or module starting between the offsets, nested ones included
+fn definition_on(node: &Node, begin: usize, end: usize) -> Option<&Node> {
+    let loc = node.expression();
+
+    match node {
+        Node::Class(_) | Node::Module(_) if loc.begin >= begin && loc.begin <= end => Some(node),
+        _ if loc.begin > begin || loc.end < end => None,
+        Node::Begin(Begin { statements, .. }) => statements
+            .iter()
+            .find_map(|statement| definition_on(statement, begin, end)),
+        Node::Class(Class { body, .. }) | Node::Module(Module { body, .. }) => {
+            definition_on(body.as_deref()?, begin, end)
+        }
+        _ => None,
+    }
+}
+
+fn statements(body: Option<&Node>) -> Vec<&Node> {
+    match body {
+        Some(Node::Begin(Begin { statements, .. })) => statements.iter().collect(),
+        Some(node) => vec![node],
+        None => vec![],
+    }
+}
+
+// Read as words, a string mentioning one counts too
+fn mentions_context(source: &str) -> bool {
+    source
+        .split(|char: char| !(char.is_alphanumeric() || char == '_'))
+        .any(|word| CONTEXT_KEYWORDS.contains(&word))
+}
+
+// The whitespace before an offset on its line, None when there's code too
+fn indentation(text: &str, offset: usize) -> Option<&str> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let prefix = &text[line_start..offset];
+
+    if prefix.chars().all(|char| char == ' ' || char == '\t') {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CHECKOUT: &str = "class Checkout
+  def call(order)
+    total = order.total
+    if total > 100
+      total -= 10
+    end
+    receipt(total)
+  end
+end
+";
+
+    fn selection(start_line: u32, end_line: u32, end_character: u32) -> Range {
+        Range::new(
+            Position::new(start_line, 2),
+            Position::new(end_line, end_character),
+        )
+    }
+
+    #[test]
+    fn extracts_the_statements_on_the_selected_lines() {
+        let extraction = extraction(CHECKOUT, selection(3, 5, 1)).unwrap();
+
+        assert_eq!(
+            extraction.range,
+            Range::new(Position::new(3, 4), Position::new(5, 7))
+        );
+        assert_eq!(
+            extraction.body,
+            vec!["if total > 100", "  total -= 10", "end"]
+        );
+        assert_eq!(extraction.method_indent, "  ");
+        assert_eq!(extraction.method_lines, 1..=7);
+        assert_eq!(extraction.method_end, Position::new(7, 5));
+        assert!(!extraction.singleton);
+    }
+
+    #[test]
+    fn leaves_out_statements_cut_partway_or_outside_a_method() {
+        // Into the `if`
+        assert!(extraction(CHECKOUT, selection(2, 4, 6)).is_none());
+        // The whole method
+        assert!(extraction(CHECKOUT, selection(1, 7, 5)).is_none());
+        assert!(extraction("total = 1\nputs total\n", selection(0, 1, 10)).is_none());
+    }
+
+    #[test]
+    fn inlines_locals_assigned_simple_values() {
+        let total = inlining(CHECKOUT, Position::new(2, 6)).unwrap();
+
+        assert_eq!(total.name, "total");
+        assert_eq!(total.value, "order.total");
+        assert_eq!(
+            total.assignment,
+            Range::new(Position::new(2, 0), Position::new(3, 0))
+        );
+
+        let text =
+            "def call\n  total = sum(prices)\n  ready = !paid?\n  receipt(total, ready)\nend\n";
+
+        assert!(inlining(text, Position::new(1, 2)).is_none());
+        assert!(inlining(text, Position::new(2, 2)).is_none());
+        // Not on the local's name
+        assert!(inlining(CHECKOUT, Position::new(2, 14)).is_none());
+    }
+
+    #[test]
+    fn finds_the_end_of_class_bodies() {
+        let checkout = body_end(CHECKOUT, 0).unwrap();
+
+        assert_eq!(checkout.position, Position::new(8, 0));
+        assert_eq!(checkout.indent, "  ");
+        assert!(!checkout.empty);
+
+        let text = "module Shop\n  class Refund\n  end\nend\n";
+        let nested = body_end(text, 1).unwrap();
+
+        assert_eq!(nested.position, Position::new(2, 0));
+        assert_eq!(nested.indent, "    ");
+        assert!(nested.empty);
+        // Not where a class starts
+        assert!(body_end(CHECKOUT, 1).is_none());
+    }
+
+    #[test]
+    fn leaves_out_statements_that_jump_out_of_the_method() {
+        let text = "def each_total\n  return if empty?\n  yield total\nend\n";
+
+        assert!(extraction(text, selection(1, 1, 16)).is_none());
+        assert!(extraction(text, selection(2, 2, 13)).is_none());
+    }
+}
@@ -0,0 +1,548 @@
+use phf::phf_map;
+use tantivy::query::{BooleanQuery, Occur, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Term};
+
+// Builds the queries every request looking a symbol up shares, whether for
+// its definitions or all of its occurrences, so they match the same
+// documents the same way.
+
+// The node types of the definitions a usage can refer to
+pub static USAGE_TYPE_RESTRICTIONS: phf::Map<&'static str, &[&str]> = phf_map! {
+    "Alias" => &[
+        "Alias", "Def", "Defs",
+        "CSend", "Send", "Super", "ZSuper",
+    ],
+    "Const" => &[
+        "Casgn", "Class", "Module",
+        "Const"
+    ],
+    "CSend" => &[
+        "Alias", "Def", "Defs",
+        "CSend", "Send", "Super", "ZSuper",
+    ],
+    "Cvar" => &[
+        "Cvasgn",
+        "Cvar"
+    ],
+    "Gvar" => &[
+        "Gvasgn",
+        "Gvar"
+    ],
+    "Ivar" => &[
+        "Ivasgn",
+        "Ivar"
+    ],
+    "Lvar" => &[
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg",
+        "Lvar", "Yield"
+    ],
+    "Send" => &[
+        "Alias", "Def", "Defs",
+        "CSend", "Send", "Super", "ZSuper",
+    ],
+    // Rails routes, to controller actions
+    "Route" => &[
+        "Def"
+    ],
+    // Symbols given to Rails callbacks, to the methods they call
+    "Callback" => &[
+        "Def"
+    ],
+    // `render` calls, to the partials they render
+    "Partial" => &[
+        "Partial"
+    ],
+    // FactoryBot `create(:user, :with_posts)` calls, to the factories and
+    // traits they build with
+    "Factory" => &[
+        "Factory"
+    ],
+    "Trait" => &[
+        "Trait"
+    ],
+    // Translation lookups, to locale file keys
+    "I18nKey" => &[
+        "I18nKey"
+    ],
+    "Super" => &[
+        "Alias", "Def", "Defs",
+        "CSend", "Send", "Super", "ZSuper",
+    ],
+    "ZSuper" => &[
+        "Alias", "Def", "Defs",
+        "CSend", "Send", "Super", "ZSuper",
+    ],
+    "ForwardedArgs" => &[
+        "ForwardArg",
+        "ForwardedArgs"
+    ],
+    "Yield" => &[
+        "Blockarg", "Def", "Defs",
+        "Lvar", "Yield"
+    ],
+    "BackRef" => &[
+        "RegexpMatch",
+        "BackRef", "NthRef"
+    ],
+    "NthRef" => &[
+        "RegexpMatch",
+        "BackRef", "NthRef"
+    ],
+};
+
+// The node types of the usages that can refer to a definition
+pub static ASSIGNMENT_TYPE_RESTRICTIONS: phf::Map<&'static str, &[&str]> = phf_map! {
+    "Alias" => &[
+        "Alias", "CSend", "Send", "Super", "ZSuper",
+        "Def", "Defs"
+    ],
+    "Arg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Blockarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Casgn" => &[
+        "Const",
+        "Casgn", "Class", "Module"
+    ],
+    "Class" => &[
+        "Const",
+        "Casgn", "Class", "Module"
+    ],
+    "Cvasgn" => &[
+        "Cvar",
+        "Cvasgn"
+    ],
+    "Def" => &[
+        "Alias", "Callback", "CSend", "Send", "Super", "ZSuper",
+        "Def"
+    ],
+    "Defs" => &[
+        "Alias", "CSend", "Send", "Super", "ZSuper",
+        "Defs"
+    ],
+    "ForwardArg" => &[
+        "ForwardedArgs",
+        "ForwardArg"
+    ],
+    "RegexpMatch" => &[
+        "BackRef", "NthRef",
+        "RegexpMatch"
+    ],
+    "Gvasgn" => &[
+        "Gvar",
+        "Gvasgn"
+    ],
+    "Ivasgn" => &[
+        "Ivar",
+        "Ivasgn"
+    ],
+    "Kwarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Kwoptarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Kwrestarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Lvasgn" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "MatchVar" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Module" => &[
+        "Const",
+        "Casgn", "Class", "Module"
+    ],
+    "Optarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Restarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+    "Shadowarg" => &[
+        "Lvar", "Yield",
+        "Arg", "Blockarg", "Kwarg", "Kwoptarg", "Kwrestarg", "Lvasgn", "MatchVar", "Optarg", "Restarg", "Shadowarg"
+    ],
+};
+
+// Node types scoped to the method or block they're in, whose occurrences
+// must share every scope
+static LOCAL_TYPES: &[&str] = &[
+    "Arg",
+    "Blockarg",
+    "ForwardArg",
+    "ForwardedArgs",
+    "Kwarg",
+    "Kwoptarg",
+    "Kwrestarg",
+    "Lvar",
+    "Lvasgn",
+    "MatchVar",
+    "Optarg",
+    "Restarg",
+    "Shadowarg",
+    "Yield",
+];
+
+fn is_local(node_type: &str) -> bool {
+    LOCAL_TYPES.contains(&node_type)
+}
+
+// Every node type an occurrence of the same symbol can have, definitions and
+// usages both
+pub fn occurrence_types(node_type: &str) -> Vec<&'static str> {
+    let mut occurrence_types = vec![];
+
+    for restrictions in [&USAGE_TYPE_RESTRICTIONS, &ASSIGNMENT_TYPE_RESTRICTIONS] {
+        for occurrence_type in restrictions.get(node_type).copied().unwrap_or_default() {
+            if !occurrence_types.contains(occurrence_type) {
+                occurrence_types.push(*occurrence_type);
+            }
+        }
+    }
+
+    occurrence_types
+}
+
+// The fields symbols are looked up by
+pub struct SymbolFields {
+    pub category: Field,
+    pub name: Field,
+    pub node_type: Field,
+    pub fuzzy_ruby_scope: Field,
+    pub file_path_id: Field,
+}
+
+// How a symbol's scope names narrow the documents matched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScopeStrategy {
+    // Every scope name must match, for locals and lookups within a class
+    Exact,
+    // Documents sharing more scope names rank first, none are left out
+    Ranked,
+}
+
+// Locals only occur in their own method or block, anything else can be
+// anywhere
+pub fn scope_strategy(node_type: &str) -> ScopeStrategy {
+    if is_local(node_type) {
+        ScopeStrategy::Exact
+    } else {
+        ScopeStrategy::Ranked
+    }
+}
+
+// A lookup of a symbol's definitions or occurrences by name:
+//
+//   SymbolQuery::named("save")
+//       .category("assignment")
+//       .node_types(&["Def", "Defs"])
+//       .scope(["User"], ScopeStrategy::Exact)
+//       .build(&fields)
+#[derive(Clone, Debug)]
+pub struct SymbolQuery<'a> {
+    name: &'a str,
+    category: Option<&'a str>,
+    node_types: Vec<&'a str>,
+    scope_names: Vec<(&'a str, ScopeStrategy)>,
+    file_path_id: Option<&'a str>,
+}
+
+impl<'a> SymbolQuery<'a> {
+    pub fn named(name: &'a str) -> Self {
+        SymbolQuery {
+            name,
+            category: None,
+            node_types: vec![],
+            scope_names: vec![],
+            file_path_id: None,
+        }
+    }
+
+    // "assignment", "usage", and so on, any category when not given
+    pub fn category(mut self, category: &'a str) -> Self {
+        self.category = Some(category);
+        self
+    }
+
+    // Any of the node types, replacing ones given before. Any node type
+    // when none are given.
+    pub fn node_types(mut self, node_types: &[&'a str]) -> Self {
+        self.node_types = node_types.to_vec();
+        self
+    }
+
+    // Adds to the scope names given before, each matched by its own strategy
+    pub fn scope(
+        mut self,
+        scope_names: impl IntoIterator<Item = &'a str>,
+        strategy: ScopeStrategy,
+    ) -> Self {
+        for scope_name in scope_names {
+            self.scope_names.push((scope_name, strategy));
+        }
+
+        self
+    }
+
+    // Only documents from one file
+    pub fn file(mut self, file_path_id: &'a str) -> Self {
+        self.file_path_id = Some(file_path_id);
+        self
+    }
+
+    pub fn build(&self, fields: &SymbolFields) -> BooleanQuery {
+        let mut queries = vec![(Occur::Must, text_query(fields.name, self.name))];
+
+        if let Some(category) = self.category {
+            queries.push((Occur::Must, text_query(fields.category, category)));
+        }
+
+        if !self.node_types.is_empty() {
+            let node_type_queries = self
+                .node_types
+                .iter()
+                .map(|node_type| (Occur::Should, text_query(fields.node_type, node_type)))
+                .collect();
+
+            queries.push((Occur::Must, Box::new(BooleanQuery::new(node_type_queries))));
+        }
+
+        for (scope_name, strategy) in &self.scope_names {
+            let occur = match strategy {
+                ScopeStrategy::Exact => Occur::Must,
+                ScopeStrategy::Ranked => Occur::Should,
+            };
+
+            queries.push((occur, text_query(fields.fuzzy_ruby_scope, scope_name)));
+        }
+
+        if let Some(file_path_id) = self.file_path_id {
+            queries.push((Occur::Must, text_query(fields.file_path_id, file_path_id)));
+        }
+
+        BooleanQuery::new(queries)
+    }
+}
+
+fn text_query(field: Field, text: &str) -> Box<dyn Query> {
+    Box::new(TermQuery::new(
+        Term::from_field_text(field, text),
+        IndexRecordOption::Basic,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::collector::TopDocs;
+    use tantivy::schema::{Schema, STORED, STRING};
+    use tantivy::{doc, Index, Searcher};
+
+    struct TestIndex {
+        searcher: Searcher,
+        fields: SymbolFields,
+        line: Field,
+    }
+
+    impl TestIndex {
+        // (file, category, name, node type, scope names), one per line
+        fn new(documents: &[(&str, &str, &str, &str, &[&str])]) -> Self {
+            let mut schema_builder = Schema::builder();
+            let fields = SymbolFields {
+                category: schema_builder.add_text_field("category", STRING),
+                name: schema_builder.add_text_field("name", STRING),
+                node_type: schema_builder.add_text_field("node_type", STRING),
+                fuzzy_ruby_scope: schema_builder.add_text_field("fuzzy_ruby_scope", STRING),
+                file_path_id: schema_builder.add_text_field("file_path_id", STRING),
+            };
+            let line = schema_builder.add_u64_field("line", STORED);
+            let index = Index::create_in_ram(schema_builder.build());
+            let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+
+            for (line_number, (file, category, name, node_type, scope_names)) in
+                documents.iter().enumerate()
+            {
+                let mut document = doc!(
+                    fields.file_path_id => *file,
+                    fields.category => *category,
+                    fields.name => *name,
+                    fields.node_type => *node_type,
+                    line => line_number as u64,
+                );
+
+                for scope_name in scope_names.iter() {
+                    document.add_text(fields.fuzzy_ruby_scope, scope_name);
+                }
+
+                index_writer.add_document(document).unwrap();
+            }
+
+            index_writer.commit().unwrap();
+
+            TestIndex {
+                searcher: index.reader().unwrap().searcher(),
+                fields,
+                line,
+            }
+        }
+
+        // The lines of the documents matched, best first
+        fn lines(&self, symbol_query: SymbolQuery) -> Vec<u64> {
+            let query = symbol_query.build(&self.fields);
+
+            self.searcher
+                .search(&query, &TopDocs::with_limit(10))
+                .unwrap()
+                .into_iter()
+                .map(|(_score, doc_address)| {
+                    let document = self.searcher.doc(doc_address).unwrap();
+
+                    document.get_first(self.line).unwrap().as_u64().unwrap()
+                })
+                .collect()
+        }
+    }
+
+    fn sorted(mut lines: Vec<u64>) -> Vec<u64> {
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn matches_by_name_category_and_node_type() {
+        let index = TestIndex::new(&[
+            ("user.rb", "assignment", "save", "Def", &["User"]),
+            ("user.rb", "usage", "save", "Send", &["User", "create"]),
+            (
+                "user.rb",
+                "assignment",
+                "save",
+                "Lvasgn",
+                &["User", "create"],
+            ),
+            ("user.rb", "assignment", "name", "Def", &["User"]),
+        ]);
+
+        assert_eq!(
+            sorted(index.lines(SymbolQuery::named("save"))),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            index.lines(
+                SymbolQuery::named("save")
+                    .category("assignment")
+                    .node_types(&["Def", "Defs"])
+            ),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn requires_exact_scopes() {
+        let index = TestIndex::new(&[
+            (
+                "user.rb",
+                "assignment",
+                "total",
+                "Lvasgn",
+                &["User", "create"],
+            ),
+            (
+                "user.rb",
+                "assignment",
+                "total",
+                "Lvasgn",
+                &["User", "update"],
+            ),
+            (
+                "order.rb",
+                "assignment",
+                "total",
+                "Lvasgn",
+                &["Order", "create"],
+            ),
+        ]);
+
+        let symbol_query =
+            SymbolQuery::named("total").scope(["User", "create"], ScopeStrategy::Exact);
+
+        assert_eq!(index.lines(symbol_query), vec![0]);
+    }
+
+    #[test]
+    fn ranks_shared_scopes_first() {
+        let index = TestIndex::new(&[
+            ("order.rb", "assignment", "save", "Def", &["Order"]),
+            ("user.rb", "assignment", "save", "Def", &["User"]),
+        ]);
+
+        let symbol_query = SymbolQuery::named("save").scope(["User"], ScopeStrategy::Ranked);
+
+        assert_eq!(index.lines(symbol_query), vec![1, 0]);
+    }
+
+    #[test]
+    fn combines_scope_strategies() {
+        let index = TestIndex::new(&[
+            (
+                "admin.rb",
+                "assignment",
+                "User",
+                "Class",
+                &["Admin", "Legacy"],
+            ),
+            ("admin.rb", "assignment", "User", "Class", &["Admin"]),
+            ("user.rb", "assignment", "User", "Class", &[]),
+        ]);
+
+        let symbol_query = SymbolQuery::named("User")
+            .scope(["Legacy"], ScopeStrategy::Ranked)
+            .scope(["Admin"], ScopeStrategy::Exact);
+
+        assert_eq!(index.lines(symbol_query), vec![0, 1]);
+    }
+
+    #[test]
+    fn filters_by_file() {
+        let index = TestIndex::new(&[
+            ("user.rb", "usage", "save", "Send", &[]),
+            ("order.rb", "usage", "save", "Send", &[]),
+        ]);
+
+        assert_eq!(
+            index.lines(SymbolQuery::named("save").file("order.rb")),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn scopes_locals_exactly() {
+        assert_eq!(scope_strategy("Lvar"), ScopeStrategy::Exact);
+        assert_eq!(scope_strategy("Kwarg"), ScopeStrategy::Exact);
+        assert_eq!(scope_strategy("Send"), ScopeStrategy::Ranked);
+        assert_eq!(scope_strategy("Const"), ScopeStrategy::Ranked);
+    }
+
+    #[test]
+    fn finds_occurrence_types_of_both_kinds() {
+        let occurrence_types = occurrence_types("Lvar");
+
+        assert!(occurrence_types.contains(&"Lvasgn"));
+        assert!(occurrence_types.contains(&"Lvar"));
+        assert!(!occurrence_types.contains(&"Send"));
+    }
+}
@@ -0,0 +1,157 @@
+use tower_lsp::lsp_types::{DocumentHighlight, DocumentHighlightKind, Location, Position};
+
+// Post-processing for the locations and highlights requests answer with.
+// A symbol can be found through more than one document, like a module
+// function indexed as both `Def` and `Defs` or an op-assignment indexed as
+// a read and a write, and the index's own order varies between segments.
+
+// Keeps the first of each location, for results already ranked
+pub fn dedupe_locations(locations: &mut Vec<Location>) {
+    let mut kept: Vec<Location> = Vec::with_capacity(locations.len());
+
+    for location in locations.drain(..) {
+        if !kept.contains(&location) {
+            kept.push(location);
+        }
+    }
+
+    *locations = kept;
+}
+
+// Orders by path, then by where the range starts and ends, without
+// duplicates, for results that aren't ranked
+pub fn sort_locations(locations: &mut Vec<Location>) {
+    locations.sort_by(|a, b| location_key(a).cmp(&location_key(b)));
+    locations.dedup();
+}
+
+// Highlights in document order, with overlapping ones merged into one that
+// covers both. Writes are shown over reads, and reads over plain text.
+pub fn merge_highlights(mut highlights: Vec<DocumentHighlight>) -> Vec<DocumentHighlight> {
+    highlights.sort_by_key(|highlight| (highlight.range.start, highlight.range.end));
+
+    let mut merged: Vec<DocumentHighlight> = Vec::with_capacity(highlights.len());
+
+    for highlight in highlights {
+        if let Some(previous) = merged.last_mut() {
+            let overlaps =
+                highlight.range == previous.range || highlight.range.start < previous.range.end;
+
+            if overlaps {
+                previous.range.end = previous.range.end.max(highlight.range.end);
+
+                if kind_precedence(highlight.kind) > kind_precedence(previous.kind) {
+                    previous.kind = highlight.kind;
+                }
+
+                continue;
+            }
+        }
+
+        merged.push(highlight);
+    }
+
+    merged
+}
+
+fn location_key(location: &Location) -> (&str, Position, Position) {
+    (
+        location.uri.as_str(),
+        location.range.start,
+        location.range.end,
+    )
+}
+
+fn kind_precedence(kind: Option<DocumentHighlightKind>) -> u8 {
+    match kind {
+        Some(DocumentHighlightKind::WRITE) => 3,
+        Some(DocumentHighlightKind::READ) => 2,
+        Some(_) => 1,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Range, Url};
+
+    fn location(path: &str, line: u32, start: u32, end: u32) -> Location {
+        Location::new(
+            Url::parse(&format!("file:///app/{}", path)).unwrap(),
+            Range::new(Position::new(line, start), Position::new(line, end)),
+        )
+    }
+
+    fn highlight(
+        line: u32,
+        start: u32,
+        end: u32,
+        kind: DocumentHighlightKind,
+    ) -> DocumentHighlight {
+        DocumentHighlight {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            kind: Some(kind),
+        }
+    }
+
+    #[test]
+    fn sorts_by_path_then_line_without_duplicates() {
+        let mut locations = vec![
+            location("user.rb", 8, 2, 6),
+            location("account.rb", 12, 4, 8),
+            location("user.rb", 3, 2, 6),
+            location("account.rb", 12, 4, 8),
+        ];
+
+        sort_locations(&mut locations);
+
+        assert_eq!(
+            locations,
+            vec![
+                location("account.rb", 12, 4, 8),
+                location("user.rb", 3, 2, 6),
+                location("user.rb", 8, 2, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn dedupes_keeping_the_ranked_order() {
+        let mut locations = vec![
+            location("user.rb", 8, 2, 6),
+            location("account.rb", 12, 4, 8),
+            location("user.rb", 8, 2, 6),
+        ];
+
+        dedupe_locations(&mut locations);
+
+        assert_eq!(
+            locations,
+            vec![
+                location("user.rb", 8, 2, 6),
+                location("account.rb", 12, 4, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_highlights() {
+        let highlights = vec![
+            highlight(4, 0, 5, DocumentHighlightKind::READ),
+            highlight(1, 2, 7, DocumentHighlightKind::TEXT),
+            highlight(4, 0, 5, DocumentHighlightKind::WRITE),
+            highlight(4, 3, 9, DocumentHighlightKind::READ),
+            highlight(4, 9, 12, DocumentHighlightKind::READ),
+        ];
+
+        assert_eq!(
+            merge_highlights(highlights),
+            vec![
+                highlight(1, 2, 7, DocumentHighlightKind::TEXT),
+                highlight(4, 0, 9, DocumentHighlightKind::WRITE),
+                highlight(4, 9, 12, DocumentHighlightKind::READ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,586 @@
+use crate::autoload;
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// Rails' routing DSL, read from the block passed to `routes.draw` in
+// `config/routes.rb`. Each route is dispatched to a controller action, named
+// outright or by convention:
+//
+//   root "pages#home"                      PagesController#home
+//   get "about", to: "pages#about"         PagesController#about
+//   resources :users, only: [:show]        UsersController#show
+//   namespace :admin do
+//     resource :profile                    Admin::ProfilesController#show, ...
+//   end
+//
+// Routes are indexed as usages of their actions, so going to the definition
+// of one opens the controller method.
+
+// The verbs routes are defined with, and what each is listed as
+static VERBS: &[(&str, &str)] = &[
+    ("get", "GET"),
+    ("post", "POST"),
+    ("put", "PUT"),
+    ("patch", "PATCH"),
+    ("delete", "DELETE"),
+    ("match", "MATCH"),
+    ("root", "GET"),
+];
+
+// A plural resource's actions, in the order Rails defines them, with the
+// verb and the path after the resource's own
+static RESOURCES_ACTIONS: &[(&str, &str, &str)] = &[
+    ("index", "GET", ""),
+    ("create", "POST", ""),
+    ("new", "GET", "/new"),
+    ("edit", "GET", "/:id/edit"),
+    ("show", "GET", "/:id"),
+    ("update", "PATCH", "/:id"),
+    ("destroy", "DELETE", "/:id"),
+];
+
+// A singular resource's, which has no index and no `:id`
+static RESOURCE_ACTIONS: &[(&str, &str, &str)] = &[
+    ("create", "POST", ""),
+    ("new", "GET", "/new"),
+    ("edit", "GET", "/edit"),
+    ("show", "GET", ""),
+    ("update", "PATCH", ""),
+    ("destroy", "DELETE", ""),
+];
+
+// Where the routes being read are nested
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteScope {
+    // Controller modules from `namespace` and `scope module:`
+    modules: Vec<String>,
+    // The URL path routes are under
+    path: String,
+    // From `controller`, or the resource whose block this is, with its
+    // modules: `admin/users`
+    controller: Option<String>,
+    // In a resource's block, where its `member` and `collection` routes are
+    member_path: Option<String>,
+    collection_path: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Route<'a> {
+    pub verb: &'static str,
+    pub path: String,
+    // `admin/users`, as in `to: "admin/users#show"`
+    pub controller: String,
+    pub action: String,
+    // What the route is written as, the `to:` string or resource name
+    pub loc: &'a Loc,
+}
+
+impl<'a> Route<'a> {
+    // `admin/users` is `["Admin", "UsersController"]`
+    pub fn controller_path(&self) -> Vec<String> {
+        let mut controller_path: Vec<String> =
+            self.controller.split('/').map(autoload::camelize).collect();
+
+        if let Some(controller_name) = controller_path.last_mut() {
+            controller_name.push_str("Controller");
+        }
+
+        controller_path
+    }
+
+    // `GET /users/:id`
+    pub fn description(&self) -> String {
+        format!("{} {}", self.verb, self.path)
+    }
+}
+
+// `Rails.application.routes.draw`, or an engine's `Engine.routes.draw`
+pub fn is_draw_block(call: &Node) -> bool {
+    match call {
+        Node::Send(Send {
+            recv: Some(recv),
+            method_name,
+            ..
+        }) if method_name == "draw" => matches!(
+            recv.as_ref(),
+            Node::Send(Send { method_name, .. }) if method_name == "routes"
+        ),
+        _ => false,
+    }
+}
+
+// The scope of the routes in a block passed to `call`. Blocks that don't
+// nest routes, like `constraints`, keep the scope they're in.
+pub fn nested_scope(scope: &RouteScope, call: &Node) -> RouteScope {
+    let mut nested_scope = scope.clone();
+
+    let (method_name, args) = match call {
+        Node::Send(Send {
+            recv: None,
+            method_name,
+            args,
+            ..
+        }) => (method_name.as_str(), args),
+        _ => return nested_scope,
+    };
+
+    match method_name {
+        "namespace" => {
+            if let Some((name, _)) = args.first().and_then(name_argument) {
+                nested_scope.path = join_path(&scope.path, &name);
+                nested_scope.modules.push(name);
+                nested_scope.controller = None;
+            }
+        }
+        "scope" => {
+            if let Some((module, _)) = option(args, "module").and_then(name_argument) {
+                nested_scope.modules.push(module);
+            }
+
+            let path = option(args, "path").or_else(|| args.first());
+
+            if let Some((path, _)) = path.and_then(name_argument) {
+                nested_scope.path = join_path(&scope.path, &path);
+            }
+
+            if let Some((controller, _)) = option(args, "controller").and_then(name_argument) {
+                nested_scope.controller = Some(scope.qualify(&controller));
+            }
+        }
+        "controller" => {
+            if let Some((controller, _)) = args.first().and_then(name_argument) {
+                nested_scope.controller = Some(scope.qualify(&controller));
+            }
+        }
+        "resources" | "resource" => {
+            let (name, _) = match args.first().and_then(name_argument) {
+                Some(name) => name,
+                None => return nested_scope,
+            };
+
+            let resource_path = join_path(&scope.path, &name);
+
+            if method_name == "resources" {
+                nested_scope.path =
+                    format!("{}/:{}_id", resource_path, autoload::singularize(&name));
+                nested_scope.member_path = Some(format!("{}/:id", resource_path));
+                nested_scope.collection_path = Some(resource_path);
+            } else {
+                nested_scope.path = resource_path.clone();
+                nested_scope.member_path = Some(resource_path.clone());
+                nested_scope.collection_path = Some(resource_path);
+            }
+
+            nested_scope.controller = Some(scope.resource_controller(method_name, &name, args));
+        }
+        "member" => {
+            if let Some(member_path) = &scope.member_path {
+                nested_scope.path = member_path.clone();
+            }
+        }
+        "collection" => {
+            if let Some(collection_path) = &scope.collection_path {
+                nested_scope.path = collection_path.clone();
+            }
+        }
+        _ => {}
+    }
+
+    nested_scope
+}
+
+// The routes a call in the draw block defines
+pub fn routes<'a>(scope: &RouteScope, method_name: &str, args: &'a [Node]) -> Vec<Route<'a>> {
+    match method_name {
+        "resources" | "resource" => scope.resource_routes(method_name, args),
+        _ => match VERBS.iter().find(|(name, _verb)| *name == method_name) {
+            Some((_name, verb)) => scope
+                .verb_route(method_name, *verb, args)
+                .into_iter()
+                .collect(),
+            None => vec![],
+        },
+    }
+}
+
+impl RouteScope {
+    // A controller named in a route, under the modules it's in
+    fn qualify(&self, controller: &str) -> String {
+        let mut parts = self.modules.clone();
+        parts.push(controller.trim_start_matches('/').to_string());
+
+        parts.join("/")
+    }
+
+    fn resource_controller(&self, method_name: &str, name: &str, args: &[Node]) -> String {
+        match option(args, "controller").and_then(name_argument) {
+            Some((controller, _)) => self.qualify(&controller),
+            None if method_name == "resource" => self.qualify(&autoload::pluralize(name)),
+            None => self.qualify(name),
+        }
+    }
+
+    // `resources :users, :posts, only: [:index, :show]`
+    fn resource_routes<'a>(&self, method_name: &str, args: &'a [Node]) -> Vec<Route<'a>> {
+        let actions = if method_name == "resources" {
+            RESOURCES_ACTIONS
+        } else {
+            RESOURCE_ACTIONS
+        };
+
+        let only = option(args, "only").map(name_list);
+        let except = option(args, "except").map(name_list).unwrap_or_default();
+
+        let mut routes = vec![];
+
+        for (name, loc) in args.iter().filter_map(name_argument) {
+            let controller = self.resource_controller(method_name, &name, args);
+            let resource_path = join_path(&self.path, &name);
+
+            for (action, verb, action_path) in actions {
+                let included = only.as_ref().map_or(true, |only| {
+                    only.iter().any(|only_action| only_action == action)
+                }) && !except.iter().any(|except_action| except_action == action);
+
+                if !included {
+                    continue;
+                }
+
+                routes.push(Route {
+                    verb: *verb,
+                    path: format!("{}{}", resource_path, action_path),
+                    controller: controller.clone(),
+                    action: action.to_string(),
+                    loc,
+                });
+            }
+        }
+
+        routes
+    }
+
+    // One route to one action:
+    //
+    //   get "about", to: "pages#about"
+    //   get "about" => "pages#about"
+    //   get "about", controller: "pages", action: :about
+    //   get :preview                          in a resource's or controller's block
+    //   get "pages/about"
+    fn verb_route<'a>(
+        &self,
+        method_name: &str,
+        verb: &'static str,
+        args: &'a [Node],
+    ) -> Option<Route<'a>> {
+        let pairs = match args.last() {
+            Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs.as_slice(),
+            _ => &[],
+        };
+
+        // `get "about" => "pages#about"`, the path is the first pair's key
+        let path_to = pairs.first().and_then(|pair| match pair {
+            Node::Pair(Pair { key, value, .. }) if matches!(key.as_ref(), Node::Str(_)) => {
+                Some((key.as_ref(), value.as_ref()))
+            }
+            _ => None,
+        });
+
+        let path_node = match path_to {
+            Some((key, _value)) => Some(key),
+            None => args.first().filter(|arg| !matches!(arg, Node::Kwargs(_))),
+        };
+        let path = match method_name {
+            "root" => String::new(),
+            _ => path_node
+                .and_then(name_argument)
+                .map(|(path, _)| path)
+                .unwrap_or_default(),
+        };
+        let full_path = match path.as_str() {
+            "" if method_name == "root" => join_path(&self.path, "/"),
+            path => join_path(&self.path, path),
+        };
+
+        // `root "pages#home"` takes the action as its path argument
+        let to = option(args, "to")
+            .or(path_to.map(|(_key, value)| value))
+            .or_else(|| args.first().filter(|_| method_name == "root"));
+
+        // Anything but a `"controller#action"` string, like a `redirect`, isn't
+        // dispatched to an action
+        if let Some(to) = to {
+            let (to, loc) = name_argument(to)?;
+            let (controller, action) = to.split_once('#')?;
+            let controller = match controller {
+                "" => self.controller.clone()?,
+                controller => self.qualify(controller),
+            };
+
+            return Some(Route {
+                verb,
+                path: full_path,
+                controller,
+                action: action.to_string(),
+                loc,
+            });
+        }
+
+        if let Some((action, loc)) = option(args, "action").and_then(name_argument) {
+            let controller = match option(args, "controller").and_then(name_argument) {
+                Some((controller, _)) => self.qualify(&controller),
+                None => self.controller.clone()?,
+            };
+
+            return Some(Route {
+                verb,
+                path: full_path,
+                controller,
+                action,
+                loc,
+            });
+        }
+
+        let (_, loc) = path_node.and_then(name_argument)?;
+        let path = path.trim_start_matches('/');
+
+        if path.contains([':', '*', '(']) {
+            return None;
+        }
+
+        // The path names the action in a controller's block, and both the
+        // controller and the action outside one
+        let (controller, action) = match (&self.controller, path.rsplit_once('/')) {
+            (Some(controller), None) => (controller.clone(), path.to_string()),
+            (None, Some((controller, action))) => (self.qualify(controller), action.to_string()),
+            _ => return None,
+        };
+
+        Some(Route {
+            verb,
+            path: full_path,
+            controller,
+            action,
+            loc,
+        })
+    }
+}
+
+// A keyword option's value
+fn option<'a>(args: &'a [Node], option_name: &str) -> Option<&'a Node> {
+    let pairs = match args.last() {
+        Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs,
+        _ => return None,
+    };
+
+    pairs.iter().find_map(|pair| match pair {
+        Node::Pair(Pair { key, value, .. }) => match name_argument(key) {
+            Some((key_name, _)) if key_name == option_name => Some(value.as_ref()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// A `:symbol` or `"string"`
+fn name_argument(node: &Node) -> Option<(String, &Loc)> {
+    match node {
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => Some((name.to_string_lossy(), expression_l)),
+        Node::Str(Str {
+            value,
+            expression_l,
+            ..
+        }) => Some((value.to_string_lossy(), expression_l)),
+        _ => None,
+    }
+}
+
+// `[:index, :show]`, or a single `:show`
+fn name_list(node: &Node) -> Vec<String> {
+    match node {
+        Node::Array(Array { elements, .. }) => elements
+            .iter()
+            .filter_map(name_argument)
+            .map(|(name, _)| name)
+            .collect(),
+        node => name_argument(node)
+            .map(|(name, _)| name)
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn join_path(prefix: &str, path: &str) -> String {
+    let path = path.trim_matches('/');
+
+    if path.is_empty() {
+        return if prefix.is_empty() {
+            "/".to_string()
+        } else {
+            prefix.to_string()
+        };
+    }
+
+    format!("{}/{}", prefix, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    // Each route in a routes file, as `VERB /path controller#action`
+    fn drawn_routes(text: &str) -> Vec<String> {
+        let ast = Parser::new(text.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+        let mut drawn_routes = vec![];
+
+        collect_routes(&ast, None, &mut drawn_routes);
+
+        drawn_routes
+    }
+
+    fn collect_routes(node: &Node, scope: Option<&RouteScope>, drawn_routes: &mut Vec<String>) {
+        match node {
+            Node::Begin(Begin { statements, .. }) => {
+                for statement in statements {
+                    collect_routes(statement, scope, drawn_routes);
+                }
+            }
+            Node::Block(Block { call, body, .. }) => {
+                collect_routes(call, scope, drawn_routes);
+
+                let nested_scope = match scope {
+                    Some(scope) => nested_scope(scope, call),
+                    None if is_draw_block(call) => RouteScope::default(),
+                    None => return,
+                };
+
+                if let Some(body) = body {
+                    collect_routes(body, Some(&nested_scope), drawn_routes);
+                }
+            }
+            Node::Send(Send {
+                recv: None,
+                method_name,
+                args,
+                ..
+            }) => {
+                if let Some(scope) = scope {
+                    for route in routes(scope, method_name, args) {
+                        drawn_routes.push(format!(
+                            "{} {}#{}",
+                            route.description(),
+                            route.controller,
+                            route.action
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn reads_routes_to_actions() {
+        let text = r#"
+Rails.application.routes.draw do
+  root "pages#home"
+  get "about", to: "pages#about"
+  get "help" => "pages#help"
+  post "search", controller: "searches", action: :create
+  get "pages/terms"
+  get "health", to: proc { [200, {}, ["ok"]] }
+end
+"#;
+
+        assert_eq!(
+            drawn_routes(text),
+            vec![
+                "GET / pages#home",
+                "GET /about pages#about",
+                "GET /help pages#help",
+                "POST /search searches#create",
+                "GET /pages/terms pages#terms",
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_resources_into_their_actions() {
+        let text = r#"
+Rails.application.routes.draw do
+  resources :users, only: [:index, :show] do
+    member do
+      get :preview
+    end
+
+    resources :posts, except: :destroy
+  end
+
+  resource :company, only: :show
+end
+"#;
+
+        assert_eq!(
+            drawn_routes(text),
+            vec![
+                "GET /users users#index",
+                "GET /users/:id users#show",
+                "GET /users/:id/preview users#preview",
+                "GET /users/:user_id/posts posts#index",
+                "POST /users/:user_id/posts posts#create",
+                "GET /users/:user_id/posts/new posts#new",
+                "GET /users/:user_id/posts/:id/edit posts#edit",
+                "GET /users/:user_id/posts/:id posts#show",
+                "PATCH /users/:user_id/posts/:id posts#update",
+                "GET /company companies#show",
+            ]
+        );
+    }
+
+    #[test]
+    fn nests_controllers_in_namespaces() {
+        let text = r#"
+Rails.application.routes.draw do
+  namespace :admin do
+    resources :orders, only: :index
+    get "stats", to: "reports#stats"
+  end
+
+  scope module: "api", path: "v1" do
+    controller :sessions do
+      delete "logout", action: :destroy
+    end
+  end
+end
+"#;
+
+        assert_eq!(
+            drawn_routes(text),
+            vec![
+                "GET /admin/orders admin/orders#index",
+                "GET /admin/stats admin/reports#stats",
+                "DELETE /v1/logout api/sessions#destroy",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_controllers_as_constants() {
+        let route = Route {
+            verb: "GET",
+            path: "/admin/line_items".to_string(),
+            controller: "admin/line_items".to_string(),
+            action: "index".to_string(),
+            loc: &Loc { begin: 0, end: 0 },
+        };
+
+        assert_eq!(
+            route.controller_path(),
+            vec!["Admin".to_string(), "LineItemsController".to_string()]
+        );
+    }
+}
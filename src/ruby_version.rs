@@ -0,0 +1,170 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tower_lsp::lsp_types::{Diagnostic, NumberOrString};
+
+// The Ruby a project runs on, from the `rubyVersion` setting or detected
+// from the workspace:
+//
+//   .ruby-version    3.1.4, or ruby-3.1.4
+//   Gemfile          ruby "~> 3.1"
+//
+// The parser always reads the latest syntax, so there's no parsing a file
+// the way an older Ruby would. Instead, syntax newer than the project's
+// Ruby is reported where it's used.
+
+pub const RUBY_VERSION_CODE: &str = "ruby-version";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RubyVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl RubyVersion {
+    pub const fn new(major: u32, minor: u32) -> RubyVersion {
+        RubyVersion { major, minor }
+    }
+
+    // Patch levels and requirement operators are ignored, `~> 2.7.1` is 2.7
+    pub fn parse(text: &str) -> Option<RubyVersion> {
+        let version = text
+            .trim()
+            .trim_start_matches("ruby-")
+            .trim_start_matches(|char: char| "~>=< ".contains(char));
+        let mut parts = version.split(|char: char| !char.is_ascii_digit());
+
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().and_then(|part| part.parse().ok()).unwrap_or(0);
+
+        Some(RubyVersion::new(major, minor))
+    }
+
+    pub fn detect(workspace_path: &str) -> Option<RubyVersion> {
+        let workspace_path = Path::new(workspace_path);
+
+        if let Ok(ruby_version) = fs::read_to_string(workspace_path.join(".ruby-version")) {
+            if let Some(version) = RubyVersion::parse(&ruby_version) {
+                return Some(version);
+            }
+        }
+
+        let gemfile = fs::read_to_string(workspace_path.join("Gemfile")).ok()?;
+
+        gemfile.lines().find_map(gemfile_ruby_version)
+    }
+}
+
+impl fmt::Display for RubyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+// Reported for syntax that still parses, so it isn't a syntax error that
+// keeps a file's last clean version indexed
+pub fn is_newer_syntax(diagnostic: &Diagnostic) -> bool {
+    matches!(&diagnostic.code, Some(NumberOrString::String(code)) if code == RUBY_VERSION_CODE)
+}
+
+// Syntax that older Rubies don't parse, and the version that added it
+pub struct SyntaxFeature {
+    pub description: &'static str,
+    pub since: RubyVersion,
+}
+
+pub static ENDLESS_RANGES: SyntaxFeature = SyntaxFeature {
+    description: "endless ranges",
+    since: RubyVersion::new(2, 6),
+};
+
+pub static BEGINLESS_RANGES: SyntaxFeature = SyntaxFeature {
+    description: "beginless ranges",
+    since: RubyVersion::new(2, 7),
+};
+
+pub static NUMBERED_PARAMETERS: SyntaxFeature = SyntaxFeature {
+    description: "numbered block parameters",
+    since: RubyVersion::new(2, 7),
+};
+
+pub static PATTERN_MATCHING: SyntaxFeature = SyntaxFeature {
+    description: "pattern matching",
+    since: RubyVersion::new(2, 7),
+};
+
+pub static ARGUMENT_FORWARDING: SyntaxFeature = SyntaxFeature {
+    description: "argument forwarding with `...`",
+    since: RubyVersion::new(2, 7),
+};
+
+pub static ENDLESS_METHODS: SyntaxFeature = SyntaxFeature {
+    description: "endless method definitions",
+    since: RubyVersion::new(3, 0),
+};
+
+pub static ONE_LINE_PATTERN_MATCHING: SyntaxFeature = SyntaxFeature {
+    description: "one-line pattern matching",
+    since: RubyVersion::new(3, 0),
+};
+
+pub static ANONYMOUS_BLOCK_ARGUMENTS: SyntaxFeature = SyntaxFeature {
+    description: "anonymous block arguments",
+    since: RubyVersion::new(3, 1),
+};
+
+// `ruby "3.1.4"`, `ruby '~> 3.1'`, or `ruby("3.1.4")`. A `ruby file:` line
+// reads `.ruby-version`, which is checked first.
+fn gemfile_ruby_version(line: &str) -> Option<RubyVersion> {
+    let arguments = line.trim_start().strip_prefix("ruby")?;
+
+    if !arguments.starts_with([' ', '(']) {
+        return None;
+    }
+
+    let quoted = arguments.trim_start_matches([' ', '(']);
+    let quote = quoted
+        .chars()
+        .next()
+        .filter(|char| *char == '"' || *char == '\'')?;
+    let version = quoted[1..].split(quote).next()?;
+
+    RubyVersion::parse(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions_and_requirements() {
+        assert_eq!(RubyVersion::parse("3.1.4\n"), Some(RubyVersion::new(3, 1)));
+        assert_eq!(
+            RubyVersion::parse("ruby-2.7.8"),
+            Some(RubyVersion::new(2, 7))
+        );
+        assert_eq!(RubyVersion::parse("~> 3.2"), Some(RubyVersion::new(3, 2)));
+        assert_eq!(RubyVersion::parse(">= 3"), Some(RubyVersion::new(3, 0)));
+        assert_eq!(RubyVersion::parse("jruby-9.4"), None);
+    }
+
+    #[test]
+    fn reads_the_gemfile_ruby_line() {
+        assert_eq!(
+            gemfile_ruby_version(r#"ruby "3.1.4""#),
+            Some(RubyVersion::new(3, 1))
+        );
+        assert_eq!(
+            gemfile_ruby_version("ruby('~> 2.7')"),
+            Some(RubyVersion::new(2, 7))
+        );
+        assert_eq!(gemfile_ruby_version(r#"ruby file: ".ruby-version""#), None);
+        assert_eq!(gemfile_ruby_version(r#"gem "ruby-progressbar""#), None);
+    }
+
+    #[test]
+    fn orders_by_major_then_minor() {
+        assert!(RubyVersion::new(2, 7) < RubyVersion::new(3, 0));
+        assert!(RubyVersion::new(3, 10) > RubyVersion::new(3, 2));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::autoload;
+use lib_ruby_parser::{nodes::*, Loc, Node};
+
+// ActiveRecord's `db/schema.rb`, the database's tables written as Ruby:
+//
+//   ActiveRecord::Schema[7.1].define(version: 2024_01_15_120000) do
+//     create_table "line_items", force: :cascade do |t|
+//       t.string "name", null: false
+//       t.references "order"
+//     end
+//   end
+//
+// Models read and write their table's columns through methods ActiveRecord
+// defines at runtime, `LineItem#name` and `LineItem#name=` here, so they're
+// indexed where the columns are declared.
+
+// Methods in a `create_table` block that don't add a column
+static TABLE_METHODS: &[&str] = &[
+    "check_constraint",
+    "exclusion_constraint",
+    "foreign_key",
+    "index",
+    "unique_constraint",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Column<'a> {
+    // The model the table belongs to by convention, `line_items` is `LineItem`
+    pub model_name: String,
+    pub table_name: String,
+    pub name: String,
+    // `string`, `references`, and so on
+    pub column_type: String,
+    pub loc: &'a Loc,
+}
+
+impl<'a> Column<'a> {
+    // For hovers, "`string` column of `line_items`"
+    pub fn description(&self) -> String {
+        format!("`{}` column of `{}`", self.column_type, self.table_name)
+    }
+}
+
+// `ActiveRecord::Schema.define`, or `ActiveRecord::Schema[7.1].define`
+pub fn is_define_block(call: &Node) -> bool {
+    let recv = match call {
+        Node::Send(Send {
+            recv: Some(recv),
+            method_name,
+            ..
+        }) if method_name == "define" => recv.as_ref(),
+        _ => return false,
+    };
+
+    let schema_const = match recv {
+        Node::Index(Index { recv, .. }) => recv.as_ref(),
+        node => node,
+    };
+
+    matches!(schema_const, Node::Const(Const { name, .. }) if name == "Schema")
+}
+
+// The columns of each table created in the define block's body, the
+// primary key first
+pub fn columns(body: Option<&Node>) -> Vec<Column<'_>> {
+    let mut columns = vec![];
+
+    for statement in statements(body) {
+        let (call, table_body) = match statement {
+            Node::Block(Block { call, body, .. }) => (call.as_ref(), body.as_deref()),
+            _ => continue,
+        };
+
+        let table_args = match call {
+            Node::Send(Send {
+                recv: None,
+                method_name,
+                args,
+                ..
+            }) if method_name == "create_table" => args,
+            _ => continue,
+        };
+
+        let (table_name, table_loc) = match table_args.first().and_then(name_argument) {
+            Some(table_name) => table_name,
+            None => continue,
+        };
+        let model_name = autoload::camelize(&autoload::singularize(&table_name));
+
+        let column = |name: String, column_type: &str, loc| Column {
+            model_name: model_name.clone(),
+            table_name: table_name.clone(),
+            name,
+            column_type: column_type.to_string(),
+            loc,
+        };
+
+        // `id: false` tables have no primary key, and `primary_key:` names it
+        match option(table_args, "id") {
+            Some(Node::False(_)) => {}
+            _ => {
+                let primary_key = option(table_args, "primary_key")
+                    .and_then(name_argument)
+                    .map_or("id".to_string(), |(primary_key, _)| primary_key);
+
+                columns.push(column(primary_key, "primary key", table_loc));
+            }
+        }
+
+        for column_statement in statements(table_body) {
+            let (method_name, args, selector_l) = match column_statement {
+                Node::Send(Send {
+                    recv: Some(_),
+                    method_name,
+                    args,
+                    selector_l,
+                    ..
+                }) => (method_name.as_str(), args, selector_l),
+                _ => continue,
+            };
+
+            if TABLE_METHODS.contains(&method_name) {
+                continue;
+            }
+
+            match method_name {
+                "timestamps" => {
+                    if let Some(loc) = selector_l {
+                        for name in ["created_at", "updated_at"] {
+                            columns.push(column(name.to_string(), "datetime", loc));
+                        }
+                    }
+                }
+                // `t.references "order", polymorphic: true` is `order_id`
+                // and `order_type`
+                "references" | "belongs_to" => {
+                    let polymorphic = matches!(option(args, "polymorphic"), Some(Node::True(_)));
+
+                    for (name, loc) in args.iter().filter_map(name_argument) {
+                        columns.push(column(format!("{}_id", name), method_name, loc));
+
+                        if polymorphic {
+                            columns.push(column(format!("{}_type", name), method_name, loc));
+                        }
+                    }
+                }
+                _ => {
+                    for (name, loc) in args.iter().filter_map(name_argument) {
+                        columns.push(column(name, method_name, loc));
+                    }
+                }
+            }
+        }
+    }
+
+    columns
+}
+
+fn statements(body: Option<&Node>) -> Vec<&Node> {
+    match body {
+        Some(Node::Begin(Begin { statements, .. })) => statements.iter().collect(),
+        Some(node) => vec![node],
+        None => vec![],
+    }
+}
+
+// A keyword option's value
+fn option<'a>(args: &'a [Node], option_name: &str) -> Option<&'a Node> {
+    let pairs = match args.last() {
+        Some(Node::Kwargs(Kwargs { pairs, .. })) => pairs,
+        _ => return None,
+    };
+
+    pairs.iter().find_map(|pair| match pair {
+        Node::Pair(Pair { key, value, .. }) => match name_argument(key) {
+            Some((key_name, _)) if key_name == option_name => Some(value.as_ref()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// A `:symbol` or `"string"`
+fn name_argument(node: &Node) -> Option<(String, &Loc)> {
+    match node {
+        Node::Sym(Sym {
+            name, expression_l, ..
+        }) => Some((name.to_string_lossy(), expression_l)),
+        Node::Str(Str {
+            value,
+            expression_l,
+            ..
+        }) => Some((value.to_string_lossy(), expression_l)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_ruby_parser::{Parser, ParserOptions};
+
+    // Each column in a schema, as `Model#column type`
+    fn schema_columns(text: &str) -> Vec<String> {
+        let ast = Parser::new(text.to_string(), ParserOptions::default())
+            .do_parse()
+            .ast
+            .unwrap();
+
+        let body = match ast.as_ref() {
+            Node::Block(Block { call, body, .. }) if is_define_block(call) => body.as_deref(),
+            _ => panic!("not a schema"),
+        };
+
+        columns(body)
+            .iter()
+            .map(|column| {
+                format!(
+                    "{}#{} {}",
+                    column.model_name, column.name, column.column_type
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_columns_of_each_table() {
+        let text = r#"
+ActiveRecord::Schema[7.1].define(version: 2024_01_15_120000) do
+  create_table "line_items", force: :cascade do |t|
+    t.string "name", null: false
+    t.references "order", null: false
+    t.index ["name"], name: "index_line_items_on_name"
+    t.timestamps
+  end
+
+  create_table "order_statuses", id: false do |t|
+    t.integer "position"
+  end
+end
+"#;
+
+        assert_eq!(
+            schema_columns(text),
+            vec![
+                "LineItem#id primary key",
+                "LineItem#name string",
+                "LineItem#order_id references",
+                "LineItem#created_at datetime",
+                "LineItem#updated_at datetime",
+                "OrderStatus#position integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_older_schemas_and_custom_primary_keys() {
+        let text = r#"
+ActiveRecord::Schema.define(version: 2019_06_01_000000) do
+  create_table "people", primary_key: "uuid" do |t|
+    t.belongs_to :company, polymorphic: true
+  end
+end
+"#;
+
+        assert_eq!(
+            schema_columns(text),
+            vec![
+                "Person#uuid primary key",
+                "Person#company_id belongs_to",
+                "Person#company_type belongs_to",
+            ]
+        );
+    }
+}
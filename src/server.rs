@@ -0,0 +1,976 @@
+use crate::benchmark;
+use crate::cancellation::{CancelOnDrop, CancellationToken};
+use crate::compaction;
+use crate::formatter;
+use crate::indexer::{self, IndexJob};
+use crate::persistence::{BatchQuery, DefinitionLink, DefinitionSuggestions, Persistence};
+use crate::telemetry;
+use crate::test_runner;
+
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tokio::time::*;
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::request::{GotoImplementationParams, GotoImplementationResponse};
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
+
+// Asks the client to run a test command in a terminal
+enum RunTest {}
+
+impl notification::Notification for RunTest {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "fuzzy/runTest";
+}
+
+pub struct Backend {
+    client: Client,
+    // Requests share the read lock, so they only wait on writes that update
+    // the open files or settings
+    persistence: Arc<RwLock<Persistence>>,
+    indexer: UnboundedSender<IndexJob>,
+    watch_editor_process: bool,
+    background_tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    // The newest request of each kind for each document, cancelled when
+    // another replaces it
+    latest_requests: std::sync::Mutex<HashMap<(&'static str, Option<Url>), CancellationToken>>,
+}
+
+// The language server, ready to be served over any transport: stdio, a
+// socket, or an in-memory stream in tests. Servers that watch the editor's
+// process exit when it does.
+pub fn service(watch_editor_process: bool) -> (LspService<Backend>, ClientSocket) {
+    let persistence = Arc::new(RwLock::new(Persistence::new().unwrap()));
+
+    LspService::build(|client| {
+        let (indexer, indexing_task) = indexer::spawn(client.clone(), Arc::clone(&persistence));
+
+        Backend {
+            client,
+            persistence,
+            indexer,
+            watch_editor_process,
+            background_tasks: std::sync::Mutex::new(vec![indexing_task]),
+            latest_requests: std::sync::Mutex::new(HashMap::new()),
+        }
+    })
+    .custom_method("fuzzy/debugAst", Backend::debug_ast)
+    .custom_method("fuzzy/clearCache", Backend::clear_cache)
+    .custom_method("fuzzy/search", Backend::search)
+    .custom_method("fuzzy/peekDefinition", Backend::peek_definition)
+    .custom_method("fuzzy/definitions", Backend::definitions)
+    .custom_method("fuzzy/batch", Backend::batch)
+    .custom_method("fuzzy/routes", Backend::routes)
+    .finish()
+}
+
+impl Backend {
+    // Times definition and highlight queries at positions sampled from the
+    // index, for comparing performance across projects and versions
+    async fn benchmark(&self) -> Result<serde_json::Value> {
+        let persistence = self.persistence.read().await;
+
+        let query_latency = tokio::task::block_in_place(|| {
+            let positions = benchmark::sample_positions(&persistence, benchmark::SAMPLE_SIZE)?;
+
+            benchmark::query_latency(&persistence, &positions)
+        })
+        .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+        let report = query_latency.to_json();
+
+        info!("Benchmark: {}", report);
+
+        Ok(report)
+    }
+
+    // A lookup that answered, when telemetry is turned on
+    async fn record_request(
+        &self,
+        persistence: &Persistence,
+        kind: &'static str,
+        started: Instant,
+        result_count: usize,
+        cancellation: &CancellationToken,
+    ) {
+        if let Some(sink) = &persistence.telemetry {
+            let event = telemetry::Event::Request {
+                kind,
+                latency: started.elapsed(),
+                result_count,
+                partial: cancellation.timed_out(),
+            };
+
+            telemetry::emit(&self.client, sink, event).await;
+        }
+    }
+
+    // Waits for the indexer to get to it, after any edits queued before
+    async fn compact_index(&self) -> Result<serde_json::Value> {
+        let (reply, compaction) = oneshot::channel();
+
+        if self.indexer.send(IndexJob::Compact(reply)).is_err() {
+            return Err(Error::internal_error());
+        }
+
+        match compaction.await {
+            Ok(Some(compaction)) => Ok(compaction.to_json()),
+            Ok(None) => Err(Error::invalid_params(
+                "Unable to compact the index, another window may be writing it",
+            )),
+            Err(_) => Err(Error::internal_error()),
+        }
+    }
+
+    // Results for an earlier cursor position are useless once the cursor
+    // has moved on, so a new request cancels the previous one of its kind in
+    // the same document. Requests in other documents, like a split view's,
+    // are left to answer.
+    fn supersede(&self, request_kind: &'static str, uri: Option<&Url>) -> CancellationToken {
+        let cancellation = CancellationToken::default();
+        let previous = self
+            .latest_requests
+            .lock()
+            .unwrap()
+            .insert((request_kind, uri.cloned()), cancellation.clone());
+
+        if let Some(previous_cancellation) = previous {
+            previous_cancellation.cancel();
+        }
+
+        cancellation
+    }
+
+    // A search that ran out of time answers with what it found, noting in the
+    // log that there may be more. One that was cancelled doesn't answer.
+    async fn finish_search(
+        &self,
+        cancellation: &CancellationToken,
+        request_kind: &str,
+        time_budget: Option<Duration>,
+    ) -> Result<()> {
+        if cancellation.timed_out() {
+            let time_budget_ms = time_budget.map_or(0, |time_budget| time_budget.as_millis());
+
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "{} stopped after {}ms, the results are partial. \
+                         Raise queryTimeBudgetMs to search longer.",
+                        request_kind, time_budget_ms
+                    ),
+                )
+                .await;
+        } else if cancellation.is_cancelled() {
+            return Err(Error::request_cancelled());
+        }
+
+        Ok(())
+    }
+
+    // Offers to jump to similarly named definitions. The request is answered
+    // without waiting on the user's choice, which opens the definition itself.
+    fn suggest_definitions(&self, definition_suggestions: DefinitionSuggestions) {
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            let jump_title = |name: &String| format!("Jump to `{}`", name);
+
+            let actions = definition_suggestions
+                .suggestions
+                .iter()
+                .map(|(name, _location)| MessageActionItem {
+                    title: jump_title(name),
+                    properties: HashMap::new(),
+                })
+                .collect();
+
+            let message = format!(
+                "No definition found for `{}`, did you mean one of these?",
+                definition_suggestions.name
+            );
+
+            let chosen_action = client
+                .show_message_request(MessageType::INFO, message, Some(actions))
+                .await;
+
+            if let Ok(Some(chosen_action)) = chosen_action {
+                let chosen_suggestion = definition_suggestions
+                    .suggestions
+                    .iter()
+                    .find(|(name, _location)| jump_title(name) == chosen_action.title);
+
+                if let Some((_name, location)) = chosen_suggestion {
+                    let _ = client
+                        .show_document(ShowDocumentParams {
+                            uri: location.uri.clone(),
+                            external: None,
+                            take_focus: Some(true),
+                            selection: Some(location.range),
+                        })
+                        .await;
+                }
+            }
+        });
+    }
+
+    async fn debug_ast(&self, params: TextDocumentIdentifier) -> Result<serde_json::Value> {
+        let persistence = self.persistence.read().await;
+
+        match persistence.debug_ast(&params.uri) {
+            Some(nodes) => Ok(nodes),
+            None => Err(Error::invalid_params(format!(
+                "Unable to read {}",
+                params.uri
+            ))),
+        }
+    }
+
+    async fn search(&self, filters: serde_json::Value) -> Result<Vec<serde_json::Value>> {
+        let persistence = self.persistence.read().await;
+
+        persistence
+            .search(&filters)
+            .map_err(|error| Error::invalid_params(error.to_string()))
+    }
+
+    // A definition request's params, plus an optional `contextLines`
+    async fn peek_definition(&self, params: serde_json::Value) -> Result<Vec<serde_json::Value>> {
+        let context_lines = params
+            .get("contextLines")
+            .and_then(|v| v.as_u64())
+            .map(|context_lines| context_lines as usize);
+        let text_document_position: TextDocumentPositionParams = serde_json::from_value(params)
+            .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+        let cancellation = self.supersede(
+            "peekDefinition",
+            Some(&text_document_position.text_document.uri),
+        );
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+        let peeks = persistence
+            .peek_definitions(text_document_position, context_lines, &cancellation)
+            .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+        self.finish_search(&cancellation, "peekDefinition", time_budget)
+            .await?;
+
+        Ok(peeks)
+    }
+
+    // Definitions in sections, for editors to list exact matches apart from
+    // guesses by name
+    async fn definitions(&self, params: TextDocumentPositionParams) -> Result<serde_json::Value> {
+        let cancellation = self.supersede("definitions", Some(&params.text_document.uri));
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+        let links = persistence
+            .find_definition_links(params, &cancellation)
+            .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+        self.finish_search(&cancellation, "Definitions", time_budget)
+            .await?;
+
+        Ok(DefinitionLink::sections(&links))
+    }
+
+    // Definitions, highlights, and hovers for several positions in one round
+    // trip, for extensions decorating everything in view:
+    //
+    //   { "queries": [{ "textDocument": .., "position": .., "include": ["hover"] }] }
+    //
+    // Queries without `include` ask for all three.
+    async fn batch(&self, params: serde_json::Value) -> Result<Vec<serde_json::Value>> {
+        let queries = params
+            .get("queries")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| Error::invalid_params("`queries` is missing"))?
+            .iter()
+            .map(batch_query)
+            .collect::<Result<Vec<BatchQuery>>>()?;
+
+        // Batches cover positions across the view rather than following the
+        // cursor, so a new one doesn't cancel the last
+        let cancellation = CancellationToken::default();
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+        let answers = persistence
+            .batch(&queries, &cancellation)
+            .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+        self.finish_search(&cancellation, "Batch", time_budget)
+            .await?;
+
+        Ok(answers)
+    }
+
+    // The Rails routes dispatched to the controller action at a position
+    async fn routes(&self, params: TextDocumentPositionParams) -> Result<Vec<serde_json::Value>> {
+        let persistence = self.persistence.read().await;
+
+        persistence
+            .action_routes(&params)
+            .map_err(|error| Error::invalid_params(error.to_string()))
+    }
+
+    async fn clear_cache(&self) -> Result<()> {
+        self.persistence.write().await.clear_index();
+        let _ = self.indexer.send(IndexJob::Rebuild);
+
+        Ok(())
+    }
+}
+
+fn batch_query(query: &serde_json::Value) -> Result<BatchQuery> {
+    let position: TextDocumentPositionParams = serde_json::from_value(query.clone())
+        .map_err(|error| Error::invalid_params(error.to_string()))?;
+
+    let include: Vec<&str> = match query.get("include").and_then(|v| v.as_array()) {
+        Some(include) => include.iter().filter_map(|v| v.as_str()).collect(),
+        None => vec!["definition", "highlights", "hover"],
+    };
+
+    Ok(BatchQuery {
+        position,
+        definition: include.contains(&"definition"),
+        highlights: include.contains(&"highlights"),
+        hover: include.contains(&"hover"),
+    })
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let mut persistence = self.persistence.write().await;
+        persistence.initialize(&params);
+        let formats_documents = persistence.format_command.is_some();
+        let reports_diagnostics = persistence.report_diagnostics;
+        drop(persistence);
+
+        let watch_editor_process = self.watch_editor_process;
+
+        tokio::spawn(async move {
+            if !watch_editor_process {
+                return;
+            }
+
+            #[cfg(not(target_family = "windows"))]
+            loop {
+                let editor_process_id = params.process_id.unwrap_or_else(|| quit::with_code(1));
+
+                let editor_process_running = psutil::process::processes()
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|process| process.ok())
+                    .find(|process| process.pid() == editor_process_id);
+
+                if let None = editor_process_running {
+                    quit::with_code(1);
+                }
+
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            #[cfg(target_family = "windows")]
+            loop {
+                let editor_process_id = params.process_id.unwrap_or_else(|| quit::with_code(1));
+
+                // `tasklist` only lists the process when it's running, and
+                // the editor is assumed to be when it can't be run
+                let editor_process_running = std::process::Command::new("tasklist")
+                    .args(["/NH", "/FI", &format!("PID eq {}", editor_process_id)])
+                    .output()
+                    .map_or(true, |output| {
+                        String::from_utf8_lossy(&output.stdout)
+                            .split_whitespace()
+                            .any(|word| word == editor_process_id.to_string())
+                    });
+
+                if !editor_process_running {
+                    quit::with_code(1);
+                }
+
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+
+        });
+
+        let indexer = self.indexer.clone();
+
+        let reindexing_task = tokio::spawn(async move {
+            let _ = indexer.send(IndexJob::Workspace);
+            let _ = indexer.send(IndexJob::Dependencies);
+
+            loop {
+                tokio::time::sleep(Duration::from_secs(600)).await;
+
+                if indexer.send(IndexJob::Workspace).is_err() {
+                    return;
+                }
+            }
+        });
+
+        self.background_tasks.lock().unwrap().push(reindexing_task);
+
+        Ok(InitializeResult {
+            server_info: None,
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::FULL), // todo: incremental
+                        will_save: Some(false),
+                        will_save_wait_until: Some(false),
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                    },
+                )),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec!["@".to_string(), ".".to_string()]),
+                    ..CompletionOptions::default()
+                }),
+                definition_provider: Some(OneOf::Left(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                inline_value_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(formats_documents)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR_EXTRACT,
+                            CodeActionKind::REFACTOR_INLINE,
+                        ]),
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                        resolve_provider: None,
+                    },
+                )),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        test_runner::RUN_TEST_COMMAND.to_string(),
+                        benchmark::BENCHMARK_COMMAND.to_string(),
+                        compaction::COMPACT_INDEX_COMMAND.to_string(),
+                    ],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                linked_editing_range_provider: Some(
+                    LinkedEditingRangeServerCapabilities::Simple(true),
+                ),
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                diagnostic_provider: if reports_diagnostics {
+                    Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                        identifier: Some("fuzzy".to_string()),
+                        // Private call checks look at definitions in other files
+                        inter_file_dependencies: true,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                    }))
+                } else {
+                    None
+                },
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        will_rename: Some(FileOperationRegistrationOptions {
+                            filters: vec![FileOperationFilter {
+                                scheme: Some("file".to_string()),
+                                pattern: FileOperationPattern {
+                                    glob: "**/*.rb".to_string(),
+                                    matches: Some(FileOperationPatternKind::File),
+                                    options: None,
+                                },
+                            }],
+                        }),
+                        ..WorkspaceFileOperationsServerCapabilities::default()
+                    }),
+                }),
+                ..ServerCapabilities::default()
+            },
+        })
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        for background_task in self.background_tasks.lock().unwrap().drain(..) {
+            background_task.abort();
+        }
+
+        // Indexing that was in progress is discarded, its files are picked up
+        // again next session since the walk's results were never recorded
+        let mut persistence = self.persistence.write().await;
+        persistence.shutdown();
+
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let mut persistence = self.persistence.write().await;
+        let mut diagnostics: Vec<tower_lsp::lsp_types::Diagnostic> = vec![];
+
+        persistence.update_open_file(&params.text_document.uri, &params.text_document.text);
+
+        if !persistence.is_walked(&params.text_document.uri) {
+            let _ = self.indexer.send(IndexJob::File {
+                uri: params.text_document.uri.clone(),
+                text: params.text_document.text.clone(),
+            });
+        }
+
+        let change_diagnostics =
+            persistence.diagnostics(&params.text_document.text, &params.text_document.uri);
+
+        for diagnostic in change_diagnostics {
+            for unwrapped_diagnostic in diagnostic {
+                if let Some(finally_diagnostic) = unwrapped_diagnostic {
+                    diagnostics.push(finally_diagnostic.to_owned());
+                }
+            }
+        }
+
+        if persistence.report_diagnostics && !persistence.pull_diagnostics {
+            self.client
+                .publish_diagnostics(
+                    params.text_document.uri,
+                    persistence.reported_diagnostics(diagnostics),
+                    Some(params.text_document.version),
+                )
+                .await;
+        }
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let mut persistence = self.persistence.write().await;
+
+        for content_change in &params.content_changes {
+            persistence.update_open_file(&params.text_document.uri, &content_change.text);
+
+            let _ = self.indexer.send(IndexJob::File {
+                uri: params.text_document.uri.clone(),
+                text: content_change.text.clone(),
+            });
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if let Some(text) = params.text {
+            let _ = self.indexer.send(IndexJob::File {
+                uri: params.text_document.uri,
+                text,
+            });
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let mut persistence = self.persistence.write().await;
+        persistence.close_file(&params.text_document.uri);
+
+        if persistence.is_detached_buffer(&params.text_document.uri) {
+            let _ = self.indexer.send(IndexJob::Discard {
+                uri: params.text_document.uri,
+            });
+        }
+
+        drop(persistence);
+
+        self.client
+            .log_message(MessageType::INFO, "file closed!")
+            .await;
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let mut persistence = self.persistence.write().await;
+
+        for workspace_folder in &params.event.removed {
+            persistence.remove_workspace_folder(&workspace_folder.uri);
+        }
+
+        for workspace_folder in &params.event.added {
+            persistence.add_workspace_folder(&workspace_folder.uri);
+        }
+
+        let _ = self.indexer.send(IndexJob::Workspace);
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let persistence = self.persistence.read().await;
+
+        let completion_response = || -> Option<CompletionResponse> {
+            let items = persistence.completions(params.text_document_position);
+            let items = items.unwrap();
+
+            Some(CompletionResponse::Array(items))
+        }();
+
+        Ok(completion_response)
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let started = Instant::now();
+        let cancellation = self.supersede(
+            "definition",
+            Some(&params.text_document_position_params.text_document.uri),
+        );
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+        let text_document_position = params.text_document_position_params;
+        let mut found_definitions = 0;
+
+        // Exact matches come first. Clients taking links are given the usage
+        // the definitions are for.
+        let definitions = || -> Option<GotoDefinitionResponse> {
+            let links =
+                persistence.find_definition_links(text_document_position.clone(), &cancellation);
+            let links = links.unwrap();
+
+            found_definitions = links.len();
+
+            if persistence.definition_link_support {
+                Some(GotoDefinitionResponse::Link(
+                    links.iter().map(|link| link.location_link()).collect(),
+                ))
+            } else {
+                Some(GotoDefinitionResponse::Array(
+                    links.into_iter().map(|link| link.location).collect(),
+                ))
+            }
+        }();
+
+        self.finish_search(&cancellation, "Go to definition", time_budget)
+            .await?;
+        self.record_request(
+            &persistence,
+            "definition",
+            started,
+            found_definitions,
+            &cancellation,
+        )
+        .await;
+
+        if found_definitions == 0 && persistence.suggest_definitions {
+            if let Ok(Some(suggestions)) =
+                persistence.definition_suggestions(&text_document_position)
+            {
+                self.suggest_definitions(suggestions);
+            }
+        }
+
+        Ok(definitions)
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let persistence = self.persistence.read().await;
+        let hover = persistence
+            .hover(params.text_document_position_params)
+            .unwrap_or(None);
+
+        Ok(hover)
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let persistence = self.persistence.read().await;
+        let signature_help = persistence
+            .signature_help(params.text_document_position_params)
+            .unwrap_or(None);
+
+        Ok(signature_help)
+    }
+
+    async fn goto_implementation(
+        &self,
+        params: GotoImplementationParams,
+    ) -> Result<Option<GotoImplementationResponse>> {
+        let persistence = self.persistence.read().await;
+        let implementations = || -> Option<GotoImplementationResponse> {
+            let locations =
+                persistence.find_implementations(params.text_document_position_params);
+            let locations = locations.unwrap();
+
+            Some(GotoImplementationResponse::Array(locations))
+        }();
+
+        Ok(implementations)
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let persistence = self.persistence.read().await;
+
+        let highlights_response = || -> Option<Vec<DocumentHighlight>> {
+            let highlights = persistence.find_highlights(params.text_document_position_params);
+            let highlights = highlights.unwrap();
+
+            Some(highlights)
+        }();
+
+        Ok(highlights_response)
+    }
+
+    async fn linked_editing_range(
+        &self,
+        params: LinkedEditingRangeParams,
+    ) -> Result<Option<LinkedEditingRanges>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(persistence
+            .linked_editing_ranges(params.text_document_position_params)
+            .unwrap_or(None))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(persistence
+            .inlay_hints(&params.text_document.uri, params.range)
+            .ok())
+    }
+
+    async fn inline_value(&self, params: InlineValueParams) -> Result<Option<Vec<InlineValue>>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(persistence.inline_values(&params).ok())
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        // The lock's released before the formatter runs
+        let format_job = self
+            .persistence
+            .read()
+            .await
+            .format_job(&params.text_document.uri)
+            .map_err(|message| Error::invalid_params(message))?;
+
+        match format_job {
+            Some(format_job) => format_job
+                .run(formatter::FORMAT_TIMEOUT)
+                .await
+                .map(Some)
+                .map_err(|message| Error::invalid_params(message)),
+            None => Ok(None),
+        }
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(persistence.code_actions(&params).ok())
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(Some(persistence.test_code_lenses(&params.text_document.uri)))
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            test_runner::RUN_TEST_COMMAND => match params.arguments.into_iter().next() {
+                Some(test_command) => {
+                    self.client
+                        .send_notification::<RunTest>(test_command.clone())
+                        .await;
+
+                    Ok(Some(test_command))
+                }
+                None => Err(Error::invalid_params("No test command given")),
+            },
+            benchmark::BENCHMARK_COMMAND => self.benchmark().await.map(Some),
+            compaction::COMPACT_INDEX_COMMAND => self.compact_index().await.map(Some),
+            command => Err(Error::invalid_params(format!(
+                "Unknown command {}",
+                command
+            ))),
+        }
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let started = Instant::now();
+        let cancellation = self.supersede(
+            "references",
+            Some(&params.text_document_position.text_document.uri),
+        );
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+
+        let locations_response = persistence
+            .reference_locations(params, &cancellation)
+            .ok();
+
+        self.finish_search(&cancellation, "Find references", time_budget)
+            .await?;
+
+        let result_count = locations_response.as_ref().map_or(0, Vec::len);
+
+        self.record_request(
+            &persistence,
+            "references",
+            started,
+            result_count,
+            &cancellation,
+        )
+        .await;
+
+        Ok(locations_response)
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let persistence = self.persistence.read().await;
+
+        match persistence.prepare_rename(params) {
+            Ok(range) => Ok(Some(PrepareRenameResponse::Range(range))),
+            Err(message) => Err(Error::invalid_params(message)),
+        }
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let persistence = self.persistence.read().await;
+        let text_position = params.clone().text_document_position;
+        let text_document = &params.text_document_position.text_document;
+        let new_name = &params.new_name;
+
+        let conflict = persistence
+            .find_rename_conflict(text_position.clone(), new_name)
+            .unwrap_or(None);
+
+        if let Some(message) = conflict {
+            return Err(Error::invalid_params(message));
+        }
+
+        let workspace_edit = || -> Option<WorkspaceEdit> {
+            let references = persistence
+                .rename_documents(text_position, &CancellationToken::default())
+                .unwrap();
+            let rename_file = persistence.constant_file_rename(&references, new_name);
+            let workspace_edit =
+                persistence.rename_tokens(&text_document.uri, references, new_name);
+
+            match rename_file {
+                Some(rename_file) => {
+                    Some(persistence.with_file_rename(workspace_edit, rename_file))
+                }
+                None => Some(workspace_edit),
+            }
+        }();
+
+        Ok(workspace_edit)
+    }
+
+    async fn will_rename_files(&self, params: RenameFilesParams) -> Result<Option<WorkspaceEdit>> {
+        let persistence = self.persistence.read().await;
+
+        Ok(persistence.file_rename_edit(&params.files).unwrap_or(None))
+    }
+
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        let persistence = self.persistence.read().await;
+        let report = tokio::task::block_in_place(|| {
+            persistence
+                .document_diagnostic_report(&params.text_document.uri, params.previous_result_id)
+        });
+
+        Ok(DocumentDiagnosticReportResult::Report(report))
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        let cancellation = self.supersede("workspaceDiagnostic", None);
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let report = tokio::task::block_in_place(|| {
+            persistence.workspace_diagnostic_report(params.previous_result_ids, &cancellation)
+        });
+
+        if cancellation.is_cancelled() {
+            return Err(Error::request_cancelled());
+        }
+
+        Ok(WorkspaceDiagnosticReportResult::Report(report))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let started = Instant::now();
+        let cancellation = self.supersede("symbol", None);
+        let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+        let persistence = self.persistence.read().await;
+        let time_budget = persistence.query_time_budget;
+        let cancellation = cancellation.with_time_budget(time_budget);
+
+        let symbol_info_response = || -> Option<Vec<SymbolInformation>> {
+            let documents = persistence
+                .find_references_in_workspace(params.query, &cancellation)
+                .unwrap_or_else(|_| Vec::new());
+            let symbol_info = persistence.documents_to_symbol_information(documents);
+
+            Some(symbol_info)
+        }();
+
+        self.finish_search(&cancellation, "Workspace symbol search", time_budget)
+            .await?;
+
+        let result_count = symbol_info_response.as_ref().map_or(0, Vec::len);
+
+        self.record_request(&persistence, "symbol", started, result_count, &cancellation)
+            .await;
+
+        Ok(symbol_info_response)
+    }
+}
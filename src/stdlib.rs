@@ -0,0 +1,109 @@
+use crate::paths;
+use crate::rbs;
+use log::info;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Ruby's core classes are written in C, so there's no source to index for
+// `Array#map` or `File.read`. The `rbs` gem, bundled with Ruby since 3.0,
+// declares and documents each of them in `core/*.rbs`. Those are unpacked
+// into Ruby stubs the first time gems are indexed:
+//
+//   ~/.cache/fuzzy_ruby_server/stdlib/<rbs gem hash>/core/array.rb
+//   ~/.cache/fuzzy_ruby_server/stdlib/<rbs gem hash>/core/array.rbs
+//
+// Each stub has every method under its documentation, and its `.rbs` file
+// beside it gives hovers the signatures. Going to the definition of a core
+// method opens its stub. The stub directory is keyed by the gem's path, so
+// a new version of the gem is unpacked again.
+
+// Written once every stub is, so a partly unpacked directory isn't reused
+const UNPACKED_MARKER_FILE: &str = ".unpacked";
+
+// The `rbs` gem the workspace's Ruby would load
+pub fn rbs_gem_path(workspace_path: &str) -> Option<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("gem which rbs")
+        .current_dir(workspace_path)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        info!("The rbs gem wasn't found, skipping Ruby's core documentation.");
+        return None;
+    }
+
+    // `<gem path>/lib/rbs.rb`
+    let rbs_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let gem_path = Path::new(&rbs_path).parent()?.parent()?;
+
+    Some(paths::normalize(&gem_path.to_string_lossy()))
+}
+
+// The stubs' paths, unpacking them first unless a previous session has
+pub fn core_stub_paths(rbs_gem_path: &str) -> io::Result<Vec<String>> {
+    let stub_dir = paths::stdlib_stub_dir(rbs_gem_path);
+    let rbs_paths = rbs_file_paths(&Path::new(rbs_gem_path).join("core"))?;
+    let unpacked = stub_dir.join(UNPACKED_MARKER_FILE).is_file();
+
+    let mut stub_paths = Vec::with_capacity(rbs_paths.len());
+
+    for rbs_path in rbs_paths {
+        let relative_path = rbs_path
+            .strip_prefix(rbs_gem_path)
+            .unwrap_or(rbs_path.as_path());
+        let stub_rbs_path = stub_dir.join(relative_path);
+        let stub_path = stub_rbs_path.with_extension("rb");
+
+        if !unpacked {
+            let text = fs::read_to_string(&rbs_path)?;
+
+            if let Some(parent_dir) = stub_path.parent() {
+                fs::create_dir_all(parent_dir)?;
+            }
+
+            fs::write(&stub_path, rbs::ruby_stub(&text))?;
+            fs::write(&stub_rbs_path, text)?;
+        }
+
+        stub_paths.push(paths::normalize(&stub_path.to_string_lossy()));
+    }
+
+    if !unpacked {
+        fs::write(stub_dir.join(UNPACKED_MARKER_FILE), "")?;
+        info!(
+            "Unpacked Ruby's core documentation to {}",
+            stub_dir.display()
+        );
+    }
+
+    Ok(stub_paths)
+}
+
+pub fn is_stub_path(path: &str) -> bool {
+    Path::new(path).starts_with(paths::stdlib_dir())
+}
+
+fn rbs_file_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut rbs_paths = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            rbs_paths.extend(rbs_file_paths(&path)?);
+        } else if path
+            .extension()
+            .map_or(false, |extension| extension == "rbs")
+        {
+            rbs_paths.push(path);
+        }
+    }
+
+    rbs_paths.sort();
+
+    Ok(rbs_paths)
+}
@@ -0,0 +1,165 @@
+use crate::paths;
+use log::info;
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower_lsp::Client;
+
+// Events about how lookups and the index hold up on real projects, so
+// performance work can go where it's needed. Off unless the `telemetry`
+// setting picks where they go:
+//
+//   "client"  `telemetry/event` notifications, for the editor extension
+//   "file"    JSON lines appended to `telemetryFile`, by default
+//             `~/.cache/fuzzy_ruby_server/telemetry.jsonl`
+//
+// Events only hold kinds, timings, and counts, never names, paths, or
+// source.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sink {
+    Client,
+    File(PathBuf),
+}
+
+impl Sink {
+    // None unless telemetry is turned on
+    pub fn from_settings(telemetry: Option<&str>, file: Option<&str>) -> Option<Sink> {
+        match telemetry? {
+            "client" => Some(Sink::Client),
+            "file" => Some(Sink::File(
+                file.map_or_else(paths::telemetry_file, PathBuf::from),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // A lookup that answered, `partial` when it ran out of time
+    Request {
+        kind: &'static str,
+        latency: Duration,
+        result_count: usize,
+        partial: bool,
+    },
+    // After the workspace is indexed or the index is compacted
+    IndexHealth {
+        documents: u64,
+        segments: usize,
+        files: usize,
+    },
+}
+
+impl Event {
+    pub fn to_json(&self) -> Value {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        match self {
+            Event::Request {
+                kind,
+                latency,
+                result_count,
+                partial,
+            } => json!({
+                "event": "request",
+                "timestamp": timestamp,
+                "kind": kind,
+                "latencyMs": latency.as_secs_f64() * 1000.0,
+                "resultCount": result_count,
+                "partial": partial,
+            }),
+            Event::IndexHealth {
+                documents,
+                segments,
+                files,
+            } => json!({
+                "event": "indexHealth",
+                "timestamp": timestamp,
+                "documents": documents,
+                "segments": segments,
+                "files": files,
+            }),
+        }
+    }
+}
+
+// Failing to record an event doesn't fail what it's about, it's logged
+pub async fn emit(client: &Client, sink: &Sink, event: Event) {
+    let event = event.to_json();
+
+    match sink {
+        Sink::Client => client.telemetry_event(event).await,
+        Sink::File(path) => {
+            if let Err(error) = append(path, &event) {
+                info!("Unable to write telemetry to {}: {}", path.display(), error);
+            }
+        }
+    }
+}
+
+fn append(path: &Path, event: &Value) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    writeln!(file, "{}", event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_off_unless_a_sink_is_chosen() {
+        assert_eq!(Sink::from_settings(None, None), None);
+        assert_eq!(Sink::from_settings(Some("off"), Some("events.jsonl")), None);
+        assert_eq!(
+            Sink::from_settings(Some("client"), None),
+            Some(Sink::Client)
+        );
+        assert_eq!(
+            Sink::from_settings(Some("file"), Some("/tmp/events.jsonl")),
+            Some(Sink::File(PathBuf::from("/tmp/events.jsonl")))
+        );
+        assert!(matches!(
+            Sink::from_settings(Some("file"), None),
+            Some(Sink::File(path)) if path.ends_with("fuzzy_ruby_server/telemetry.jsonl")
+        ));
+    }
+
+    #[test]
+    fn appends_events_as_json_lines() {
+        let dir = std::env::temp_dir().join(format!("fuzzy-telemetry-{}", std::process::id()));
+        let path = dir.join("events.jsonl");
+        let event = Event::Request {
+            kind: "definition",
+            latency: Duration::from_micros(1500),
+            result_count: 2,
+            partial: false,
+        };
+
+        append(&path, &event.to_json()).unwrap();
+        append(&path, &event.to_json()).unwrap();
+
+        let lines: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], json!("request"));
+        assert_eq!(lines[0]["kind"], json!("definition"));
+        assert_eq!(lines[0]["latencyMs"], json!(1.5));
+        assert_eq!(lines[0]["resultCount"], json!(2));
+    }
+}
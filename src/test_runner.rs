@@ -0,0 +1,327 @@
+use std::fs;
+use std::path::Path;
+
+// Builds the shell commands the "Run test" and "Run file" code lenses run.
+// Spec files run with RSpec, by line:
+//
+//   bundle exec rspec spec/models/user_spec.rb:12
+//
+// Test files run with Minitest, by line in Rails apps and by name otherwise:
+//
+//   bin/rails test test/models/user_test.rb:12
+//   bundle exec ruby -Itest test/user_test.rb --name test_valid_email
+//
+// A `bin/rspec` binstub or Spring in the Gemfile.lock is used when present.
+// The `testCommandTemplate` setting replaces all of this for setups that
+// run tests some other way, like in a container:
+//
+//   docker compose run web bin/rspec {location}
+
+pub const RUN_TEST_COMMAND: &str = "fuzzy.runTest";
+
+static RSPEC_BLOCK_METHODS: &[&str] = &[
+    "context", "describe", "example", "feature", "it", "scenario", "specify",
+];
+
+// `test` is ActiveSupport's, the rest are minitest/spec
+static MINITEST_BLOCK_METHODS: &[&str] = &["describe", "it", "test"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Framework {
+    RSpec,
+    Minitest,
+}
+
+pub fn framework(path: &str) -> Option<Framework> {
+    let file_name = path.rsplit("/").next().unwrap_or(path);
+
+    if file_name.ends_with("_spec.rb") {
+        Some(Framework::RSpec)
+    } else if file_name.ends_with("_test.rb") || file_name.starts_with("test_") {
+        Some(Framework::Minitest)
+    } else {
+        None
+    }
+}
+
+// The method, when a block with it defines tests in any framework, so test
+// blocks can be found before knowing which framework a file uses
+pub fn test_block_method(method_name: &str) -> Option<&'static str> {
+    RSPEC_BLOCK_METHODS
+        .iter()
+        .chain(MINITEST_BLOCK_METHODS)
+        .find(|test_method| **test_method == method_name)
+        .copied()
+}
+
+pub struct TestBlock<'a> {
+    // The block's method, or "Def" for `def test_*` methods
+    pub node_type: &'a str,
+    // The block's description, or the method's name
+    pub name: &'a str,
+    // Starting at 1, as test runners expect
+    pub line: u32,
+}
+
+pub struct TestCommands<'a> {
+    pub framework: Framework,
+    pub workspace_path: &'a str,
+    pub relative_path: &'a str,
+    // With `{path}`, `{line}`, `{location}` (`{path}:{line}`, or just the
+    // path for the whole file), and `{name}` (Minitest's test name filter)
+    pub template: Option<&'a str>,
+    // Rails apps run Minitest through `rails test`, by line
+    pub rails: bool,
+}
+
+impl<'a> TestCommands<'a> {
+    pub fn file_command(&self) -> String {
+        let path = shell_quote(self.relative_path);
+
+        if let Some(template) = self.template {
+            return expand_template(template, &path, "", &path, "");
+        }
+
+        match self.framework {
+            Framework::RSpec => format!("{} {}", rspec_runner(self.workspace_path), path),
+            Framework::Minitest if self.rails => {
+                format!("{} {}", rails_test_runner(self.workspace_path), path)
+            }
+            Framework::Minitest => format!("bundle exec ruby -Itest {}", path),
+        }
+    }
+
+    // The command running one test or group, None when the framework can't
+    // select it, like a Minitest `it` without a description
+    pub fn test_command(&self, test_block: &TestBlock) -> Option<String> {
+        let test_method = match self.framework {
+            Framework::RSpec => RSPEC_BLOCK_METHODS.contains(&test_block.node_type),
+            Framework::Minitest => {
+                test_block.node_type == "Def"
+                    || MINITEST_BLOCK_METHODS.contains(&test_block.node_type)
+            }
+        };
+
+        if !test_method {
+            return None;
+        }
+
+        let path = shell_quote(self.relative_path);
+        let line = test_block.line.to_string();
+        let location = shell_quote(&format!("{}:{}", self.relative_path, line));
+        let name = minitest_name(test_block).map(|name| shell_quote(&name));
+
+        if let Some(template) = self.template {
+            let name = name.unwrap_or_default();
+
+            return Some(expand_template(template, &path, &line, &location, &name));
+        }
+
+        match self.framework {
+            Framework::RSpec => Some(format!(
+                "{} {}",
+                rspec_runner(self.workspace_path),
+                location
+            )),
+            Framework::Minitest if self.rails => Some(format!(
+                "{} {}",
+                rails_test_runner(self.workspace_path),
+                location
+            )),
+            Framework::Minitest => {
+                Some(format!("bundle exec ruby -Itest {} --name {}", path, name?))
+            }
+        }
+    }
+}
+
+// The name, or a pattern, Minitest's `--name` selects a test or group by
+fn minitest_name(test_block: &TestBlock) -> Option<String> {
+    match test_block.node_type {
+        "Def" => Some(test_block.name.to_string()),
+        // ActiveSupport defines `test "valid email"` as `test_valid_email`
+        "test" => Some(format!(
+            "test_{}",
+            test_block
+                .name
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join("_")
+        )),
+        "describe" | "it" if test_block.name.len() > 0 => {
+            Some(format!("/{}/", regex::escape(test_block.name)))
+        }
+        _ => None,
+    }
+}
+
+fn rspec_runner(workspace_path: &str) -> &'static str {
+    if Path::new(workspace_path).join("bin/rspec").is_file() {
+        "bin/rspec"
+    } else if locks_gem(workspace_path, "spring-commands-rspec") {
+        "bundle exec spring rspec"
+    } else {
+        "bundle exec rspec"
+    }
+}
+
+// The `bin/rails` binstub loads Spring itself when the app uses it
+fn rails_test_runner(workspace_path: &str) -> &'static str {
+    if Path::new(workspace_path).join("bin/rails").is_file() {
+        "bin/rails test"
+    } else if locks_gem(workspace_path, "spring") {
+        "bundle exec spring rails test"
+    } else {
+        "bundle exec rails test"
+    }
+}
+
+fn locks_gem(workspace_path: &str, gem_name: &str) -> bool {
+    let gemfile_lock = match fs::read_to_string(Path::new(workspace_path).join("Gemfile.lock")) {
+        Ok(gemfile_lock) => gemfile_lock,
+        Err(_) => return false,
+    };
+
+    let locked_version = format!("{} (", gem_name);
+
+    gemfile_lock
+        .lines()
+        .any(|line| line.trim_start().starts_with(&locked_version))
+}
+
+// Values are already quoted for the shell
+fn expand_template(template: &str, path: &str, line: &str, location: &str, name: &str) -> String {
+    template
+        .replace("{path}", path)
+        .replace("{line}", line)
+        .replace("{location}", location)
+        .replace("{name}", name)
+}
+
+fn shell_quote(argument: &str) -> String {
+    let safe = argument
+        .chars()
+        .all(|char| char.is_ascii_alphanumeric() || "/._-:=".contains(char));
+
+    if safe && argument.len() > 0 {
+        argument.to_string()
+    } else {
+        format!("'{}'", argument.replace("'", "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn test_block<'a>(node_type: &'a str, name: &'a str) -> TestBlock<'a> {
+        TestBlock {
+            node_type,
+            name,
+            line: 12,
+        }
+    }
+
+    fn workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fuzzy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn quotes_arguments_for_the_shell() {
+        assert_eq!(
+            shell_quote("spec/models/user_spec.rb:12"),
+            "spec/models/user_spec.rb:12"
+        );
+        assert_eq!(
+            shell_quote("spec/my models/user_spec.rb"),
+            "'spec/my models/user_spec.rb'"
+        );
+        assert_eq!(shell_quote("it's valid"), "'it'\\''s valid'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn names_minitest_tests() {
+        let name = |node_type, description| minitest_name(&test_block(node_type, description));
+
+        assert_eq!(name("Def", "test_valid_email").unwrap(), "test_valid_email");
+        assert_eq!(name("test", "valid  email").unwrap(), "test_valid_email");
+        assert_eq!(name("it", "saves (twice)").unwrap(), "/saves \\(twice\\)/");
+        assert_eq!(name("it", ""), None);
+        assert_eq!(name("context", "when valid"), None);
+    }
+
+    #[test]
+    fn expands_template_placeholders() {
+        assert_eq!(
+            expand_template("bin/rspec {location}", "a_spec.rb", "3", "a_spec.rb:3", ""),
+            "bin/rspec a_spec.rb:3"
+        );
+
+        let commands = TestCommands {
+            framework: Framework::Minitest,
+            workspace_path: "/nonexistent",
+            relative_path: "test/user's test.rb",
+            template: Some("run {path}:{line} --name {name}"),
+            rails: false,
+        };
+
+        assert_eq!(
+            commands.test_command(&test_block("test", "valid email")),
+            Some("run 'test/user'\\''s test.rb':12 --name test_valid_email".to_string())
+        );
+        assert_eq!(
+            commands.file_command(),
+            "run 'test/user'\\''s test.rb': --name "
+        );
+    }
+
+    #[test]
+    fn prefers_binstubs_then_spring() {
+        let gemfile_lock = "GEM\n  specs:\n    spring (4.1.1)\n    spring-commands-rspec (1.0.4)\n";
+        let workspaces = [
+            (
+                workspace("bundler", &[]),
+                "bundle exec rspec",
+                "bundle exec rails test",
+            ),
+            (
+                workspace("spring", &[("Gemfile.lock", gemfile_lock)]),
+                "bundle exec spring rspec",
+                "bundle exec spring rails test",
+            ),
+            (
+                workspace(
+                    "binstubs",
+                    &[
+                        ("Gemfile.lock", gemfile_lock),
+                        ("bin/rspec", ""),
+                        ("bin/rails", ""),
+                    ],
+                ),
+                "bin/rspec",
+                "bin/rails test",
+            ),
+        ];
+
+        for (dir, rspec, rails_test) in workspaces {
+            let workspace_path = dir.to_string_lossy();
+
+            assert_eq!(rspec_runner(&workspace_path), rspec);
+            assert_eq!(rails_test_runner(&workspace_path), rails_test);
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
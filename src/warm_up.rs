@@ -0,0 +1,141 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// The order a workspace walk's files are indexed in, so the symbols needed
+// soonest are searchable within the first seconds of a cold start:
+//
+//   recent    modified in the last day, what's being worked on
+//   source    app/ and lib/
+//   other     config/, db/, and everything else
+//   tests     spec/ and test/
+//   vendored  vendor/ and installed bundles, even when recently modified
+//
+// Within a tier, the most recently modified files go first. The indexer
+// commits after each tier, so the rest stream in behind it.
+
+static RECENT_SECONDS: i64 = 24 * 60 * 60;
+
+static VENDORED_DIRS: &[&str] = &["vendor", ".bundle"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    Recent,
+    Source,
+    Other,
+    Tests,
+    Vendored,
+}
+
+// The tier of a file by its path in the workspace, and how many seconds ago
+// it was modified
+pub fn tier(relative_path: &str, age: Option<i64>) -> Tier {
+    let mut dir_names = relative_path.split('/').rev().skip(1);
+
+    if dir_names.any(|dir_name| VENDORED_DIRS.contains(&dir_name)) {
+        return Tier::Vendored;
+    }
+
+    if age.map_or(false, |age| age < RECENT_SECONDS) {
+        return Tier::Recent;
+    }
+
+    match relative_path.split_once('/') {
+        Some(("app", _)) | Some(("lib", _)) => Tier::Source,
+        Some(("spec", _)) | Some(("test", _)) => Tier::Tests,
+        _ => Tier::Other,
+    }
+}
+
+pub struct WarmUpQueue {
+    // Seconds since the epoch that ages are measured from
+    now: i64,
+    files: BinaryHeap<Reverse<(Tier, Reverse<i64>, String)>>,
+}
+
+impl WarmUpQueue {
+    pub fn new(now: i64) -> WarmUpQueue {
+        WarmUpQueue {
+            now,
+            files: BinaryHeap::new(),
+        }
+    }
+
+    // `modified` is in seconds since the epoch, None when it couldn't be read
+    pub fn push(&mut self, path: String, relative_path: &str, modified: Option<i64>) {
+        let tier = tier(relative_path, modified.map(|modified| self.now - modified));
+
+        self.files
+            .push(Reverse((tier, Reverse(modified.unwrap_or(0)), path)));
+    }
+
+    pub fn pop(&mut self) -> Option<(Tier, String)> {
+        self.files
+            .pop()
+            .map(|Reverse((tier, _modified, path))| (tier, path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_files_by_where_they_are_and_when_they_changed() {
+        let hour = Some(60 * 60);
+        let week = Some(7 * RECENT_SECONDS);
+
+        assert_eq!(tier("app/models/order.rb", week), Tier::Source);
+        assert_eq!(tier("lib/shop/order.rb", None), Tier::Source);
+        assert_eq!(tier("spec/models/order_spec.rb", hour), Tier::Recent);
+        assert_eq!(tier("spec/models/order_spec.rb", week), Tier::Tests);
+        assert_eq!(tier("config/routes.rb", week), Tier::Other);
+        assert_eq!(tier("Rakefile.rb", week), Tier::Other);
+        assert_eq!(tier("vendor/gems/money/lib/money.rb", hour), Tier::Vendored);
+        // A file named like a directory
+        assert_eq!(tier("app.rb", week), Tier::Other);
+    }
+
+    #[test]
+    fn pops_the_highest_tier_most_recently_modified_first() {
+        let now = 100 * RECENT_SECONDS;
+        let mut queue = WarmUpQueue::new(now);
+
+        for (path, age) in [
+            ("spec/order_spec.rb", 3 * RECENT_SECONDS),
+            ("app/models/product.rb", 5 * RECENT_SECONDS),
+            ("vendor/money.rb", 60),
+            ("app/models/order.rb", 2 * RECENT_SECONDS),
+            ("config/routes.rb", 60),
+        ] {
+            queue.push(path.to_string(), path, Some(now - age));
+        }
+
+        assert_eq!(queue.len(), 5);
+
+        let mut popped = vec![];
+
+        while let Some((tier, path)) = queue.pop() {
+            popped.push((tier, path));
+        }
+
+        assert_eq!(
+            popped,
+            vec![
+                (Tier::Recent, "config/routes.rb".to_string()),
+                (Tier::Source, "app/models/order.rb".to_string()),
+                (Tier::Source, "app/models/product.rb".to_string()),
+                (Tier::Tests, "spec/order_spec.rb".to_string()),
+                (Tier::Vendored, "vendor/money.rb".to_string()),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+}
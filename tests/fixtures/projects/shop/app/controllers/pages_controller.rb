@@ -0,0 +1,6 @@
+class PagesController < ApplicationController
+  skip_before_action :authenticate_user!
+
+  def home
+  end
+end
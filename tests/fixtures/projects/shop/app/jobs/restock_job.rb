@@ -0,0 +1,5 @@
+class RestockJob
+  def perform(name)
+    Restock.schedule(product_name: name)
+  end
+end
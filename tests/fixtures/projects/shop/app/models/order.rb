@@ -0,0 +1,15 @@
+class Order
+  def initialize
+    @products = []
+  end
+
+  def add(name, price)
+    product = Product.new(name, price)
+    @products << product
+    product
+  end
+
+  def total
+    @products.sum { |product| product.price_with_tax }
+  end
+end
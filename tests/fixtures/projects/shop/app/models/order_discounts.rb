@@ -0,0 +1,11 @@
+class Order
+  def discount_count
+    @products.size
+  end
+end
+
+class Wishlist
+  def initialize
+    @products = []
+  end
+end
@@ -0,0 +1,20 @@
+class Product
+  TAX_RATE = 0.2
+
+  attr_reader :name, :price
+
+  def initialize(name, price)
+    @name = name
+    @price = price
+  end
+
+  def price_with_tax
+    price + tax
+  end
+
+  private
+
+  def tax
+    price * TAX_RATE
+  end
+end
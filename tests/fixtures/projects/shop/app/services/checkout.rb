@@ -0,0 +1,10 @@
+module Checkout
+  def self.call(order)
+    total = order.total
+    receipt(total)
+  end
+
+  def self.receipt(total)
+    "Total: #{total}"
+  end
+end
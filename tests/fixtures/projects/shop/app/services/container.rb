@@ -0,0 +1,5 @@
+class Container
+  extend Dry::Container::Mixin
+
+  register("services.mailer") { Mailer.new }
+end
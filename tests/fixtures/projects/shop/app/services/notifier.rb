@@ -0,0 +1,3 @@
+class Notifier
+  include Import["services.mailer"]
+end
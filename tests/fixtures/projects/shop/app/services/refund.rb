@@ -0,0 +1,7 @@
+class Refund
+  def call(order)
+    @order = order
+    notify_customer
+    @order.cancel
+  end
+end
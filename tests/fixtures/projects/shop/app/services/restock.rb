@@ -0,0 +1,10 @@
+class Restock
+  def call(name, price)
+    product = Product.new(name, price)
+    product.price_with_tax
+  end
+
+  def self.schedule(product_name:, at: nil)
+    new.call(product_name, 0)
+  end
+end
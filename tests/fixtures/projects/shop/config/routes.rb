@@ -0,0 +1,5 @@
+Rails.application.routes.draw do
+  root "orders#index"
+  resources :orders, only: [:index, :show]
+  get "checkout", to: "orders#checkout"
+end
@@ -0,0 +1,13 @@
+FactoryBot.define do
+  factory :user do
+    email { "user@example.com" }
+
+    trait :with_orders do
+      orders { [association(:order)] }
+    end
+
+    factory :admin_user do
+      admin { true }
+    end
+  end
+end
@@ -0,0 +1,7 @@
+RSpec.describe Order do
+  it "belongs to a user" do
+    user = create(:admin_user, :with_orders)
+
+    expect(user.orders).not_to be_empty
+  end
+end
@@ -0,0 +1,17 @@
+module Tasks
+  def self.run(items)
+    items.each_with_index do |item, index|
+      puts "#{index}: #{item}"
+    end
+
+    items.map { _1 * 2 }
+
+    items.each do |item; shadow|
+      shadow = item
+    end
+  end
+
+  def self.each_item(items, &block)
+    items.each(&block)
+  end
+end
@@ -0,0 +1,16 @@
+class Order < ApplicationRecord
+  before_validation :normalize_email
+  validate :check_totals
+  after_commit :enqueue_sync, if: [:persisted?, :shipped?], on: :update
+
+  private
+
+  def normalize_email
+  end
+
+  def check_totals
+  end
+
+  def enqueue_sync
+  end
+end
@@ -0,0 +1,17 @@
+module Billing
+  class Invoice < Document
+    include Payable
+
+    STATUSES = %w[draft sent paid].freeze
+
+    class LineItem
+    end
+  end
+
+  module Reports
+    VERSION = 2
+  end
+end
+
+class Billing::Receipt < Billing::Invoice
+end
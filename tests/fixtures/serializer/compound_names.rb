@@ -0,0 +1,14 @@
+module Shipping
+  class Carrier::Rates::Table
+    def lookup(weight)
+      weight
+    end
+  end
+
+  module Zones::Domestic
+    REGIONS = 4
+  end
+end
+
+class ::Warehouse
+end
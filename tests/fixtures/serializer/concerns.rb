@@ -0,0 +1,23 @@
+module Publishable
+  extend ActiveSupport::Concern
+
+  included do
+    has_many :revisions
+    scope :published, -> { where(published: true) }
+
+    def publish!
+      update(published: true)
+    end
+
+    private
+
+    def notify_subscribers
+    end
+  end
+
+  class_methods do
+    def latest
+      published.last
+    end
+  end
+end
@@ -0,0 +1,11 @@
+class Cart
+  extend Forwardable
+
+  def_delegator :@items, :size, :count
+  def_delegators :@items, :first, :last, "empty?"
+  delegate [:push, :pop] => :@items
+
+  delegate :name, :email, to: :owner
+  delegate :currency, to: :store, prefix: true
+  delegate :total, to: :summary, prefix: :order, allow_nil: true
+end
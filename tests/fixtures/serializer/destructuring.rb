@@ -0,0 +1,7 @@
+def swap(values, f)
+  a, (b, *c), @d, self.e, f[0] = values
+end
+
+def split(values)
+  *a, b = values
+end
@@ -0,0 +1,22 @@
+FactoryBot.define do
+  trait :archived do
+    archived_at { Time.current }
+  end
+
+  factory :user, aliases: [:author] do
+    trait :with_posts do
+      posts { build_list(:post, 2) }
+    end
+
+    factory :admin_user
+  end
+
+  factory :guest, parent: :user
+
+  factory :post do
+    association :author, factory: [:user, :with_posts]
+  end
+end
+
+create(:admin_user, :with_posts, :archived)
+FactoryBot.build_stubbed(:guest)
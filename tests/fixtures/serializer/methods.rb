@@ -0,0 +1,35 @@
+class Account
+  attr_reader :balance
+
+  def initialize(owner, balance = 0, *rest, currency:, overdraft: false, **options, &block)
+    @owner = owner
+    @balance = balance
+  end
+
+  def self.open(owner)
+    new(owner).tap { |account| account.deposit(0) }
+  end
+
+  def deposit(amount)
+    @balance += amount
+    notify(:deposit, amount)
+  end
+
+  def each_transaction
+    yield @balance
+  end
+
+  alias_method :add, :deposit
+
+  private
+
+  def notify(event, amount)
+    Notifier.call(event, amount: amount)
+  end
+
+  protected
+
+  def owner
+    @owner
+  end
+end
@@ -0,0 +1,11 @@
+class Cart
+  Config::LIMIT ||= 10
+
+  def items
+    @items ||= []
+  end
+
+  def add(count)
+    count += 1
+  end
+end
@@ -0,0 +1,14 @@
+Rails.application.routes.draw do
+  root "pages#home"
+  get "about", to: "pages#about"
+
+  resources :users, only: [:index, :show] do
+    member do
+      get :preview
+    end
+  end
+
+  namespace :admin do
+    resource :profile, except: :destroy
+  end
+end
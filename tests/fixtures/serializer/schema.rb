@@ -0,0 +1,8 @@
+ActiveRecord::Schema[7.1].define(version: 2024_01_15_120000) do
+  create_table "line_items", force: :cascade do |t|
+    t.string "name", null: false
+    t.references "order", null: false
+    t.index ["name"], name: "index_line_items_on_name"
+    t.timestamps
+  end
+end
@@ -0,0 +1,25 @@
+class Registry
+  class << self
+    def register(name, handler)
+      handlers[name] = handler
+    end
+
+    def handlers
+      @handlers ||= {}
+    end
+
+    class Entry
+      def name
+      end
+    end
+  end
+
+  def lookup(name)
+    self.class.handlers.fetch(name)
+  end
+end
+
+class << Registry
+  def reset
+  end
+end
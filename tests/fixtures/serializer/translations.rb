@@ -0,0 +1,9 @@
+module Admin
+  class UsersController < ApplicationController
+    def show
+      @title = t(".title")
+      @greeting = I18n.t("users.greeting", name: current_user.name)
+      @empty = translate(:empty, scope: [:users, :index])
+    end
+  end
+end
@@ -0,0 +1,27 @@
+$logger = Logger.new($stdout)
+
+class Counter
+  @@instances = 0
+
+  def initialize
+    @@instances += 1
+    @count = 0
+  end
+
+  def increment(by = 1)
+    total = @count + by
+    @count = total
+    $logger.info(total)
+  end
+
+  def matches(text)
+    if /(?<word>\w+)/ =~ text
+      word
+    end
+  end
+
+  def pair
+    first, second = [1, 2]
+    [first, second]
+  end
+end
@@ -0,0 +1,1368 @@
+mod support;
+
+use fuzzy::paths;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use support::{fixture_project, TestClient};
+
+fn location_summary(location: &Value) -> (String, u64, u64) {
+    let uri = location["uri"].as_str().unwrap();
+    let file_name = uri.rsplit("/").next().unwrap().to_string();
+
+    (
+        file_name,
+        location["range"]["start"]["line"].as_u64().unwrap(),
+        location["range"]["start"]["character"].as_u64().unwrap(),
+    )
+}
+
+// Where each edit of a workspace edit starts, in order
+fn edit_summaries(workspace_edit: &Value) -> Vec<(String, u64, u64)> {
+    let file_edits: Vec<(&str, &Value)> = match workspace_edit["documentChanges"].as_array() {
+        Some(operations) => operations
+            .iter()
+            .filter(|operation| operation.get("edits").is_some())
+            .map(|operation| {
+                let uri = operation["textDocument"]["uri"].as_str().unwrap();
+
+                (uri, &operation["edits"])
+            })
+            .collect(),
+        None => workspace_edit["changes"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(uri, edits)| (uri.as_str(), edits))
+            .collect(),
+    };
+    let mut edits: Vec<(String, u64, u64)> = file_edits
+        .into_iter()
+        .flat_map(|(uri, edits)| {
+            edits.as_array().unwrap().iter().map(move |edit| {
+                location_summary(&json!({ "uri": uri, "range": edit["range"] }))
+            })
+        })
+        .collect();
+    edits.sort();
+
+    edits
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn initialize_reports_capabilities() {
+    let mut client = TestClient::start().await;
+    let result = client.initialize(&fixture_project("shop")).await;
+    let capabilities = &result["capabilities"];
+
+    assert_eq!(capabilities["definitionProvider"], json!(true));
+    assert_eq!(capabilities["referencesProvider"], json!(true));
+    assert_eq!(capabilities["documentHighlightProvider"], json!(true));
+    assert_eq!(
+        capabilities["executeCommandProvider"]["commands"],
+        json!(["fuzzy.runTest", "fuzzy.benchmark", "fuzzy.compactIndex"])
+    );
+    // Only offered with a `format_command`
+    assert_eq!(capabilities["documentFormattingProvider"], json!(false));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn definition_of_a_constant_in_another_file() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    let locations = client.definition("app/models/order.rb", 6, 14).await;
+    let locations: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(locations, vec![("product.rb".to_string(), 0, 6)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn definition_of_a_method_called_on_a_block_argument() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    let locations = client.definition("app/models/order.rb", 12, 38).await;
+    let locations: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(locations, vec![("product.rb".to_string(), 10, 6)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn definitions_are_sectioned_by_how_exact_the_match_is() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    let found = |sections: &Value| sections["exact"] != json!([]) || sections["fuzzy"] != json!([]);
+    let link_summaries = |links: &Value| -> Vec<(String, u64, u64)> {
+        links
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|link| {
+                location_summary(&json!({
+                    "uri": link["targetUri"],
+                    "range": link["targetSelectionRange"],
+                }))
+            })
+            .collect()
+    };
+
+    // A top-level constant is in a scope around every usage
+    let params = client.position_params("app/models/order.rb", 6, 14);
+    let sections = client.request_until("fuzzy/definitions", params, found).await;
+
+    assert_eq!(
+        link_summaries(&sections["exact"]),
+        vec![("product.rb".to_string(), 0, 6)]
+    );
+    assert_eq!(link_summaries(&sections["fuzzy"]), vec![]);
+    assert_eq!(
+        sections["exact"][0]["originSelectionRange"],
+        json!({ "start": { "line": 6, "character": 14 }, "end": { "line": 6, "character": 21 } })
+    );
+
+    // The block argument's class isn't known, so the method's matched by name
+    let params = client.position_params("app/models/order.rb", 12, 38);
+    let sections = client.request_until("fuzzy/definitions", params, found).await;
+
+    assert_eq!(link_summaries(&sections["exact"]), vec![]);
+    assert_eq!(
+        link_summaries(&sections["fuzzy"]),
+        vec![("product.rb".to_string(), 10, 6)]
+    );
+
+    // A local assigned `Product.new` holds a product
+    let params = client.position_params("app/services/restock.rb", 3, 12);
+    let sections = client.request_until("fuzzy/definitions", params, found).await;
+
+    assert_eq!(
+        link_summaries(&sections["exact"]),
+        vec![("product.rb".to_string(), 10, 6)]
+    );
+    assert_eq!(link_summaries(&sections["fuzzy"]), vec![]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hovers_are_plain_text_unless_the_client_takes_markdown() {
+    let capabilities = json!({
+        "textDocument": { "hover": { "contentFormat": ["markdown", "plaintext"] } },
+    });
+
+    for (capabilities, kind) in [(json!({}), "plaintext"), (capabilities, "markdown")] {
+        let mut client = TestClient::start().await;
+        client
+            .initialize_with(&fixture_project("shop"), json!({}), capabilities)
+            .await;
+        client.did_open("app/models/order.rb").await;
+        client.definition("app/models/order.rb", 6, 14).await;
+
+        let params = client.position_params("app/models/order.rb", 6, 14);
+        let hover = client.request("textDocument/hover", params).await.unwrap();
+
+        assert_eq!(hover["contents"]["kind"], json!(kind));
+        assert_eq!(
+            hover["contents"]["value"].as_str().unwrap().contains("```ruby"),
+            kind == "markdown"
+        );
+
+        client.shutdown().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batch_answers_each_query_with_what_it_asks_for() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    let query = |line: u32, character: u32, include: Option<Value>| {
+        let mut query = client.position_params("app/models/order.rb", line, character);
+
+        if let Some(include) = include {
+            query["include"] = include;
+        }
+
+        query
+    };
+    let params = json!({
+        "queries": [
+            query(6, 14, Some(json!(["definition"]))),
+            query(6, 4, Some(json!(["highlights"]))),
+            query(12, 38, None),
+        ]
+    });
+
+    let answers = client
+        .request_until("fuzzy/batch", params, |answers| {
+            answers[0]["definition"]["exact"] != json!([])
+        })
+        .await;
+
+    assert_eq!(answers.as_array().unwrap().len(), 3);
+
+    let constant = answers[0].as_object().unwrap();
+    assert_eq!(constant.keys().collect::<Vec<_>>(), vec!["definition"]);
+    assert_eq!(
+        constant["definition"]["exact"][0]["targetUri"],
+        json!(client.uri("app/models/product.rb"))
+    );
+
+    let highlighted_lines: Vec<u64> = answers[1]["highlights"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|highlight| highlight["range"]["start"]["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(highlighted_lines, vec![6, 7, 8]);
+
+    let method = &answers[2];
+    assert!(method["hover"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("price_with_tax"));
+    assert_eq!(
+        method["definition"]["fuzzy"][0]["targetSelectionRange"]["start"],
+        json!({ "line": 10, "character": 6 })
+    );
+    assert!(method["highlights"].is_array());
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_files_are_indexed_without_a_workspace_root() {
+    let mut client = TestClient::start().await;
+    client.initialize_without_root(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+
+    let locations = client.definition("app/services/checkout.rb", 3, 12).await;
+
+    assert_eq!(
+        location_summary(&locations[0]),
+        ("checkout.rb".to_string(), 2, 4)
+    );
+
+    // Nothing else in the project is indexed
+    let params = client.position_params("app/services/checkout.rb", 2, 18);
+    let locations = client.request("textDocument/definition", params).await.unwrap();
+
+    assert_eq!(locations, json!([]));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn untitled_buffers_are_indexed_under_their_uri() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client
+        .notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": "untitled:Untitled-1",
+                    "languageId": "ruby",
+                    "version": 1,
+                    "text": "def greet\n  42\nend\n\ngreet\n",
+                },
+            }),
+        )
+        .await;
+
+    let params = json!({
+        "textDocument": { "uri": "untitled:Untitled-1" },
+        "position": { "line": 4, "character": 0 },
+    });
+    let locations = client
+        .request_until("textDocument/definition", params, |locations| {
+            locations.as_array().map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+
+    assert_eq!(locations[0]["uri"], json!("untitled:Untitled-1"));
+    assert_eq!(locations[0]["range"]["start"], json!({ "line": 0, "character": 4 }));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn highlights_a_local_variable_within_its_method() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+
+    let params = client.position_params("app/services/checkout.rb", 2, 4);
+    let mut highlights = client
+        .request_until("textDocument/documentHighlight", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |highlights| highlights.len() > 1)
+        })
+        .await;
+
+    highlights
+        .as_array_mut()
+        .unwrap()
+        .sort_by_key(|highlight| highlight["range"]["start"]["line"].as_u64());
+
+    assert_eq!(
+        highlights,
+        json!([
+            {
+                "range": {
+                    "start": { "line": 2, "character": 4 },
+                    "end": { "line": 2, "character": 9 },
+                },
+                "kind": 3,
+            },
+            {
+                "range": {
+                    "start": { "line": 3, "character": 12 },
+                    "end": { "line": 3, "character": 17 },
+                },
+                "kind": 2,
+            },
+        ])
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn did_open_publishes_syntax_errors() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client
+        .did_open_with_text("app/models/broken.rb", "class Broken\n  def call(\nend\n")
+        .await;
+
+    let notification = client.notification("textDocument/publishDiagnostics").await;
+    let diagnostics = notification["params"]["diagnostics"].as_array().unwrap();
+
+    assert_eq!(
+        notification["params"]["uri"],
+        json!(client.uri("app/models/broken.rb"))
+    );
+    assert!(diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic["severity"] == json!(1)));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pulled_diagnostics_are_unchanged_until_the_file_is() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client
+        .did_open_with_text("app/models/broken.rb", "class Broken\n  def call(\nend\n")
+        .await;
+
+    let params = json!({ "textDocument": { "uri": client.uri("app/models/broken.rb") } });
+    let report = client
+        .request_until("textDocument/diagnostic", params.clone(), |result| {
+            result["items"]
+                .as_array()
+                .map_or(false, |items| !items.is_empty())
+        })
+        .await;
+
+    assert_eq!(report["kind"], json!("full"));
+
+    let mut repeat_params = params;
+    repeat_params["previousResultId"] = report["resultId"].clone();
+    let repeat_report = client
+        .request("textDocument/diagnostic", repeat_params)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        repeat_report,
+        json!({ "kind": "unchanged", "resultId": report["resultId"] })
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inline_values_stop_where_execution_did() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+
+    let params = json!({
+        "textDocument": { "uri": client.uri("app/services/checkout.rb") },
+        "range": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 9, "character": 3 },
+        },
+        "context": {
+            "frameId": 1,
+            "stoppedLocation": {
+                "start": { "line": 3, "character": 4 },
+                "end": { "line": 3, "character": 18 },
+            },
+        },
+    });
+    let values = client
+        .request_until("textDocument/inlineValue", params, |result| {
+            result.as_array().map_or(false, |values| !values.is_empty())
+        })
+        .await;
+
+    let variables: Vec<(u64, u64, &str)> = values
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| {
+            (
+                value["range"]["start"]["line"].as_u64().unwrap(),
+                value["range"]["start"]["character"].as_u64().unwrap(),
+                value["variableName"].as_str().unwrap(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        variables,
+        vec![(2, 4, "total"), (2, 12, "order"), (3, 12, "total")]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_commands_are_invalid_params() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+
+    let error = client
+        .request(
+            "workspace/executeCommand",
+            json!({ "command": "fuzzy.unknown", "arguments": [] }),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(error["code"], json!(-32602));
+    assert_eq!(error["message"], json!("Unknown command fuzzy.unknown"));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn telemetry_reports_lookups_and_index_health_when_turned_on() {
+    let mut client = TestClient::start().await;
+    let options = json!({ "telemetry": "client" });
+    client.initialize_with_options(&fixture_project("shop"), options).await;
+    client.did_open("app/models/order.rb").await;
+    client.definition("app/models/order.rb", 6, 14).await;
+
+    let mut definition = None;
+    let mut index_health = None;
+
+    // Lookups made before the workspace was indexed report no results
+    while definition.is_none() || index_health.is_none() {
+        let event = client.notification("telemetry/event").await["params"].clone();
+
+        match event["event"].as_str() {
+            Some("request") if event["resultCount"] == json!(1) => definition = Some(event),
+            Some("indexHealth") => index_health = Some(event),
+            _ => {}
+        }
+    }
+
+    let definition = definition.unwrap();
+    let index_health = index_health.unwrap();
+
+    assert_eq!(definition["kind"], json!("definition"));
+    assert_eq!(definition["partial"], json!(false));
+    assert!(definition["latencyMs"].as_f64().unwrap() >= 0.0);
+    assert!(index_health["documents"].as_u64().unwrap() > 0);
+    assert!(index_health["segments"].as_u64().unwrap() > 0);
+    assert!(index_health["files"].as_u64().unwrap() > 0);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compacting_the_index_merges_what_edits_committed() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+    client.definition("app/models/order.rb", 6, 14).await;
+
+    let uri = client.uri("app/models/order.rb");
+
+    // Each edit is committed as a segment of its own, waiting for one to
+    // be searchable before making the next so they aren't indexed together
+    for (version, name) in [(2, "Product"), (3, "Item"), (4, "Product")] {
+        let method_name = format!("add_{}", version);
+        let text = format!(
+            "class Order\n  def {}\n    {}.new\n  end\nend\n",
+            method_name, name
+        );
+
+        client
+            .notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [{ "text": text }],
+                }),
+            )
+            .await;
+
+        client
+            .request_until("workspace/symbol", json!({ "query": method_name }), |result| {
+                result.as_array().map_or(false, |symbols| {
+                    symbols.iter().any(|symbol| symbol["name"] == json!(method_name))
+                })
+            })
+            .await;
+    }
+
+    let compaction = client
+        .request(
+            "workspace/executeCommand",
+            json!({ "command": "fuzzy.compactIndex", "arguments": [] }),
+        )
+        .await
+        .unwrap();
+
+    assert!(compaction["segmentsBefore"].as_u64().unwrap() > 1);
+    assert_eq!(compaction["segmentsAfter"], json!(1));
+
+    // Still answers from the merged segment
+    let locations = client.definition("app/models/order.rb", 2, 6).await;
+    let locations: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(locations, vec![("product.rb".to_string(), 0, 6)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_second_window_takes_over_writing_the_index_once_the_first_closes() {
+    let root = std::env::temp_dir().join(format!("fuzzy-index-handoff-{}", std::process::id()));
+    fs::create_dir_all(root.join("app/models")).unwrap();
+    fs::write(
+        root.join("app/models/order.rb"),
+        "class Order\n  def total\n  end\nend\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("app/models/cart.rb"),
+        "class Cart\n  def checkout\n    Order.new.total\n  end\nend\n",
+    )
+    .unwrap();
+    let options = json!({ "allocationType": "disk" });
+
+    // A shared index is only read once a session has shut down and saved
+    // what it indexed
+    let mut first = TestClient::start().await;
+    first.initialize_with_options(&root, options.clone()).await;
+    first.definition("app/models/cart.rb", 2, 15).await;
+    first.shutdown().await;
+
+    let mut first = TestClient::start().await;
+    first.initialize_with_options(&root, options.clone()).await;
+    first.definition("app/models/cart.rb", 2, 15).await;
+
+    // The second window reads the first's index
+    let mut second = TestClient::start().await;
+    second.initialize_with_options(&root, options).await;
+    let locations = second.definition("app/models/cart.rb", 2, 15).await;
+    let locations: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(locations, vec![("order.rb".to_string(), 1, 6)]);
+
+    // but can't write it, so its edit is queued
+    let uri = second.uri("app/models/order.rb");
+    let text = "class Order\n  def total\n    refund\n  end\n\n  def refund\n  end\nend\n";
+    let change = |version: i64| {
+        json!({
+            "textDocument": { "uri": uri, "version": version },
+            "contentChanges": [{ "text": text }],
+        })
+    };
+    second.notify("textDocument/didChange", change(2)).await;
+
+    loop {
+        let diagnostics = second.notification("textDocument/publishDiagnostics").await;
+
+        if diagnostics["params"]["uri"] == json!(uri) {
+            break;
+        }
+    }
+
+    let params = second.position_params("app/models/order.rb", 2, 4);
+    let locations = second.request("textDocument/definition", params).await.unwrap();
+
+    assert!(locations.as_array().map_or(true, Vec::is_empty));
+
+    // and the queue is indexed once the first window closes
+    first.shutdown().await;
+    second.notify("textDocument/didChange", change(3)).await;
+
+    let locations = second.definition("app/models/order.rb", 2, 4).await;
+    let locations: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(locations, vec![("order.rb".to_string(), 5, 6)]);
+
+    second.shutdown().await;
+
+    let index_dir = paths::index_dir(&paths::canonicalize(&root.to_string_lossy()));
+    fs::remove_dir_all(&index_dir).unwrap();
+    let _ = fs::remove_file(index_dir.with_extension("lock"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+fn git(root: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["-c", "user.name=Fuzzy", "-c", "user.email=fuzzy@example.com"])
+        .args(args)
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changes_stashed_between_sessions_are_reindexed() {
+    let root = std::env::temp_dir().join(format!("fuzzy-stash-{}", std::process::id()));
+    fs::create_dir_all(root.join("app/models")).unwrap();
+    fs::write(
+        root.join("app/models/order.rb"),
+        "class Order\n  def total\n  end\nend\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("app/models/cart.rb"),
+        "class Cart\n  def checkout\n    Order.new.total\n  end\nend\n",
+    )
+    .unwrap();
+    git(&root, &["init", "--quiet"]);
+    git(&root, &["add", "."]);
+    git(&root, &["commit", "--quiet", "-m", "Add models"]);
+
+    // Indexed with a change that isn't committed
+    fs::write(
+        root.join("app/models/order.rb"),
+        "class Order\n  # Sums the line items\n\n  def total\n  end\nend\n",
+    )
+    .unwrap();
+
+    let options = json!({ "allocationType": "disk" });
+    let total_line = |locations: &Value| {
+        locations
+            .as_array()
+            .and_then(|locations| locations.first())
+            .map(location_summary)
+            .map(|(_file_name, line, _character)| line)
+    };
+
+    let mut client = TestClient::start().await;
+    client.initialize_with_options(&root, options.clone()).await;
+    let locations = client.definition("app/models/cart.rb", 2, 15).await;
+
+    assert_eq!(total_line(&locations), Some(3));
+
+    client.shutdown().await;
+
+    // Git no longer sees the file as changed since the indexed revision
+    git(&root, &["stash", "--quiet"]);
+
+    let mut client = TestClient::start().await;
+    client.initialize_with_options(&root, options).await;
+    let params = client.position_params("app/models/cart.rb", 2, 15);
+    let locations = client
+        .request_until("textDocument/definition", params, |locations| {
+            total_line(locations) == Some(1)
+        })
+        .await;
+
+    assert_eq!(total_line(&locations), Some(1));
+
+    client.shutdown().await;
+
+    let index_dir = paths::index_dir(&paths::canonicalize(&root.to_string_lossy()));
+    fs::remove_dir_all(&index_dir).unwrap();
+    let _ = fs::remove_file(index_dir.with_extension("lock"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peek_definition_includes_the_source_around_it() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    let mut params = client.position_params("app/models/order.rb", 6, 14);
+    params["contextLines"] = json!(1);
+
+    let peeks = client
+        .request_until("fuzzy/peekDefinition", params, |result| {
+            result.as_array().map_or(false, |peeks| !peeks.is_empty())
+        })
+        .await;
+
+    assert_eq!(
+        location_summary(&peeks[0]),
+        ("product.rb".to_string(), 0, 6)
+    );
+    assert_eq!(
+        peeks[0]["snippet"],
+        json!({ "startLine": 0, "text": "class Product\n  TAX_RATE = 0.2" })
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg(feature = "rails")]
+async fn routes_lead_to_controller_actions_and_back() {
+    let mut client = TestClient::start().await;
+    client
+        .initialize_with_options(&fixture_project("shop"), json!({ "rails": true }))
+        .await;
+    client.did_open("config/routes.rb").await;
+
+    let locations = client.definition("config/routes.rb", 3, 25).await;
+
+    assert_eq!(
+        location_summary(&locations[0]),
+        ("orders_controller.rb".to_string(), 7, 6)
+    );
+
+    // `resources` routes to each of its actions
+    let locations = client.definition("config/routes.rb", 2, 14).await;
+    let mut actions: Vec<(String, u64, u64)> = locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+    actions.sort();
+
+    assert_eq!(
+        actions,
+        vec![
+            ("orders_controller.rb".to_string(), 1, 6),
+            ("orders_controller.rb".to_string(), 4, 6),
+        ]
+    );
+
+    let params = client.position_params("app/controllers/orders_controller.rb", 4, 6);
+    let routes = client
+        .request_until("fuzzy/routes", params, |result| {
+            result.as_array().map_or(false, |routes| !routes.is_empty())
+        })
+        .await;
+
+    assert_eq!(
+        location_summary(&routes[0]),
+        ("routes.rb".to_string(), 2, 12)
+    );
+    assert_eq!(routes[0]["route"], json!("GET /orders/:id"));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg(feature = "rails")]
+async fn translations_lead_to_locale_keys_and_back() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/controllers/orders_controller.rb").await;
+
+    // `t(".placed")` in `OrdersController#checkout`
+    let locations = client
+        .definition("app/controllers/orders_controller.rb", 8, 25)
+        .await;
+
+    assert_eq!(location_summary(&locations[0]), ("en.yml".to_string(), 3, 6));
+
+    let mut params = client.position_params("config/locales/en.yml", 3, 8);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(references, vec![("orders_controller.rb".to_string(), 8, 23)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg(feature = "rails")]
+async fn partials_lead_to_their_files_and_back() {
+    let mut client = TestClient::start().await;
+    client
+        .initialize_with_options(&fixture_project("shop"), json!({ "rails": true }))
+        .await;
+    client.did_open("app/views/orders/show.html.erb").await;
+
+    let locations = client.definition("app/views/orders/show.html.erb", 0, 14).await;
+
+    assert_eq!(location_summary(&locations[0]), ("_header.html.erb".to_string(), 0, 0));
+
+    // Named without a directory, next to the view
+    let locations = client.definition("app/views/orders/show.html.erb", 2, 22).await;
+
+    assert_eq!(location_summary(&locations[0]), ("_item.html.erb".to_string(), 0, 0));
+
+    let mut params = client.position_params("app/views/shared/_header.html.erb", 0, 3);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(references, vec![("show.html.erb".to_string(), 0, 11)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg(feature = "rails")]
+async fn callbacks_lead_to_methods_in_the_class_hierarchy() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/controllers/pages_controller.rb").await;
+
+    let locations = client.definition("app/controllers/pages_controller.rb", 1, 24).await;
+
+    assert_eq!(
+        location_summary(&locations[0]),
+        ("application_controller.rb".to_string(), 5, 6)
+    );
+
+    let mut params = client.position_params("app/controllers/application_controller.rb", 5, 8);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| locations.len() > 1)
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(
+        references,
+        vec![
+            ("application_controller.rb".to_string(), 1, 16),
+            ("pages_controller.rb".to_string(), 1, 21),
+        ]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn references_include_calls_on_receivers_named_after_the_class() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    // `order.total` in `Checkout`, but not the local `total` beside it
+    let mut params = client.position_params("app/models/order.rb", 11, 6);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(references, vec![("checkout.rb".to_string(), 2, 18)]);
+
+    // `product.price_with_tax` on the block argument, and on a local
+    // assigned `Product.new`
+    let mut params = client.position_params("app/models/product.rb", 10, 6);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request("textDocument/references", params)
+        .await
+        .unwrap();
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(
+        references,
+        vec![
+            ("order.rb".to_string(), 12, 38),
+            ("restock.rb".to_string(), 3, 12),
+        ]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keyword_arguments_lead_to_their_parameters_and_are_renamed_with_them() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/jobs/restock_job.rb").await;
+
+    let locations = client.definition("app/jobs/restock_job.rb", 2, 21).await;
+
+    assert_eq!(
+        location_summary(&locations[0]),
+        ("restock.rb".to_string(), 6, 20)
+    );
+
+    let mut params = client.position_params("app/services/restock.rb", 6, 20);
+    params["newName"] = json!("item_name");
+
+    let workspace_edit = client.request("textDocument/rename", params).await.unwrap();
+    let edits = edit_summaries(&workspace_edit);
+
+    assert_eq!(
+        edits,
+        vec![
+            ("restock.rb".to_string(), 6, 20),
+            ("restock.rb".to_string(), 7, 13),
+            ("restock_job.rb".to_string(), 2, 21),
+        ]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn instance_variables_are_renamed_in_their_class_across_files() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/models/order.rb").await;
+
+    // Once `Order` reopened in another file is indexed
+    client.definition("app/models/order_discounts.rb", 2, 4).await;
+
+    let mut params = client.position_params("app/models/order.rb", 2, 4);
+    params["newName"] = json!("@items");
+
+    let workspace_edit = client.request("textDocument/rename", params).await.unwrap();
+    let edits = edit_summaries(&workspace_edit);
+
+    // `Wishlist`'s `@products` is its own
+    assert_eq!(
+        edits,
+        vec![
+            ("order.rb".to_string(), 2, 4),
+            ("order.rb".to_string(), 7, 4),
+            ("order.rb".to_string(), 12, 4),
+            ("order_discounts.rb".to_string(), 2, 4),
+        ]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn locals_are_renamed_in_their_method_unless_the_name_is_taken() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+    client.definition("app/services/checkout.rb", 3, 12).await;
+
+    let mut params = client.position_params("app/services/checkout.rb", 2, 4);
+    params["newName"] = json!("amount");
+
+    let workspace_edit = client.request("textDocument/rename", params).await.unwrap();
+    let edits = edit_summaries(&workspace_edit);
+
+    // `receipt`'s `total` is its own
+    assert_eq!(
+        edits,
+        vec![
+            ("checkout.rb".to_string(), 2, 4),
+            ("checkout.rb".to_string(), 3, 12),
+        ]
+    );
+
+    let mut params = client.position_params("app/services/checkout.rb", 2, 4);
+    params["newName"] = json!("order");
+
+    let error = client.request("textDocument/rename", params).await.unwrap_err();
+
+    assert_eq!(
+        error["message"],
+        json!("`order` is already a local variable in `self.call` at line 2.")
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn selected_statements_are_extracted_into_a_method() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+    client.definition("app/services/checkout.rb", 3, 12).await;
+
+    let params = json!({
+        "textDocument": { "uri": client.uri("app/services/checkout.rb") },
+        "range": {
+            "start": { "line": 2, "character": 0 },
+            "end": { "line": 3, "character": 0 },
+        },
+        "context": { "diagnostics": [], "only": ["refactor.extract"] },
+    });
+
+    let actions = client.request("textDocument/codeAction", params).await.unwrap();
+    let action = &actions[0];
+
+    assert_eq!(action["title"], json!("Extract method"));
+    assert_eq!(action["kind"], json!("refactor.extract"));
+
+    // `order` is passed in, and `total` is returned for the receipt
+    let edits = &action["edit"]["changes"][client.uri("app/services/checkout.rb")];
+
+    assert_eq!(edits[0]["newText"], json!("total = extracted_method(order)"));
+    assert_eq!(
+        edits[1]["newText"],
+        json!("\n\n  def self.extracted_method(order)\n    total = order.total\n    total\n  end")
+    );
+    assert_eq!(edits[1]["range"]["start"], json!({ "line": 4, "character": 5 }));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn locals_are_inlined_where_they_are_read() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/checkout.rb").await;
+    client.definition("app/services/checkout.rb", 3, 12).await;
+
+    let params = json!({
+        "textDocument": { "uri": client.uri("app/services/checkout.rb") },
+        "range": {
+            "start": { "line": 2, "character": 4 },
+            "end": { "line": 2, "character": 4 },
+        },
+        "context": { "diagnostics": [] },
+    });
+
+    let actions = client.request("textDocument/codeAction", params).await.unwrap();
+    let action = &actions[0];
+
+    assert_eq!(action["title"], json!("Inline local variable `total`"));
+    assert_eq!(action["kind"], json!("refactor.inline"));
+
+    let edits = &action["edit"]["changes"][client.uri("app/services/checkout.rb")];
+
+    assert_eq!(edits[0]["range"]["start"], json!({ "line": 3, "character": 12 }));
+    assert_eq!(edits[0]["newText"], json!("order.total"));
+    assert_eq!(
+        edits[1]["range"],
+        json!({
+            "start": { "line": 2, "character": 0 },
+            "end": { "line": 3, "character": 0 },
+        })
+    );
+    assert_eq!(edits[1]["newText"], json!(""));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn constants_defined_in_files_not_required_are_required() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/jobs/restock_job.rb").await;
+    client.definition("app/jobs/restock_job.rb", 2, 6).await;
+
+    let params = json!({
+        "textDocument": { "uri": client.uri("app/jobs/restock_job.rb") },
+        "range": {
+            "start": { "line": 2, "character": 6 },
+            "end": { "line": 2, "character": 6 },
+        },
+        "context": { "diagnostics": [], "only": ["quickfix"] },
+    });
+
+    let actions = client.request("textDocument/codeAction", params).await.unwrap();
+    let action = &actions[0];
+
+    assert_eq!(action["title"], json!("Add `require_relative \"../services/restock\"`"));
+
+    let edits = &action["edit"]["changes"][client.uri("app/jobs/restock_job.rb")];
+
+    assert_eq!(edits[0]["range"]["start"], json!({ "line": 0, "character": 0 }));
+    assert_eq!(edits[0]["newText"], json!("require_relative \"../services/restock\"\n\n"));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_methods_are_created_in_the_receivers_class() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("app/services/refund.rb").await;
+
+    let uri = client.uri("app/services/refund.rb");
+    let params = |line: u32, character: u32| {
+        let position = json!({ "line": line, "character": character });
+
+        json!({
+            "textDocument": { "uri": uri },
+            "range": { "start": position, "end": position },
+            "context": { "diagnostics": [], "only": ["quickfix"] },
+        })
+    };
+    let offered = |actions: &Value| actions.as_array().map_or(false, |actions| !actions.is_empty());
+
+    let actions = client
+        .request_until("textDocument/codeAction", params(3, 4), offered)
+        .await;
+    let action = &actions[0];
+
+    assert_eq!(action["title"], json!("Create method `notify_customer` in Refund"));
+
+    let edits = &action["edit"]["changes"][&uri];
+
+    assert_eq!(edits[0]["range"]["start"], json!({ "line": 6, "character": 0 }));
+    assert_eq!(edits[0]["newText"], json!("\n  def notify_customer\n  end\n"));
+
+    // On an instance variable named after a class
+    let actions = client
+        .request_until("textDocument/codeAction", params(4, 11), offered)
+        .await;
+    let action = &actions[0];
+
+    assert_eq!(action["title"], json!("Create method `cancel` in Order"));
+
+    let edits = &action["edit"]["changes"][client.uri("app/models/order.rb")];
+
+    assert_eq!(edits[0]["range"]["start"], json!({ "line": 14, "character": 0 }));
+    assert_eq!(edits[0]["newText"], json!("\n  def cancel\n  end\n"));
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn classes_are_renamed_across_the_workspace_and_their_file_moved() {
+    let mut client = TestClient::start().await;
+    let capabilities = json!({
+        "workspace": {
+            "workspaceEdit": {
+                "documentChanges": true,
+                "resourceOperations": ["create", "rename", "delete"],
+                "changeAnnotationSupport": {},
+            },
+        },
+    });
+    client
+        .initialize_with(&fixture_project("shop"), json!({ "rails": true }), capabilities)
+        .await;
+    client.did_open("app/models/order.rb").await;
+
+    let mut params = client.position_params("app/models/order.rb", 6, 14);
+    params["newName"] = json!("LineItem");
+
+    // Once every file using it is indexed
+    let workspace_edit = client
+        .request_until("textDocument/rename", params, |workspace_edit| {
+            edit_summaries(workspace_edit).len() == 3
+        })
+        .await;
+
+    assert_eq!(
+        edit_summaries(&workspace_edit),
+        vec![
+            ("order.rb".to_string(), 6, 14),
+            ("product.rb".to_string(), 0, 6),
+            ("restock.rb".to_string(), 2, 14),
+        ]
+    );
+
+    // Moved after its edits, once the user confirms
+    let operations = workspace_edit["documentChanges"].as_array().unwrap();
+    let rename_file = operations.last().unwrap();
+
+    let old_uri = client.uri("app/models/product.rb");
+    let new_uri = old_uri.replace("product.rb", "line_item.rb");
+
+    assert_eq!(rename_file["kind"], json!("rename"));
+    assert_eq!(rename_file["oldUri"], json!(old_uri));
+    assert_eq!(rename_file["newUri"], json!(new_uri));
+    assert_eq!(
+        workspace_edit["changeAnnotations"][rename_file["annotationId"].as_str().unwrap()]
+            ["needsConfirmation"],
+        json!(true)
+    );
+
+    let mut params = client.position_params("app/models/order.rb", 6, 14);
+    params["newName"] = json!("line_item");
+
+    assert!(client.request("textDocument/rename", params).await.is_err());
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg(feature = "factory_bot")]
+async fn factories_and_traits_lead_to_their_declarations_and_back() {
+    let mut client = TestClient::start().await;
+    client.initialize(&fixture_project("shop")).await;
+    client.did_open("spec/models/order_spec.rb").await;
+
+    let factory = client.definition("spec/models/order_spec.rb", 2, 19).await;
+
+    assert_eq!(location_summary(&factory[0]), ("users.rb".to_string(), 8, 12));
+
+    let factory_trait = client.definition("spec/models/order_spec.rb", 2, 32).await;
+
+    assert_eq!(location_summary(&factory_trait[0]), ("users.rb".to_string(), 4, 10));
+
+    let mut params = client.position_params("spec/factories/users.rb", 4, 12);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(references, vec![("order_spec.rb".to_string(), 2, 31)]);
+
+    client.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dsl_rules_link_their_definitions_and_usages() {
+    let options = json!({
+        "dslRules": [
+            {
+                "pattern": "^register\\(\"(?P<name>[\\w.]+)\"",
+                "kind": "definition",
+                "type": "ContainerKey"
+            },
+            {
+                "pattern": "^Import\\[\"(?P<name>[\\w.]+)\"\\]",
+                "kind": "usage",
+                "type": "ContainerKey"
+            }
+        ]
+    });
+
+    let mut client = TestClient::start().await;
+    client.initialize_with_options(&fixture_project("shop"), options).await;
+    client.did_open("app/services/notifier.rb").await;
+
+    let locations = client.definition("app/services/notifier.rb", 1, 20).await;
+
+    assert_eq!(location_summary(&locations[0]), ("container.rb".to_string(), 3, 12));
+
+    let mut params = client.position_params("app/services/container.rb", 3, 14);
+    params["context"] = json!({ "includeDeclaration": false });
+
+    let references = client
+        .request_until("textDocument/references", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await;
+    let references: Vec<(String, u64, u64)> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(location_summary)
+        .collect();
+
+    assert_eq!(references, vec![("notifier.rb".to_string(), 1, 18)]);
+
+    client.shutdown().await;
+}
@@ -0,0 +1,215 @@
+use fuzzy::persistence::Persistence;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::NumberOrString;
+
+// Each Ruby file in `tests/fixtures/serializer` is parsed and its nodes are
+// compared to the `.golden` file beside it, one node per line:
+//
+//   12:6-13 assignment Def deposit scope=[Account] class=[Account] params=[arg:amount] visibility=public
+//
+// Changes to what's extracted show up as diffs to the golden files. After
+// checking the new output is right, write it with:
+//
+//   UPDATE_GOLDEN=1 cargo test --test serializer
+//
+// A new fixture fails until its golden file is written the same way.
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/serializer")
+}
+
+// Lines and columns start at 0, as in the index
+fn render_node(node: &Value) -> String {
+    let list = |key: &str| {
+        let items: Vec<&str> = node[key]
+            .as_array()
+            .map(|items| items.iter().flat_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        format!("[{}]", items.join(", "))
+    };
+
+    let mut line = format!(
+        "{}:{}-{} {} {} {} scope={} class={}",
+        node["line"],
+        node["startColumn"],
+        node["endColumn"],
+        node["category"].as_str().unwrap_or(""),
+        node["nodeType"].as_str().unwrap_or(""),
+        node["name"].as_str().unwrap_or(""),
+        list("fuzzyRubyScope"),
+        list("classScope"),
+    );
+
+    if node["parameters"]
+        .as_array()
+        .map_or(false, |params| params.len() > 0)
+    {
+        line.push_str(&format!(" params={}", list("parameters")));
+    }
+
+    if let Some(visibility) = node["visibility"].as_str() {
+        line.push_str(&format!(" visibility={}", visibility));
+    }
+
+    line
+}
+
+fn render_fixture(parser: &mut Persistence, path: &Path) -> String {
+    let text = fs::read_to_string(path).unwrap();
+    let parsed_file = parser.parse_file(&text, false);
+
+    assert!(parsed_file.parsed, "{} failed to parse", path.display());
+
+    let mut rendered: String = parsed_file
+        .debug_nodes()
+        .iter()
+        .map(render_node)
+        .collect::<Vec<String>>()
+        .join("\n");
+    rendered.push('\n');
+
+    rendered
+}
+
+// The first line that differs, so a failure points at the change
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+
+    for line_number in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (expected_line, actual_line) if expected_line == actual_line => continue,
+            (expected_line, actual_line) => {
+                return format!(
+                    "line {}:\n  expected: {}\n  actual:   {}",
+                    line_number,
+                    expected_line.unwrap_or("<end of file>"),
+                    actual_line.unwrap_or("<end of file>")
+                );
+            }
+        }
+    }
+
+    String::new()
+}
+
+// The lines of the methods warned about as already defined
+fn duplicate_method_lines(source: &str) -> Vec<u32> {
+    let parsed_file = Persistence::new()
+        .unwrap()
+        .parse_file(&source.to_string(), false);
+
+    parsed_file
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            diagnostic.code == Some(NumberOrString::String("duplicate-method".to_string()))
+        })
+        .map(|diagnostic| diagnostic.range.start.line)
+        .collect()
+}
+
+#[test]
+fn methods_defined_in_either_branch_of_a_condition_are_not_duplicates() {
+    let source = r#"
+class Report
+  if Rails.env.production?
+    def path
+      "/reports"
+    end
+  else
+    def path
+      "/tmp/reports"
+    end
+  end
+
+  def title; end
+  def title; end
+end
+"#;
+
+    assert_eq!(duplicate_method_lines(source), vec![13]);
+}
+
+#[test]
+fn methods_defined_in_sibling_blocks_are_not_duplicates() {
+    let source = r#"
+RSpec.describe Order do
+  context "when paid" do
+    def order
+      build(:order, :paid)
+    end
+  end
+
+  context "when refunded" do
+    def order
+      build(:order, :refunded)
+    end
+  end
+end
+
+Plain = Class.new do
+  def call; end
+end
+
+Fancy = Class.new do
+  def call; end
+end
+"#;
+
+    assert_eq!(duplicate_method_lines(source), Vec::<u32>::new());
+}
+
+#[test]
+fn serializer_output_matches_golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut parser = Persistence::new().unwrap();
+    let mut failures = vec![];
+
+    let mut fixture_paths: Vec<PathBuf> = fs::read_dir(fixture_dir())
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "rb")
+        })
+        .collect();
+    fixture_paths.sort();
+
+    assert!(fixture_paths.len() > 0, "No serializer fixtures found");
+
+    for fixture_path in fixture_paths {
+        let golden_path = fixture_path.with_extension("golden");
+        let actual = render_fixture(&mut parser, &fixture_path);
+
+        match fs::read_to_string(&golden_path) {
+            Ok(expected) if !update => {
+                if expected != actual {
+                    failures.push(format!(
+                        "{} differs from its golden file, {}",
+                        fixture_path.display(),
+                        first_difference(&expected, &actual)
+                    ));
+                }
+            }
+            Err(_) if !update => {
+                failures.push(format!("{} has no golden file", fixture_path.display()));
+            }
+            _ => {
+                fs::write(&golden_path, &actual).unwrap();
+                println!("Wrote {}", golden_path.display());
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\n\nRun `UPDATE_GOLDEN=1 cargo test --test serializer` to accept the new output.",
+        failures.join("\n\n")
+    );
+}
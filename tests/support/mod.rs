@@ -0,0 +1,335 @@
+use fuzzy::paths;
+use fuzzy::server;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::task::JoinHandle;
+use tower_lsp::Server;
+
+// Talks to the server the way an editor does, over JSON-RPC, through an
+// in-memory stream rather than stdio:
+//
+//   let mut client = TestClient::start().await;
+//   client.initialize(&fixture_project("shop")).await;
+//   client.did_open("app/models/order.rb").await;
+//   let locations = client.definition("app/models/order.rb", 6, 14).await;
+//
+// Requests the server sends the client, like `window/showMessageRequest`,
+// are answered with null. Notifications are kept for assertions.
+
+// Indexing happens in the background after `initialized`, requests that
+// need the index are retried until they answer or this runs out
+const INDEXING_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct TestClient {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+    next_id: i64,
+    root: PathBuf,
+    pub notifications: VecDeque<Value>,
+    server_task: JoinHandle<()>,
+}
+
+pub fn fixture_project(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/projects")
+        .join(name)
+}
+
+impl TestClient {
+    pub async fn start() -> TestClient {
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let (client_read, client_write) = tokio::io::split(client_stream);
+
+        // There's no editor process to watch
+        let (service, socket) = server::service(false);
+        let server_task = tokio::spawn(async move {
+            Server::new(server_read, server_write, socket)
+                .serve(service)
+                .await;
+        });
+
+        TestClient {
+            reader: BufReader::new(client_read),
+            writer: client_write,
+            next_id: 0,
+            root: PathBuf::new(),
+            notifications: VecDeque::new(),
+            server_task,
+        }
+    }
+
+    // Gems aren't indexed, only the fixture project's files
+    pub async fn initialize(&mut self, root: &Path) -> Value {
+        self.initialize_with_options(root, json!({})).await
+    }
+
+    pub async fn initialize_with_options(&mut self, root: &Path, options: Value) -> Value {
+        self.initialize_with(root, options, json!({})).await
+    }
+
+    // Initializes as an editor with a single file open and no folder, the
+    // root only locating the files it opens
+    pub async fn initialize_without_root(&mut self, root: &Path) -> Value {
+        self.root = root.to_path_buf();
+
+        let result = self
+            .request(
+                "initialize",
+                json!({
+                    "processId": null,
+                    "rootUri": null,
+                    "capabilities": {},
+                    "initializationOptions": { "indexGems": false },
+                }),
+            )
+            .await
+            .expect("initialize failed");
+
+        self.notify("initialized", json!({})).await;
+
+        result
+    }
+
+    // Initializes as a client with the capabilities
+    pub async fn initialize_with(
+        &mut self,
+        root: &Path,
+        options: Value,
+        capabilities: Value,
+    ) -> Value {
+        self.root = root.to_path_buf();
+
+        let mut initialization_options = json!({ "indexGems": false });
+
+        for (key, value) in options.as_object().cloned().unwrap_or_default() {
+            initialization_options[key] = value;
+        }
+
+        let result = self
+            .request(
+                "initialize",
+                json!({
+                    "processId": null,
+                    "rootUri": self.uri(""),
+                    "capabilities": capabilities,
+                    "initializationOptions": initialization_options,
+                }),
+            )
+            .await
+            .expect("initialize failed");
+
+        self.notify("initialized", json!({})).await;
+
+        result
+    }
+
+    pub async fn shutdown(mut self) {
+        self.request("shutdown", Value::Null)
+            .await
+            .expect("shutdown failed");
+        self.notify("exit", Value::Null).await;
+        self.server_task.abort();
+    }
+
+    // The file URI of a path relative to the project root
+    pub fn uri(&self, relative_path: &str) -> String {
+        let path = self.root.join(relative_path);
+
+        paths::path_to_uri(&paths::canonicalize(&path.to_string_lossy())).to_string()
+    }
+
+    pub async fn did_open(&mut self, relative_path: &str) {
+        let text = fs::read_to_string(self.root.join(relative_path)).unwrap();
+
+        self.did_open_with_text(relative_path, &text).await;
+    }
+
+    pub async fn did_open_with_text(&mut self, relative_path: &str, text: &str) {
+        let uri = self.uri(relative_path);
+
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "ruby",
+                    "version": 1,
+                    "text": text,
+                },
+            }),
+        )
+        .await;
+    }
+
+    // Lines and characters start at 0, as in the protocol
+    pub fn position_params(&self, relative_path: &str, line: u32, character: u32) -> Value {
+        json!({
+            "textDocument": { "uri": self.uri(relative_path) },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    // Definitions once the index has them
+    pub async fn definition(&mut self, relative_path: &str, line: u32, character: u32) -> Value {
+        let params = self.position_params(relative_path, line, character);
+
+        self.request_until("textDocument/definition", params, |result| {
+            result
+                .as_array()
+                .map_or(false, |locations| !locations.is_empty())
+        })
+        .await
+    }
+
+    // Retries a request until its result passes, for requests answered from
+    // an index that's still being built
+    pub async fn request_until(
+        &mut self,
+        method: &str,
+        params: Value,
+        ready: impl Fn(&Value) -> bool,
+    ) -> Value {
+        let give_up_at = tokio::time::Instant::now() + INDEXING_TIMEOUT;
+
+        loop {
+            let result = self
+                .request(method, params.clone())
+                .await
+                .unwrap_or_else(|error| panic!("{} failed: {}", method, error));
+
+            if ready(&result) || tokio::time::Instant::now() > give_up_at {
+                return result;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    // The result, or the error the server responded with
+    pub async fn request(&mut self, method: &str, params: Value) -> Result<Value, Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        self.send(message(Some(id), method, params)).await;
+
+        loop {
+            let message = self.receive().await;
+
+            if message.get("method").is_some() {
+                self.handle_server_message(message).await;
+                continue;
+            }
+
+            if message["id"] != json!(id) {
+                continue;
+            }
+
+            return match message.get("error") {
+                Some(error) => Err(error.clone()),
+                None => Ok(message["result"].clone()),
+            };
+        }
+    }
+
+    // The next notification with the method, received already or waited for
+    pub async fn notification(&mut self, method: &str) -> Value {
+        let received = self
+            .notifications
+            .iter()
+            .position(|notification| notification["method"] == method);
+
+        if let Some(index) = received {
+            return self.notifications.remove(index).unwrap();
+        }
+
+        let wait = async {
+            loop {
+                let message = self.receive().await;
+
+                if message["method"] == method && message.get("id").is_none() {
+                    return message;
+                }
+
+                if message.get("method").is_some() {
+                    self.handle_server_message(message).await;
+                }
+            }
+        };
+
+        tokio::time::timeout(INDEXING_TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("No {} notification", method))
+    }
+
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        self.send(message(None, method, params)).await;
+    }
+
+    async fn handle_server_message(&mut self, message: Value) {
+        match message.get("id") {
+            Some(id) => {
+                let id = id.clone();
+
+                self.send(json!({ "jsonrpc": "2.0", "id": id, "result": null }))
+                    .await;
+            }
+            None => self.notifications.push_back(message),
+        }
+    }
+
+    async fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+
+        self.writer.write_all(frame.as_bytes()).await.unwrap();
+        self.writer.flush().await.unwrap();
+    }
+
+    async fn receive(&mut self) -> Value {
+        let mut content_length = None;
+
+        loop {
+            let mut header = String::new();
+
+            if self.reader.read_line(&mut header).await.unwrap() == 0 {
+                panic!("The server closed the connection");
+            }
+
+            let header = header.trim_end();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some(length) = header.strip_prefix("Content-Length: ") {
+                content_length = Some(length.parse::<usize>().unwrap());
+            }
+        }
+
+        let mut body = vec![0; content_length.expect("Message without a Content-Length")];
+        self.reader.read_exact(&mut body).await.unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+// Requests and notifications without params, like `shutdown`, leave them out
+fn message(id: Option<i64>, method: &str, params: Value) -> Value {
+    let mut message = json!({ "jsonrpc": "2.0", "method": method });
+
+    if let Some(id) = id {
+        message["id"] = json!(id);
+    }
+
+    if !params.is_null() {
+        message["params"] = params;
+    }
+
+    message
+}